@@ -0,0 +1,115 @@
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::Path,
+};
+
+use crate::{manifest::ManifestSource, ManifestFormat};
+
+/// The manifests that may be committed at the root of an artifact, in order of preference.
+pub(crate) const EMBEDDED_MANIFESTS: [(&str, ManifestFormat); 3] = [
+    ("pond.toml", ManifestFormat::Toml),
+    ("pond.json", ManifestFormat::Json),
+    ("pond.yaml", ManifestFormat::Yaml),
+];
+
+/// Reads the manifest at the root of the zip archive or release directory at
+/// `artifact_location`, returning its file name along with the manifest.
+pub(crate) fn embedded_manifest(
+    artifact_location: &Path,
+) -> anyhow::Result<Option<(&'static str, ManifestSource)>> {
+    if artifact_location.is_dir() {
+        for (file_name, format) in EMBEDDED_MANIFESTS {
+            let path = artifact_location.join(file_name);
+            if path.is_file() {
+                let content = fs::read_to_string(path)?;
+                return Ok(Some((
+                    file_name,
+                    ManifestSource {
+                        content,
+                        format: Some(format),
+                    },
+                )));
+            }
+        }
+        return Ok(None);
+    }
+    let mut archive = zip::ZipArchive::new(File::open(artifact_location)?)?;
+    for (file_name, format) in EMBEDDED_MANIFESTS {
+        let mut file = match archive.by_name(file_name) {
+            Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let mut content = String::new();
+        file.read_to_string(&mut content)?;
+        return Ok(Some((
+            file_name,
+            ManifestSource {
+                content,
+                format: Some(format),
+            },
+        )));
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{io::Write, path::PathBuf};
+
+    use super::*;
+
+    /// Writes a zip archive containing `files` to the temporary directory.
+    pub(crate) fn zip_artifact(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pond-artifact-{}.zip", name));
+        let mut writer = zip::ZipWriter::new(File::create(&path).unwrap());
+        for (file_name, content) in files {
+            writer
+                .start_file(*file_name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_embedded_manifest() {
+        let artifact = zip_artifact(
+            "embedded",
+            &[
+                ("index.html", "<h1>Hello</h1>"),
+                ("pond.json", "{}"),
+                ("pond.toml", "name = \"blog\""),
+            ],
+        );
+        let (file_name, manifest) = embedded_manifest(&artifact).unwrap().unwrap();
+        assert_eq!(file_name, "pond.toml");
+        assert_eq!(manifest.content, "name = \"blog\"");
+        assert_eq!(manifest.format, Some(ManifestFormat::Toml));
+    }
+
+    #[test]
+    fn test_nested_manifests_are_ignored() {
+        let artifact = zip_artifact("nested", &[("site/pond.toml", "name = \"blog\"")]);
+        assert!(embedded_manifest(&artifact).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_manifest_in_directory() {
+        let directory = std::env::temp_dir().join("pond-artifact-directory");
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(directory.join("pond.yaml"), "name: blog").unwrap();
+        let (file_name, manifest) = embedded_manifest(&directory).unwrap().unwrap();
+        assert_eq!(file_name, "pond.yaml");
+        assert_eq!(manifest.format, Some(ManifestFormat::Yaml));
+    }
+
+    #[test]
+    fn test_invalid_archive() {
+        let path = std::env::temp_dir().join("pond-artifact-invalid.zip");
+        std::fs::write(&path, "not a zip archive").unwrap();
+        assert!(embedded_manifest(&path).is_err());
+    }
+}
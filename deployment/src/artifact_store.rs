@@ -0,0 +1,294 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use figment::{providers::Serialized, Figment};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ConfigurationError, state::JsonFile};
+
+const ARTIFACTS: &str = "artifacts";
+const ARTIFACTS_FILE_NAME: &str = "artifacts.json";
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Serialize, Deserialize)]
+struct ArtifactsConfig {
+    /// Defaults to `<state_directory>/artifacts`.
+    directory: Option<PathBuf>,
+    keep_last: usize,
+    keep_days: u64,
+}
+
+impl Default for ArtifactsConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            keep_last: 5,
+            keep_days: 30,
+        }
+    }
+}
+
+/// A deployment made from an artifact.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ArtifactReference {
+    pub deployment_name: String,
+    /// In seconds since the Unix epoch.
+    pub deployed_at: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct StoredArtifact {
+    /// The hex encoded SHA-256 digest of the artifact, which it is stored under.
+    pub sha256: String,
+    pub size: u64,
+    /// The access token the artifact was first uploaded with.
+    pub uploaded_by: Option<String>,
+    /// In seconds since the Unix epoch.
+    pub created_at: u64,
+    pub deployments: Vec<ArtifactReference>,
+}
+
+/// Uploaded artifacts, stored once per SHA-256 digest so they can be deployed again without
+/// uploading them. Artifacts are kept while one of the last `keep_last` deployments of a site
+/// uses them or for `keep_days` after they were uploaded.
+pub struct ArtifactStore {
+    directory: PathBuf,
+    artifacts: JsonFile<BTreeMap<String, StoredArtifact>>,
+    keep_last: usize,
+    keep_days: u64,
+}
+
+impl ArtifactStore {
+    pub fn configure(
+        figment: &Figment,
+        state_directory: &Path,
+    ) -> Result<Self, ConfigurationError> {
+        let config: ArtifactsConfig = figment.extract_inner(ARTIFACTS)?;
+        let directory = config
+            .directory
+            .unwrap_or_else(|| state_directory.join(ARTIFACTS));
+        let mut store =
+            ArtifactStore::open(&directory).map_err(|e| ConfigurationError::Other(e.into()))?;
+        store.keep_last = config.keep_last.max(1);
+        store.keep_days = config.keep_days;
+        Ok(store)
+    }
+
+    pub fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(ARTIFACTS, ArtifactsConfig::default()))
+    }
+
+    /// Opens the store kept in `directory`, along with its metadata.
+    pub fn open(directory: &Path) -> anyhow::Result<Self> {
+        let defaults = ArtifactsConfig::default();
+        Ok(ArtifactStore {
+            directory: directory.to_owned(),
+            artifacts: JsonFile::open(directory, ARTIFACTS_FILE_NAME)?,
+            keep_last: defaults.keep_last,
+            keep_days: defaults.keep_days,
+        })
+    }
+
+    /// Moves the file at `location` into the store. If an artifact with the same digest is
+    /// stored already, the file is removed instead.
+    pub fn store(
+        &self,
+        location: &Path,
+        sha256: &str,
+        uploaded_by: Option<&str>,
+    ) -> anyhow::Result<StoredArtifact> {
+        let path = self.path(sha256);
+        self.artifacts.update(|artifacts| {
+            if let Some(artifact) = artifacts.get(sha256) {
+                if path.exists() {
+                    fs::remove_file(location)?;
+                    return Ok(artifact.clone());
+                }
+            }
+
+            fs::create_dir_all(&self.directory)?;
+            // The artifact may have been uploaded to another file system
+            if fs::rename(location, &path).is_err() {
+                fs::copy(location, &path)?;
+                fs::remove_file(location)?;
+            }
+            let artifact = StoredArtifact {
+                sha256: sha256.to_owned(),
+                size: fs::metadata(&path)?.len(),
+                uploaded_by: uploaded_by.map(str::to_owned),
+                created_at: now(),
+                deployments: vec![],
+            };
+            artifacts.insert(sha256.to_owned(), artifact.clone());
+            Ok(artifact)
+        })?
+    }
+
+    pub fn get(&self, sha256: &str) -> anyhow::Result<Option<StoredArtifact>> {
+        let artifact = self
+            .artifacts
+            .read(|artifacts| artifacts.get(sha256).cloned())?;
+        Ok(artifact.filter(|_| self.path(sha256).exists()))
+    }
+
+    pub fn list(&self) -> anyhow::Result<Vec<StoredArtifact>> {
+        self.artifacts
+            .read(|artifacts| artifacts.values().cloned().collect())
+    }
+
+    /// Where the artifact with `sha256` is stored.
+    pub fn path(&self, sha256: &str) -> PathBuf {
+        // Digests are also taken from URLs and form fields
+        let sha256: String = sha256.chars().filter(char::is_ascii_hexdigit).collect();
+        self.directory.join(format!("{}.artifact", sha256))
+    }
+
+    /// Remembers that `deployment_name` was deployed from the artifact with `sha256`.
+    pub fn add_reference(&self, sha256: &str, deployment_name: &str) -> anyhow::Result<()> {
+        self.artifacts.update(|artifacts| {
+            if let Some(artifact) = artifacts.get_mut(sha256) {
+                artifact.deployments.push(ArtifactReference {
+                    deployment_name: deployment_name.to_owned(),
+                    deployed_at: now(),
+                });
+            }
+        })
+    }
+
+    /// Removes the artifacts that neither belong to the last `keep_last` deployments of a site
+    /// nor are younger than `keep_days`, returning their digests.
+    pub fn apply_retention(&self) -> anyhow::Result<Vec<String>> {
+        let kept_since = now().saturating_sub(self.keep_days * SECONDS_PER_DAY);
+        let removed = self.artifacts.update(|artifacts| {
+            let mut deployments: BTreeMap<&str, Vec<(u64, &str)>> = BTreeMap::new();
+            for artifact in artifacts.values() {
+                for reference in &artifact.deployments {
+                    deployments
+                        .entry(&reference.deployment_name)
+                        .or_default()
+                        .push((reference.deployed_at, &artifact.sha256));
+                }
+            }
+            let mut kept = BTreeSet::new();
+            for references in deployments.values_mut() {
+                references.sort_by(|a, b| b.cmp(a));
+                let mut site_kept: Vec<&str> = vec![];
+                for (_, sha256) in references.iter() {
+                    if site_kept.len() == self.keep_last {
+                        break;
+                    }
+                    if !site_kept.contains(sha256) {
+                        site_kept.push(sha256);
+                    }
+                }
+                kept.extend(site_kept.into_iter().map(str::to_owned));
+            }
+
+            let removed: Vec<String> = artifacts
+                .values()
+                .filter(|a| a.created_at < kept_since && !kept.contains(&a.sha256))
+                .map(|a| a.sha256.clone())
+                .collect();
+            for sha256 in &removed {
+                artifacts.remove(sha256);
+            }
+            removed
+        })?;
+
+        for sha256 in &removed {
+            info!("Removing artifact {}", sha256);
+            if let Err(e) = fs::remove_file(self.path(sha256)) {
+                warn!("Failed to remove artifact {}: {:?}", sha256, e);
+            }
+        }
+        Ok(removed)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn store(name: &str) -> ArtifactStore {
+        let directory = std::env::temp_dir().join(format!("pond-artifact-store-{}", name));
+        fs::remove_dir_all(&directory).ok();
+        ArtifactStore::open(&directory).unwrap()
+    }
+
+    fn upload(store: &ArtifactStore, content: &str, sha256: &str) -> StoredArtifact {
+        let location = store.directory.with_extension(format!("{}.upload", sha256));
+        fs::write(&location, content).unwrap();
+        let artifact = store.store(&location, sha256, Some("ci")).unwrap();
+        assert!(!location.exists());
+        artifact
+    }
+
+    #[test]
+    fn test_artifacts_are_deduplicated() {
+        let store = store("deduplicated");
+        let first = upload(&store, "site", "aa");
+        assert_eq!(first.size, 4);
+        assert_eq!(first.uploaded_by.as_deref(), Some("ci"));
+        let second = upload(&store, "site", "aa");
+        assert_eq!(first, second);
+        assert_eq!(store.list().unwrap().len(), 1);
+        assert_eq!(fs::read_to_string(store.path("aa")).unwrap(), "site");
+        assert!(store.get("bb").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_retention() {
+        let mut store = store("retention");
+        store.keep_last = 2;
+        store.keep_days = 1;
+        for sha256 in ["aa", "bb", "cc", "dd"] {
+            upload(&store, sha256, sha256);
+        }
+        // All were uploaded before the retention period, and all but dd deployed in order
+        store
+            .artifacts
+            .update(|artifacts| {
+                for (index, artifact) in artifacts.values_mut().enumerate() {
+                    artifact.created_at -= 2 * SECONDS_PER_DAY;
+                    if artifact.sha256 != "dd" {
+                        artifact.deployments.push(ArtifactReference {
+                            deployment_name: "blog".to_owned(),
+                            deployed_at: index as u64,
+                        });
+                    }
+                }
+                // Redeploying an artifact doesn't count twice towards `keep_last`
+                artifacts
+                    .get_mut("cc")
+                    .unwrap()
+                    .deployments
+                    .push(ArtifactReference {
+                        deployment_name: "blog".to_owned(),
+                        deployed_at: 10,
+                    });
+            })
+            .unwrap();
+        let recent = upload(&store, "ee", "ee");
+
+        assert_eq!(store.apply_retention().unwrap(), vec!["aa", "dd"]);
+        assert!(!store.path("aa").exists());
+        let remaining: Vec<String> = store
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|a| a.sha256)
+            .collect();
+        assert_eq!(remaining, vec!["bb", "cc", recent.sha256.as_str()]);
+    }
+}
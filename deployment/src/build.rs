@@ -0,0 +1,440 @@
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    fs::{self, File},
+    io,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+use figment::{providers::Serialized, Figment};
+use rand::{distributions::DistString, thread_rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ConfigurationError,
+    deployer::DeploymentHandle,
+    execution::{ExecutionSettings, ResourceLimits},
+    helpers::run_command_with_timeout,
+    manifest::BuildStep,
+};
+
+const BUILD: &str = "build";
+const DEFAULT_PATH: &str = "/usr/local/bin:/usr/bin:/bin";
+
+#[derive(Clone, Serialize, Deserialize)]
+struct BuildConfig {
+    /// Where working directories are created. Defaults to the temporary directory.
+    directory: Option<PathBuf>,
+    /// The unprivileged user and group builds run as, instead of pond's. Both are required
+    /// when pond runs as root.
+    uid: Option<u32>,
+    gid: Option<u32>,
+    max_timeout_seconds: u64,
+    max_memory_bytes: u64,
+    max_processes: u64,
+    max_file_size_bytes: u64,
+    /// Runs builds in namespaces through bubblewrap, failing them if it isn't installed.
+    bubblewrap: bool,
+    bwrap_command_name: String,
+}
+
+impl Default for BuildConfig {
+    fn default() -> Self {
+        Self {
+            directory: None,
+            uid: None,
+            gid: None,
+            max_timeout_seconds: 60 * 60,
+            max_memory_bytes: 4 * 1024 * 1024 * 1024,
+            max_processes: 512,
+            max_file_size_bytes: 1024 * 1024 * 1024,
+            bubblewrap: true,
+            bwrap_command_name: "bwrap".to_owned(),
+        }
+    }
+}
+
+/// The working directory of a build, which is removed when dropped.
+pub(crate) struct BuildDirectory {
+    directory: PathBuf,
+    /// The output directory of the build step within the working directory.
+    pub output: PathBuf,
+}
+
+impl Drop for BuildDirectory {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.directory) {
+            warn!(
+                "Failed to remove build directory {:?}: {:?}",
+                self.directory, e
+            );
+        }
+    }
+}
+
+/// Runs the build steps of manifests in throwaway working directories, limited through
+/// `setrlimit` and optionally isolated with bubblewrap. Commands only see the variables of
+/// their build step, never those of pond.
+#[derive(Clone, Default)]
+pub struct Builder {
+    config: BuildConfig,
+}
+
+impl Builder {
+    pub fn configure(figment: &Figment) -> Result<Self, ConfigurationError> {
+        Ok(Builder {
+            config: figment.extract_inner(BUILD)?,
+        })
+    }
+
+    pub fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(BUILD, BuildConfig::default()))
+    }
+
+    /// Extracts the zip archive at `artifact_location` into a new working directory and runs
+    /// the command of `step` in it.
+    pub(crate) fn build(
+        &self,
+        step: &BuildStep,
+        environment: &BTreeMap<String, String>,
+        artifact_location: &Path,
+        mut deployment_handle: DeploymentHandle,
+    ) -> io::Result<BuildDirectory> {
+        self.check_isolation(effective_uid())?;
+        let directory = self
+            .config
+            .directory
+            .clone()
+            .unwrap_or_else(env::temp_dir)
+            .join(format!(
+                "pond-build-{}",
+                rand::distributions::Alphanumeric.sample_string(&mut thread_rng(), 16)
+            ));
+        let build_directory = BuildDirectory {
+            output: directory.join(&step.output_dir),
+            directory,
+        };
+        fs::create_dir_all(&build_directory.directory)?;
+        zip::ZipArchive::new(File::open(artifact_location)?)?
+            .extract(&build_directory.directory)?;
+        if let Some(uid) = self.config.uid {
+            chown_recursive(&build_directory.directory, uid, self.config.gid)?;
+        }
+
+        writeln!(deployment_handle.info(), "Building with {}", step.command).ok();
+        let timeout = step.timeout_seconds.min(self.config.max_timeout_seconds);
+        let command = self.command(step, environment, &build_directory.directory)?;
+        let exit_status = run_command_with_timeout(
+            command,
+            &self.execution_settings(),
+            deployment_handle,
+            Some(Duration::from_secs(timeout)),
+        )?;
+        if !exit_status.success() {
+            return Err(io::Error::other(format!(
+                "Build command exited with status {}",
+                exit_status
+            )));
+        }
+        check_output(&build_directory.directory, &build_directory.output)?;
+        Ok(build_directory)
+    }
+
+    /// Refuses to run builds with the privileges of root, or without the isolation they are
+    /// configured with.
+    fn check_isolation(&self, effective_uid: u32) -> io::Result<()> {
+        let unprivileged = self.config.uid.is_some_and(|uid| uid != 0)
+            && self.config.gid.is_some_and(|gid| gid != 0);
+        if effective_uid == 0 && !unprivileged {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "Builds don't run as root, configure an unprivileged build.uid and build.gid",
+            ));
+        }
+        if self.config.bubblewrap && find_executable(&self.config.bwrap_command_name).is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!(
+                    "Builds run in bubblewrap, but {} is not installed",
+                    self.config.bwrap_command_name
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    fn execution_settings(&self) -> ExecutionSettings {
+        ExecutionSettings {
+            uid: self.config.uid,
+            gid: self.config.gid,
+            rlimits: ResourceLimits {
+                max_memory_bytes: Some(self.config.max_memory_bytes),
+                max_processes: Some(self.config.max_processes),
+                max_file_size_bytes: Some(self.config.max_file_size_bytes),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn command(
+        &self,
+        step: &BuildStep,
+        environment: &BTreeMap<String, String>,
+        directory: &Path,
+    ) -> io::Result<Command> {
+        let mut args: Vec<OsString> = vec![];
+        if self.config.bubblewrap {
+            let bwrap = find_executable(&self.config.bwrap_command_name).ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("{} is not installed", self.config.bwrap_command_name),
+                )
+            })?;
+            args.push(bwrap.into());
+            args.extend(
+                [
+                    "--ro-bind",
+                    "/",
+                    "/",
+                    "--dev",
+                    "/dev",
+                    "--proc",
+                    "/proc",
+                    "--tmpfs",
+                    "/tmp",
+                    "--unshare-all",
+                    "--share-net",
+                    "--die-with-parent",
+                    "--bind",
+                ]
+                .map(OsString::from),
+            );
+            args.push(directory.into());
+            args.push(directory.into());
+            args.push("--".into());
+        }
+        args.extend(["sh", "-c"].map(OsString::from));
+        args.push((&step.command).into());
+
+        let mut command = Command::new(&args[0]);
+        command
+            .args(&args[1..])
+            .current_dir(directory)
+            .env_clear()
+            .env("PATH", env::var_os("PATH").unwrap_or(DEFAULT_PATH.into()))
+            .env("HOME", directory)
+            .envs(environment);
+        Ok(command)
+    }
+}
+
+#[cfg(unix)]
+fn effective_uid() -> u32 {
+    // SAFETY: geteuid has no preconditions and can't fail.
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(not(unix))]
+fn effective_uid() -> u32 {
+    u32::MAX
+}
+
+fn find_executable(name: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|directory| directory.join(name))
+        .find(|path| path.is_file())
+}
+
+#[cfg(unix)]
+fn chown_recursive(path: &Path, uid: u32, gid: Option<u32>) -> io::Result<()> {
+    std::os::unix::fs::lchown(path, Some(uid), gid)?;
+    if fs::symlink_metadata(path)?.is_dir() {
+        for entry in fs::read_dir(path)? {
+            chown_recursive(&entry?.path(), uid, gid)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn chown_recursive(_path: &Path, _uid: u32, _gid: Option<u32>) -> io::Result<()> {
+    Err(io::Error::other(
+        "Builds can only run as another user on unix",
+    ))
+}
+
+/// Makes sure the output is a directory within the working directory and has no symbolic
+/// links leading out of it, which the build could use to publish files of the server.
+fn check_output(directory: &Path, output: &Path) -> io::Result<()> {
+    if !output.is_dir() {
+        return Err(io::Error::other(format!(
+            "The build did not create the output directory {:?}",
+            output.strip_prefix(directory).unwrap_or(output)
+        )));
+    }
+    let output = output.canonicalize()?;
+    if !output.starts_with(directory.canonicalize()?) {
+        return Err(io::Error::other(
+            "The output directory is outside of the build directory",
+        ));
+    }
+    check_links(&output, &output)
+}
+
+fn check_links(output: &Path, path: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(path)? {
+        let path = entry?.path();
+        let metadata = fs::symlink_metadata(&path)?;
+        if metadata.is_symlink() {
+            let target = path.canonicalize();
+            if !target.is_ok_and(|target| target.starts_with(output)) {
+                return Err(io::Error::other(format!(
+                    "{:?} links outside of the output directory",
+                    path.strip_prefix(output).unwrap_or(&path)
+                )));
+            }
+        } else if metadata.is_dir() {
+            check_links(output, &path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{artifact::test::zip_artifact, deployer::deployment_handle};
+
+    /// Builds as nobody when the tests run as root, and as the current user otherwise.
+    pub(crate) fn builder() -> Builder {
+        let (uid, gid) = match effective_uid() {
+            0 => (65534, 65534),
+            // SAFETY: getegid has no preconditions and can't fail.
+            uid => (uid, unsafe { libc::getegid() }),
+        };
+        Builder {
+            config: BuildConfig {
+                uid: Some(uid),
+                gid: Some(gid),
+                bubblewrap: false,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn step(command: &str) -> BuildStep {
+        BuildStep {
+            command: command.to_owned(),
+            output_dir: "dist".to_owned(),
+            timeout_seconds: 60,
+            env: BTreeMap::from([("GREETING".to_owned(), "Hello".to_owned())]),
+        }
+    }
+
+    fn build(
+        builder: &Builder,
+        name: &str,
+        step: &BuildStep,
+    ) -> (io::Result<BuildDirectory>, String) {
+        let artifact = zip_artifact(name, &[("src/index.md", "# Blog")]);
+        let (handle, mut logs) = deployment_handle();
+        let result = builder.build(step, &step.env, &artifact, handle);
+        let output = io::read_to_string(logs.info()).unwrap();
+        (result, output)
+    }
+
+    #[test]
+    fn test_build() {
+        let (result, output) = build(
+            &builder(),
+            "build",
+            &step("mkdir dist && cp src/index.md dist/index.html && echo $GREETING"),
+        );
+        let build_directory = result.unwrap();
+        assert!(output.contains("Hello"), "{}", output);
+        assert_eq!(
+            fs::read_to_string(build_directory.output.join("index.html")).unwrap(),
+            "# Blog"
+        );
+
+        let directory = build_directory.directory.clone();
+        drop(build_directory);
+        assert!(!directory.exists());
+    }
+
+    #[test]
+    fn test_pond_environment_is_hidden() {
+        std::env::set_var("POND_BUILD_TEST_TOKEN", "secret");
+        let (result, _) = build(
+            &builder(),
+            "build-environment",
+            &step("test -z \"$POND_BUILD_TEST_TOKEN\" && mkdir dist"),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_build_failures() {
+        let (result, _) = build(&builder(), "build-failed", &step("exit 1"));
+        assert!(result.is_err());
+        let (result, _) = build(&builder(), "build-no-output", &step("true"));
+        assert!(result.is_err());
+        let (result, _) = build(&builder(), "build-escaping", &step("ln -s / dist"));
+        assert!(result.is_err());
+        let (result, _) = build(
+            &builder(),
+            "build-linking",
+            &step("mkdir dist && ln -s /etc/passwd dist/passwd"),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_build_limits() {
+        let mut builder = builder();
+        builder.config.max_timeout_seconds = 1;
+        let started = Instant::now();
+        let (result, _) = build(&builder, "build-timeout", &step("sleep 10"));
+        assert_eq!(result.err().unwrap().kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        builder.config.max_file_size_bytes = 1024;
+        let (result, _) = build(
+            &builder,
+            "build-file-size",
+            &step("mkdir dist && head -c 4096 /dev/zero > dist/large"),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_builds_without_isolation_are_refused() {
+        let mut builder = builder();
+        builder.config.uid = None;
+        assert_eq!(
+            builder.check_isolation(0).unwrap_err().kind(),
+            io::ErrorKind::PermissionDenied
+        );
+        builder.config.uid = Some(0);
+        assert!(builder.check_isolation(0).is_err());
+        assert!(builder.check_isolation(1000).is_ok());
+
+        let mut builder = Builder::default();
+        builder.config.uid = Some(990);
+        builder.config.gid = Some(990);
+        builder.config.bwrap_command_name = "pond-missing-bwrap".to_owned();
+        assert_eq!(
+            builder.check_isolation(0).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+        let (result, output) = build(&builder, "build-no-bwrap", &step("mkdir dist"));
+        assert!(result.is_err());
+        assert!(!output.contains("Building"), "{}", output);
+    }
+}
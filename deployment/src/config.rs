@@ -1,18 +1,32 @@
 use crate::{
-    deployer::StaticSiteDeployer,
+    artifact_store::ArtifactStore,
+    build::Builder,
+    deployer::{Redactions, StaticSiteDeployer},
+    deployments::DeploymentStore,
+    domains::{DomainVerifier, HickoryTxtResolver},
+    execution::ExecutionSettings,
+    git::GitSettings,
+    health::HealthChecker,
     ingress::{
-        self,
-        dns::{DnsService, NoOpDnsService},
+        dns::{DnsReconciler, DnsServiceRegistry},
         static_site::NginxStaticSiteIngressService,
     },
+    manager::RegisterDeployment,
+    reaper::DeploymentReaper,
+    releases::ReleaseStore,
+    root_domains::RootDomains,
+    secrets::SecretStore,
+    uploads::UploadStore,
+    verification::SigningKeys,
     DeploymentManager,
 };
-use figment::Figment;
-use std::error;
+use figment::{providers::Serialized, Figment};
+use std::{error, path::PathBuf, sync::Arc};
 
 #[derive(Debug)]
 pub enum ConfigurationError {
     MissingConfigurationValue(String),
+    UnknownDnsProvider(String),
     Other(Box<dyn error::Error + Send + Sync>),
 }
 
@@ -27,69 +41,114 @@ impl From<figment::Error> for ConfigurationError {
     }
 }
 
-const ROOT_DOMAIN_NAME: &str = "root_domain_name";
+const INSTANCE_ID: &str = "instance_id";
+const DEFAULT_INSTANCE_ID: &str = "pond";
+
+const STATE_DIRECTORY: &str = "state_directory";
+const DEFAULT_STATE_DIRECTORY: &str = "./state";
 
 const SCRIPTS_LOCATION: &str = "scripts_location";
 const DEFAULT_SCRIPTS_LOCATION: &str = "./scripts";
 
 fn figment_default_values() -> Figment {
-    ingress::dns::cloudflare::CloudflareDnsService::figment_default_values()
+    DnsServiceRegistry::default()
+        .figment_default_values()
         .join(NginxStaticSiteIngressService::figment_default_values())
+        .join(DnsReconciler::figment_default_values())
+        .join(UploadStore::figment_default_values())
+        .join(ArtifactStore::figment_default_values())
+        .join(ReleaseStore::figment_default_values())
+        .join(Builder::figment_default_values())
+        .join(GitSettings::figment_default_values())
+        .join(HealthChecker::figment_default_values())
+        .join(DeploymentReaper::figment_default_values())
+        .join(Serialized::default(INSTANCE_ID, DEFAULT_INSTANCE_ID))
+        .join(Serialized::default(
+            STATE_DIRECTORY,
+            DEFAULT_STATE_DIRECTORY,
+        ))
 }
 
 pub fn manager(figment: &Figment) -> Result<DeploymentManager, ConfigurationError> {
     let figment = figment.clone().join(figment_default_values());
-    let domain_name: String = figment
-        .extract_inner(ROOT_DOMAIN_NAME)
-        .map_err(|_e| ConfigurationError::MissingConfigurationValue(ROOT_DOMAIN_NAME.into()))?;
-    let mut result = DeploymentManager::new(domain_name);
-    configure_default_deployers(&mut result, &figment)?;
+    let root_domains = Arc::new(RootDomains::configure(&figment)?);
+    let state_directory: PathBuf = figment.extract_inner(STATE_DIRECTORY)?;
+    let deployments = Arc::new(
+        DeploymentStore::open(&state_directory).map_err(|e| ConfigurationError::Other(e.into()))?,
+    );
+    let domains = Arc::new(
+        DomainVerifier::open(
+            &root_domains.names(),
+            &state_directory,
+            Box::new(HickoryTxtResolver),
+        )
+        .map_err(|e| ConfigurationError::Other(e.into()))?,
+    );
+    let secrets = Arc::new(SecretStore::configure(&figment, &state_directory)?);
+    let uploads = Arc::new(UploadStore::configure(&figment, &state_directory)?);
+    let artifacts = Arc::new(ArtifactStore::configure(&figment, &state_directory)?);
+    let releases = Arc::new(ReleaseStore::configure(&figment, &state_directory)?);
+    let instance_id: String = figment.extract_inner(INSTANCE_ID)?;
+    let ingress_service = NginxStaticSiteIngressService::configure(
+        &figment,
+        instance_id.clone(),
+        root_domains.clone(),
+        deployments.clone(),
+    )?;
+
+    let mut result = DeploymentManager::new(
+        root_domains.clone(),
+        deployments.clone(),
+        domains,
+        secrets,
+        uploads,
+        artifacts,
+        releases,
+    );
+    result.set_redactions(Redactions::configure(&figment)?);
+    result.set_signing_keys(SigningKeys::configure(&figment)?);
+    result.set_builder(Builder::configure(&figment)?);
+    result.set_git_settings(GitSettings::configure(&figment)?);
+    result.set_health_checker(HealthChecker::configure(&figment)?);
+    result.set_reaper(DeploymentReaper::configure(&figment, deployments.clone())?);
+    if root_domains.iter().any(|r| r.manages_records) {
+        let dns_reconciler =
+            DnsReconciler::configure(&figment, deployments, instance_id, root_domains)?;
+        result.set_dns_reconciler(dns_reconciler);
+    }
+    configure_default_deployers(&mut result, &figment, ingress_service)?;
     Ok(result)
 }
 
 fn configure_default_deployers(
     manager: &mut DeploymentManager,
     figment: &Figment,
+    ingress_service: NginxStaticSiteIngressService,
 ) -> Result<(), ConfigurationError> {
-    let ingress_service = ingress_manager(figment)?;
     let scripts_path: String = figment
         .extract_inner(SCRIPTS_LOCATION)
         .unwrap_or(DEFAULT_SCRIPTS_LOCATION.to_owned());
-    let static_site_deployer = StaticSiteDeployer::new(scripts_path, Box::new(ingress_service));
+    let mut static_site_deployer = StaticSiteDeployer::new(scripts_path, Box::new(ingress_service));
+    static_site_deployer.set_execution_settings(ExecutionSettings::configure(
+        figment,
+        StaticSiteDeployer::deployment_type(),
+    )?);
     manager.register_deployer(static_site_deployer);
     Ok(())
 }
 
-fn configure_dns_service(
-    figment: &Figment,
-) -> Result<Box<dyn DnsService + Send + Sync + 'static>, ConfigurationError> {
-    let result: Box<dyn DnsService + Send + Sync> =
-        match ingress::dns::cloudflare::CloudflareDnsService::configure(figment)? {
-            Some(service) => Box::new(service),
-            None => Box::new(NoOpDnsService),
-        };
-    Ok(result)
-}
-
-fn ingress_manager(figment: &Figment) -> Result<NginxStaticSiteIngressService, ConfigurationError> {
-    let dns_service = configure_dns_service(figment)?;
-
-    NginxStaticSiteIngressService::configure(figment, dns_service)
-}
-
 #[cfg(test)]
 mod test {
-    use figment::providers::Serialized;
-
     use super::*;
 
     #[test]
     fn test_load_default_values_work() {
         let required_values = Serialized::globals(serde_json::json!({
-            ROOT_DOMAIN_NAME: "example.com",
+            "root_domain_name": "example.com",
         }));
         let figment = figment_default_values().merge(required_values);
         let manager = manager(&figment);
         assert!(manager.is_ok());
+        assert!(manager.unwrap().dns_reconciler().is_none());
     }
 }
@@ -10,6 +10,8 @@ use std::{
 #[cfg(test)]
 use std::time::Duration;
 
+use super::Redactions;
+
 #[derive(Clone)]
 struct MutexVecDequeWrite {
     inner: Arc<Mutex<VecDeque<u8>>>,
@@ -60,6 +62,97 @@ impl<A: Write, B: Write> Write for TeeWrite<A, B> {
     }
 }
 
+/// Output is emitted without waiting for the end of the line unless it is longer than this.
+const MAX_PENDING_LENGTH: usize = 8192;
+
+/// Masks the secrets of a deployment before they reach the log stream. Output that may be
+/// the beginning of a secret is held back until the following writes complete or rule it out.
+struct RedactWrite<W: Write> {
+    inner: W,
+    pending: Vec<u8>,
+    redactions: Arc<Mutex<Redactions>>,
+}
+
+impl<W: Write> RedactWrite<W> {
+    fn new(inner: W, redactions: Arc<Mutex<Redactions>>) -> Self {
+        RedactWrite {
+            inner,
+            pending: vec![],
+            redactions,
+        }
+    }
+}
+
+impl<W: Write> Write for RedactWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let redactions = self
+            .redactions
+            .lock()
+            .map_err(|_e| std::io::Error::other("Failed to lock mutex"))?;
+        self.pending.extend_from_slice(buf);
+
+        let complete = if redactions.has_patterns() {
+            match self.pending.iter().rposition(|&b| b == b'\n' || b == b'\r') {
+                Some(position) => position + 1,
+                None if self.pending.len() > MAX_PENDING_LENGTH => self.pending.len(),
+                None => 0,
+            }
+        } else {
+            self.pending.len()
+        };
+        let rest = self.pending.split_off(complete);
+        let mut redacted = redactions.redact(&self.pending);
+        let held_back =
+            redacted.split_off(redacted.len() - redactions.incomplete_suffix(&redacted));
+        self.pending = [held_back, rest].concat();
+
+        if !redacted.is_empty() {
+            self.inner.write_all(&redacted)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for RedactWrite<W> {
+    fn drop(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        if let Ok(redactions) = self.redactions.lock() {
+            self.inner.write_all(&redactions.redact(&self.pending)).ok();
+        }
+    }
+}
+
+/// Shares one writer between all clones of a handle, so held back output is emitted in order.
+struct SharedWrite<W: Write>(Arc<Mutex<W>>);
+
+impl<W: Write> Clone for SharedWrite<W> {
+    fn clone(&self) -> Self {
+        SharedWrite(self.0.clone())
+    }
+}
+
+impl<W: Write> Write for SharedWrite<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .map_err(|_e| std::io::Error::other("Failed to lock mutex"))?
+            .write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0
+            .lock()
+            .map_err(|_e| std::io::Error::other("Failed to lock mutex"))?
+            .flush()
+    }
+}
+
 pub struct MutexVecDequeRead {
     inner: Arc<Mutex<VecDeque<u8>>>,
     notify: Receiver<()>,
@@ -117,26 +210,21 @@ impl Read for MutexVecDequeRead {
     }
 }
 
+#[derive(Clone)]
 pub struct DeploymentHandle {
-    inner_info: TeeWrite<MutexVecDequeWrite, Stdout>,
-    inner_error: TeeWrite<MutexVecDequeWrite, Stderr>,
+    inner_info: SharedWrite<RedactWrite<TeeWrite<MutexVecDequeWrite, Stdout>>>,
+    inner_error: SharedWrite<RedactWrite<TeeWrite<MutexVecDequeWrite, Stderr>>>,
+    redactions: Arc<Mutex<Redactions>>,
 }
-impl Clone for DeploymentHandle {
-    fn clone(&self) -> Self {
-        DeploymentHandle {
-            inner_info: TeeWrite {
-                a: self.inner_info.a.clone(),
-                b: stdout(),
-            },
-            inner_error: TeeWrite {
-                a: self.inner_error.a.clone(),
-                b: stderr(),
-            },
+
+impl DeploymentHandle {
+    /// Masks `secret` in everything written to this handle and its clones from now on.
+    pub fn redact(&self, secret: &str) {
+        if let Ok(mut redactions) = self.redactions.lock() {
+            redactions.add_secret(secret);
         }
     }
-}
 
-impl DeploymentHandle {
     pub fn info(&mut self) -> &mut dyn Write {
         &mut self.inner_info
     }
@@ -181,19 +269,32 @@ fn vec_deque_channel() -> (MutexVecDequeWrite, MutexVecDequeRead) {
 }
 
 pub fn deployment_handle() -> (DeploymentHandle, DeploymentLogs) {
+    redacted_deployment_handle(Redactions::default())
+}
+
+/// A handle that masks `redactions` in everything written to it.
+pub fn redacted_deployment_handle(redactions: Redactions) -> (DeploymentHandle, DeploymentLogs) {
     let info = vec_deque_channel();
     let err = vec_deque_channel();
+    let redactions = Arc::new(Mutex::new(redactions));
 
     (
         DeploymentHandle {
-            inner_info: TeeWrite {
-                a: info.0,
-                b: stdout(),
-            },
-            inner_error: TeeWrite {
-                a: err.0,
-                b: stderr(),
-            },
+            inner_info: SharedWrite(Arc::new(Mutex::new(RedactWrite::new(
+                TeeWrite {
+                    a: info.0,
+                    b: stdout(),
+                },
+                redactions.clone(),
+            )))),
+            inner_error: SharedWrite(Arc::new(Mutex::new(RedactWrite::new(
+                TeeWrite {
+                    a: err.0,
+                    b: stderr(),
+                },
+                redactions.clone(),
+            )))),
+            redactions,
         },
         DeploymentLogs {
             inner_info: info.1,
@@ -272,4 +373,56 @@ mod test {
 
         assert_eq!(result, "Hello!");
     }
+
+    #[test]
+    fn test_secrets_are_redacted() {
+        let (mut stream, mut consumer) = deployment_handle();
+        let mut clone = stream.clone();
+        stream.redact("hunter2");
+        write!(clone.info(), "Logging in with hunter2").unwrap();
+        write!(stream.error(), "hunter2 was rejected").unwrap();
+        drop(stream);
+        drop(clone);
+
+        let mut info = String::new();
+        consumer.info().read_to_string(&mut info).unwrap();
+        let mut error = String::new();
+        consumer.error().read_to_string(&mut error).unwrap();
+        assert_eq!(info, "Logging in with ********");
+        assert_eq!(error, "******** was rejected");
+    }
+
+    #[test]
+    fn test_secrets_split_across_writes_are_redacted() {
+        let mut redactions = Redactions::default();
+        redactions.add_secret("hunter2");
+        redactions.add_pattern(regex::bytes::Regex::new("password=\\S+").unwrap());
+        let (mut stream, mut consumer) = redacted_deployment_handle(redactions);
+        let mut clone = stream.clone();
+        write!(stream.info(), "Logging in with hun").unwrap();
+        write!(clone.info(), "ter2 and password=").unwrap();
+        write!(stream.info(), "abc\nhunt").unwrap();
+        write!(clone.info(), "ing done, hu").unwrap();
+        drop(stream);
+        drop(clone);
+
+        let mut info = String::new();
+        consumer.info().read_to_string(&mut info).unwrap();
+        assert_eq!(
+            info,
+            "Logging in with ******** and ********\nhunting done, hu"
+        );
+    }
+
+    #[test]
+    fn test_output_without_secrets_is_not_held_back() {
+        let mut redactions = Redactions::default();
+        redactions.add_secret("hunter2");
+        let (mut stream, mut consumer) = redacted_deployment_handle(redactions);
+        write!(stream.info(), "Logging in with hun").unwrap();
+
+        let mut buffer = [0; 64];
+        let read = consumer.info().read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b"Logging in with ");
+    }
 }
@@ -1,21 +1,57 @@
-use std::{io, path::Path};
+use std::{collections::BTreeMap, io, path::Path};
 
 pub(crate) mod handle;
+mod redaction;
 pub use handle::deployment_handle;
 pub use handle::MutexVecDequeRead as LogStream;
 pub use handle::{DeploymentHandle, DeploymentLogs};
+pub use redaction::Redactions;
 
-use crate::Manifest;
+use crate::{maintenance::Maintenance, Manifest};
 
 mod static_site;
 
 pub(crate) use static_site::StaticSiteDeployer;
 
 pub trait Deployer {
+    /// Deploys the artifact at `artifact_location`. `environment` holds the variables of the
+    /// manifest with secrets resolved.
     fn deploy(
         &self,
         manifest: Manifest,
+        environment: &BTreeMap<String, String>,
         artifact_location: &Path,
         deployment_handle: DeploymentHandle,
     ) -> io::Result<()>;
+
+    /// Deploys the files currently served for `source` byte for byte as `manifest`.
+    fn promote(
+        &self,
+        source: &Manifest,
+        manifest: Manifest,
+        environment: &BTreeMap<String, String>,
+        deployment_handle: DeploymentHandle,
+    ) -> io::Result<()>;
+
+    /// Serves the deployment `failed` replaced again, as `previous`, after `failed` turned out
+    /// unhealthy. Returns `false` if the files of `previous` are gone and need to be deployed
+    /// again instead.
+    fn roll_back(
+        &self,
+        failed: &Manifest,
+        previous: &Manifest,
+        deployment_handle: DeploymentHandle,
+    ) -> io::Result<bool>;
+
+    /// Serves a maintenance page instead of the deployment of `manifest`, or the deployment
+    /// again for `None`, without touching the deployed files.
+    fn set_maintenance(
+        &self,
+        manifest: &Manifest,
+        maintenance: Option<&Maintenance>,
+        deployment_handle: DeploymentHandle,
+    ) -> io::Result<()>;
+
+    /// Removes everything the deployments of `manifest` created, including any canary.
+    fn remove(&self, manifest: &Manifest, deployment_handle: DeploymentHandle) -> io::Result<()>;
 }
@@ -1,12 +1,13 @@
 use std::{
+    collections::BTreeMap,
     io,
     path::{Path, PathBuf},
     process::Command,
 };
 
 use crate::{
-    helpers::run_command, ingress::static_site::StaticSiteIngressService,
-    manager::RegisterDeployment,
+    execution::ExecutionSettings, helpers::run_command,
+    ingress::static_site::StaticSiteIngressService, manager::RegisterDeployment,
 };
 
 use super::{Deployer, DeploymentHandle};
@@ -14,10 +15,15 @@ use super::{Deployer, DeploymentHandle};
 pub struct StaticSiteDeployer {
     scripts_path: PathBuf,
     ingress_service: Box<dyn StaticSiteIngressService + 'static + Send + Sync>,
+    execution: ExecutionSettings,
 }
 
 const ARTIFACT_LOCATION: &str = "ARTIFACT_LOCATION";
 const DEPLOYMENT_NAME: &str = "DEPLOYMENT_NAME";
+const CANARY_WEIGHT: &str = "CANARY_WEIGHT";
+/// Where `static_site.sh` puts the files of each site, keeping those of the deployment before
+/// in `<name>.previous`.
+const SITES_DIRECTORY: &str = "/var/www";
 
 impl StaticSiteDeployer {
     pub fn new(
@@ -27,43 +33,49 @@ impl StaticSiteDeployer {
         StaticSiteDeployer {
             scripts_path: scripts_path.as_ref().to_owned(),
             ingress_service,
+            execution: ExecutionSettings::default(),
         }
     }
+
+    /// Sets the user, environment and limits the deployment script runs with.
+    pub fn set_execution_settings(&mut self, execution: ExecutionSettings) {
+        self.execution = execution;
+    }
 }
 
 impl Deployer for StaticSiteDeployer {
     fn deploy(
         &self,
         manifest: crate::Manifest,
+        environment: &BTreeMap<String, String>,
         artifact_location: &Path,
         deployment_handle: DeploymentHandle,
     ) -> io::Result<()> {
-        let script_location = self.scripts_path.join("static_site.sh");
+        // The script may run in another working directory
+        let script_location = std::path::absolute(self.scripts_path.join("static_site.sh"))?;
         info!("Launching command {:?}", script_location);
         let mut script_command = Command::new(script_location);
         script_command
+            .envs(environment)
             .env(DEPLOYMENT_NAME, &manifest.name)
-            .env(ARTIFACT_LOCATION, artifact_location);
+            .env(ARTIFACT_LOCATION, std::path::absolute(artifact_location)?);
+        if let Some(canary) = &manifest.canary {
+            script_command.env(CANARY_WEIGHT, canary.weight.to_string());
+        }
 
-        let exit_status = run_command(script_command, deployment_handle.clone())
-            .inspect_err(|e| error!("Failed to run static site deployment script. Error: {}", e))?;
+        let exit_status = run_command(script_command, &self.execution, deployment_handle.clone())
+            .inspect_err(|e| {
+            error!("Failed to run static site deployment script. Error: {}", e)
+        })?;
 
         if !exit_status.success() {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                format!(
-                    "Failed to deploy static site. Command exited with status {}",
-                    exit_status
-                ),
-            ));
+            return Err(io::Error::other(format!(
+                "Failed to deploy static site. Command exited with status {}",
+                exit_status
+            )));
         }
         self.ingress_service
-            .add_static_site_ingress(
-                &manifest.name,
-                artifact_location,
-                &manifest.domain_names,
-                deployment_handle,
-            )
+            .add_static_site_ingress(&manifest, artifact_location, deployment_handle)
             .inspect_err(|e| {
                 error!(
                     "Failed to add ingress for deployment {}. Error: {}",
@@ -72,6 +84,101 @@ impl Deployer for StaticSiteDeployer {
             })?;
         Ok(())
     }
+
+    fn promote(
+        &self,
+        source: &crate::Manifest,
+        manifest: crate::Manifest,
+        environment: &BTreeMap<String, String>,
+        deployment_handle: DeploymentHandle,
+    ) -> io::Result<()> {
+        let source_directory = Path::new(SITES_DIRECTORY).join(&source.name);
+        if !source_directory.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no deployed files", source.name),
+            ));
+        }
+        // Directories are copied as they are
+        self.deploy(manifest, environment, &source_directory, deployment_handle)
+    }
+
+    fn roll_back(
+        &self,
+        failed: &crate::Manifest,
+        previous: &crate::Manifest,
+        deployment_handle: DeploymentHandle,
+    ) -> io::Result<bool> {
+        let site_directory = Path::new(SITES_DIRECTORY).join(&previous.name);
+        let previous_directory =
+            Path::new(SITES_DIRECTORY).join(format!("{}.previous", previous.name));
+        // Failed canaries are simply removed
+        if failed.canary.is_none() && !previous_directory.is_dir() {
+            return Ok(false);
+        }
+
+        let script_location =
+            std::path::absolute(self.scripts_path.join("roll_back_static_site.sh"))?;
+        let mut script_command = Command::new(script_location);
+        script_command.env(DEPLOYMENT_NAME, &previous.name);
+        if let Some(canary) = &failed.canary {
+            script_command.env(CANARY_WEIGHT, canary.weight.to_string());
+        }
+        let exit_status = run_command(script_command, &self.execution, deployment_handle.clone())?;
+        if !exit_status.success() {
+            return Err(io::Error::other(format!(
+                "Failed to roll back static site. Command exited with status {}",
+                exit_status
+            )));
+        }
+
+        // The failed deployment may have changed the domains or the canary of the site
+        if failed.served_domain_names() != previous.served_domain_names()
+            || failed.canary != previous.canary
+        {
+            self.ingress_service.add_static_site_ingress(
+                previous,
+                &site_directory,
+                deployment_handle,
+            )?;
+        }
+        Ok(true)
+    }
+
+    fn set_maintenance(
+        &self,
+        manifest: &crate::Manifest,
+        maintenance: Option<&crate::maintenance::Maintenance>,
+        deployment_handle: DeploymentHandle,
+    ) -> io::Result<()> {
+        self.ingress_service.set_static_site_maintenance(
+            manifest,
+            maintenance.cloned(),
+            deployment_handle,
+        )
+    }
+
+    fn remove(
+        &self,
+        manifest: &crate::Manifest,
+        deployment_handle: DeploymentHandle,
+    ) -> io::Result<()> {
+        // Stop serving the site before its files disappear
+        self.ingress_service
+            .remove_static_site_ingress(manifest, deployment_handle.clone())?;
+
+        let script_location = std::path::absolute(self.scripts_path.join("remove_static_site.sh"))?;
+        let mut script_command = Command::new(script_location);
+        script_command.env(DEPLOYMENT_NAME, &manifest.name);
+        let exit_status = run_command(script_command, &self.execution, deployment_handle)?;
+        if !exit_status.success() {
+            return Err(io::Error::other(format!(
+                "Failed to remove static site. Command exited with status {}",
+                exit_status
+            )));
+        }
+        Ok(())
+    }
 }
 
 impl RegisterDeployment for StaticSiteDeployer {
@@ -0,0 +1,365 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{state::JsonFile, Manifest};
+
+const DEPLOYMENTS_FILE_NAME: &str = "deployments.json";
+
+/// The latest successful deployment of a name.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Deployment {
+    pub manifest: Manifest,
+    /// The SHA-256 digest of the deployed artifact, hex encoded. Missing for deployments made
+    /// before digests were recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_sha256: Option<String>,
+    /// The commit the artifact was archived from, for manifests with a git source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_sha: Option<String>,
+    /// The candidate served to a share of the visitors until it is promoted or aborted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<Box<Deployment>>,
+    /// The deployment whose served files were promoted to this one. The digest and commit are
+    /// those of that deployment's artifact, which redeployments build with this manifest.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub promoted_from: Option<String>,
+    /// When the deployment is removed, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+/// Remembers the manifests of successful deployments so background jobs know which sites
+/// pond serves, even after a restart.
+pub struct DeploymentStore {
+    deployments: JsonFile<BTreeMap<String, Deployment>>,
+}
+
+impl DeploymentStore {
+    /// Opens the store kept in `state_directory`.
+    pub fn open(state_directory: impl AsRef<Path>) -> anyhow::Result<Self> {
+        Ok(DeploymentStore {
+            deployments: JsonFile::open(state_directory.as_ref(), DEPLOYMENTS_FILE_NAME)?,
+        })
+    }
+
+    /// A store that isn't persisted.
+    pub fn in_memory() -> Self {
+        DeploymentStore {
+            deployments: JsonFile::in_memory(),
+        }
+    }
+
+    pub fn record(
+        &self,
+        manifest: &Manifest,
+        artifact_sha256: &str,
+        commit_sha: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.insert(Deployment {
+            manifest: manifest.clone(),
+            artifact_sha256: Some(artifact_sha256.to_owned()),
+            commit_sha: commit_sha.map(str::to_owned),
+            canary: None,
+            promoted_from: None,
+            expires_at: expires_at(manifest),
+        })
+    }
+
+    /// Records that the files of `source` are now served under `manifest`, keeping the
+    /// digest and commit of the artifact they were deployed from. The promoted files aren't
+    /// stored themselves, redeployments start from that artifact again.
+    pub fn record_promotion(&self, manifest: &Manifest, source: &Deployment) -> anyhow::Result<()> {
+        self.insert(Deployment {
+            manifest: manifest.clone(),
+            artifact_sha256: source.artifact_sha256.clone(),
+            commit_sha: source.commit_sha.clone(),
+            canary: None,
+            promoted_from: Some(source.manifest.name.clone()),
+            expires_at: expires_at(manifest),
+        })
+    }
+
+    fn insert(&self, deployment: Deployment) -> anyhow::Result<()> {
+        self.deployments.update(|deployments| {
+            deployments.insert(deployment.manifest.name.clone(), deployment);
+        })
+    }
+
+    /// Records the canary of an existing deployment, replacing any previous canary. Canaries
+    /// expire along with their deployment.
+    pub fn record_canary(
+        &self,
+        manifest: &Manifest,
+        artifact_sha256: &str,
+        commit_sha: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let canary = Deployment {
+            manifest: manifest.clone(),
+            artifact_sha256: Some(artifact_sha256.to_owned()),
+            commit_sha: commit_sha.map(str::to_owned),
+            canary: None,
+            promoted_from: None,
+            expires_at: None,
+        };
+        self.deployments.update(|deployments| {
+            let deployment = deployments
+                .get_mut(&manifest.name)
+                .ok_or_else(|| anyhow::anyhow!("{} has not been deployed", manifest.name))?;
+            deployment.canary = Some(Box::new(canary));
+            Ok(())
+        })?
+    }
+
+    pub fn get(&self, name: &str) -> anyhow::Result<Option<Deployment>> {
+        self.deployments
+            .read(|deployments| deployments.get(name).cloned())
+    }
+
+    /// Forgets the deployment of `name`, returning it.
+    pub fn remove(&self, name: &str) -> anyhow::Result<Option<Deployment>> {
+        self.deployments
+            .update(|deployments| deployments.remove(name))
+    }
+
+    /// The deployments that expire, soonest first.
+    pub fn expiring(&self) -> anyhow::Result<Vec<Deployment>> {
+        self.deployments.read(|deployments| {
+            let mut expiring: Vec<Deployment> = deployments
+                .values()
+                .filter(|deployment| deployment.expires_at.is_some())
+                .cloned()
+                .collect();
+            expiring.sort_by_key(|deployment| deployment.expires_at);
+            expiring
+        })
+    }
+
+    pub fn list(&self) -> anyhow::Result<Vec<Manifest>> {
+        self.deployments.read(|deployments| {
+            deployments
+                .values()
+                .map(|deployment| deployment.manifest.clone())
+                .collect()
+        })
+    }
+}
+
+fn expires_at(manifest: &Manifest) -> Option<u64> {
+    manifest
+        .expires_at
+        .or_else(|| manifest.ttl_seconds.map(|ttl| now().saturating_add(ttl)))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::*;
+
+    fn manifest(name: &str) -> Manifest {
+        crate::manifest::test::manifest(name, &[&format!("{}.example.com", name)])
+    }
+
+    #[test]
+    fn test_record_replaces_previous_deployment() {
+        let store = DeploymentStore::in_memory();
+        store.record(&manifest("blog"), "digest", None).unwrap();
+        let mut updated = manifest("blog");
+        updated.domain_names.push("example.com".to_owned());
+        store
+            .record(&updated, "updated digest", Some("commit"))
+            .unwrap();
+        store.record(&manifest("shop"), "digest", None).unwrap();
+
+        assert_eq!(store.list().unwrap(), vec![updated, manifest("shop")]);
+        assert_eq!(
+            store
+                .get("blog")
+                .unwrap()
+                .unwrap()
+                .artifact_sha256
+                .as_deref(),
+            Some("updated digest")
+        );
+        assert_eq!(
+            store.get("blog").unwrap().unwrap().commit_sha.as_deref(),
+            Some("commit")
+        );
+        assert_eq!(store.get("shop").unwrap().unwrap().commit_sha, None);
+    }
+
+    #[test]
+    fn test_deployments_survive_reopening() {
+        let state_directory = std::env::temp_dir().join("pond-deployment-store-test");
+        fs::remove_dir_all(&state_directory).ok();
+
+        let store = DeploymentStore::open(&state_directory).unwrap();
+        assert!(store.list().unwrap().is_empty());
+        store.record(&manifest("blog"), "digest", None).unwrap();
+
+        let reopened = DeploymentStore::open(&state_directory).unwrap();
+        assert_eq!(reopened.list().unwrap(), vec![manifest("blog")]);
+        fs::remove_dir_all(&state_directory).ok();
+    }
+
+    #[test]
+    fn test_deployment_details_survive_reopening() {
+        let state_directory = std::env::temp_dir().join("pond-deployment-store-details-test");
+        fs::remove_dir_all(&state_directory).ok();
+        let mut blog = manifest("blog");
+        blog.env.insert("GREETING".to_owned(), "hello".to_owned());
+        blog.cname_domains.push("www.example.org".to_owned());
+
+        let store = DeploymentStore::open(&state_directory).unwrap();
+        store.record(&blog, "digest", Some("commit")).unwrap();
+        let source = store.get("blog").unwrap().unwrap();
+        store.record_promotion(&manifest("app"), &source).unwrap();
+
+        let reopened = DeploymentStore::open(&state_directory).unwrap();
+        assert_eq!(reopened.get("blog").unwrap(), Some(source));
+        assert_eq!(
+            reopened
+                .get("app")
+                .unwrap()
+                .unwrap()
+                .promoted_from
+                .as_deref(),
+            Some("blog")
+        );
+        fs::remove_dir_all(&state_directory).ok();
+    }
+
+    #[test]
+    fn test_deployments_without_digest() {
+        let state_directory = std::env::temp_dir().join("pond-deployment-store-legacy-test");
+        fs::remove_dir_all(&state_directory).ok();
+        fs::create_dir_all(&state_directory).unwrap();
+        let legacy =
+            BTreeMap::from([("blog", serde_json::json!({ "manifest": manifest("blog") }))]);
+        fs::write(
+            state_directory.join(DEPLOYMENTS_FILE_NAME),
+            serde_json::to_string(&legacy).unwrap(),
+        )
+        .unwrap();
+
+        let store = DeploymentStore::open(&state_directory).unwrap();
+        assert_eq!(store.list().unwrap(), vec![manifest("blog")]);
+        assert_eq!(store.get("blog").unwrap().unwrap().artifact_sha256, None);
+        fs::remove_dir_all(&state_directory).ok();
+    }
+
+    #[test]
+    fn test_canary_is_kept_until_next_deployment() {
+        let store = DeploymentStore::in_memory();
+        let mut canary = manifest("blog");
+        canary.canary = Some(crate::manifest::Canary { weight: 10 });
+        assert!(store.record_canary(&canary, "canary digest", None).is_err());
+
+        store.record(&manifest("blog"), "digest", None).unwrap();
+        store.record_canary(&canary, "canary digest", None).unwrap();
+        let deployment = store.get("blog").unwrap().unwrap();
+        assert_eq!(deployment.artifact_sha256.as_deref(), Some("digest"));
+        let recorded = deployment.canary.unwrap();
+        assert_eq!(recorded.manifest, canary);
+        assert_eq!(recorded.artifact_sha256.as_deref(), Some("canary digest"));
+        assert_eq!(store.list().unwrap(), vec![manifest("blog")]);
+
+        store.record(&manifest("blog"), "digest", None).unwrap();
+        assert_eq!(store.get("blog").unwrap().unwrap().canary, None);
+    }
+
+    #[test]
+    fn test_canaries_survive_reopening() {
+        let state_directory = std::env::temp_dir().join("pond-deployment-store-canary-test");
+        fs::remove_dir_all(&state_directory).ok();
+        let mut canary = manifest("blog");
+        canary.canary = Some(crate::manifest::Canary { weight: 10 });
+
+        let store = DeploymentStore::open(&state_directory).unwrap();
+        store.record(&manifest("blog"), "digest", None).unwrap();
+        store.record_canary(&canary, "canary digest", None).unwrap();
+
+        let reopened = DeploymentStore::open(&state_directory).unwrap();
+        let deployment = reopened.get("blog").unwrap().unwrap();
+        assert_eq!(deployment.manifest, manifest("blog"));
+        let recorded = deployment.canary.unwrap();
+        assert_eq!(recorded.manifest, canary);
+        assert_eq!(recorded.artifact_sha256.as_deref(), Some("canary digest"));
+        fs::remove_dir_all(&state_directory).ok();
+    }
+
+    #[test]
+    fn test_promotion_keeps_provenance() {
+        let store = DeploymentStore::in_memory();
+        store
+            .record(&manifest("app-staging"), "digest", Some("commit"))
+            .unwrap();
+        let source = store.get("app-staging").unwrap().unwrap();
+        store.record_promotion(&manifest("app"), &source).unwrap();
+
+        let promoted = store.get("app").unwrap().unwrap();
+        assert_eq!(promoted.manifest, manifest("app"));
+        assert_eq!(promoted.artifact_sha256.as_deref(), Some("digest"));
+        assert_eq!(promoted.commit_sha.as_deref(), Some("commit"));
+        assert_eq!(promoted.promoted_from.as_deref(), Some("app-staging"));
+
+        store
+            .record(&manifest("app"), "other digest", None)
+            .unwrap();
+        assert_eq!(store.get("app").unwrap().unwrap().promoted_from, None);
+    }
+
+    #[test]
+    fn test_expiry_survives_reopening() {
+        let state_directory = std::env::temp_dir().join("pond-deployment-store-expiry-test");
+        fs::remove_dir_all(&state_directory).ok();
+        let mut release = manifest("release");
+        release.expires_at = Some(1);
+        let mut preview = manifest("preview");
+        preview.ttl_seconds = Some(60);
+
+        let store = DeploymentStore::open(&state_directory).unwrap();
+        store.record(&release, "digest", None).unwrap();
+        store.record(&preview, "digest", None).unwrap();
+        let expiring = store.expiring().unwrap();
+
+        let reopened = DeploymentStore::open(&state_directory).unwrap();
+        assert_eq!(reopened.expiring().unwrap(), expiring);
+        assert_eq!(reopened.get("release").unwrap().unwrap().manifest, release);
+        fs::remove_dir_all(&state_directory).ok();
+    }
+
+    #[test]
+    fn test_expiring_deployments() {
+        let store = DeploymentStore::in_memory();
+        let mut preview = manifest("preview");
+        preview.ttl_seconds = Some(60);
+        let mut release = manifest("release");
+        release.expires_at = Some(1);
+        store.record(&preview, "digest", None).unwrap();
+        store.record(&release, "digest", None).unwrap();
+        store.record(&manifest("blog"), "digest", None).unwrap();
+
+        let expiring = store.expiring().unwrap();
+        let names: Vec<&str> = expiring.iter().map(|d| d.manifest.name.as_str()).collect();
+        assert_eq!(names, vec!["release", "preview"]);
+        assert_eq!(expiring[0].expires_at, Some(1));
+        assert!(expiring[1].expires_at.unwrap() >= now() + 59);
+
+        assert_eq!(store.remove("release").unwrap().unwrap().manifest, release);
+        assert_eq!(store.remove("release").unwrap(), None);
+        assert_eq!(store.expiring().unwrap().len(), 1);
+    }
+}
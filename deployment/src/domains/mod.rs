@@ -0,0 +1,470 @@
+use std::{collections::BTreeMap, error, fmt, path::Path};
+
+use rand::{distributions::DistString, thread_rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{helpers::is_valid_domain_name, state::JsonFile};
+
+mod resolver;
+
+pub use resolver::HickoryTxtResolver;
+#[cfg(test)]
+pub use resolver::MockTxtResolver;
+pub use resolver::TxtResolver;
+
+const DOMAINS_FILE_NAME: &str = "domains.json";
+const CHALLENGE_LABEL: &str = "_pond-challenge";
+const CHALLENGE_PREFIX: &str = "pond-verification=";
+const CHALLENGE_TOKEN_LENGTH: usize = 32;
+
+/// The access token a request is made with.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TokenScope {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The root domain the token is restricted to, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root_domain_name: Option<String>,
+}
+
+impl TokenScope {
+    /// Tokens restricted to a root domain only see the claims they made themselves.
+    fn may_use(&self, claim: &DomainClaim) -> bool {
+        self.root_domain_name.is_none() || claim.claimed_by == *self
+    }
+}
+
+/// A custom domain somebody asked to deploy to, along with the TXT record that proves they
+/// control it.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct DomainClaim {
+    pub domain_name: String,
+    pub challenge_record_name: String,
+    pub challenge_value: String,
+    pub verified: bool,
+    /// The token that made the claim. Claims made before tokens were recorded belong to the
+    /// unrestricted tokens.
+    #[serde(default)]
+    pub claimed_by: TokenScope,
+}
+
+#[derive(Debug)]
+pub enum DomainVerificationError {
+    InvalidDomainName(String),
+    NotClaimed(String),
+    AlreadyClaimed(String),
+    ChallengeNotFound(Box<DomainClaim>),
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for DomainVerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DomainVerificationError::InvalidDomainName(reason) => write!(f, "{}", reason),
+            DomainVerificationError::NotClaimed(domain_name) => {
+                write!(f, "Domain {} has not been claimed", domain_name)
+            }
+            DomainVerificationError::AlreadyClaimed(domain_name) => write!(
+                f,
+                "Domain {} has been claimed with another access token",
+                domain_name
+            ),
+            DomainVerificationError::ChallengeNotFound(claim) => write!(
+                f,
+                "TXT record {} with value {:?} was not found",
+                claim.challenge_record_name, claim.challenge_value
+            ),
+            DomainVerificationError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for DomainVerificationError {}
+
+impl From<anyhow::Error> for DomainVerificationError {
+    fn from(error: anyhow::Error) -> Self {
+        DomainVerificationError::Other(error)
+    }
+}
+
+/// Keeps track of the custom domains deployments may use.
+///
+/// Domains below the root domains are allowed to the tokens that may deploy below them. Any
+/// other domain has to be claimed and verified by publishing a TXT challenge first. A verified
+/// domain covers its subdomains and may be used by the token that claimed it, as well as by
+/// unrestricted tokens.
+pub struct DomainVerifier {
+    root_domain_names: Vec<String>,
+    claims: JsonFile<BTreeMap<String, DomainClaim>>,
+    resolver: Box<dyn TxtResolver + Send + Sync>,
+}
+
+impl DomainVerifier {
+    /// Opens the verified domains kept in `state_directory`.
+    pub fn open(
+        root_domain_names: &[String],
+        state_directory: impl AsRef<Path>,
+        resolver: Box<dyn TxtResolver + Send + Sync>,
+    ) -> anyhow::Result<Self> {
+        Ok(DomainVerifier {
+            root_domain_names: root_domain_names.iter().map(|d| normalize(d)).collect(),
+            claims: JsonFile::open(state_directory.as_ref(), DOMAINS_FILE_NAME)?,
+            resolver,
+        })
+    }
+
+    /// A verifier that doesn't persist claims.
+    pub fn in_memory(
+        root_domain_names: &[String],
+        resolver: Box<dyn TxtResolver + Send + Sync>,
+    ) -> Self {
+        DomainVerifier {
+            root_domain_names: root_domain_names.iter().map(|d| normalize(d)).collect(),
+            claims: JsonFile::in_memory(),
+            resolver,
+        }
+    }
+
+    /// Claims `domain_name` for `token`, returning the challenge that has to be published to
+    /// verify it. Claiming a domain again returns the existing claim if `token` may use it.
+    /// Unverified claims of other tokens are replaced, so nobody can hold on to a domain they
+    /// don't control.
+    pub fn claim(
+        &self,
+        domain_name: &str,
+        token: &TokenScope,
+    ) -> Result<DomainClaim, DomainVerificationError> {
+        let domain_name = normalize(domain_name);
+        validate_domain_name(&domain_name)?;
+        if let Some(root_domain_name) = self.root_domain_of(&domain_name) {
+            return Err(DomainVerificationError::InvalidDomainName(format!(
+                "Domain {} is part of the root domain {} and needs no verification",
+                domain_name, root_domain_name
+            )));
+        }
+
+        let claim = self.claims.update(|claims| {
+            let existing = claims
+                .get(&domain_name)
+                .filter(|claim| claim.verified || token.may_use(claim));
+            if let Some(claim) = existing {
+                return claim.clone();
+            }
+
+            // A fresh challenge, as the previous claimant may already have published theirs
+            let claim = DomainClaim {
+                challenge_record_name: format!("{}.{}", CHALLENGE_LABEL, domain_name),
+                challenge_value: format!(
+                    "{}{}",
+                    CHALLENGE_PREFIX,
+                    rand::distributions::Alphanumeric
+                        .sample_string(&mut thread_rng(), CHALLENGE_TOKEN_LENGTH)
+                ),
+                domain_name: domain_name.clone(),
+                verified: false,
+                claimed_by: token.clone(),
+            };
+            claims.insert(domain_name.clone(), claim.clone());
+            claim
+        })?;
+        if !token.may_use(&claim) {
+            return Err(DomainVerificationError::AlreadyClaimed(domain_name));
+        }
+        Ok(claim)
+    }
+
+    /// Looks up the challenge of a domain claimed by `token` and marks the domain as verified if
+    /// it was published.
+    pub fn verify(
+        &self,
+        domain_name: &str,
+        token: &TokenScope,
+    ) -> Result<DomainClaim, DomainVerificationError> {
+        let domain_name = normalize(domain_name);
+        let claim = self
+            .claims
+            .read(|claims| claims.get(&domain_name).cloned())?
+            .filter(|claim| token.may_use(claim))
+            .ok_or_else(|| DomainVerificationError::NotClaimed(domain_name.clone()))?;
+        if claim.verified {
+            return Ok(claim);
+        }
+
+        let records = self.resolver.txt_records(&claim.challenge_record_name)?;
+        if !records
+            .iter()
+            .any(|r| r.trim_matches('"') == claim.challenge_value)
+        {
+            return Err(DomainVerificationError::ChallengeNotFound(Box::new(claim)));
+        }
+
+        info!("Verified domain {}", domain_name);
+        let claim = self.claims.update(|claims| {
+            let claim = claims
+                .get_mut(&domain_name)
+                .expect("Claims are never removed");
+            claim.verified = true;
+            claim.clone()
+        })?;
+        Ok(claim)
+    }
+
+    /// The claims `token` may use.
+    pub fn list(&self, token: &TokenScope) -> anyhow::Result<Vec<DomainClaim>> {
+        self.claims.read(|claims| {
+            claims
+                .values()
+                .filter(|claim| token.may_use(claim))
+                .cloned()
+                .collect()
+        })
+    }
+
+    /// Whether deployments made with `token` may point `domain_name` at pond.
+    pub fn is_allowed(&self, domain_name: &str, token: &TokenScope) -> anyhow::Result<bool> {
+        let domain_name = normalize(domain_name);
+        if let Some(root_domain_name) = self.root_domain_of(&domain_name) {
+            return Ok(token
+                .root_domain_name
+                .as_deref()
+                .is_none_or(|allowed| normalize(allowed) == root_domain_name));
+        }
+        self.claims.read(|claims| {
+            claims.values().any(|c| {
+                c.verified && is_same_or_subdomain(&domain_name, &c.domain_name) && token.may_use(c)
+            })
+        })
+    }
+
+    fn root_domain_of(&self, domain_name: &str) -> Option<&str> {
+        self.root_domain_names
+            .iter()
+            .find(|root_domain_name| is_same_or_subdomain(domain_name, root_domain_name))
+            .map(String::as_str)
+    }
+}
+
+fn normalize(domain_name: &str) -> String {
+    domain_name.trim().trim_end_matches('.').to_lowercase()
+}
+
+fn is_same_or_subdomain(domain_name: &str, parent: &str) -> bool {
+    domain_name == parent
+        || domain_name
+            .strip_suffix(parent)
+            .is_some_and(|prefix| prefix.ends_with('.'))
+}
+
+fn validate_domain_name(domain_name: &str) -> Result<(), DomainVerificationError> {
+    if !is_valid_domain_name(domain_name) {
+        return Err(DomainVerificationError::InvalidDomainName(format!(
+            "{:?} is not a valid domain name",
+            domain_name
+        )));
+    }
+
+    // Claiming a public suffix like `co.uk` would allow every domain below it
+    if psl::domain_str(domain_name).is_none() {
+        return Err(DomainVerificationError::InvalidDomainName(format!(
+            "{} is a public suffix and can't be claimed",
+            domain_name
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn verifier(resolver: MockTxtResolver) -> DomainVerifier {
+        DomainVerifier::in_memory(
+            &["example.com".to_owned(), "example.net".to_owned()],
+            Box::new(resolver),
+        )
+    }
+
+    #[test]
+    fn test_root_domains_are_allowed() {
+        let verifier = verifier(MockTxtResolver::new());
+        assert!(verifier
+            .is_allowed("example.com", &TokenScope::default())
+            .unwrap());
+        assert!(verifier
+            .is_allowed("Blog.Example.com.", &TokenScope::default())
+            .unwrap());
+        assert!(verifier
+            .is_allowed("shop.example.net", &TokenScope::default())
+            .unwrap());
+        assert!(!verifier
+            .is_allowed("notexample.com", &TokenScope::default())
+            .unwrap());
+        assert!(!verifier
+            .is_allowed("example.org", &TokenScope::default())
+            .unwrap());
+    }
+
+    #[test]
+    fn test_claim_returns_challenge() {
+        let verifier = verifier(MockTxtResolver::new());
+        let claim = verifier
+            .claim("Example.org", &TokenScope::default())
+            .unwrap();
+        assert_eq!(claim.domain_name, "example.org");
+        assert_eq!(claim.challenge_record_name, "_pond-challenge.example.org");
+        assert!(claim.challenge_value.starts_with("pond-verification="));
+        assert!(!claim.verified);
+        assert_eq!(
+            verifier
+                .claim("example.org", &TokenScope::default())
+                .unwrap(),
+            claim
+        );
+        assert!(!verifier
+            .is_allowed("example.org", &TokenScope::default())
+            .unwrap());
+    }
+
+    #[test]
+    fn test_invalid_claims_are_rejected() {
+        let verifier = verifier(MockTxtResolver::new());
+        for domain_name in [
+            "blog.example.com",
+            "example.net",
+            "co.uk",
+            "exa mple.org",
+            "example..org",
+        ] {
+            assert!(matches!(
+                verifier.claim(domain_name, &TokenScope::default()),
+                Err(DomainVerificationError::InvalidDomainName(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_verify_with_published_challenge() {
+        let mut resolver = MockTxtResolver::new();
+        let challenge = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+        let published = challenge.clone();
+        resolver
+            .expect_txt_records()
+            .withf(|name| name == "_pond-challenge.example.org")
+            .times(1)
+            .returning(move |_| Ok(vec![format!("\"{}\"", published.lock().unwrap())]));
+        let verifier = verifier(resolver);
+
+        *challenge.lock().unwrap() = verifier
+            .claim("example.org", &TokenScope::default())
+            .unwrap()
+            .challenge_value;
+        assert!(
+            verifier
+                .verify("example.org", &TokenScope::default())
+                .unwrap()
+                .verified
+        );
+        // Verified claims aren't checked again
+        assert!(
+            verifier
+                .verify("example.org", &TokenScope::default())
+                .unwrap()
+                .verified
+        );
+        assert!(verifier
+            .is_allowed("example.org", &TokenScope::default())
+            .unwrap());
+        assert!(verifier
+            .is_allowed("www.example.org", &TokenScope::default())
+            .unwrap());
+    }
+
+    #[test]
+    fn test_verify_without_challenge_fails() {
+        let mut resolver = MockTxtResolver::new();
+        resolver
+            .expect_txt_records()
+            .returning(|_| Ok(vec!["pond-verification=wrong".to_owned()]));
+        let verifier = verifier(resolver);
+
+        verifier
+            .claim("example.org", &TokenScope::default())
+            .unwrap();
+        assert!(matches!(
+            verifier.verify("example.org", &TokenScope::default()),
+            Err(DomainVerificationError::ChallengeNotFound(_))
+        ));
+        assert!(!verifier
+            .is_allowed("example.org", &TokenScope::default())
+            .unwrap());
+    }
+
+    #[test]
+    fn test_verify_unclaimed_domain_fails() {
+        let verifier = verifier(MockTxtResolver::new());
+        assert!(matches!(
+            verifier.verify("example.org", &TokenScope::default()),
+            Err(DomainVerificationError::NotClaimed(_))
+        ));
+    }
+
+    fn scoped(name: &str, root_domain_name: &str) -> TokenScope {
+        TokenScope {
+            name: Some(name.to_owned()),
+            root_domain_name: Some(root_domain_name.to_owned()),
+        }
+    }
+
+    #[test]
+    fn test_scoped_tokens_only_use_their_root_domain() {
+        let verifier = verifier(MockTxtResolver::new());
+        let token = scoped("brand", "example.net");
+        assert!(verifier.is_allowed("shop.example.net", &token).unwrap());
+        assert!(!verifier.is_allowed("blog.example.com", &token).unwrap());
+    }
+
+    #[test]
+    fn test_claims_are_bound_to_their_token() {
+        let mut resolver = MockTxtResolver::new();
+        let challenge = std::sync::Arc::new(std::sync::Mutex::new(String::new()));
+        let published = challenge.clone();
+        resolver
+            .expect_txt_records()
+            .returning(move |_| Ok(vec![published.lock().unwrap().clone()]));
+        let verifier = verifier(resolver);
+        let brand = scoped("brand", "example.net");
+        let other = scoped("other", "example.net");
+
+        // Unverified claims are replaced by claims of other tokens
+        let abandoned = verifier.claim("example.org", &brand).unwrap();
+        let replaced = verifier.claim("example.org", &other).unwrap();
+        assert_eq!(replaced.claimed_by, other);
+        assert_ne!(replaced.challenge_value, abandoned.challenge_value);
+        assert!(matches!(
+            verifier.verify("example.org", &brand),
+            Err(DomainVerificationError::NotClaimed(_))
+        ));
+
+        *challenge.lock().unwrap() = verifier
+            .claim("example.org", &brand)
+            .unwrap()
+            .challenge_value;
+        assert!(verifier.verify("example.org", &brand).unwrap().verified);
+        assert!(matches!(
+            verifier.claim("example.org", &other),
+            Err(DomainVerificationError::AlreadyClaimed(_))
+        ));
+        assert!(matches!(
+            verifier.verify("example.org", &other),
+            Err(DomainVerificationError::NotClaimed(_))
+        ));
+
+        assert!(verifier.is_allowed("example.org", &brand).unwrap());
+        assert!(!verifier.is_allowed("example.org", &other).unwrap());
+        assert!(verifier
+            .is_allowed("example.org", &TokenScope::default())
+            .unwrap());
+        assert_eq!(verifier.list(&brand).unwrap().len(), 1);
+        assert!(verifier.list(&other).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,26 @@
+use hickory_resolver::{error::ResolveErrorKind, Resolver};
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+
+#[cfg_attr(test, automock)]
+pub trait TxtResolver {
+    /// Returns the TXT records of the fully qualified `name`, or nothing if there are none.
+    fn txt_records(&self, name: &str) -> anyhow::Result<Vec<String>>;
+}
+
+/// Resolves TXT records with the name servers of the system configuration.
+pub struct HickoryTxtResolver;
+
+impl TxtResolver for HickoryTxtResolver {
+    fn txt_records(&self, name: &str) -> anyhow::Result<Vec<String>> {
+        // A fresh resolver per lookup, so a challenge that was just published isn't hidden by a
+        // cached negative answer
+        let resolver = Resolver::from_system_conf()?;
+        match resolver.txt_lookup(format!("{}.", name.trim_end_matches('.'))) {
+            Ok(lookup) => Ok(lookup.iter().map(|txt| txt.to_string()).collect()),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
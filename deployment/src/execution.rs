@@ -0,0 +1,262 @@
+use std::{
+    env,
+    ffi::OsString,
+    io,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use figment::Figment;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ConfigurationError;
+
+const EXECUTION: &str = "execution";
+
+/// Limits of the resources a command may use, applied through `setrlimit`.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResourceLimits {
+    pub max_memory_bytes: Option<u64>,
+    pub max_processes: Option<u64>,
+    pub max_open_files: Option<u64>,
+    pub max_file_size_bytes: Option<u64>,
+    pub max_cpu_seconds: Option<u64>,
+}
+
+/// How the commands of a deployer are run. Unset values keep those of pond.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExecutionSettings {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// The variables of pond's environment passed to commands. All others are cleared, except
+    /// for the ones the deployer sets itself.
+    pub env_allowlist: Option<Vec<String>>,
+    pub working_directory: Option<PathBuf>,
+    /// The file mode creation mask, e.g. `0o022`.
+    pub umask: Option<u32>,
+    pub rlimits: ResourceLimits,
+}
+
+impl ExecutionSettings {
+    /// Reads the settings of `deployment_type` from `execution.<deployment_type>` and checks
+    /// that commands can be run with them.
+    pub fn configure(figment: &Figment, deployment_type: &str) -> Result<Self, ConfigurationError> {
+        let settings: ExecutionSettings =
+            match figment.extract_inner(&format!("{}.{}", EXECUTION, deployment_type)) {
+                Ok(settings) => settings,
+                Err(e) if e.missing() => return Ok(ExecutionSettings::default()),
+                Err(e) => return Err(e.into()),
+            };
+        settings.verify().map_err(|e| {
+            ConfigurationError::Other(
+                format!(
+                    "Commands of {} deployments can't be run with the configured execution settings: {}",
+                    deployment_type, e
+                )
+                .into(),
+            )
+        })?;
+        Ok(settings)
+    }
+
+    /// Runs a command that does nothing with these settings, which fails if the privileges
+    /// can't be dropped or the limits not be set.
+    pub fn verify(&self) -> io::Result<()> {
+        if *self == ExecutionSettings::default() {
+            return Ok(());
+        }
+        let mut command = Command::new("/bin/sh");
+        command
+            .args(["-c", "exit 0"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        self.apply(&mut command);
+        let exit_status = command.status()?;
+        if !exit_status.success() {
+            return Err(io::Error::other(format!(
+                "Test command exited with status {}",
+                exit_status
+            )));
+        }
+        Ok(())
+    }
+
+    /// Configures `command` to run with these settings.
+    pub(crate) fn apply(&self, command: &mut Command) {
+        if let Some(allowlist) = &self.env_allowlist {
+            let explicit: Vec<(OsString, Option<OsString>)> = command
+                .get_envs()
+                .map(|(name, value)| (name.to_owned(), value.map(|v| v.to_owned())))
+                .collect();
+            command.env_clear();
+            for name in allowlist {
+                if let Some(value) = env::var_os(name) {
+                    command.env(name, value);
+                }
+            }
+            for (name, value) in explicit {
+                match value {
+                    Some(value) => command.env(name, value),
+                    None => command.env_remove(name),
+                };
+            }
+        }
+        if let Some(working_directory) = &self.working_directory {
+            command.current_dir(working_directory);
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::CommandExt;
+            if let Some(gid) = self.gid {
+                command.gid(gid);
+            }
+            if let Some(uid) = self.uid {
+                command.uid(uid);
+            }
+            let umask = self.umask;
+            let limits = self.rlimits.clone();
+            if umask.is_some() || limits != ResourceLimits::default() {
+                // SAFETY: The closure runs between fork and exec and only makes system calls,
+                // without allocating or taking locks.
+                unsafe {
+                    command.pre_exec(move || {
+                        if let Some(umask) = umask {
+                            libc::umask(umask as libc::mode_t);
+                        }
+                        set_rlimit(libc::RLIMIT_AS, limits.max_memory_bytes)?;
+                        set_rlimit(libc::RLIMIT_NPROC, limits.max_processes)?;
+                        set_rlimit(libc::RLIMIT_NOFILE, limits.max_open_files)?;
+                        set_rlimit(libc::RLIMIT_FSIZE, limits.max_file_size_bytes)?;
+                        set_rlimit(libc::RLIMIT_CPU, limits.max_cpu_seconds)?;
+                        Ok(())
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_env = "gnu"))]
+type Resource = libc::__rlimit_resource_t;
+#[cfg(not(all(target_os = "linux", target_env = "gnu")))]
+type Resource = libc::c_int;
+
+#[cfg(unix)]
+fn set_rlimit(resource: Resource, value: Option<u64>) -> io::Result<()> {
+    let Some(value) = value else {
+        return Ok(());
+    };
+    let limit = libc::rlimit {
+        rlim_cur: value as libc::rlim_t,
+        rlim_max: value as libc::rlim_t,
+    };
+    // SAFETY: `limit` is a valid rlimit that outlives the call.
+    if unsafe { libc::setrlimit(resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use figment::providers::Serialized;
+
+    use super::*;
+    use crate::{deployer::deployment_handle, helpers::run_command};
+
+    fn config_figment(config: &str) -> Figment {
+        Figment::from(Serialized::defaults(
+            toml::from_str::<toml::Table>(config).unwrap(),
+        ))
+    }
+
+    fn run(settings: &ExecutionSettings, command: Command) -> String {
+        let (handle, mut logs) = deployment_handle();
+        settings.verify().unwrap();
+        let exit_status = run_command(command, settings, handle);
+        assert!(exit_status.unwrap().success());
+        io::read_to_string(logs.info()).unwrap()
+    }
+
+    fn shell(script: &str) -> Command {
+        let mut command = Command::new("/bin/sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    #[test]
+    fn test_configure() {
+        let figment = config_figment(
+            r#"
+            [execution.static-site]
+            env_allowlist = ["PATH"]
+            umask = 0o027
+            rlimits = { max_open_files = 256 }
+            "#,
+        );
+        let settings = ExecutionSettings::configure(&figment, "static-site").unwrap();
+        assert_eq!(settings.umask, Some(0o027));
+        assert_eq!(settings.rlimits.max_open_files, Some(256));
+        assert_eq!(
+            ExecutionSettings::configure(&figment, "other").unwrap(),
+            ExecutionSettings::default()
+        );
+
+        let figment =
+            config_figment("[execution.static-site]\nworking_directory = \"/does/not/exist\"");
+        assert!(ExecutionSettings::configure(&figment, "static-site").is_err());
+    }
+
+    #[test]
+    fn test_environment_is_restricted() {
+        std::env::set_var("POND_EXECUTION_TEST_TOKEN", "secret");
+        let settings = ExecutionSettings {
+            env_allowlist: Some(vec!["PATH".to_owned()]),
+            ..Default::default()
+        };
+        let mut command = shell("echo ${POND_EXECUTION_TEST_TOKEN:-unset} $DEPLOYMENT_NAME");
+        command.env("DEPLOYMENT_NAME", "blog");
+        assert_eq!(run(&settings, command), "unset blog\n");
+        let command = shell("echo $POND_EXECUTION_TEST_TOKEN");
+        assert_eq!(run(&ExecutionSettings::default(), command), "secret\n");
+    }
+
+    #[test]
+    fn test_umask_limits_and_working_directory() {
+        let settings = ExecutionSettings {
+            working_directory: Some(std::env::temp_dir()),
+            umask: Some(0o077),
+            rlimits: ResourceLimits {
+                max_open_files: Some(64),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let output = run(&settings, shell("umask; ulimit -n; pwd"));
+        assert_eq!(
+            output,
+            format!(
+                "0077\n64\n{}\n",
+                std::env::temp_dir().canonicalize().unwrap().display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_privileges_are_dropped() {
+        let settings = ExecutionSettings {
+            uid: Some(65534),
+            gid: Some(65534),
+            ..Default::default()
+        };
+        // SAFETY: geteuid has no preconditions.
+        if unsafe { libc::geteuid() } == 0 {
+            assert_eq!(run(&settings, shell("id -u; id -g")), "65534\n65534\n");
+        } else {
+            assert!(settings.verify().is_err());
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use std::{fs, io, path::Path, process::Command};
+
+use figment::{providers::Serialized, Figment};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ConfigurationError, deployer::DeploymentHandle, execution::ExecutionSettings,
+    helpers::run_command, manifest::GitSource,
+};
+
+const GIT: &str = "git";
+
+/// Which repositories manifests may be deployed from.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct GitSettings {
+    /// Allows `file://` repositories, which can be any repository on the server. Only
+    /// `https://` repositories are allowed otherwise.
+    pub(crate) allow_file_urls: bool,
+}
+
+impl GitSettings {
+    pub fn configure(figment: &Figment) -> Result<Self, ConfigurationError> {
+        Ok(figment.extract_inner(GIT)?)
+    }
+
+    pub fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(GIT, GitSettings::default()))
+    }
+
+    /// Makes sure the repository of `source` may be fetched.
+    pub(crate) fn check(&self, source: &GitSource) -> Result<(), String> {
+        if source.git.starts_with("file://") && !self.allow_file_urls {
+            return Err(format!(
+                "{:?} is a file:// URL, which this server doesn't allow",
+                source.git
+            ));
+        }
+        Ok(())
+    }
+
+    /// The protocols git may use, also for redirects and submodules.
+    fn allowed_protocols(&self) -> &'static str {
+        if self.allow_file_urls {
+            "https:file"
+        } else {
+            "https"
+        }
+    }
+}
+
+/// Clones the repository of `source` and writes the files below its `path` at `ref` to a zip
+/// archive at `destination`, returning the SHA of the commit they were taken from.
+pub(crate) fn archive(
+    source: &GitSource,
+    settings: &GitSettings,
+    destination: &Path,
+    deployment_handle: DeploymentHandle,
+) -> io::Result<String> {
+    let repository = destination.with_extension("repository");
+    fs::remove_dir_all(&repository).ok();
+    let result = archive_from(
+        source,
+        settings,
+        &repository,
+        destination,
+        deployment_handle,
+    );
+    fs::remove_dir_all(&repository).ok();
+    result
+}
+
+fn archive_from(
+    source: &GitSource,
+    settings: &GitSettings,
+    repository: &Path,
+    destination: &Path,
+    deployment_handle: DeploymentHandle,
+) -> io::Result<String> {
+    let mut clone = git(None);
+    clone.env("GIT_ALLOW_PROTOCOL", settings.allowed_protocols());
+    clone
+        .args(["clone", "--bare", "--quiet", "--"])
+        .arg(&source.git)
+        .arg(repository);
+    run_git(clone, deployment_handle.clone())?;
+
+    let output = git(Some(repository))
+        .args(["rev-parse", "--verify", "--quiet", "--end-of-options"])
+        .arg(format!("{}^{{commit}}", source.reference))
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} has no branch, tag or commit {}",
+            source.git, source.reference
+        )));
+    }
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+
+    let tree = match &source.path {
+        Some(path) => format!("{}:{}", commit, path),
+        None => commit.clone(),
+    };
+    let mut archive = git(Some(repository));
+    archive
+        .args(["archive", "--format=zip", "-o"])
+        .arg(destination)
+        .arg(tree);
+    run_git(archive, deployment_handle)?;
+    Ok(commit)
+}
+
+fn git(repository: Option<&Path>) -> Command {
+    let mut command = Command::new("git");
+    // Fail instead of waiting for credentials nobody will enter
+    command.env("GIT_TERMINAL_PROMPT", "0");
+    if let Some(repository) = repository {
+        command.arg("-C").arg(repository);
+    }
+    command
+}
+
+fn run_git(command: Command, deployment_handle: DeploymentHandle) -> io::Result<()> {
+    let description = format!("{:?}", command);
+    let exit_status = run_command(command, &ExecutionSettings::default(), deployment_handle)?;
+    if !exit_status.success() {
+        return Err(io::Error::other(format!(
+            "{} exited with status {}",
+            description, exit_status
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use std::{io::Read, path::PathBuf};
+
+    use super::*;
+    use crate::deployer::deployment_handle;
+
+    fn run(directory: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(directory)
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap().trim().to_owned()
+    }
+
+    /// Creates a bare repository holding `dist/index.html` at the tag `v1`, returning its path
+    /// and the SHA of the commit.
+    pub(crate) fn bare_repository(name: &str) -> (PathBuf, String) {
+        let directory = std::env::temp_dir().join(format!("pond-git-{}", name));
+        fs::remove_dir_all(&directory).ok();
+        let work = directory.join("work");
+        fs::create_dir_all(work.join("dist")).unwrap();
+        fs::write(work.join("dist/index.html"), "Hello").unwrap();
+        fs::write(work.join("README.md"), "Blog").unwrap();
+        run(&work, &["init", "--quiet", "-b", "main"]);
+        run(&work, &["add", "."]);
+        run(
+            &work,
+            &[
+                "-c",
+                "user.name=Pond",
+                "-c",
+                "user.email=pond@example.com",
+                "commit",
+                "--quiet",
+                "-m",
+                "Initial commit",
+            ],
+        );
+        run(&work, &["tag", "v1"]);
+        let bare = directory.join("blog.git");
+        run(
+            &directory,
+            &["clone", "--bare", "--quiet", "work", "blog.git"],
+        );
+        (bare, run(&work, &["rev-parse", "HEAD"]))
+    }
+
+    /// Settings that allow the `file://` repositories of the tests.
+    pub(crate) fn file_settings() -> GitSettings {
+        GitSettings {
+            allow_file_urls: true,
+        }
+    }
+
+    fn source(repository: &Path, reference: &str, path: Option<&str>) -> GitSource {
+        GitSource {
+            git: format!("file://{}", repository.display()),
+            reference: reference.to_owned(),
+            path: path.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn test_archive_subdirectory() {
+        let (repository, commit) = bare_repository("subdirectory");
+        let destination = repository.with_extension("zip");
+        let (handle, _logs) = deployment_handle();
+        let archived = archive(
+            &source(&repository, "v1", Some("dist")),
+            &file_settings(),
+            &destination,
+            handle,
+        )
+        .unwrap();
+        assert_eq!(archived, commit);
+
+        let mut archive = zip::ZipArchive::new(fs::File::open(&destination).unwrap()).unwrap();
+        let mut content = String::new();
+        archive
+            .by_name("index.html")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "Hello");
+        assert!(archive.by_name("README.md").is_err());
+        assert!(!destination.with_extension("repository").exists());
+    }
+
+    #[test]
+    fn test_refs_are_resolved() {
+        let (repository, commit) = bare_repository("refs");
+        let destination = repository.with_extension("zip");
+        for reference in ["main", &commit[..12], &commit] {
+            let (handle, _logs) = deployment_handle();
+            let archived = archive(
+                &source(&repository, reference, None),
+                &file_settings(),
+                &destination,
+                handle,
+            );
+            assert_eq!(archived.unwrap(), commit);
+        }
+        let (handle, _logs) = deployment_handle();
+        assert!(archive(
+            &source(&repository, "unknown", None),
+            &file_settings(),
+            &destination,
+            handle
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_file_urls_are_refused_by_default() {
+        let (repository, _) = bare_repository("file-urls");
+        let source = source(&repository, "main", None);
+        assert!(GitSettings::default().check(&source).is_err());
+        assert!(file_settings().check(&source).is_ok());
+
+        let (handle, _logs) = deployment_handle();
+        let destination = repository.with_extension("zip");
+        assert!(archive(&source, &GitSettings::default(), &destination, handle).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    thread,
+    time::Duration,
+};
+
+use figment::{providers::Serialized, Figment};
+use reqwest::{blocking::Client, redirect::Policy};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ConfigurationError, deployer::DeploymentHandle, manifest::HealthCheck};
+
+const HEALTH_CHECK: &str = "health_check";
+/// Makes nginx serve the canary of a site instead of splitting requests between the canary and
+/// the current deployment.
+const CANARY_HEADER: &str = "X-Pond-Canary";
+
+#[derive(Clone, Serialize, Deserialize)]
+struct HealthCheckConfig {
+    /// Where requests are sent instead of the address the domain resolves to, e.g. `127.0.0.1`
+    /// to reach the local nginx before DNS records have propagated.
+    target_address: Option<IpAddr>,
+    /// Defaults to the port of the scheme.
+    port: Option<u16>,
+    scheme: String,
+    retry_interval_seconds: u64,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            target_address: None,
+            port: None,
+            scheme: "https".to_owned(),
+            retry_interval_seconds: 2,
+        }
+    }
+}
+
+/// Sends the health checks of manifests to the domains of a deployment.
+#[derive(Clone, Default)]
+pub struct HealthChecker {
+    config: HealthCheckConfig,
+}
+
+impl HealthChecker {
+    pub fn configure(figment: &Figment) -> Result<Self, ConfigurationError> {
+        let config: HealthCheckConfig = figment.extract_inner(HEALTH_CHECK)?;
+        if config.scheme != "http" && config.scheme != "https" {
+            return Err(ConfigurationError::Other(
+                format!("{:?} is not a health check scheme", config.scheme).into(),
+            ));
+        }
+        Ok(HealthChecker { config })
+    }
+
+    pub fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(
+            HEALTH_CHECK,
+            HealthCheckConfig::default(),
+        ))
+    }
+
+    /// Probes every domain in `domain_names`, failing with the last problem found for the first
+    /// domain that stays unhealthy after all retries. Probes of a `canary` only reach the
+    /// canary.
+    pub(crate) fn check(
+        &self,
+        health_check: &HealthCheck,
+        domain_names: &[String],
+        canary: bool,
+        mut deployment_handle: DeploymentHandle,
+    ) -> io::Result<()> {
+        for domain_name in domain_names {
+            let client = self.client(health_check, domain_name)?;
+            let url = self.url(health_check, domain_name);
+            let mut attempt = 0;
+            loop {
+                writeln!(deployment_handle.info(), "Checking health of {}", url).ok();
+                let Err(problem) = probe(&client, &url, health_check, canary) else {
+                    break;
+                };
+                writeln!(deployment_handle.error(), "{}", problem).ok();
+                if attempt >= health_check.retries {
+                    return Err(io::Error::other(format!(
+                        "{} is unhealthy: {}",
+                        domain_name, problem
+                    )));
+                }
+                attempt += 1;
+                thread::sleep(Duration::from_secs(self.config.retry_interval_seconds));
+            }
+        }
+        Ok(())
+    }
+
+    fn client(&self, health_check: &HealthCheck, domain_name: &str) -> io::Result<Client> {
+        let mut builder = Client::builder()
+            .timeout(Duration::from_secs(health_check.timeout_seconds))
+            .redirect(Policy::none());
+        // The port is taken from the URL, which keeps the host header and TLS server name
+        if let Some(target_address) = self.config.target_address {
+            builder = builder.resolve(domain_name, SocketAddr::new(target_address, 0));
+        }
+        builder.build().map_err(io::Error::other)
+    }
+
+    fn url(&self, health_check: &HealthCheck, domain_name: &str) -> String {
+        match self.config.port {
+            Some(port) => format!(
+                "{}://{}:{}{}",
+                self.config.scheme, domain_name, port, health_check.path
+            ),
+            None => format!(
+                "{}://{}{}",
+                self.config.scheme, domain_name, health_check.path
+            ),
+        }
+    }
+}
+
+fn probe(
+    client: &Client,
+    url: &str,
+    health_check: &HealthCheck,
+    canary: bool,
+) -> Result<(), String> {
+    let mut request = client.get(url);
+    if canary {
+        request = request.header(CANARY_HEADER, "1");
+    }
+    let response = request
+        .send()
+        .map_err(|e| format!("Request failed: {}", e))?;
+    let status = response.status().as_u16();
+    if status != health_check.expected_status {
+        return Err(format!(
+            "Responded with status {} instead of {}",
+            status, health_check.expected_status
+        ));
+    }
+    if let Some(expected) = &health_check.body_contains {
+        let body = response
+            .text()
+            .map_err(|e| format!("Failed to read the response: {}", e))?;
+        if !body.contains(expected.as_str()) {
+            return Err(format!("The response does not contain {:?}", expected));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use mockito::{Server, ServerGuard};
+
+    use super::*;
+    use crate::deployer::deployment_handle;
+
+    /// A checker sending requests for any domain to `server`.
+    pub(crate) fn checker(server: &ServerGuard) -> HealthChecker {
+        let address = server.socket_address();
+        HealthChecker {
+            config: HealthCheckConfig {
+                target_address: Some(address.ip()),
+                port: Some(address.port()),
+                scheme: "http".to_owned(),
+                retry_interval_seconds: 0,
+            },
+        }
+    }
+
+    fn health_check(body_contains: Option<&str>) -> HealthCheck {
+        HealthCheck {
+            path: "/health".to_owned(),
+            expected_status: 200,
+            body_contains: body_contains.map(str::to_owned),
+            retries: 2,
+            timeout_seconds: 5,
+        }
+    }
+
+    fn check(checker: &HealthChecker, health_check: &HealthCheck) -> io::Result<()> {
+        let (handle, _logs) = deployment_handle();
+        checker.check(
+            health_check,
+            &["blog.example.com".to_owned(), "example.com".to_owned()],
+            false,
+            handle,
+        )
+    }
+
+    #[test]
+    fn test_healthy_domains() {
+        let mut server = Server::new();
+        let port = server.socket_address().port();
+        let mocks = ["blog.example.com", "example.com"].map(|domain_name| {
+            server
+                .mock("GET", "/health")
+                .match_header("host", format!("{}:{}", domain_name, port).as_str())
+                .with_body("Welcome to the blog")
+                .create()
+        });
+        check(&checker(&server), &health_check(Some("Welcome"))).unwrap();
+        for mock in mocks {
+            mock.assert();
+        }
+    }
+
+    #[test]
+    fn test_unhealthy_domains_are_retried() {
+        let mut server = Server::new();
+        let mock = server
+            .mock("GET", "/health")
+            .with_status(404)
+            .expect(3)
+            .create();
+        let error = check(&checker(&server), &health_check(None)).unwrap_err();
+        assert!(error.to_string().contains("blog.example.com"), "{}", error);
+        assert!(error.to_string().contains("404"), "{}", error);
+        mock.assert();
+    }
+
+    #[test]
+    fn test_body_is_checked() {
+        let mut server = Server::new();
+        let _mock = server
+            .mock("GET", "/health")
+            .with_body("Maintenance")
+            .create();
+        let error = check(&checker(&server), &health_check(Some("Welcome"))).unwrap_err();
+        assert!(error.to_string().contains("Welcome"), "{}", error);
+    }
+
+    #[test]
+    fn test_canaries_are_probed_directly() {
+        let mut server = Server::new();
+        let _current = server.mock("GET", "/health").with_body("Current").create();
+        let canary = server
+            .mock("GET", "/health")
+            .match_header("x-pond-canary", "1")
+            .with_body("Canary")
+            .expect(2)
+            .create();
+        let (handle, _logs) = deployment_handle();
+        checker(&server)
+            .check(
+                &health_check(Some("Canary")),
+                &["blog.example.com".to_owned(), "example.com".to_owned()],
+                true,
+                handle,
+            )
+            .unwrap();
+        canary.assert();
+    }
+}
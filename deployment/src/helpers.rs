@@ -1,16 +1,37 @@
 use std::{
-    panic,
-    process::{Command, ExitStatus, Stdio},
+    io, panic,
+    path::{Component, Path},
+    process::{Child, Command, ExitStatus, Stdio},
     thread,
+    time::{Duration, Instant},
 };
 
-use crate::deployer::DeploymentHandle;
+use crate::{deployer::DeploymentHandle, execution::ExecutionSettings};
 
+/// Runs `command` with `execution` applied, copying its output to `message_stream`.
 pub fn run_command(
+    command: Command,
+    execution: &ExecutionSettings,
+    message_stream: DeploymentHandle,
+) -> io::Result<ExitStatus> {
+    run_command_with_timeout(command, execution, message_stream, None)
+}
+
+/// Like [`run_command`], but kills the command along with the processes it started once
+/// `timeout` has passed.
+pub fn run_command_with_timeout(
     mut command: Command,
+    execution: &ExecutionSettings,
     mut message_stream: DeploymentHandle,
-) -> std::io::Result<ExitStatus> {
+    timeout: Option<Duration>,
+) -> io::Result<ExitStatus> {
+    execution.apply(&mut command);
     command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    #[cfg(unix)]
+    if timeout.is_some() {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
 
     let mut spawned = command.spawn()?;
     let mut stdout = spawned.stdout.take().unwrap();
@@ -22,26 +43,84 @@ pub fn run_command(
         debug!("stderr copied");
         Ok::<(), std::io::Error>(())
     });
-    std::io::copy(&mut stdout, message_stream.info())?;
+    let out_jh = thread::spawn(move || {
+        std::io::copy(&mut stdout, message_stream.info())?;
+        Ok::<(), std::io::Error>(())
+    });
 
-    let result = spawned
-        .wait()
-        .inspect(|_r| debug!("Command terminated successfully: {:?}", command))
-        .inspect_err(|e| error!("Command {:?} failed {:?}", command, e));
+    let result = match timeout {
+        Some(timeout) => wait_with_timeout(&mut spawned, timeout),
+        None => spawned.wait(),
+    }
+    .inspect(|_r| debug!("Command terminated successfully: {:?}", command))
+    .inspect_err(|e| error!("Command {:?} failed {:?}", command, e));
+    // Processes that escaped the process group may keep the output open
+    if result
+        .as_ref()
+        .is_err_and(|e| e.kind() == io::ErrorKind::TimedOut)
+    {
+        return result;
+    }
 
-    match err_jh.join() {
-        Ok(result) => {
-            result?;
+    for jh in [out_jh, err_jh] {
+        match jh.join() {
+            Ok(result) => {
+                result?;
+            }
+            Err(e) => panic::resume_unwind(e),
         }
-        Err(e) => panic::resume_unwind(e),
     }
     result
 }
 
+fn wait_with_timeout(child: &mut Child, timeout: Duration) -> io::Result<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            Command::new("kill")
+                .args(["-KILL", "--", &format!("-{}", child.id())])
+                .status()
+                .ok();
+            child.kill().ok();
+            child.wait().ok();
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("Command timed out after {} seconds", timeout.as_secs()),
+            ));
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+/// Whether `label` can be used as one label of a host name.
+pub fn is_valid_dns_label(label: &str) -> bool {
+    !label.is_empty()
+        && label.len() <= 63
+        && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !label.starts_with('-')
+        && !label.ends_with('-')
+}
+
+pub fn is_valid_domain_name(domain_name: &str) -> bool {
+    domain_name.len() <= 253 && domain_name.split('.').all(is_valid_dns_label)
+}
+
+/// Whether `path` is relative and stays within the directory it is relative to.
+pub fn is_relative_path(path: &str) -> bool {
+    !path.is_empty()
+        && !path.contains('\\')
+        && Path::new(path)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::{io, process::Command};
+    use std::process::Command;
 
     use crate::deployer::handle::deployment_handle;
 
@@ -50,9 +129,30 @@ mod test {
         let mut command = Command::new("echo");
         command.arg("Hello!");
         let (write, mut read) = deployment_handle();
-        run_command(command, write).expect("Could not launch echo command");
+        run_command(command, &ExecutionSettings::default(), write)
+            .expect("Could not launch echo command");
 
-        let output = io::read_to_string(read.info()).expect("Could not read command output");
+        let output = std::io::read_to_string(read.info()).expect("Could not read command output");
         assert_eq!(output, "Hello!\n")
     }
+
+    #[test]
+    fn test_run_command_with_timeout() {
+        let mut command = Command::new("sh");
+        command.args(["-c", "echo started; sleep 10 & sleep 10"]);
+        let (write, mut read) = deployment_handle();
+        let started = Instant::now();
+        let result = run_command_with_timeout(
+            command,
+            &ExecutionSettings::default(),
+            write,
+            Some(Duration::from_secs(1)),
+        );
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let mut output = String::new();
+        read.info().read_to_string(&mut output).unwrap();
+        assert_eq!(output, "started\n");
+    }
 }
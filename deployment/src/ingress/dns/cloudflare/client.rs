@@ -1,7 +1,13 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, fmt, thread, time::Duration};
 
-use reqwest::header::{HeaderMap, HeaderValue};
-use serde::{Deserialize, Serialize};
+use reqwest::{
+    blocking::{RequestBuilder, Response},
+    header::{HeaderMap, HeaderValue, RETRY_AFTER},
+    StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::ingress::dns::DnsRecord;
 
 #[cfg(test)]
 use mockall::{automock, predicate::*};
@@ -11,12 +17,147 @@ pub struct CloudflareClient {
     cloudflare_base_url: String,
     api_key: String,
     api_client: reqwest::blocking::Client,
+    retry_policy: RetryPolicy,
+}
+
+/// How often and how patiently rate limited (429) and failed (5xx) requests are retried.
+/// `max_backoff` is the longest pond waits before a retry, also when Cloudflare asks for more.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry `attempt`, or `None` if `Retry-After` asks for more than
+    /// `max_backoff`, as retrying earlier would only be rate limited again.
+    fn backoff(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        match retry_after {
+            Some(retry_after) => (retry_after <= self.max_backoff).then_some(retry_after),
+            None => Some(
+                self.initial_backoff
+                    .saturating_mul(2u32.saturating_pow(attempt))
+                    .min(self.max_backoff),
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CloudflareError {
+    Http(reqwest::Error),
+    /// The response body could not be decoded. Carries the status code and the raw body.
+    InvalidResponse {
+        status: StatusCode,
+        body: String,
+        error: serde_json::Error,
+    },
+    /// The API answered with `success: false`.
+    Api {
+        errors: Vec<CloudflareMessage>,
+    },
+    /// The API reported success but the result has an unexpected shape.
+    UnexpectedResult(String),
+    /// A request failed and `Retry-After` asked for a longer wait than the retry policy allows.
+    RetryAfterTooLong {
+        status: StatusCode,
+        retry_after: Duration,
+    },
+}
+
+#[allow(unused)]
+impl CloudflareError {
+    pub fn codes(&self) -> Vec<u32> {
+        match self {
+            CloudflareError::Api { errors } => errors.iter().map(|e| e.code).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl fmt::Display for CloudflareError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CloudflareError::Http(e) => write!(f, "Cloudflare request failed: {}", e),
+            CloudflareError::InvalidResponse {
+                status,
+                body,
+                error,
+            } => write!(
+                f,
+                "Cloudflare responded with status {} and a body that could not be decoded ({}): {}",
+                status, error, body
+            ),
+            CloudflareError::Api { errors } => {
+                write!(f, "Cloudflare API returned errors:")?;
+                for error in errors {
+                    write!(f, " [{}] {}", error.code, error.message)?;
+                }
+                Ok(())
+            }
+            CloudflareError::UnexpectedResult(message) => {
+                write!(
+                    f,
+                    "Cloudflare API returned an unexpected result: {}",
+                    message
+                )
+            }
+            CloudflareError::RetryAfterTooLong {
+                status,
+                retry_after,
+            } => write!(
+                f,
+                "Cloudflare responded with status {} and asked to retry in {:?}, which is longer than pond waits",
+                status, retry_after
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CloudflareError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CloudflareError::Http(e) => Some(e),
+            CloudflareError::InvalidResponse { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for CloudflareError {
+    fn from(error: reqwest::Error) -> Self {
+        CloudflareError::Http(error)
+    }
+}
+
+impl<T> CloudflareV4Result<T> {
+    /// Turns an unsuccessful envelope into a typed error carrying the API error codes.
+    pub fn into_result(self) -> Result<Option<T>, CloudflareError> {
+        if self.success {
+            Ok(self.result)
+        } else {
+            Err(CloudflareError::Api {
+                errors: self.errors,
+            })
+        }
+    }
 }
 
 #[allow(unused)]
 #[cfg_attr(test, automock)]
 impl CloudflareClient {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(cloudflare_base_url: String, api_key: String, retry_policy: RetryPolicy) -> Self {
         let mut default_headers = HeaderMap::new();
         default_headers.insert(
             "Authorization",
@@ -29,37 +170,35 @@ impl CloudflareClient {
             .unwrap();
 
         CloudflareClient {
-            cloudflare_base_url: "https://api.cloudflare.com".to_string(),
+            cloudflare_base_url,
             api_key,
             api_client,
+            retry_policy,
         }
     }
 
-    pub fn list_zones(&self, name: &str) -> anyhow::Result<CloudflareListZonesResponse> {
+    pub fn list_zones(
+        &self,
+        name: &str,
+        page: u32,
+    ) -> Result<CloudflareListZonesResponse, CloudflareError> {
         let url = format!("{}/client/v4/zones", self.cloudflare_base_url);
-        let response = self
-            .api_client
-            .get(&url)
-            .query(&[("name", name)])
-            .send()
-            .unwrap();
-        let response = serde_json::from_reader(response)?;
-        Ok(response)
+        let page = page.to_string();
+        let query_params = [("name", name), ("page", page.as_str())];
+        self.send(self.api_client.get(&url).query(&query_params))
     }
 
     pub fn add_dns_record(
         &self,
         zone_id: &ZoneId,
         request: &CloudflareDnsRecordBody,
-    ) -> anyhow::Result<CloudflareCreateRecordResponse> {
+    ) -> Result<CloudflareCreateRecordResponse, CloudflareError> {
         let url = format!(
             "{}/client/v4/zones/{}/dns_records",
             self.cloudflare_base_url, zone_id.0
         );
 
-        let response = self.api_client.post(&url).json(request).send()?;
-        let response = serde_json::from_reader(response)?;
-        Ok(response)
+        self.send(self.api_client.post(&url).json(request))
     }
 
     pub fn update_dns_record(
@@ -67,15 +206,26 @@ impl CloudflareClient {
         zone_id: &ZoneId,
         record_id: &RecordId,
         request: &CloudflareDnsRecordBody,
-    ) -> anyhow::Result<CloudflareUpdateRecordResponse> {
+    ) -> Result<CloudflareUpdateRecordResponse, CloudflareError> {
+        let url = format!(
+            "{}/client/v4/zones/{}/dns_records/{}",
+            self.cloudflare_base_url, zone_id.0, record_id.0
+        );
+
+        self.send(self.api_client.patch(&url).json(request))
+    }
+
+    pub fn delete_dns_record(
+        &self,
+        zone_id: &ZoneId,
+        record_id: &RecordId,
+    ) -> Result<CloudflareDeleteRecordResponse, CloudflareError> {
         let url = format!(
             "{}/client/v4/zones/{}/dns_records/{}",
             self.cloudflare_base_url, zone_id.0, record_id.0
         );
 
-        let response = self.api_client.patch(&url).json(request).send()?;
-        let response = serde_json::from_reader(response)?;
-        Ok(response)
+        self.send(self.api_client.delete(&url))
     }
 
     pub fn list_dns_records(
@@ -83,7 +233,7 @@ impl CloudflareClient {
         zone_id: &ZoneId,
         domain_name: &str,
         page: u32,
-    ) -> anyhow::Result<CloudflareListRecordsResponse> {
+    ) -> Result<CloudflareListRecordsResponse, CloudflareError> {
         let url = format!(
             "{}/client/v4/zones/{}/dns_records",
             self.cloudflare_base_url, zone_id.0
@@ -92,28 +242,78 @@ impl CloudflareClient {
         let page = page.to_string();
         let query_params = [("page", page.as_str()), ("name", domain_name)];
 
-        let response = self.api_client.get(&url).query(&query_params).send()?;
-        let response = serde_json::from_reader(response)?;
+        self.send(self.api_client.get(&url).query(&query_params))
+    }
+}
+
+impl CloudflareClient {
+    fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, CloudflareError> {
+        let response = self.send_with_retries(request)?;
+        let status = response.status();
+        let body = response.text()?;
+        serde_json::from_str(&body).map_err(|error| CloudflareError::InvalidResponse {
+            status,
+            body,
+            error,
+        })
+    }
 
-        Ok(response)
+    fn send_with_retries(&self, request: RequestBuilder) -> Result<Response, CloudflareError> {
+        let mut attempt = 0;
+        loop {
+            // Requests with streaming bodies can't be cloned and are therefore sent only once
+            let retry_request = match request.try_clone() {
+                Some(retry_request) if attempt < self.retry_policy.max_retries => retry_request,
+                _ => return Ok(request.send()?),
+            };
+
+            let response = retry_request.send()?;
+            if !is_retryable(response.status()) {
+                return Ok(response);
+            }
+
+            let retry_after = retry_after(&response);
+            let Some(backoff) = self.retry_policy.backoff(attempt, retry_after) else {
+                return Err(CloudflareError::RetryAfterTooLong {
+                    status: response.status(),
+                    retry_after: retry_after.unwrap_or_default(),
+                });
+            };
+            warn!(
+                "Cloudflare responded with status {} to {}. Retrying in {:?}",
+                response.status(),
+                response.url(),
+                backoff
+            );
+            thread::sleep(backoff);
+            attempt += 1;
+        }
     }
 }
 
+fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
+}
+
+/// Reads the delay requested by a `Retry-After` header given in seconds.
+fn retry_after(response: &Response) -> Option<Duration> {
+    response
+        .headers()
+        .get(RETRY_AFTER)?
+        .to_str()
+        .ok()?
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .map(Duration::from_secs)
+}
+
 #[derive(Clone, Debug, Default, Deserialize)]
 pub struct ZoneId(pub String);
 
 #[derive(Clone, Debug, Deserialize)]
 pub struct RecordId(pub String);
 
-#[derive(Clone, Debug, Deserialize)]
-#[allow(unused)]
-pub struct CloudflareEnvelope<T> {
-    pub result: T,
-    pub success: bool,
-    pub errors: Vec<CloudflareMessage>,
-    pub messages: Vec<String>,
-}
-
 #[derive(Clone, Debug, Deserialize)]
 #[allow(unused)]
 pub struct CloudflareV4Result<T> {
@@ -138,6 +338,17 @@ pub struct CloudflareV4ResultInfo {
     pub per_page: u32,
     pub count: u32,
     pub total_count: u32,
+    #[serde(default)]
+    pub total_pages: Option<u32>,
+}
+
+impl CloudflareV4ResultInfo {
+    pub fn has_next_page(&self) -> bool {
+        let total_pages = self
+            .total_pages
+            .unwrap_or_else(|| self.total_count.div_ceil(self.per_page.max(1)));
+        self.count > 0 && self.page < total_pages
+    }
 }
 
 pub type CloudflareListRecordsResponse = CloudflareV4Result<ResultOrObject<Vec<GetDnsRecord>>>;
@@ -148,6 +359,14 @@ pub type CloudflareCreateRecordResponse = CloudflareV4Result<ResultOrObject<GetD
 
 pub type CloudflareUpdateRecordResponse = CloudflareV4Result<Option<GetDnsRecord>>;
 
+pub type CloudflareDeleteRecordResponse = CloudflareV4Result<DeletedRecord>;
+
+#[derive(Clone, Debug, Deserialize)]
+#[allow(unused)]
+pub struct DeletedRecord {
+    pub id: RecordId,
+}
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(untagged)]
 #[allow(unused)]
@@ -201,6 +420,8 @@ pub struct GetDnsRecord {
     pub content: String,
     pub ttl: u32,
     pub proxied: bool,
+    #[serde(default)]
+    pub comment: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -215,6 +436,25 @@ pub struct CloudflareDnsRecordBody {
     pub proxied: bool,
 }
 
+impl CloudflareDnsRecordBody {
+    pub fn new(
+        name: &str,
+        record: &DnsRecord,
+        comment: Option<String>,
+        ttl: u32,
+        proxied: bool,
+    ) -> Self {
+        CloudflareDnsRecordBody {
+            type_: record.record_type().to_owned(),
+            name: name.to_owned(),
+            comment,
+            content: record.content(),
+            ttl,
+            proxied,
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod testhelpers {
     pub const ADD_RECORD_FAILURE_RESPONSE: &str = r#"{
@@ -347,22 +587,28 @@ mod tests {
     use testhelpers::ADD_RECORD_FAILURE_RESPONSE;
 
     fn test_client(server: &ServerGuard) -> CloudflareClient {
-        let mut client = CloudflareClient::new("test_api_key".to_string());
-        client.cloudflare_base_url = server.url();
-        client
+        CloudflareClient::new(
+            server.url(),
+            "test_api_key".to_string(),
+            RetryPolicy {
+                max_retries: 2,
+                initial_backoff: Duration::from_millis(1),
+                max_backoff: Duration::from_millis(10),
+            },
+        )
     }
 
     #[test]
     fn test_list_zones() {
         let mut server = Server::new();
         let _m = server
-            .mock("GET", "/client/v4/zones?name=example.com")
+            .mock("GET", "/client/v4/zones?name=example.com&page=1")
             .with_status(200)
             .with_body(testhelpers::LIST_ZONES_RESPONSE)
             .create();
 
         let client = test_client(&server);
-        let response = client.list_zones("example.com").unwrap();
+        let response = client.list_zones("example.com", 1).unwrap();
         assert!(response.result.is_some());
         assert!(response.result.unwrap().is_result());
         assert!(response.success);
@@ -372,7 +618,7 @@ mod tests {
     fn test_list_zones_404() {
         let mut server = Server::new();
         let _m = server
-            .mock("GET", "/client/v4/zones?name=example.com")
+            .mock("GET", "/client/v4/zones?name=example.com&page=1")
             .with_status(404)
             .with_body(
                 r#"{
@@ -385,7 +631,7 @@ mod tests {
             .create();
 
         let client = test_client(&server);
-        let response = client.list_zones("example.com").unwrap();
+        let response = client.list_zones("example.com", 1).unwrap();
         assert!(response.result.is_some());
         assert!(!response.result.unwrap().is_result());
         assert!(!response.success);
@@ -584,4 +830,138 @@ mod tests {
             .unwrap();
         assert!(!response.success);
     }
+
+    #[test]
+    fn test_delete_dns_record() {
+        let mut server = Server::new();
+        let _m = server
+            .mock(
+                "DELETE",
+                "/client/v4/zones/test_zone_id/dns_records/test_record_id",
+            )
+            .with_status(200)
+            .with_body(
+                r#"{
+                  "errors": [],
+                  "messages": [],
+                  "success": true,
+                  "result": { "id": "test_record_id" }
+                }"#,
+            )
+            .create();
+
+        let client = test_client(&server);
+        let response = client
+            .delete_dns_record(
+                &ZoneId("test_zone_id".to_string()),
+                &RecordId("test_record_id".to_string()),
+            )
+            .unwrap();
+        assert_eq!(response.result.unwrap().id.0, "test_record_id");
+    }
+
+    #[test]
+    fn test_rate_limited_request_is_retried() {
+        let mut server = Server::new();
+        let rate_limited = server
+            .mock("GET", "/client/v4/zones?name=example.com&page=1")
+            .with_status(429)
+            .with_header("Retry-After", "0")
+            .with_body(r#"{"success": false, "errors": [{"code": 10000, "message": "Rate limited"}], "messages": [], "result": null}"#)
+            .expect(1)
+            .create();
+        let succeeded = server
+            .mock("GET", "/client/v4/zones?name=example.com&page=1")
+            .with_status(200)
+            .with_body(testhelpers::LIST_ZONES_RESPONSE)
+            .expect(1)
+            .create();
+
+        let client = test_client(&server);
+        let response = client.list_zones("example.com", 1).unwrap();
+        assert!(response.success);
+        rate_limited.assert();
+        succeeded.assert();
+    }
+
+    #[test]
+    fn test_long_retry_after_gives_up() {
+        let mut server = Server::new();
+        let rate_limited = server
+            .mock("GET", "/client/v4/zones?name=example.com&page=1")
+            .with_status(429)
+            .with_header("Retry-After", "3600")
+            .with_body(r#"{"success": false, "errors": [{"code": 10000, "message": "Rate limited"}], "messages": [], "result": null}"#)
+            .expect(1)
+            .create();
+
+        let client = test_client(&server);
+        let error = client.list_zones("example.com", 1).unwrap_err();
+        rate_limited.assert();
+        assert!(matches!(
+            error,
+            CloudflareError::RetryAfterTooLong { status, retry_after }
+                if status == StatusCode::TOO_MANY_REQUESTS && retry_after == Duration::from_secs(3600)
+        ));
+    }
+
+    #[test]
+    fn test_server_errors_give_up_after_max_retries() {
+        let mut server = Server::new();
+        let failing = server
+            .mock("GET", "/client/v4/zones?name=example.com&page=1")
+            .with_status(502)
+            .with_body("<html>Bad gateway</html>")
+            .expect(3)
+            .create();
+
+        let client = test_client(&server);
+        let error = client.list_zones("example.com", 1).unwrap_err();
+        failing.assert();
+        assert!(matches!(
+            error,
+            CloudflareError::InvalidResponse { status, .. } if status == StatusCode::BAD_GATEWAY
+        ));
+    }
+
+    #[test]
+    fn test_unsuccessful_envelope_carries_error_codes() {
+        let response: CloudflareCreateRecordResponse =
+            serde_json::from_str(ADD_RECORD_FAILURE_RESPONSE).unwrap();
+        let error = response.into_result().unwrap_err();
+        assert_eq!(error.codes(), vec![1004]);
+        assert!(error.to_string().contains("DNS Validation Error"));
+    }
+
+    #[test]
+    fn test_retry_policy_backoff() {
+        let policy = RetryPolicy {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+        };
+        assert_eq!(policy.backoff(0, None), Some(Duration::from_millis(100)));
+        assert_eq!(policy.backoff(2, None), Some(Duration::from_millis(400)));
+        assert_eq!(policy.backoff(5, None), Some(Duration::from_secs(1)));
+        assert_eq!(
+            policy.backoff(0, Some(Duration::from_millis(700))),
+            Some(Duration::from_millis(700))
+        );
+        assert_eq!(policy.backoff(0, Some(Duration::from_secs(2))), None);
+    }
+
+    #[test]
+    fn test_result_info_pagination() {
+        let info = |page, count, total_count| CloudflareV4ResultInfo {
+            page,
+            per_page: 20,
+            count,
+            total_count,
+            total_pages: None,
+        };
+        assert!(info(1, 20, 45).has_next_page());
+        assert!(info(2, 20, 45).has_next_page());
+        assert!(!info(3, 5, 45).has_next_page());
+        assert!(!info(1, 0, 45).has_next_page());
+    }
 }
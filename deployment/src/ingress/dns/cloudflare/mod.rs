@@ -1,12 +1,16 @@
-use anyhow::anyhow;
-use client::{CloudflareDnsRecordBody, GetDnsRecord, ResultOrObject, Zone, ZoneId};
+use std::time::Duration;
+
+use client::{
+    CloudflareDnsRecordBody, CloudflareError, CloudflareV4Result, CloudflareV4ResultInfo,
+    GetDnsRecord, ResultOrObject, RetryPolicy, Zone, ZoneId,
+};
 use figment::{providers::Serialized, Figment};
 use mockall_double::double;
 use serde::{Deserialize, Serialize};
 
 mod client;
 
-use super::DnsService;
+use super::{DnsRecord, DnsService, RecordOwnership, RegisterDnsService, ZoneCache};
 use crate::config::ConfigurationError;
 
 #[double]
@@ -16,43 +20,58 @@ pub struct CloudflareDnsService {
     client: CloudflareClient,
     ttl: u32,
     proxied: bool,
+    zones: ZoneCache<Zone>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct CloudflareDnsServiceConfig {
     #[serde(skip_serializing)]
     api_key: String,
+    base_url: String,
     dns_ttl: u32,
     proxied: bool,
     enabled: bool,
+    max_retries: u32,
+    retry_backoff_milliseconds: u64,
 }
 
 impl Default for CloudflareDnsServiceConfig {
     fn default() -> Self {
         Self {
             api_key: Default::default(),
+            base_url: "https://api.cloudflare.com".to_owned(),
             dns_ttl: 300,
             proxied: false,
             enabled: false,
+            max_retries: 3,
+            retry_backoff_milliseconds: 500,
         }
     }
 }
 
-impl CloudflareDnsService {
-    pub fn configure(figment: &Figment) -> Result<Option<Self>, ConfigurationError> {
-        if figment.extract_inner::<bool>("cloudflare.enabled")? == false {
-            return Ok(None);
-        }
+impl RegisterDnsService for CloudflareDnsService {
+    fn provider_name() -> &'static str {
+        "cloudflare"
+    }
+
+    fn configure(figment: &Figment) -> Result<Self, ConfigurationError> {
         let configuration = figment.extract_inner::<CloudflareDnsServiceConfig>("cloudflare")?;
-        let client = CloudflareClient::new(configuration.api_key);
-        Ok(Some(Self {
+        let retry_policy = RetryPolicy {
+            max_retries: configuration.max_retries,
+            initial_backoff: Duration::from_millis(configuration.retry_backoff_milliseconds),
+            ..Default::default()
+        };
+        let client =
+            CloudflareClient::new(configuration.base_url, configuration.api_key, retry_policy);
+        Ok(Self {
             client,
             ttl: configuration.dns_ttl,
             proxied: configuration.proxied,
-        }))
+            zones: ZoneCache::new(),
+        })
     }
 
-    pub fn figment_default_values() -> Figment {
+    fn figment_default_values() -> Figment {
         Figment::from(Serialized::default(
             "cloudflare",
             CloudflareDnsServiceConfig::default(),
@@ -60,162 +79,215 @@ impl CloudflareDnsService {
     }
 }
 
-fn base_domain<'a>(domain_name: &'a str) -> &'a str {
-    let num_parts = domain_name.split(".").count();
-    if num_parts <= 2 {
-        return domain_name;
-    }
-    let len: usize = domain_name
-        .split(".")
-        .take(num_parts - 2)
-        .map(|l| l.len())
-        .sum::<usize>()
-        + num_parts
-        - 2;
-    &domain_name[len..]
-}
-
-#[test]
-fn test_base_domain() {
-    assert_eq!(base_domain("example.com"), "example.com");
-    assert_eq!(base_domain("hello.example.com"), "example.com");
-    assert_eq!(base_domain("com"), "com");
+type Page<T> = (Vec<T>, Option<CloudflareV4ResultInfo>);
+
+/// Unpacks a list response, failing with the API errors if the call was unsuccessful.
+fn list_result<T>(
+    response: CloudflareV4Result<ResultOrObject<Vec<T>>>,
+) -> Result<Page<T>, CloudflareError> {
+    let result_info = response.result_info.clone();
+    match response.into_result()? {
+        Some(ResultOrObject::Result(items)) => Ok((items, result_info)),
+        other => Err(CloudflareError::UnexpectedResult(format!(
+            "Expected a list but got {:?}",
+            other.map(|o| o.is_result())
+        ))),
+    }
 }
 
 impl CloudflareDnsService {
     fn get_zone(&self, domain_name: &str) -> anyhow::Result<Zone> {
-        let zones = self.client.list_zones(base_domain(domain_name))?;
-        if !zones.success {
-            return Err(anyhow!(
-                "Failed to list zones with following response {:?}",
-                zones
-            ));
-        }
-
-        if !matches!(zones.result, Some(ResultOrObject::Result(_))) {
-            return Err(anyhow!(
-                "List zones returned returned success but invalid result {:?}",
-                zones
-            ));
-        }
-
-        let mut zones = zones.result.unwrap().unwrap();
-        if zones.is_empty() {
-            return Err(anyhow!(
-                "No zones listed for name {} - {:?}",
-                domain_name,
-                zones
-            ));
-        }
+        self.zones
+            .find_zone(domain_name, |name| Ok(self.find_zone_by_name(name)?))
+    }
 
+    fn find_zone_by_name(&self, name: &str) -> Result<Option<Zone>, CloudflareError> {
+        // Zone names are unique within an account, so the first page holds every match
+        let (mut zones, _) = list_result(self.client.list_zones(name, 1)?)?;
         if zones.len() > 1 {
-            return Err(anyhow!("Too many zones for domain {:?}", zones));
+            return Err(CloudflareError::UnexpectedResult(format!(
+                "Too many zones for domain {:?}",
+                zones
+            )));
         }
 
-        Ok(zones.pop().unwrap())
+        Ok(zones.pop())
     }
 
     fn get_existing_records(
         &self,
         zone_id: &ZoneId,
         domain_name: &str,
-    ) -> anyhow::Result<Vec<GetDnsRecord>> {
-        let records = self.client.list_dns_records(zone_id, domain_name, 1)?;
-        if !records.success {
-            return Err(anyhow!(
-                "Could not fetch dns records with following body {:?}",
-                records
-            ));
+    ) -> Result<Vec<GetDnsRecord>, CloudflareError> {
+        let mut records = vec![];
+        let mut page = 1;
+        loop {
+            let (page_records, result_info) =
+                list_result(self.client.list_dns_records(zone_id, domain_name, page)?)?;
+            let page_was_empty = page_records.is_empty();
+            records.extend(page_records);
+            match result_info {
+                Some(result_info) if result_info.has_next_page() && !page_was_empty => {
+                    page = result_info.page + 1
+                }
+                _ => return Ok(records),
+            }
         }
-
-        if !matches!(records.result, Some(ResultOrObject::Result(_))) {
-            return Err(anyhow!(
-                "List records api call succeeded but gave invalid response {:?}",
-                records
-            ));
-        }
-        let records = records.result.unwrap().unwrap();
-
-        Ok(records)
     }
 
     fn create_dns_record(
         &self,
         zone_id: &ZoneId,
         domain_name: &str,
-        ip_address: std::net::IpAddr,
-    ) -> anyhow::Result<()> {
-        let request = CloudflareDnsRecordBody {
-            type_: type_string(ip_address).to_owned(),
-            name: domain_name.to_owned(),
-            comment: Some("Record created by pond".to_string()),
-            content: ip_address.to_string(),
-            ttl: self.ttl,
-            proxied: self.proxied,
-        };
+        record: &DnsRecord,
+        ownership: &RecordOwnership,
+    ) -> Result<(), CloudflareError> {
+        let request = CloudflareDnsRecordBody::new(
+            domain_name,
+            record,
+            Some(ownership.marker()),
+            self.ttl,
+            self.proxied,
+        );
 
-        let response = self.client.add_dns_record(zone_id, &request)?;
-        if !response.success {
-            Err(anyhow!("Failed to create dns record for zone {}, domain {} and ip {:?} with the following response {:?}", zone_id.0, domain_name, ip_address, response))
-        } else {
-            Ok(())
-        }
+        self.client
+            .add_dns_record(zone_id, &request)?
+            .into_result()
+            .inspect_err(|e| {
+                error!(
+                    "Failed to create dns record for zone {}, domain {} and record {}: {}",
+                    zone_id.0, domain_name, record, e
+                )
+            })?;
+        Ok(())
     }
 
     fn update_dns_record(
         &self,
         zone_id: &ZoneId,
-        record: GetDnsRecord,
-        ip_address: std::net::IpAddr,
-    ) -> anyhow::Result<()> {
-        let request = CloudflareDnsRecordBody {
-            type_: record.type_,
-            name: record.name,
-            comment: Some("Record updated by pond".to_string()),
-            content: ip_address.to_string(),
-            ttl: self.ttl,
-            proxied: self.proxied,
-        };
+        existing_record: GetDnsRecord,
+        record: &DnsRecord,
+        ownership: &RecordOwnership,
+    ) -> Result<(), CloudflareError> {
+        let request = CloudflareDnsRecordBody::new(
+            &existing_record.name,
+            record,
+            Some(ownership.marker()),
+            self.ttl,
+            self.proxied,
+        );
 
-        let response = self
-            .client
-            .update_dns_record(zone_id, &record.id, &request)?;
-        if !response.success {
-            Err(anyhow!("Failed to create dns record for zone {}, domain {} and ip {:?} with the following response {:?}", zone_id.0, &request.name, ip_address, response))
-        } else {
-            Ok(())
-        }
+        self.client
+            .update_dns_record(zone_id, &existing_record.id, &request)?
+            .into_result()
+            .inspect_err(|e| {
+                error!(
+                    "Failed to update dns record for zone {}, domain {} and record {}: {}",
+                    zone_id.0, &request.name, record, e
+                )
+            })?;
+        Ok(())
     }
-}
 
-fn type_string(ip_address: std::net::IpAddr) -> &'static str {
-    if ip_address.is_ipv4() {
-        "A"
-    } else {
-        "AAAA"
+    fn delete_dns_record(
+        &self,
+        zone_id: &ZoneId,
+        record: &GetDnsRecord,
+    ) -> Result<(), CloudflareError> {
+        self.client
+            .delete_dns_record(zone_id, &record.id)?
+            .into_result()
+            .inspect_err(|e| {
+                error!(
+                    "Failed to delete dns record {} of type {} for {}: {}",
+                    record.id.0, record.type_, record.name, e
+                )
+            })?;
+        Ok(())
     }
 }
 
 impl DnsService for CloudflareDnsService {
+    /// Points `domain_name` at `record`.
+    ///
+    /// If several records of that type exist, the first one is updated and the others are
+    /// deleted so the domain resolves to exactly one address afterwards. Ownership is stored
+    /// in the record comment and checked for every record before any of them is touched.
+    /// Cloudflare flattens `CNAME` records at the zone apex, so custom apex domains can alias
+    /// the default host of a deployment as well.
     fn set_dns_record(
         &self,
         domain_name: &str,
-        ip_address: std::net::IpAddr,
+        record: &DnsRecord,
+        ownership: &RecordOwnership,
     ) -> anyhow::Result<()> {
         let zone = self.get_zone(domain_name)?;
         let records = self.get_existing_records(&zone.id, domain_name)?;
-        let type_string = type_string(ip_address);
-        let mut relevant_records: Vec<GetDnsRecord> = records
-            .into_iter()
-            .filter(|r| r.type_ == type_string)
-            .collect();
+        let (relevant_records, conflicting_records): (Vec<GetDnsRecord>, Vec<GetDnsRecord>) =
+            records
+                .into_iter()
+                .filter(|r| record.occupies(&r.type_))
+                .partition(|r| r.type_ == record.record_type());
+
+        for existing in relevant_records.iter().chain(&conflicting_records) {
+            ownership.verify(domain_name, existing.comment.as_deref())?;
+        }
 
-        if relevant_records.is_empty() {
-            self.create_dns_record(&zone.id, domain_name, ip_address)?;
-        } else if relevant_records.len() == 1 {
-            self.update_dns_record(&zone.id, relevant_records.pop().unwrap(), ip_address)?;
+        for conflicting in &conflicting_records {
+            info!(
+                "Deleting conflicting {} record {} for {}",
+                conflicting.type_, conflicting.id.0, conflicting.name
+            );
+            self.delete_dns_record(&zone.id, conflicting)?;
         }
 
+        let mut relevant_records = relevant_records.into_iter();
+        match relevant_records.next() {
+            None => self.create_dns_record(&zone.id, domain_name, record, ownership)?,
+            Some(existing) => self.update_dns_record(&zone.id, existing, record, ownership)?,
+        }
+
+        for duplicate in relevant_records {
+            info!(
+                "Deleting duplicate {} record {} for {}",
+                duplicate.type_, duplicate.id.0, duplicate.name
+            );
+            self.delete_dns_record(&zone.id, &duplicate)?;
+        }
+
+        Ok(())
+    }
+
+    fn get_dns_records(&self, domain_name: &str) -> anyhow::Result<Vec<DnsRecord>> {
+        let zone = self.get_zone(domain_name)?;
+        let records = self.get_existing_records(&zone.id, domain_name)?;
+        Ok(records
+            .iter()
+            .filter_map(|r| DnsRecord::parse(&r.type_, &r.content))
+            .collect())
+    }
+
+    fn delete_dns_records(
+        &self,
+        domain_name: &str,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        let zone = self.get_zone(domain_name)?;
+        let records: Vec<GetDnsRecord> = self
+            .get_existing_records(&zone.id, domain_name)?
+            .into_iter()
+            .filter(|r| DnsRecord::parse(&r.type_, &r.content).is_some())
+            .collect();
+        for existing in &records {
+            ownership.verify(domain_name, existing.comment.as_deref())?;
+        }
+        for existing in &records {
+            info!(
+                "Deleting {} record {} for {}",
+                existing.type_, existing.id.0, existing.name
+            );
+            self.delete_dns_record(&zone.id, existing)?;
+        }
         Ok(())
     }
 }
@@ -237,7 +309,7 @@ mod tests {
         let mut mock = CloudflareClient::default();
 
         mock.expect_list_zones()
-            .returning(|_| Ok(list_zones_response()));
+            .returning(|_, _| Ok(list_zones_response()));
 
         mock.expect_list_dns_records().returning(|_, _, _| {
             let mut records: CloudflareListRecordsResponse =
@@ -254,23 +326,19 @@ mod tests {
             client: mock,
             ttl: 1,
             proxied: false,
+            zones: ZoneCache::new(),
         };
 
         service
-            .set_dns_record("example.com", IpAddr::from_str("127.0.0.1").unwrap())
+            .set_dns_record("example.com", &address("127.0.0.1"), &ownership())
             .unwrap();
     }
 
-    #[test]
-    fn test_base_domain_with_sub_sub_domain() {
-        assert_eq!(base_domain("sub.sub.example.com"), "example.com");
-    }
-
     #[test]
     fn test_get_zone_with_multiple_zones() {
         let mut mock = CloudflareClient::default();
 
-        mock.expect_list_zones().returning(|_| {
+        mock.expect_list_zones().returning(|_, _| {
             let mut response = list_zones_response();
 
             response.result = Some(ResultOrObject::Result(vec![
@@ -293,17 +361,79 @@ mod tests {
             client: mock,
             ttl: 1,
             proxied: false,
+            zones: ZoneCache::new(),
         };
 
         let result = service.get_zone("example.com");
         assert!(result.is_err());
     }
 
+    #[test]
+    fn test_get_zone_walks_up_to_registrable_domain() {
+        let mut mock = CloudflareClient::default();
+
+        mock.expect_list_zones().times(2).returning(|name, _| {
+            let mut response = list_zones_response();
+            let zones = match name {
+                "example.co.uk" => vec![Zone {
+                    id: ZoneId("1".to_string()),
+                    name: "example.co.uk".to_string(),
+                    ..Default::default()
+                }],
+                _ => vec![],
+            };
+            response.result = Some(ResultOrObject::Result(zones));
+            Ok(response)
+        });
+
+        let service = CloudflareDnsService {
+            client: mock,
+            ttl: 1,
+            proxied: false,
+            zones: ZoneCache::new(),
+        };
+
+        assert_eq!(service.get_zone("www.example.co.uk").unwrap().id.0, "1");
+        // The second lookup is answered from the cache
+        assert_eq!(service.get_zone("www.example.co.uk").unwrap().id.0, "1");
+    }
+
+    #[test]
+    fn test_get_zone_prefers_delegated_subzone() {
+        let mut mock = CloudflareClient::default();
+
+        mock.expect_list_zones().returning(|name, _| {
+            let mut response = list_zones_response();
+            let zones = match name {
+                "dev.example.com" | "example.com" => vec![Zone {
+                    id: ZoneId(name.to_string()),
+                    name: name.to_string(),
+                    ..Default::default()
+                }],
+                _ => vec![],
+            };
+            response.result = Some(ResultOrObject::Result(zones));
+            Ok(response)
+        });
+
+        let service = CloudflareDnsService {
+            client: mock,
+            ttl: 1,
+            proxied: false,
+            zones: ZoneCache::new(),
+        };
+
+        let zone = service.get_zone("app.dev.example.com").unwrap();
+        assert_eq!(zone.name, "dev.example.com");
+        let zone = service.get_zone("www.example.com").unwrap();
+        assert_eq!(zone.name, "example.com");
+    }
+
     #[test]
     fn test_get_zone_with_no_zones() {
         let mut mock = CloudflareClient::default();
 
-        mock.expect_list_zones().returning(|_| {
+        mock.expect_list_zones().returning(|_, _| {
             let mut response = list_zones_response();
             response.result = Some(ResultOrObject::Result(vec![]));
             Ok(response)
@@ -313,6 +443,7 @@ mod tests {
             client: mock,
             ttl: 1,
             proxied: false,
+            zones: ZoneCache::new(),
         };
 
         let result = service.get_zone("example.com");
@@ -334,6 +465,7 @@ mod tests {
             client: mock,
             ttl: 1,
             proxied: false,
+            zones: ZoneCache::new(),
         };
 
         let result = service.get_existing_records(&ZoneId("zone_id".to_string()), "example.com");
@@ -353,12 +485,14 @@ mod tests {
             client: mock,
             ttl: 1,
             proxied: false,
+            zones: ZoneCache::new(),
         };
 
         let result = service.create_dns_record(
             &ZoneId("zone_id".to_string()),
             "example.com",
-            IpAddr::from_str("127.0.0.1").unwrap(),
+            &address("127.0.0.1"),
+            &ownership(),
         );
         assert!(result.is_err());
     }
@@ -375,6 +509,7 @@ mod tests {
             client: mock,
             ttl: 1,
             proxied: false,
+            zones: ZoneCache::new(),
         };
 
         let record = GetDnsRecord {
@@ -384,13 +519,334 @@ mod tests {
             content: "127.0.0.1".to_string(),
             ttl: 1,
             proxied: true,
+            comment: None,
         };
 
         let result = service.update_dns_record(
             &ZoneId("zone_id".to_string()),
             record,
-            IpAddr::from_str("127.0.0.1").unwrap(),
+            &address("127.0.0.1"),
+            &ownership(),
         );
         assert!(result.is_err());
     }
+
+    fn record(id: &str, type_: &str) -> GetDnsRecord {
+        GetDnsRecord {
+            id: RecordId(id.to_string()),
+            type_: type_.to_string(),
+            name: "example.com".to_string(),
+            content: "198.51.100.4".to_string(),
+            ttl: 1,
+            proxied: false,
+            comment: Some(ownership().marker()),
+        }
+    }
+
+    fn ownership() -> RecordOwnership {
+        RecordOwnership::new("pond", "example")
+    }
+
+    fn address(ip_address: &str) -> DnsRecord {
+        DnsRecord::Address(IpAddr::from_str(ip_address).unwrap())
+    }
+
+    fn records_page(
+        records: Vec<GetDnsRecord>,
+        page: u32,
+        per_page: u32,
+        total_count: u32,
+    ) -> CloudflareListRecordsResponse {
+        let mut response: CloudflareListRecordsResponse =
+            serde_json::from_str(client::testhelpers::LIST_RECORDS_RESPONSE).unwrap();
+        response.result_info = Some(CloudflareV4ResultInfo {
+            page,
+            per_page,
+            count: records.len() as u32,
+            total_count,
+            total_pages: None,
+        });
+        response.result = Some(ResultOrObject::Result(records));
+        response
+    }
+
+    #[test]
+    fn test_get_existing_records_follows_pagination() {
+        let mut mock = CloudflareClient::default();
+
+        mock.expect_list_dns_records()
+            .times(3)
+            .returning(|_, _, page| {
+                let records = match page {
+                    1 => vec![record("1", "A"), record("2", "TXT")],
+                    2 => vec![record("3", "AAAA"), record("4", "MX")],
+                    3 => vec![record("5", "A")],
+                    _ => panic!("Requested page {} past the end", page),
+                };
+                Ok(records_page(records, page, 2, 5))
+            });
+
+        let service = CloudflareDnsService {
+            client: mock,
+            ttl: 1,
+            proxied: false,
+            zones: ZoneCache::new(),
+        };
+
+        let records = service
+            .get_existing_records(&ZoneId("zone_id".to_string()), "example.com")
+            .unwrap();
+        let ids: Vec<_> = records.iter().map(|r| r.id.0.as_str()).collect();
+        assert_eq!(ids, vec!["1", "2", "3", "4", "5"]);
+    }
+
+    #[test]
+    fn test_duplicate_records_are_updated_and_deleted() {
+        let mut mock = CloudflareClient::default();
+
+        mock.expect_list_zones()
+            .returning(|_, _| Ok(list_zones_response()));
+        mock.expect_list_dns_records().returning(|_, _, page| {
+            Ok(records_page(
+                vec![record("1", "A"), record("2", "AAAA"), record("3", "A")],
+                page,
+                20,
+                3,
+            ))
+        });
+        mock.expect_update_dns_record()
+            .times(1)
+            .returning(|_, record_id, _| {
+                assert_eq!(record_id.0, "1");
+                Ok(serde_json::from_str(client::testhelpers::ADD_RECORD_RESPONSE).unwrap())
+            });
+        mock.expect_delete_dns_record()
+            .times(1)
+            .returning(|_, record_id| {
+                assert_eq!(record_id.0, "3");
+                Ok(serde_json::from_str(
+                    r#"{"errors": [], "messages": [], "success": true, "result": {"id": "3"}}"#,
+                )
+                .unwrap())
+            });
+
+        let service = CloudflareDnsService {
+            client: mock,
+            ttl: 1,
+            proxied: false,
+            zones: ZoneCache::new(),
+        };
+
+        service
+            .set_dns_record("example.com", &address("127.0.0.1"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_api_errors_are_typed() {
+        let mut mock = CloudflareClient::default();
+
+        mock.expect_list_zones().returning(|_, _| {
+            Ok(serde_json::from_str(client::testhelpers::ADD_RECORD_FAILURE_RESPONSE).unwrap())
+        });
+
+        let service = CloudflareDnsService {
+            client: mock,
+            ttl: 1,
+            proxied: false,
+            zones: ZoneCache::new(),
+        };
+
+        let error = service.get_zone("example.com").unwrap_err();
+        let error = error.downcast_ref::<CloudflareError>().unwrap();
+        assert_eq!(error.codes(), vec![1004]);
+    }
+
+    fn service_with_existing_record(comment: Option<&'static str>) -> CloudflareDnsService {
+        let mut mock = CloudflareClient::default();
+
+        mock.expect_list_zones()
+            .returning(|_, _| Ok(list_zones_response()));
+        mock.expect_list_dns_records().returning(move |_, _, page| {
+            let mut existing = record("1", "A");
+            existing.comment = comment.map(|c| c.to_string());
+            Ok(records_page(vec![existing], page, 20, 1))
+        });
+        mock.expect_update_dns_record().returning(|_, _, request| {
+            assert_eq!(
+                request.comment.as_deref(),
+                Some("pond-owner instance=pond deployment=example")
+            );
+            Ok(serde_json::from_str(client::testhelpers::ADD_RECORD_RESPONSE).unwrap())
+        });
+
+        CloudflareDnsService {
+            client: mock,
+            ttl: 1,
+            proxied: false,
+            zones: ZoneCache::new(),
+        }
+    }
+
+    #[test]
+    fn test_records_created_by_hand_are_not_modified() {
+        let service = service_with_existing_record(Some("Our mail server"));
+        let result = service.set_dns_record("example.com", &address("127.0.0.1"), &ownership());
+        assert!(result.is_err());
+
+        let service = service_with_existing_record(None);
+        let result = service.set_dns_record("example.com", &address("127.0.0.1"), &ownership());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_takeover_claims_records_created_by_hand() {
+        let service = service_with_existing_record(Some("Our mail server"));
+        let ownership = RecordOwnership {
+            takeover: true,
+            ..ownership()
+        };
+        service
+            .set_dns_record("example.com", &address("127.0.0.1"), &ownership)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_legacy_pond_records_of_recorded_domains_are_adopted() {
+        let service = service_with_existing_record(Some("Record created by pond"));
+        let result = service.set_dns_record("example.com", &address("127.0.0.1"), &ownership());
+        assert!(result.is_err());
+
+        let ownership = RecordOwnership {
+            adopted_domain_names: vec!["example.com".to_owned()],
+            ..ownership()
+        };
+        service
+            .set_dns_record("example.com", &address("127.0.0.1"), &ownership)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_get_dns_records_returns_addresses() {
+        let mut mock = CloudflareClient::default();
+
+        mock.expect_list_zones()
+            .returning(|_, _| Ok(list_zones_response()));
+        mock.expect_list_dns_records().returning(|_, _, page| {
+            let mut ipv6 = record("2", "AAAA");
+            ipv6.content = "::1".to_string();
+            let mut cname = record("3", "CNAME");
+            cname.content = "blog.example.com".to_string();
+            Ok(records_page(
+                vec![record("1", "A"), ipv6, cname, record("4", "TXT")],
+                page,
+                20,
+                4,
+            ))
+        });
+
+        let service = CloudflareDnsService {
+            client: mock,
+            ttl: 1,
+            proxied: false,
+            zones: ZoneCache::new(),
+        };
+
+        assert_eq!(
+            service.get_dns_records("example.com").unwrap(),
+            vec![
+                address("198.51.100.4"),
+                address("::1"),
+                DnsRecord::cname("blog.example.com")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apex_cname_replaces_address_records() {
+        let mut mock = CloudflareClient::default();
+
+        mock.expect_list_zones()
+            .returning(|_, _| Ok(list_zones_response()));
+        mock.expect_list_dns_records().returning(|_, _, page| {
+            Ok(records_page(
+                vec![record("1", "A"), record("2", "AAAA"), record("3", "MX")],
+                page,
+                20,
+                3,
+            ))
+        });
+        mock.expect_delete_dns_record()
+            .times(2)
+            .returning(|_, record_id| {
+                assert!(record_id.0 == "1" || record_id.0 == "2");
+                Ok(serde_json::from_str(
+                    r#"{"errors": [], "messages": [], "success": true, "result": {"id": "1"}}"#,
+                )
+                .unwrap())
+            });
+        mock.expect_add_dns_record()
+            .times(1)
+            .returning(|_, request| {
+                assert_eq!(request.type_, "CNAME");
+                assert_eq!(request.name, "example.com");
+                assert_eq!(request.content, "blog.example.org");
+                Ok(serde_json::from_str(client::testhelpers::ADD_RECORD_RESPONSE).unwrap())
+            });
+
+        let service = CloudflareDnsService {
+            client: mock,
+            ttl: 1,
+            proxied: false,
+            zones: ZoneCache::new(),
+        };
+
+        service
+            .set_dns_record(
+                "example.com",
+                &DnsRecord::cname("blog.example.org"),
+                &ownership(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_delete_dns_records_removes_owned_records() {
+        let mut mock = CloudflareClient::default();
+
+        mock.expect_list_zones()
+            .returning(|_, _| Ok(list_zones_response()));
+        mock.expect_list_dns_records().returning(|_, _, page| {
+            Ok(records_page(
+                vec![record("1", "A"), record("2", "MX"), record("3", "AAAA")],
+                page,
+                20,
+                3,
+            ))
+        });
+        mock.expect_delete_dns_record()
+            .times(2)
+            .withf(|_, record_id| record_id.0 == "1" || record_id.0 == "3")
+            .returning(|_, _| {
+                Ok(serde_json::from_str(
+                    r#"{"errors": [], "messages": [], "success": true, "result": {"id": "1"}}"#,
+                )
+                .unwrap())
+            });
+
+        let service = CloudflareDnsService {
+            client: mock,
+            ttl: 1,
+            proxied: false,
+            zones: ZoneCache::new(),
+        };
+        service
+            .delete_dns_records("example.com", &ownership())
+            .unwrap();
+
+        let service = service_with_existing_record(Some("Our mail server"));
+        assert!(service
+            .delete_dns_records("example.com", &ownership())
+            .is_err());
+    }
 }
@@ -0,0 +1,302 @@
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::ingress::dns::http::{json_response, success_response};
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+
+const PROVIDER: &str = "DigitalOcean";
+
+pub struct DigitalOceanClient {
+    base_url: String,
+    api_client: reqwest::blocking::Client,
+}
+
+#[allow(unused)]
+#[cfg_attr(test, automock)]
+impl DigitalOceanClient {
+    pub fn new(base_url: String, api_token: String) -> Self {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert(
+            "Authorization",
+            HeaderValue::from_str(&format!("Bearer {}", api_token)).unwrap(),
+        );
+
+        let api_client = reqwest::blocking::Client::builder()
+            .default_headers(default_headers)
+            .build()
+            .unwrap();
+
+        DigitalOceanClient {
+            base_url,
+            api_client,
+        }
+    }
+
+    pub fn get_domain(&self, name: &str) -> anyhow::Result<Option<DigitalOceanDomain>> {
+        let url = format!("{}/domains/{}", self.base_url, name);
+        let response = self.api_client.get(&url).send()?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response: DigitalOceanDomainResponse = json_response(PROVIDER, response)?;
+        Ok(Some(response.domain))
+    }
+
+    /// Lists the records of `type_` for the fully qualified `name` inside `domain`.
+    pub fn list_records(
+        &self,
+        domain: &str,
+        name: &str,
+        type_: &str,
+    ) -> anyhow::Result<Vec<DigitalOceanRecord>> {
+        let url = format!("{}/domains/{}/records", self.base_url, domain);
+        let response = self
+            .api_client
+            .get(&url)
+            .query(&[("name", name), ("type", type_), ("per_page", "200")])
+            .send()?;
+        let response: DigitalOceanRecordsResponse = json_response(PROVIDER, response)?;
+        Ok(response.domain_records)
+    }
+
+    pub fn create_record(
+        &self,
+        domain: &str,
+        request: &DigitalOceanRecordBody,
+    ) -> anyhow::Result<DigitalOceanRecord> {
+        let url = format!("{}/domains/{}/records", self.base_url, domain);
+        let response = self.api_client.post(&url).json(request).send()?;
+        let response: DigitalOceanRecordResponse = json_response(PROVIDER, response)?;
+        Ok(response.domain_record)
+    }
+
+    pub fn update_record(
+        &self,
+        domain: &str,
+        record_id: u64,
+        request: &DigitalOceanRecordBody,
+    ) -> anyhow::Result<DigitalOceanRecord> {
+        let url = format!("{}/domains/{}/records/{}", self.base_url, domain, record_id);
+        let response = self.api_client.put(&url).json(request).send()?;
+        let response: DigitalOceanRecordResponse = json_response(PROVIDER, response)?;
+        Ok(response.domain_record)
+    }
+
+    pub fn delete_record(&self, domain: &str, record_id: u64) -> anyhow::Result<()> {
+        let url = format!("{}/domains/{}/records/{}", self.base_url, domain, record_id);
+        let response = self.api_client.delete(&url).send()?;
+        success_response(PROVIDER, response)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[allow(unused)]
+pub struct DigitalOceanDomain {
+    pub name: String,
+    #[serde(default)]
+    pub ttl: Option<u32>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[allow(unused)]
+pub struct DigitalOceanRecord {
+    pub id: u64,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub name: String,
+    pub data: String,
+    #[serde(default)]
+    pub ttl: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DigitalOceanRecordBody {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub name: String,
+    pub data: String,
+    pub ttl: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct DigitalOceanDomainResponse {
+    domain: DigitalOceanDomain,
+}
+
+#[derive(Debug, Deserialize)]
+struct DigitalOceanRecordsResponse {
+    domain_records: Vec<DigitalOceanRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DigitalOceanRecordResponse {
+    domain_record: DigitalOceanRecord,
+}
+
+#[cfg(test)]
+pub mod testhelpers {
+    pub const GET_DOMAIN_RESPONSE: &str = r#"{
+      "domain": {
+        "name": "example.com",
+        "ttl": 1800,
+        "zone_file": "$ORIGIN example.com.\n$TTL 1800\n"
+      }
+    }"#;
+
+    pub const LIST_RECORDS_RESPONSE: &str = r#"{
+      "domain_records": [
+        {
+          "id": 28448433,
+          "type": "A",
+          "name": "www",
+          "data": "198.51.100.4",
+          "priority": null,
+          "port": null,
+          "ttl": 1800,
+          "weight": null,
+          "flags": null,
+          "tag": null
+        }
+      ],
+      "links": {},
+      "meta": { "total": 1 }
+    }"#;
+
+    pub const RECORD_RESPONSE: &str = r#"{
+      "domain_record": {
+        "id": 28448433,
+        "type": "A",
+        "name": "www",
+        "data": "198.51.100.4",
+        "priority": null,
+        "port": null,
+        "ttl": 1800,
+        "weight": null,
+        "flags": null,
+        "tag": null
+      }
+    }"#;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server, ServerGuard};
+
+    fn test_client(server: &ServerGuard) -> DigitalOceanClient {
+        DigitalOceanClient::new(server.url(), "test_api_token".to_string())
+    }
+
+    fn test_record_body() -> DigitalOceanRecordBody {
+        DigitalOceanRecordBody {
+            type_: "A".to_string(),
+            name: "www".to_string(),
+            data: "198.51.100.4".to_string(),
+            ttl: 1800,
+        }
+    }
+
+    #[test]
+    fn test_get_domain() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/domains/example.com")
+            .match_header("Authorization", "Bearer test_api_token")
+            .with_status(200)
+            .with_body(testhelpers::GET_DOMAIN_RESPONSE)
+            .create();
+
+        let domain = test_client(&server).get_domain("example.com").unwrap();
+        assert_eq!(domain.unwrap().name, "example.com");
+    }
+
+    #[test]
+    fn test_get_unknown_domain() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/domains/example.org")
+            .with_status(404)
+            .with_body(r#"{"id": "not_found", "message": "The resource you were accessing could not be found."}"#)
+            .create();
+
+        let domain = test_client(&server).get_domain("example.org").unwrap();
+        assert!(domain.is_none());
+    }
+
+    #[test]
+    fn test_list_records() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/domains/example.com/records")
+            .match_query(Matcher::AllOf(vec![
+                Matcher::UrlEncoded("name".into(), "www.example.com".into()),
+                Matcher::UrlEncoded("type".into(), "A".into()),
+            ]))
+            .with_status(200)
+            .with_body(testhelpers::LIST_RECORDS_RESPONSE)
+            .create();
+
+        let records = test_client(&server)
+            .list_records("example.com", "www.example.com", "A")
+            .unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].id, 28448433);
+    }
+
+    #[test]
+    fn test_create_record() {
+        let request = test_record_body();
+        let mut server = Server::new();
+        let _m = server
+            .mock("POST", "/domains/example.com/records")
+            .match_body(Matcher::JsonString(
+                serde_json::to_string(&request).unwrap(),
+            ))
+            .with_status(201)
+            .with_body(testhelpers::RECORD_RESPONSE)
+            .create();
+
+        let record = test_client(&server)
+            .create_record("example.com", &request)
+            .unwrap();
+        assert_eq!(record.data, "198.51.100.4");
+    }
+
+    #[test]
+    fn test_update_record_error() {
+        let request = test_record_body();
+        let mut server = Server::new();
+        let _m = server
+            .mock("PUT", "/domains/example.com/records/28448433")
+            .with_status(401)
+            .with_body(r#"{"id": "unauthorized", "message": "Unable to authenticate you."}"#)
+            .create();
+
+        let result = test_client(&server).update_record("example.com", 28448433, &request);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Unable to authenticate you."));
+    }
+
+    #[test]
+    fn test_delete_record() {
+        let mut server = Server::new();
+        let m = server
+            .mock("DELETE", "/domains/example.com/records/28448433")
+            .match_header("Authorization", "Bearer test_api_token")
+            .with_status(204)
+            .create();
+
+        test_client(&server)
+            .delete_record("example.com", 28448433)
+            .unwrap();
+        m.assert();
+    }
+}
@@ -0,0 +1,512 @@
+use anyhow::anyhow;
+use client::{DigitalOceanDomain, DigitalOceanRecord, DigitalOceanRecordBody};
+use figment::{providers::Serialized, Figment};
+use mockall_double::double;
+use serde::{Deserialize, Serialize};
+
+mod client;
+
+use super::{
+    ownership_record_name, relative_name, DnsRecord, DnsService, RecordOwnership,
+    RegisterDnsService, ZoneCache,
+};
+use crate::config::ConfigurationError;
+
+#[double]
+use client::DigitalOceanClient;
+
+pub struct DigitalOceanDnsService {
+    client: DigitalOceanClient,
+    ttl: u32,
+    domains: ZoneCache<DigitalOceanDomain>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DigitalOceanDnsServiceConfig {
+    #[serde(skip_serializing)]
+    api_token: String,
+    base_url: String,
+    dns_ttl: u32,
+}
+
+impl Default for DigitalOceanDnsServiceConfig {
+    fn default() -> Self {
+        Self {
+            api_token: Default::default(),
+            base_url: "https://api.digitalocean.com/v2".to_owned(),
+            dns_ttl: 300,
+        }
+    }
+}
+
+impl RegisterDnsService for DigitalOceanDnsService {
+    fn provider_name() -> &'static str {
+        "digitalocean"
+    }
+
+    fn configure(figment: &Figment) -> Result<Self, ConfigurationError> {
+        let configuration =
+            figment.extract_inner::<DigitalOceanDnsServiceConfig>("digitalocean")?;
+        Ok(Self {
+            client: DigitalOceanClient::new(configuration.base_url, configuration.api_token),
+            ttl: configuration.dns_ttl,
+            domains: ZoneCache::new(),
+        })
+    }
+
+    fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(
+            "digitalocean",
+            DigitalOceanDnsServiceConfig::default(),
+        ))
+    }
+}
+
+impl DigitalOceanDnsService {
+    fn get_domain(&self, domain_name: &str) -> anyhow::Result<DigitalOceanDomain> {
+        self.domains
+            .find_zone(domain_name, |name| self.client.get_domain(name))
+    }
+}
+
+impl DigitalOceanDnsService {
+    fn upsert_record(
+        &self,
+        domain: &str,
+        existing_records: &[DigitalOceanRecord],
+        request: DigitalOceanRecordBody,
+    ) -> anyhow::Result<()> {
+        let Some((record, duplicates)) = existing_records.split_first() else {
+            self.client.create_record(domain, &request)?;
+            return Ok(());
+        };
+        // CNAME targets are returned without the trailing dot they are written with
+        if record.data.trim_end_matches('.') != request.data.trim_end_matches('.') {
+            self.client.update_record(domain, record.id, &request)?;
+        }
+        for duplicate in duplicates {
+            info!(
+                "Deleting duplicate {} record {} for {}",
+                duplicate.type_, duplicate.id, duplicate.name
+            );
+            self.client.delete_record(domain, duplicate.id)?;
+        }
+        Ok(())
+    }
+}
+
+/// DigitalOcean expects fully qualified `CNAME` targets terminated by a dot.
+fn record_data(record: &DnsRecord) -> String {
+    match record {
+        DnsRecord::Address(_) => record.content(),
+        DnsRecord::Cname(target) => format!("{}.", target),
+    }
+}
+
+const RECORD_TYPES: [&str; 3] = ["A", "AAAA", "CNAME"];
+
+impl DnsService for DigitalOceanDnsService {
+    /// DigitalOcean records can't carry comments, so ownership lives in a `_pond-owner` TXT
+    /// record next to the address records.
+    fn set_dns_record(
+        &self,
+        domain_name: &str,
+        record: &DnsRecord,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        let domain = self.get_domain(domain_name)?;
+        let name = relative_name(domain_name, &domain.name);
+        if matches!(record, DnsRecord::Cname(_)) && name == "@" {
+            return Err(anyhow!(
+                "DigitalOcean doesn't support CNAME records at the domain apex {}",
+                domain.name
+            ));
+        }
+
+        let ownership_name = ownership_record_name(domain_name);
+        let records = self
+            .client
+            .list_records(&domain.name, domain_name, record.record_type())?;
+        let mut conflicting_records = vec![];
+        for type_ in RECORD_TYPES.iter().filter(|t| record.conflicts_with(t)) {
+            conflicting_records.extend(self.client.list_records(
+                &domain.name,
+                domain_name,
+                type_,
+            )?);
+        }
+        let ownership_records = self
+            .client
+            .list_records(&domain.name, &ownership_name, "TXT")?;
+
+        let marker = ownership_records.first().map(|r| r.data.as_str());
+        if !records.is_empty() || !conflicting_records.is_empty() || marker.is_some() {
+            ownership.verify(domain_name, marker)?;
+        }
+
+        for conflicting in &conflicting_records {
+            info!(
+                "Deleting conflicting {} record {} for {}",
+                conflicting.type_, conflicting.id, domain_name
+            );
+            self.client.delete_record(&domain.name, conflicting.id)?;
+        }
+
+        self.upsert_record(
+            &domain.name,
+            &records,
+            DigitalOceanRecordBody {
+                type_: record.record_type().to_owned(),
+                name,
+                data: record_data(record),
+                ttl: self.ttl,
+            },
+        )?;
+        self.upsert_record(
+            &domain.name,
+            &ownership_records,
+            DigitalOceanRecordBody {
+                type_: "TXT".to_owned(),
+                name: relative_name(&ownership_name, &domain.name),
+                data: ownership.marker(),
+                ttl: self.ttl,
+            },
+        )
+    }
+
+    fn get_dns_records(&self, domain_name: &str) -> anyhow::Result<Vec<DnsRecord>> {
+        let domain = self.get_domain(domain_name)?;
+        let mut result = vec![];
+        for type_ in RECORD_TYPES {
+            let records = self.client.list_records(&domain.name, domain_name, type_)?;
+            result.extend(
+                records
+                    .iter()
+                    .filter_map(|r| DnsRecord::parse(&r.type_, &r.data)),
+            );
+        }
+        Ok(result)
+    }
+
+    fn delete_dns_records(
+        &self,
+        domain_name: &str,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        let domain = self.get_domain(domain_name)?;
+        let mut records = vec![];
+        for type_ in RECORD_TYPES {
+            records.extend(self.client.list_records(&domain.name, domain_name, type_)?);
+        }
+        let ownership_records =
+            self.client
+                .list_records(&domain.name, &ownership_record_name(domain_name), "TXT")?;
+        if records.is_empty() && ownership_records.is_empty() {
+            return Ok(());
+        }
+        ownership.verify(
+            domain_name,
+            ownership_records.first().map(|r| r.data.as_str()),
+        )?;
+
+        for record in records.iter().chain(&ownership_records) {
+            info!(
+                "Deleting {} record {} for {}",
+                record.type_, record.id, domain_name
+            );
+            self.client.delete_record(&domain.name, record.id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use client::DigitalOceanRecord;
+
+    use super::*;
+
+    fn domain() -> DigitalOceanDomain {
+        DigitalOceanDomain {
+            name: "example.com".to_string(),
+            ttl: Some(1800),
+        }
+    }
+
+    fn record(id: u64, name: &str) -> DigitalOceanRecord {
+        DigitalOceanRecord {
+            id,
+            type_: "AAAA".to_string(),
+            name: name.to_string(),
+            data: "::1".to_string(),
+            ttl: Some(1800),
+        }
+    }
+
+    fn service(client: DigitalOceanClient) -> DigitalOceanDnsService {
+        DigitalOceanDnsService {
+            client,
+            ttl: 60,
+            domains: ZoneCache::new(),
+        }
+    }
+
+    fn ownership() -> RecordOwnership {
+        RecordOwnership::new("pond", "example")
+    }
+
+    fn address(ip_address: &str) -> DnsRecord {
+        DnsRecord::Address(IpAddr::from_str(ip_address).unwrap())
+    }
+
+    fn ownership_record(id: u64, name: &str, deployment_name: &str) -> DigitalOceanRecord {
+        DigitalOceanRecord {
+            type_: "TXT".to_string(),
+            data: format!("pond-owner instance=pond deployment={}", deployment_name),
+            ..record(id, name)
+        }
+    }
+
+    #[test]
+    fn test_creates_missing_record_and_ownership_record() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain()
+            .times(2)
+            .returning(|name| Ok((name == "example.com").then(domain)));
+        mock.expect_list_records()
+            .withf(|domain, name, type_| {
+                domain == "example.com"
+                    && name == "www.example.com"
+                    && (type_ == "AAAA" || type_ == "CNAME")
+            })
+            .returning(|_, _, _| Ok(vec![]));
+        mock.expect_list_records()
+            .withf(|domain, name, type_| {
+                domain == "example.com" && name == "_pond-owner.www.example.com" && type_ == "TXT"
+            })
+            .returning(|_, _, _| Ok(vec![]));
+        mock.expect_create_record()
+            .withf(|_, request| request.type_ == "AAAA")
+            .times(1)
+            .returning(|_, request| {
+                assert_eq!(request.name, "www");
+                assert_eq!(request.data, "::1");
+                Ok(record(1, "www"))
+            });
+        mock.expect_create_record()
+            .withf(|_, request| request.type_ == "TXT")
+            .times(1)
+            .returning(|_, request| {
+                assert_eq!(request.name, "_pond-owner.www");
+                assert_eq!(request.data, "pond-owner instance=pond deployment=example");
+                Ok(ownership_record(2, "_pond-owner.www", "example"))
+            });
+
+        service(mock)
+            .set_dns_record("www.example.com", &address("::1"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_updates_owned_record() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(Some(domain())));
+        mock.expect_list_records()
+            .returning(|_, _, type_| match type_ {
+                "TXT" => Ok(vec![ownership_record(8, "_pond-owner", "example")]),
+                "AAAA" => Ok(vec![record(7, "@")]),
+                _ => Ok(vec![]),
+            });
+        mock.expect_update_record()
+            .times(1)
+            .returning(|_, record_id, request| {
+                assert_eq!(record_id, 7);
+                assert_eq!(request.name, "@");
+                assert_eq!(request.data, "::2");
+                Ok(record(7, "@"))
+            });
+
+        service(mock)
+            .set_dns_record("example.com", &address("::2"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_records_are_updated_and_deleted() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(Some(domain())));
+        mock.expect_list_records()
+            .returning(|_, _, type_| match type_ {
+                "TXT" => Ok(vec![ownership_record(8, "_pond-owner", "example")]),
+                "AAAA" => Ok(vec![record(7, "@"), record(9, "@")]),
+                _ => Ok(vec![]),
+            });
+        mock.expect_update_record()
+            .times(1)
+            .returning(|_, record_id, _| {
+                assert_eq!(record_id, 7);
+                Ok(record(7, "@"))
+            });
+        mock.expect_delete_record()
+            .times(1)
+            .returning(|_, record_id| {
+                assert_eq!(record_id, 9);
+                Ok(())
+            });
+
+        service(mock)
+            .set_dns_record("example.com", &address("::2"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_records_of_other_deployments_are_refused() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(Some(domain())));
+        mock.expect_list_records()
+            .returning(|_, _, type_| match type_ {
+                "TXT" => Ok(vec![ownership_record(8, "_pond-owner", "shop")]),
+                "AAAA" => Ok(vec![record(7, "@")]),
+                _ => Ok(vec![]),
+            });
+        mock.expect_update_record().never();
+
+        let result = service(mock).set_dns_record("example.com", &address("::2"), &ownership());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_hand_made_records_are_taken_over() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(Some(domain())));
+        mock.expect_list_records()
+            .returning(|_, _, type_| match type_ {
+                "TXT" => Ok(vec![]),
+                "AAAA" => Ok(vec![record(7, "@")]),
+                _ => Ok(vec![]),
+            });
+        mock.expect_update_record()
+            .times(1)
+            .returning(|_, _, _| Ok(record(7, "@")));
+        mock.expect_create_record()
+            .times(1)
+            .returning(|_, _| Ok(ownership_record(8, "_pond-owner", "example")));
+
+        let ownership = RecordOwnership {
+            takeover: true,
+            ..ownership()
+        };
+        service(mock)
+            .set_dns_record("example.com", &address("::2"), &ownership)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unknown_domain_fails() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(None));
+
+        let result = service(mock).set_dns_record("example.com", &address("::1"), &ownership());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_get_dns_records_returns_addresses() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(Some(domain())));
+        mock.expect_list_records()
+            .returning(|_, _, type_| match type_ {
+                "AAAA" => Ok(vec![record(7, "www")]),
+                _ => Ok(vec![]),
+            });
+
+        let records = service(mock).get_dns_records("www.example.com").unwrap();
+        assert_eq!(records, vec![address("::1")]);
+    }
+
+    #[test]
+    fn test_cname_replaces_owned_address_records() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(Some(domain())));
+        mock.expect_list_records()
+            .returning(|_, _, type_| match type_ {
+                "TXT" => Ok(vec![ownership_record(8, "_pond-owner.www", "example")]),
+                "AAAA" => Ok(vec![record(7, "www")]),
+                _ => Ok(vec![]),
+            });
+        mock.expect_delete_record()
+            .times(1)
+            .returning(|_, record_id| {
+                assert_eq!(record_id, 7);
+                Ok(())
+            });
+        mock.expect_create_record()
+            .times(1)
+            .returning(|_, request| {
+                assert_eq!(request.type_, "CNAME");
+                assert_eq!(request.name, "www");
+                assert_eq!(request.data, "blog.example.org.");
+                Ok(record(9, "www"))
+            });
+
+        service(mock)
+            .set_dns_record(
+                "www.example.com",
+                &DnsRecord::cname("blog.example.org"),
+                &ownership(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_apex_cname_fails() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(Some(domain())));
+
+        let result = service(mock).set_dns_record(
+            "example.com",
+            &DnsRecord::cname("blog.example.org"),
+            &ownership(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_delete_dns_records_removes_ownership_record() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(Some(domain())));
+        mock.expect_list_records()
+            .returning(|_, _, type_| match type_ {
+                "TXT" => Ok(vec![ownership_record(8, "_pond-owner", "example")]),
+                "AAAA" => Ok(vec![record(7, "@")]),
+                _ => Ok(vec![]),
+            });
+        mock.expect_delete_record()
+            .withf(|domain, record_id| domain == "example.com" && [7, 8].contains(record_id))
+            .times(2)
+            .returning(|_, _| Ok(()));
+
+        service(mock)
+            .delete_dns_records("example.com", &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_delete_dns_records_of_other_deployments_is_refused() {
+        let mut mock = DigitalOceanClient::default();
+        mock.expect_get_domain().returning(|_| Ok(Some(domain())));
+        mock.expect_list_records()
+            .returning(|_, _, type_| match type_ {
+                "TXT" => Ok(vec![ownership_record(8, "_pond-owner", "shop")]),
+                "AAAA" => Ok(vec![record(7, "@")]),
+                _ => Ok(vec![]),
+            });
+        mock.expect_delete_record().never();
+
+        assert!(service(mock)
+            .delete_dns_records("example.com", &ownership())
+            .is_err());
+    }
+}
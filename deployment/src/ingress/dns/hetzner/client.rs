@@ -0,0 +1,319 @@
+use reqwest::{
+    header::{HeaderMap, HeaderValue},
+    StatusCode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::ingress::dns::http::{json_response, success_response};
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+
+const PROVIDER: &str = "Hetzner DNS";
+
+pub struct HetznerClient {
+    base_url: String,
+    api_client: reqwest::blocking::Client,
+}
+
+#[allow(unused)]
+#[cfg_attr(test, automock)]
+impl HetznerClient {
+    pub fn new(base_url: String, api_token: String) -> Self {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert("Auth-API-Token", HeaderValue::from_str(&api_token).unwrap());
+
+        let api_client = reqwest::blocking::Client::builder()
+            .default_headers(default_headers)
+            .build()
+            .unwrap();
+
+        HetznerClient {
+            base_url,
+            api_client,
+        }
+    }
+
+    pub fn list_zones(&self, name: &str) -> anyhow::Result<Vec<HetznerZone>> {
+        let url = format!("{}/zones", self.base_url);
+        let response = self.api_client.get(&url).query(&[("name", name)]).send()?;
+        // The API answers with 404 instead of an empty list when searching for an unknown name
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(vec![]);
+        }
+        let response: HetznerZonesResponse = json_response(PROVIDER, response)?;
+        Ok(response.zones)
+    }
+
+    pub fn list_records(&self, zone_id: &HetznerZoneId) -> anyhow::Result<Vec<HetznerRecord>> {
+        let url = format!("{}/records", self.base_url);
+        let response = self
+            .api_client
+            .get(&url)
+            .query(&[("zone_id", zone_id.0.as_str())])
+            .send()?;
+        let response: HetznerRecordsResponse = json_response(PROVIDER, response)?;
+        Ok(response.records)
+    }
+
+    pub fn create_record(&self, request: &HetznerRecordBody) -> anyhow::Result<HetznerRecord> {
+        let url = format!("{}/records", self.base_url);
+        let response = self.api_client.post(&url).json(request).send()?;
+        let response: HetznerRecordResponse = json_response(PROVIDER, response)?;
+        Ok(response.record)
+    }
+
+    pub fn update_record(
+        &self,
+        record_id: &HetznerRecordId,
+        request: &HetznerRecordBody,
+    ) -> anyhow::Result<HetznerRecord> {
+        let url = format!("{}/records/{}", self.base_url, record_id.0);
+        let response = self.api_client.put(&url).json(request).send()?;
+        let response: HetznerRecordResponse = json_response(PROVIDER, response)?;
+        Ok(response.record)
+    }
+
+    pub fn delete_record(&self, record_id: &HetznerRecordId) -> anyhow::Result<()> {
+        let url = format!("{}/records/{}", self.base_url, record_id.0);
+        let response = self.api_client.delete(&url).send()?;
+        success_response(PROVIDER, response)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct HetznerZoneId(pub String);
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+pub struct HetznerRecordId(pub String);
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[allow(unused)]
+pub struct HetznerZone {
+    pub id: HetznerZoneId,
+    pub name: String,
+    #[serde(default)]
+    pub ttl: u32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[allow(unused)]
+pub struct HetznerRecord {
+    pub id: HetznerRecordId,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub name: String,
+    pub value: String,
+    pub zone_id: HetznerZoneId,
+    #[serde(default)]
+    pub ttl: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HetznerRecordBody {
+    pub zone_id: HetznerZoneId,
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub name: String,
+    pub value: String,
+    pub ttl: u32,
+}
+
+#[derive(Debug, Deserialize)]
+struct HetznerZonesResponse {
+    zones: Vec<HetznerZone>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HetznerRecordsResponse {
+    records: Vec<HetznerRecord>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HetznerRecordResponse {
+    record: HetznerRecord,
+}
+
+#[cfg(test)]
+pub mod testhelpers {
+    pub const LIST_ZONES_RESPONSE: &str = r#"{
+      "zones": [
+        {
+          "id": "zone-1",
+          "created": "2020-11-09 12:36:40.104 +0000 UTC",
+          "modified": "2020-11-09 12:36:40.104 +0000 UTC",
+          "legacy_dns_host": "",
+          "legacy_ns": [],
+          "name": "example.com",
+          "ns": ["hydrogen.ns.hetzner.com"],
+          "owner": "",
+          "paused": false,
+          "permission": "",
+          "project": "",
+          "registrar": "",
+          "status": "verified",
+          "ttl": 86400,
+          "verified": "",
+          "records_count": 2,
+          "is_secondary_dns": false
+        }
+      ],
+      "meta": {
+        "pagination": { "page": 1, "per_page": 100, "last_page": 1, "total_entries": 1 }
+      }
+    }"#;
+
+    pub const LIST_RECORDS_RESPONSE: &str = r#"{
+      "records": [
+        {
+          "id": "record-1",
+          "type": "A",
+          "name": "www",
+          "value": "198.51.100.4",
+          "zone_id": "zone-1",
+          "ttl": 300,
+          "created": "2020-11-09 12:36:40.104 +0000 UTC",
+          "modified": "2020-11-09 12:36:40.104 +0000 UTC"
+        },
+        {
+          "id": "record-2",
+          "type": "NS",
+          "name": "@",
+          "value": "hydrogen.ns.hetzner.com.",
+          "zone_id": "zone-1",
+          "created": "2020-11-09 12:36:40.104 +0000 UTC",
+          "modified": "2020-11-09 12:36:40.104 +0000 UTC"
+        }
+      ]
+    }"#;
+
+    pub const RECORD_RESPONSE: &str = r#"{
+      "record": {
+        "id": "record-1",
+        "type": "A",
+        "name": "www",
+        "value": "198.51.100.4",
+        "zone_id": "zone-1",
+        "ttl": 300,
+        "created": "2020-11-09 12:36:40.104 +0000 UTC",
+        "modified": "2020-11-09 12:36:40.104 +0000 UTC"
+      }
+    }"#;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server, ServerGuard};
+
+    fn test_client(server: &ServerGuard) -> HetznerClient {
+        HetznerClient::new(server.url(), "test_api_token".to_string())
+    }
+
+    fn test_record_body() -> HetznerRecordBody {
+        HetznerRecordBody {
+            zone_id: HetznerZoneId("zone-1".to_string()),
+            type_: "A".to_string(),
+            name: "www".to_string(),
+            value: "198.51.100.4".to_string(),
+            ttl: 300,
+        }
+    }
+
+    #[test]
+    fn test_list_zones() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/zones?name=example.com")
+            .match_header("Auth-API-Token", "test_api_token")
+            .with_status(200)
+            .with_body(testhelpers::LIST_ZONES_RESPONSE)
+            .create();
+
+        let zones = test_client(&server).list_zones("example.com").unwrap();
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].id.0, "zone-1");
+        assert_eq!(zones[0].name, "example.com");
+    }
+
+    #[test]
+    fn test_list_zones_not_found() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/zones?name=example.org")
+            .with_status(404)
+            .with_body(r#"{"zones": [], "error": {"message": "zone not found", "code": 404}}"#)
+            .create();
+
+        let zones = test_client(&server).list_zones("example.org").unwrap();
+        assert!(zones.is_empty());
+    }
+
+    #[test]
+    fn test_list_records() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/records")
+            .match_query(Matcher::UrlEncoded("zone_id".into(), "zone-1".into()))
+            .with_status(200)
+            .with_body(testhelpers::LIST_RECORDS_RESPONSE)
+            .create();
+
+        let records = test_client(&server)
+            .list_records(&HetznerZoneId("zone-1".to_string()))
+            .unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].name, "www");
+        assert_eq!(records[1].ttl, None);
+    }
+
+    #[test]
+    fn test_create_record() {
+        let request = test_record_body();
+        let mut server = Server::new();
+        let _m = server
+            .mock("POST", "/records")
+            .match_body(Matcher::JsonString(
+                serde_json::to_string(&request).unwrap(),
+            ))
+            .with_status(200)
+            .with_body(testhelpers::RECORD_RESPONSE)
+            .create();
+
+        let record = test_client(&server).create_record(&request).unwrap();
+        assert_eq!(record.id.0, "record-1");
+    }
+
+    #[test]
+    fn test_update_record_error() {
+        let request = test_record_body();
+        let mut server = Server::new();
+        let _m = server
+            .mock("PUT", "/records/record-1")
+            .with_status(422)
+            .with_body(r#"{"error": {"message": "invalid value", "code": 422}}"#)
+            .create();
+
+        let result =
+            test_client(&server).update_record(&HetznerRecordId("record-1".to_string()), &request);
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("422"));
+        assert!(error.contains("invalid value"));
+    }
+
+    #[test]
+    fn test_delete_record() {
+        let mut server = Server::new();
+        let m = server
+            .mock("DELETE", "/records/record-1")
+            .match_header("Auth-API-Token", "test_api_token")
+            .with_status(200)
+            .create();
+
+        test_client(&server)
+            .delete_record(&HetznerRecordId("record-1".to_string()))
+            .unwrap();
+        m.assert();
+    }
+}
@@ -0,0 +1,550 @@
+use anyhow::anyhow;
+use client::{HetznerRecord, HetznerRecordBody, HetznerZone};
+use figment::{providers::Serialized, Figment};
+use mockall_double::double;
+use serde::{Deserialize, Serialize};
+
+mod client;
+
+use super::{
+    ownership_record_name, relative_name, DnsRecord, DnsService, RecordOwnership,
+    RegisterDnsService, ZoneCache,
+};
+use crate::config::ConfigurationError;
+
+#[double]
+use client::HetznerClient;
+
+pub struct HetznerDnsService {
+    client: HetznerClient,
+    ttl: u32,
+    zones: ZoneCache<HetznerZone>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HetznerDnsServiceConfig {
+    #[serde(skip_serializing)]
+    api_token: String,
+    base_url: String,
+    dns_ttl: u32,
+}
+
+impl Default for HetznerDnsServiceConfig {
+    fn default() -> Self {
+        Self {
+            api_token: Default::default(),
+            base_url: "https://dns.hetzner.com/api/v1".to_owned(),
+            dns_ttl: 300,
+        }
+    }
+}
+
+impl RegisterDnsService for HetznerDnsService {
+    fn provider_name() -> &'static str {
+        "hetzner"
+    }
+
+    fn configure(figment: &Figment) -> Result<Self, ConfigurationError> {
+        let configuration = figment.extract_inner::<HetznerDnsServiceConfig>("hetzner")?;
+        Ok(Self {
+            client: HetznerClient::new(configuration.base_url, configuration.api_token),
+            ttl: configuration.dns_ttl,
+            zones: ZoneCache::new(),
+        })
+    }
+
+    fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(
+            "hetzner",
+            HetznerDnsServiceConfig::default(),
+        ))
+    }
+}
+
+impl HetznerDnsService {
+    fn get_zone(&self, domain_name: &str) -> anyhow::Result<HetznerZone> {
+        self.zones
+            .find_zone(domain_name, |name| self.find_zone_by_name(name))
+    }
+
+    fn find_zone_by_name(&self, name: &str) -> anyhow::Result<Option<HetznerZone>> {
+        let mut zones: Vec<HetznerZone> = self
+            .client
+            .list_zones(name)?
+            .into_iter()
+            .filter(|z| z.name == name)
+            .collect();
+
+        match zones.len() {
+            0 | 1 => Ok(zones.pop()),
+            _ => Err(anyhow!("Too many zones for domain {:?}", zones)),
+        }
+    }
+}
+
+impl HetznerDnsService {
+    fn upsert_record(
+        &self,
+        existing_records: &[HetznerRecord],
+        request: HetznerRecordBody,
+    ) -> anyhow::Result<()> {
+        let Some((record, duplicates)) = existing_records.split_first() else {
+            self.client.create_record(&request)?;
+            return Ok(());
+        };
+        if record.value != request.value {
+            self.client.update_record(&record.id, &request)?;
+        }
+        for duplicate in duplicates {
+            info!(
+                "Deleting duplicate {} record {} for {}",
+                duplicate.type_, duplicate.id.0, duplicate.name
+            );
+            self.client.delete_record(&duplicate.id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hetzner expects fully qualified `CNAME` targets terminated by a dot.
+fn record_value(record: &DnsRecord) -> String {
+    match record {
+        DnsRecord::Address(_) => record.content(),
+        DnsRecord::Cname(target) => format!("{}.", target),
+    }
+}
+
+impl DnsService for HetznerDnsService {
+    /// Hetzner records can't carry comments, so ownership lives in a `_pond-owner` TXT record
+    /// next to the address records.
+    fn set_dns_record(
+        &self,
+        domain_name: &str,
+        record: &DnsRecord,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        let zone = self.get_zone(domain_name)?;
+        let name = relative_name(domain_name, &zone.name);
+        if matches!(record, DnsRecord::Cname(_)) && name == "@" {
+            return Err(anyhow!(
+                "Hetzner DNS doesn't support CNAME records at the zone apex {}",
+                zone.name
+            ));
+        }
+
+        let ownership_name = relative_name(&ownership_record_name(domain_name), &zone.name);
+        let records = self.client.list_records(&zone.id)?;
+        let (relevant_records, conflicting_records): (Vec<HetznerRecord>, Vec<HetznerRecord>) =
+            records
+                .iter()
+                .filter(|r| r.name == name && record.occupies(&r.type_))
+                .cloned()
+                .partition(|r| r.type_ == record.record_type());
+        let ownership_records: Vec<HetznerRecord> = records
+            .into_iter()
+            .filter(|r| r.name == ownership_name && r.type_ == "TXT")
+            .collect();
+
+        let marker = ownership_records.first().map(|r| r.value.as_str());
+        if !relevant_records.is_empty() || !conflicting_records.is_empty() || marker.is_some() {
+            ownership.verify(domain_name, marker)?;
+        }
+
+        for conflicting in &conflicting_records {
+            info!(
+                "Deleting conflicting {} record {} for {}",
+                conflicting.type_, conflicting.id.0, domain_name
+            );
+            self.client.delete_record(&conflicting.id)?;
+        }
+
+        self.upsert_record(
+            &relevant_records,
+            HetznerRecordBody {
+                zone_id: zone.id.clone(),
+                type_: record.record_type().to_owned(),
+                name,
+                value: record_value(record),
+                ttl: self.ttl,
+            },
+        )?;
+        self.upsert_record(
+            &ownership_records,
+            HetznerRecordBody {
+                zone_id: zone.id,
+                type_: "TXT".to_owned(),
+                name: ownership_name,
+                value: format!("\"{}\"", ownership.marker()),
+                ttl: self.ttl,
+            },
+        )
+    }
+
+    fn get_dns_records(&self, domain_name: &str) -> anyhow::Result<Vec<DnsRecord>> {
+        let zone = self.get_zone(domain_name)?;
+        let name = relative_name(domain_name, &zone.name);
+        Ok(self
+            .client
+            .list_records(&zone.id)?
+            .iter()
+            .filter(|r| r.name == name)
+            .filter_map(|r| DnsRecord::parse(&r.type_, &r.value))
+            .collect())
+    }
+
+    fn delete_dns_records(
+        &self,
+        domain_name: &str,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        let zone = self.get_zone(domain_name)?;
+        let name = relative_name(domain_name, &zone.name);
+        let ownership_name = relative_name(&ownership_record_name(domain_name), &zone.name);
+        let (ownership_records, records): (Vec<HetznerRecord>, Vec<HetznerRecord>) = self
+            .client
+            .list_records(&zone.id)?
+            .into_iter()
+            .filter(|r| {
+                (r.name == name && DnsRecord::parse(&r.type_, &r.value).is_some())
+                    || (r.name == ownership_name && r.type_ == "TXT")
+            })
+            .partition(|r| r.name == ownership_name);
+        if records.is_empty() && ownership_records.is_empty() {
+            return Ok(());
+        }
+        ownership.verify(
+            domain_name,
+            ownership_records.first().map(|r| r.value.as_str()),
+        )?;
+
+        for record in records.iter().chain(&ownership_records) {
+            info!(
+                "Deleting {} record {} for {}",
+                record.type_, record.id.0, domain_name
+            );
+            self.client.delete_record(&record.id)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use client::{HetznerRecordId, HetznerZoneId};
+
+    use super::*;
+
+    fn zone() -> HetznerZone {
+        HetznerZone {
+            id: HetznerZoneId("zone-1".to_string()),
+            name: "example.com".to_string(),
+            ttl: 86400,
+        }
+    }
+
+    fn record(id: &str, name: &str, type_: &str) -> HetznerRecord {
+        HetznerRecord {
+            id: HetznerRecordId(id.to_string()),
+            type_: type_.to_string(),
+            name: name.to_string(),
+            value: "198.51.100.4".to_string(),
+            zone_id: HetznerZoneId("zone-1".to_string()),
+            ttl: Some(300),
+        }
+    }
+
+    fn ownership() -> RecordOwnership {
+        RecordOwnership::new("pond", "example")
+    }
+
+    fn address(ip_address: &str) -> DnsRecord {
+        DnsRecord::Address(IpAddr::from_str(ip_address).unwrap())
+    }
+
+    fn ownership_record(deployment_name: &str) -> HetznerRecord {
+        HetznerRecord {
+            value: format!(
+                "\"pond-owner instance=pond deployment={}\"",
+                deployment_name
+            ),
+            ..record("owner-1", "_pond-owner", "TXT")
+        }
+    }
+
+    fn service(client: HetznerClient) -> HetznerDnsService {
+        HetznerDnsService {
+            client,
+            ttl: 60,
+            zones: ZoneCache::new(),
+        }
+    }
+
+    #[test]
+    fn test_creates_missing_record_and_ownership_record() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones()
+            .times(2)
+            .returning(|name| match name {
+                "example.com" => Ok(vec![zone()]),
+                _ => Ok(vec![]),
+            });
+        mock.expect_list_records()
+            .returning(|_| Ok(vec![record("record-1", "www", "AAAA")]));
+        mock.expect_create_record()
+            .withf(|request| request.type_ == "A")
+            .times(1)
+            .returning(|request| {
+                assert_eq!(request.name, "www");
+                assert_eq!(request.value, "127.0.0.1");
+                assert_eq!(request.ttl, 60);
+                Ok(record("record-2", "www", "A"))
+            });
+        mock.expect_create_record()
+            .withf(|request| request.type_ == "TXT")
+            .times(1)
+            .returning(|request| {
+                assert_eq!(request.name, "_pond-owner.www");
+                assert_eq!(
+                    request.value,
+                    "\"pond-owner instance=pond deployment=example\""
+                );
+                Ok(ownership_record("example"))
+            });
+
+        service(mock)
+            .set_dns_record("www.example.com", &address("127.0.0.1"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_updates_owned_apex_record() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![zone()]));
+        mock.expect_list_records().returning(|_| {
+            Ok(vec![
+                record("record-1", "@", "A"),
+                HetznerRecord {
+                    name: "_pond-owner".to_string(),
+                    ..ownership_record("example")
+                },
+            ])
+        });
+        mock.expect_update_record()
+            .times(1)
+            .returning(|record_id, request| {
+                assert_eq!(record_id.0, "record-1");
+                assert_eq!(request.name, "@");
+                Ok(record("record-1", "@", "A"))
+            });
+
+        service(mock)
+            .set_dns_record("example.com", &address("127.0.0.1"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_records_without_ownership_are_refused() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![zone()]));
+        mock.expect_list_records()
+            .returning(|_| Ok(vec![record("record-1", "@", "A")]));
+
+        let result =
+            service(mock).set_dns_record("example.com", &address("127.0.0.1"), &ownership());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_records_of_other_deployments_are_taken_over() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![zone()]));
+        mock.expect_list_records().returning(|_| {
+            Ok(vec![
+                record("record-1", "www", "A"),
+                HetznerRecord {
+                    name: "_pond-owner.www".to_string(),
+                    ..ownership_record("shop")
+                },
+            ])
+        });
+        mock.expect_update_record()
+            .times(2)
+            .returning(|record_id, request| {
+                if request.type_ == "TXT" {
+                    assert_eq!(record_id.0, "owner-1");
+                    assert!(request.value.contains("deployment=example"));
+                }
+                Ok(record("record-1", "www", "A"))
+            });
+
+        let takeover = RecordOwnership {
+            takeover: true,
+            ..ownership()
+        };
+        let refused = ownership();
+        let service = service(mock);
+        assert!(service
+            .set_dns_record("www.example.com", &address("127.0.0.1"), &refused)
+            .is_err());
+        service
+            .set_dns_record("www.example.com", &address("127.0.0.1"), &takeover)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_records_are_updated_and_deleted() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![zone()]));
+        mock.expect_list_records().returning(|_| {
+            Ok(vec![
+                record("record-1", "www", "A"),
+                record("record-2", "www", "A"),
+                HetznerRecord {
+                    name: "_pond-owner.www".to_string(),
+                    ..ownership_record("example")
+                },
+            ])
+        });
+        mock.expect_update_record()
+            .times(1)
+            .returning(|record_id, _| {
+                assert_eq!(record_id.0, "record-1");
+                Ok(record("record-1", "www", "A"))
+            });
+        mock.expect_delete_record().times(1).returning(|record_id| {
+            assert_eq!(record_id.0, "record-2");
+            Ok(())
+        });
+
+        service(mock)
+            .set_dns_record("www.example.com", &address("127.0.0.1"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_record_in_delegated_subzone() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|name| match name {
+            "dev.example.com" => Ok(vec![HetznerZone {
+                id: HetznerZoneId("zone-2".to_string()),
+                name: "dev.example.com".to_string(),
+                ttl: 86400,
+            }]),
+            _ => Ok(vec![]),
+        });
+        mock.expect_list_records().returning(|zone_id| {
+            assert_eq!(zone_id.0, "zone-2");
+            Ok(vec![])
+        });
+        mock.expect_create_record().times(2).returning(|request| {
+            assert!(request.name == "app" || request.name == "_pond-owner.app");
+            Ok(record("record-3", "app", "A"))
+        });
+
+        service(mock)
+            .set_dns_record("app.dev.example.com", &address("127.0.0.1"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unknown_zone_fails() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![]));
+
+        let result =
+            service(mock).set_dns_record("www.example.com", &address("127.0.0.1"), &ownership());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_get_dns_records_returns_addresses() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![zone()]));
+        mock.expect_list_records().returning(|_| {
+            Ok(vec![
+                record("record-1", "www", "A"),
+                record("record-2", "@", "A"),
+                HetznerRecord {
+                    name: "_pond-owner.www".to_string(),
+                    ..ownership_record("example")
+                },
+            ])
+        });
+
+        let records = service(mock).get_dns_records("www.example.com").unwrap();
+        assert_eq!(records, vec![address("198.51.100.4")]);
+    }
+
+    #[test]
+    fn test_cname_replaces_owned_address_record() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![zone()]));
+        mock.expect_list_records().returning(|_| {
+            Ok(vec![
+                record("record-1", "www", "A"),
+                HetznerRecord {
+                    name: "_pond-owner.www".to_string(),
+                    ..ownership_record("example")
+                },
+            ])
+        });
+        mock.expect_delete_record().times(1).returning(|record_id| {
+            assert_eq!(record_id.0, "record-1");
+            Ok(())
+        });
+        mock.expect_create_record().times(1).returning(|request| {
+            assert_eq!(request.type_, "CNAME");
+            assert_eq!(request.name, "www");
+            assert_eq!(request.value, "blog.example.org.");
+            Ok(record("record-2", "www", "CNAME"))
+        });
+
+        service(mock)
+            .set_dns_record(
+                "www.example.com",
+                &DnsRecord::cname("blog.example.org"),
+                &ownership(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_apex_cname_fails() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![zone()]));
+
+        let result = service(mock).set_dns_record(
+            "example.com",
+            &DnsRecord::cname("blog.example.org"),
+            &ownership(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_delete_dns_records_removes_ownership_record() {
+        let mut mock = HetznerClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![zone()]));
+        mock.expect_list_records().returning(|_| {
+            Ok(vec![
+                record("record-1", "www", "A"),
+                record("record-2", "www", "MX"),
+                record("record-3", "shop", "A"),
+                HetznerRecord {
+                    name: "_pond-owner.www".to_string(),
+                    ..ownership_record("example")
+                },
+            ])
+        });
+        mock.expect_delete_record()
+            .withf(|record_id| record_id.0 == "record-1" || record_id.0 == "owner-1")
+            .times(2)
+            .returning(|_| Ok(()));
+
+        service(mock)
+            .delete_dns_records("www.example.com", &ownership())
+            .unwrap();
+    }
+}
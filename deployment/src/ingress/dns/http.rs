@@ -0,0 +1,28 @@
+use anyhow::anyhow;
+use reqwest::blocking::Response;
+use serde::de::DeserializeOwned;
+
+/// Deserializes a successful response, turning error statuses into errors that carry the body.
+pub(crate) fn json_response<T: DeserializeOwned>(
+    provider: &str,
+    response: Response,
+) -> anyhow::Result<T> {
+    let response = success_response(provider, response)?;
+    Ok(serde_json::from_reader(response)?)
+}
+
+pub(crate) fn success_response(provider: &str, response: Response) -> anyhow::Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let url = response.url().clone();
+    let body = response.text().unwrap_or_default();
+    Err(anyhow!(
+        "{} API request to {} failed with status {}: {}",
+        provider,
+        url,
+        status,
+        body
+    ))
+}
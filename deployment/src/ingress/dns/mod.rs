@@ -5,27 +5,142 @@ use std::{
 };
 
 pub mod cloudflare;
+pub mod digitalocean;
+pub mod hetzner;
+mod http;
+mod ownership;
+pub mod powerdns;
+mod reconciler;
+mod record;
+mod registry;
+mod zone;
+
+pub(crate) use ownership::ownership_record_name;
+pub use ownership::RecordOwnership;
+pub use reconciler::{DnsDrift, DnsReconciler};
+pub use record::DnsRecord;
+pub use registry::{DnsServiceRegistry, RegisterDnsService};
+pub(crate) use zone::ZoneCache;
+
+use crate::Manifest;
 
 #[cfg(test)]
 use mockall::{automock, predicate::*};
 
 #[cfg_attr(test, automock)]
 pub trait DnsService {
-    fn set_dns_record(&self, domain_name: &str, ip_address: IpAddr) -> anyhow::Result<()>;
+    /// Points `domain_name` at `record`, refusing to touch records `ownership` doesn't cover.
+    ///
+    /// Records that conflict with `record`, like an `A` record when setting a `CNAME`, are
+    /// removed. If several records of its type exist, the first one is updated and the others
+    /// are deleted.
+    fn set_dns_record(
+        &self,
+        domain_name: &str,
+        record: &DnsRecord,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()>;
+
+    /// Returns the `A`, `AAAA` and `CNAME` records of `domain_name`.
+    fn get_dns_records(&self, domain_name: &str) -> anyhow::Result<Vec<DnsRecord>>;
+
+    /// Removes the `A`, `AAAA` and `CNAME` records of `domain_name` along with their ownership
+    /// marker, refusing to touch records `ownership` doesn't cover.
+    fn delete_dns_records(
+        &self,
+        domain_name: &str,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()>;
 }
 
 impl DnsService for Box<dyn DnsService> {
-    fn set_dns_record(&self, domain_name: &str, ip_address: IpAddr) -> anyhow::Result<()> {
-        self.as_ref().set_dns_record(domain_name, ip_address)
+    fn set_dns_record(
+        &self,
+        domain_name: &str,
+        record: &DnsRecord,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        self.as_ref().set_dns_record(domain_name, record, ownership)
+    }
+
+    fn get_dns_records(&self, domain_name: &str) -> anyhow::Result<Vec<DnsRecord>> {
+        self.as_ref().get_dns_records(domain_name)
+    }
+
+    fn delete_dns_records(
+        &self,
+        domain_name: &str,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        self.as_ref().delete_dns_records(domain_name, ownership)
     }
 }
 
 pub struct NoOpDnsService;
 
 impl DnsService for NoOpDnsService {
-    fn set_dns_record(&self, _domain_name: &str, _ip_address: IpAddr) -> anyhow::Result<()> {
+    fn set_dns_record(
+        &self,
+        _domain_name: &str,
+        _record: &DnsRecord,
+        _ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
         Ok(())
     }
+
+    fn get_dns_records(&self, _domain_name: &str) -> anyhow::Result<Vec<DnsRecord>> {
+        Ok(vec![])
+    }
+
+    fn delete_dns_records(
+        &self,
+        _domain_name: &str,
+        _ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+impl RegisterDnsService for NoOpDnsService {
+    fn provider_name() -> &'static str {
+        "none"
+    }
+
+    fn configure(_figment: &figment::Figment) -> Result<Self, crate::config::ConfigurationError> {
+        Ok(NoOpDnsService)
+    }
+}
+
+/// Returns the name of `domain_name` relative to `zone_name`, using `@` for the zone apex.
+pub(crate) fn relative_name(domain_name: &str, zone_name: &str) -> String {
+    if domain_name == zone_name {
+        return "@".to_owned();
+    }
+    domain_name
+        .strip_suffix(zone_name)
+        .and_then(|prefix| prefix.strip_suffix('.'))
+        .unwrap_or(domain_name)
+        .to_owned()
+}
+
+/// The records the domains of `manifest` should have. Domains listed in `cname_domains` alias
+/// the default host of the deployment, all others point at `ip_addresses`.
+pub(crate) fn desired_records(
+    manifest: &Manifest,
+    root_domain_name: &str,
+    ip_addresses: &[IpAddr],
+) -> Vec<(String, DnsRecord)> {
+    let address_records = manifest.domain_names.iter().flat_map(|domain_name| {
+        ip_addresses
+            .iter()
+            .map(|ip_address| (domain_name.clone(), DnsRecord::Address(*ip_address)))
+    });
+    let default_domain_name = manifest.default_domain_name(root_domain_name);
+    let cname_records = manifest
+        .cname_domains
+        .iter()
+        .map(|domain_name| (domain_name.clone(), DnsRecord::cname(&default_domain_name)));
+    address_records.chain(cname_records).collect()
 }
 
 // This will not work due to operating system caching.
@@ -56,10 +171,39 @@ pub fn wait_for_dns_records(
 mod test {
     use super::*;
 
+    #[test]
+    fn test_relative_name() {
+        assert_eq!(relative_name("example.com", "example.com"), "@");
+        assert_eq!(relative_name("www.example.com", "example.com"), "www");
+        assert_eq!(relative_name("a.b.example.com", "example.com"), "a.b");
+    }
+
+    #[test]
+    fn test_desired_records() {
+        let manifest = Manifest {
+            cname_domains: vec!["example.org".to_owned()],
+            ..crate::manifest::test::manifest("blog", &["blog.example.com"])
+        };
+        let ip_address = IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 10));
+        assert_eq!(
+            desired_records(&manifest, "example.com", &[ip_address]),
+            vec![
+                (
+                    "blog.example.com".to_owned(),
+                    DnsRecord::Address(ip_address)
+                ),
+                (
+                    "example.org".to_owned(),
+                    DnsRecord::Cname("blog.example.com".to_owned())
+                ),
+            ]
+        );
+    }
+
     #[test]
     fn test_wait_for_dns_records_not_fulfilled() {
         let domain_name = "example.com";
-        let ip_addresses = vec![IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1))];
+        let ip_addresses = [IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1))];
         let timeout = std::time::Duration::from_millis(200);
         let result = wait_for_dns_records(domain_name, ip_addresses.iter().cloned(), timeout);
         assert!(result.is_err());
@@ -69,7 +213,7 @@ mod test {
     fn test_wait_for_dns_records_fulfilled() {
         let domain_name = "example.com";
 
-        let ip_addresses = vec![(domain_name, 80)
+        let ip_addresses = [(domain_name, 80)
             .to_socket_addrs()
             .unwrap()
             .next()
@@ -0,0 +1,263 @@
+use anyhow::anyhow;
+
+use crate::Manifest;
+
+const MARKER_PREFIX: &str = "pond-owner";
+const OWNERSHIP_RECORD_LABEL: &str = "_pond-owner";
+/// Comments written by pond versions that didn't track ownership yet.
+const LEGACY_COMMENTS: [&str; 2] = ["Record created by pond", "Record updated by pond"];
+
+/// Identifies which pond instance and deployment a DNS record belongs to, and whether the
+/// deployment may claim records that belong to someone else.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordOwnership {
+    pub instance_id: String,
+    pub deployment_name: String,
+    pub takeover: bool,
+    /// Domains whose records without an ownership marker, but created by an earlier pond
+    /// version, belong to this deployment.
+    pub adopted_domain_names: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Marker<'a> {
+    Owner {
+        instance_id: &'a str,
+        deployment_name: &'a str,
+    },
+    Legacy,
+    Foreign,
+}
+
+impl<'a> Marker<'a> {
+    fn parse(marker: &'a str) -> Marker<'a> {
+        let marker = marker.trim().trim_matches('"');
+        if LEGACY_COMMENTS.iter().any(|c| marker.starts_with(c)) {
+            return Marker::Legacy;
+        }
+
+        let Some(fields) = marker.strip_prefix(MARKER_PREFIX) else {
+            return Marker::Foreign;
+        };
+        let mut instance_id = None;
+        let mut deployment_name = None;
+        for field in fields.split_whitespace() {
+            match field.split_once('=') {
+                Some(("instance", value)) => instance_id = Some(value),
+                Some(("deployment", value)) => deployment_name = Some(value),
+                _ => {}
+            }
+        }
+        match (instance_id, deployment_name) {
+            (Some(instance_id), Some(deployment_name)) => Marker::Owner {
+                instance_id,
+                deployment_name,
+            },
+            _ => Marker::Foreign,
+        }
+    }
+}
+
+impl RecordOwnership {
+    pub fn new(instance_id: impl Into<String>, deployment_name: impl Into<String>) -> Self {
+        RecordOwnership {
+            instance_id: instance_id.into(),
+            deployment_name: deployment_name.into(),
+            takeover: false,
+            adopted_domain_names: vec![],
+        }
+    }
+
+    /// Adopts the records earlier pond versions created for the domains of `manifest`, unless
+    /// another of the `recorded` deployments serves them. Sites deployed before the upgrade
+    /// aren't recorded yet, so their own domains are adopted on their first redeploy.
+    pub fn adopting(mut self, manifest: &Manifest, recorded: &[Manifest]) -> Self {
+        let claimed_elsewhere: Vec<String> = recorded
+            .iter()
+            .filter(|m| m.name != manifest.name)
+            .flat_map(Manifest::served_domain_names)
+            .collect();
+        self.adopted_domain_names = manifest
+            .served_domain_names()
+            .into_iter()
+            .filter(|domain_name| !claimed_elsewhere.contains(domain_name))
+            .collect();
+        self
+    }
+
+    /// The marker stored in record comments or ownership TXT records.
+    pub fn marker(&self) -> String {
+        format!(
+            "{} instance={} deployment={}",
+            MARKER_PREFIX, self.instance_id, self.deployment_name
+        )
+    }
+
+    /// Checks that an existing record carrying `marker` may be modified or deleted.
+    ///
+    /// Records without a marker were created by hand and are only touched with `takeover`.
+    /// Records created by pond before ownership tracking existed are adopted for the
+    /// `adopted_domain_names` and otherwise need `takeover` as well.
+    pub fn verify(&self, domain_name: &str, marker: Option<&str>) -> anyhow::Result<()> {
+        let marker = marker.map(Marker::parse);
+        let owned = match marker {
+            Some(Marker::Owner {
+                instance_id,
+                deployment_name,
+            }) => instance_id == self.instance_id && deployment_name == self.deployment_name,
+            Some(Marker::Legacy) => self.adopted_domain_names.iter().any(|d| d == domain_name),
+            Some(Marker::Foreign) | None => false,
+        };
+
+        if owned {
+            return Ok(());
+        }
+
+        let current_owner = match marker {
+            Some(Marker::Owner {
+                instance_id,
+                deployment_name,
+            }) => format!(
+                "deployment {} of pond instance {}",
+                deployment_name, instance_id
+            ),
+            Some(Marker::Legacy) => "an earlier pond version".to_owned(),
+            _ => "somebody else".to_owned(),
+        };
+
+        if self.takeover {
+            warn!(
+                "Deployment {} takes over DNS records of {} owned by {}",
+                self.deployment_name, domain_name, current_owner
+            );
+            return Ok(());
+        }
+
+        Err(anyhow!(
+            "Refusing to modify DNS records of {} because they are owned by {}. Set `takeover = true` in the manifest to claim them",
+            domain_name,
+            current_owner
+        ))
+    }
+}
+
+/// Name of the TXT record that carries the ownership marker for providers without comments.
+pub(crate) fn ownership_record_name(domain_name: &str) -> String {
+    format!("{}.{}", OWNERSHIP_RECORD_LABEL, domain_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::manifest::test::manifest;
+
+    fn ownership() -> RecordOwnership {
+        RecordOwnership::new("pond-1", "blog")
+    }
+
+    #[test]
+    fn test_marker_round_trip() {
+        let marker = ownership().marker();
+        assert_eq!(marker, "pond-owner instance=pond-1 deployment=blog");
+        assert_eq!(
+            Marker::parse(&format!("\"{}\"", marker)),
+            Marker::Owner {
+                instance_id: "pond-1",
+                deployment_name: "blog"
+            }
+        );
+    }
+
+    #[test]
+    fn test_own_records_may_be_modified() {
+        let ownership = ownership();
+        assert!(ownership
+            .verify("example.com", Some(&ownership.marker()))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_legacy_records_of_deployed_domains_are_adopted() {
+        let blog = manifest("blog", &["blog.example.com", "www.example.com"]);
+        let ownership = ownership().adopting(&blog, &[]);
+        for domain_name in ["blog.example.com", "www.example.com"] {
+            assert!(ownership
+                .verify(domain_name, Some("Record created by pond"))
+                .is_ok());
+        }
+        assert!(ownership
+            .verify("shop.example.com", Some("Record updated by pond"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_legacy_records_of_other_deployments_are_not_adopted() {
+        let blog = manifest("blog", &["blog.example.com", "www.example.com"]);
+        let recorded = [
+            manifest("blog", &["blog.example.com"]),
+            manifest("shop", &["www.example.com"]),
+        ];
+        let ownership = ownership().adopting(&blog, &recorded);
+        assert!(ownership
+            .verify("blog.example.com", Some("Record created by pond"))
+            .is_ok());
+        assert!(ownership
+            .verify("www.example.com", Some("Record created by pond"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_takeover_claims_legacy_records() {
+        let ownership = RecordOwnership {
+            takeover: true,
+            ..ownership()
+        };
+        assert!(ownership
+            .verify("example.com", Some("Record created by pond"))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_foreign_records_are_refused() {
+        let ownership = ownership();
+        assert!(ownership.verify("example.com", None).is_err());
+        assert!(ownership
+            .verify("example.com", Some("Mail server, do not touch"))
+            .is_err());
+        assert!(ownership
+            .verify(
+                "example.com",
+                Some("pond-owner instance=pond-1 deployment=shop")
+            )
+            .is_err());
+        assert!(ownership
+            .verify(
+                "example.com",
+                Some("pond-owner instance=pond-2 deployment=blog")
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_takeover_claims_foreign_records() {
+        let ownership = RecordOwnership {
+            takeover: true,
+            ..ownership()
+        };
+        assert!(ownership.verify("example.com", None).is_ok());
+        assert!(ownership
+            .verify(
+                "example.com",
+                Some("pond-owner instance=pond-1 deployment=shop")
+            )
+            .is_ok());
+    }
+
+    #[test]
+    fn test_ownership_record_name() {
+        assert_eq!(
+            ownership_record_name("www.example.com"),
+            "_pond-owner.www.example.com"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use crate::ingress::dns::http::{json_response, success_response};
+
+#[cfg(test)]
+use mockall::{automock, predicate::*};
+
+const PROVIDER: &str = "PowerDNS";
+
+pub struct PowerDnsClient {
+    base_url: String,
+    server_id: String,
+    api_client: reqwest::blocking::Client,
+}
+
+#[allow(unused)]
+#[cfg_attr(test, automock)]
+impl PowerDnsClient {
+    pub fn new(base_url: String, server_id: String, api_key: String) -> Self {
+        let mut default_headers = HeaderMap::new();
+        default_headers.insert("X-API-Key", HeaderValue::from_str(&api_key).unwrap());
+
+        let api_client = reqwest::blocking::Client::builder()
+            .default_headers(default_headers)
+            .build()
+            .unwrap();
+
+        PowerDnsClient {
+            base_url,
+            server_id,
+            api_client,
+        }
+    }
+
+    /// Lists the zones matching the canonical (dot terminated) `name`.
+    pub fn list_zones(&self, name: &str) -> anyhow::Result<Vec<PowerDnsZone>> {
+        let url = format!("{}/zones", self.server_url());
+        let response = self.api_client.get(&url).query(&[("zone", name)]).send()?;
+        json_response(PROVIDER, response)
+    }
+
+    pub fn get_zone(&self, zone_id: &str) -> anyhow::Result<PowerDnsZone> {
+        let url = format!("{}/zones/{}", self.server_url(), zone_id);
+        let response = self.api_client.get(&url).send()?;
+        json_response(PROVIDER, response)
+    }
+
+    pub fn patch_rrsets(&self, zone_id: &str, rrsets: Vec<PowerDnsRRSet>) -> anyhow::Result<()> {
+        let url = format!("{}/zones/{}", self.server_url(), zone_id);
+        let response = self
+            .api_client
+            .patch(&url)
+            .json(&PowerDnsPatchZoneBody { rrsets })
+            .send()?;
+        success_response(PROVIDER, response)?;
+        Ok(())
+    }
+
+    fn server_url(&self) -> String {
+        format!("{}/api/v1/servers/{}", self.base_url, self.server_id)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[allow(unused)]
+pub struct PowerDnsZone {
+    pub id: String,
+    pub name: String,
+    #[serde(default)]
+    pub kind: String,
+    #[serde(default)]
+    pub rrsets: Vec<PowerDnsRRSet>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PowerDnsRRSet {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub type_: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changetype: Option<String>,
+    #[serde(default)]
+    pub records: Vec<PowerDnsRecord>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comments: Vec<PowerDnsComment>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PowerDnsRecord {
+    pub content: String,
+    #[serde(default)]
+    pub disabled: bool,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct PowerDnsComment {
+    pub content: String,
+    #[serde(default)]
+    pub account: String,
+}
+
+#[derive(Debug, Serialize)]
+struct PowerDnsPatchZoneBody {
+    rrsets: Vec<PowerDnsRRSet>,
+}
+
+#[cfg(test)]
+pub mod testhelpers {
+    pub const LIST_ZONES_RESPONSE: &str = r#"[
+      {
+        "account": "",
+        "dnssec": false,
+        "edited_serial": 2024010101,
+        "id": "example.com.",
+        "kind": "Native",
+        "last_check": 0,
+        "masters": [],
+        "name": "example.com.",
+        "notified_serial": 0,
+        "serial": 2024010101,
+        "url": "/api/v1/servers/localhost/zones/example.com."
+      }
+    ]"#;
+
+    pub const GET_ZONE_RESPONSE: &str = r#"{
+      "id": "example.com.",
+      "kind": "Native",
+      "name": "example.com.",
+      "serial": 2024010101,
+      "url": "/api/v1/servers/localhost/zones/example.com.",
+      "rrsets": [
+        {
+          "comments": [{ "content": "pond-owner instance=pond deployment=example", "account": "pond", "modified_at": 1704067200 }],
+          "name": "www.example.com.",
+          "records": [{ "content": "198.51.100.4", "disabled": false }],
+          "ttl": 300,
+          "type": "A"
+        },
+        {
+          "comments": [],
+          "name": "example.com.",
+          "records": [{ "content": "ns1.example.com. hostmaster.example.com. 1 10800 3600 604800 3600", "disabled": false }],
+          "ttl": 3600,
+          "type": "SOA"
+        }
+      ]
+    }"#;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::{Matcher, Server, ServerGuard};
+
+    fn test_client(server: &ServerGuard) -> PowerDnsClient {
+        PowerDnsClient::new(
+            server.url(),
+            "localhost".to_string(),
+            "test_api_key".to_string(),
+        )
+    }
+
+    #[test]
+    fn test_list_zones() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/api/v1/servers/localhost/zones")
+            .match_header("X-API-Key", "test_api_key")
+            .match_query(Matcher::UrlEncoded("zone".into(), "example.com.".into()))
+            .with_status(200)
+            .with_body(testhelpers::LIST_ZONES_RESPONSE)
+            .create();
+
+        let zones = test_client(&server).list_zones("example.com.").unwrap();
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].id, "example.com.");
+    }
+
+    #[test]
+    fn test_get_zone() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("GET", "/api/v1/servers/localhost/zones/example.com.")
+            .with_status(200)
+            .with_body(testhelpers::GET_ZONE_RESPONSE)
+            .create();
+
+        let zone = test_client(&server).get_zone("example.com.").unwrap();
+        assert_eq!(zone.rrsets.len(), 2);
+        assert_eq!(zone.rrsets[0].records[0].content, "198.51.100.4");
+        assert_eq!(
+            zone.rrsets[0].comments[0].content,
+            "pond-owner instance=pond deployment=example"
+        );
+    }
+
+    #[test]
+    fn test_patch_rrsets() {
+        let rrsets = vec![PowerDnsRRSet {
+            name: "www.example.com.".to_string(),
+            type_: "A".to_string(),
+            ttl: Some(300),
+            changetype: Some("REPLACE".to_string()),
+            records: vec![PowerDnsRecord {
+                content: "198.51.100.5".to_string(),
+                disabled: false,
+            }],
+            comments: vec![PowerDnsComment {
+                content: "pond-owner instance=pond deployment=example".to_string(),
+                account: "pond".to_string(),
+            }],
+        }];
+
+        let mut server = Server::new();
+        let _m = server
+            .mock("PATCH", "/api/v1/servers/localhost/zones/example.com.")
+            .match_body(Matcher::Json(serde_json::json!({ "rrsets": rrsets })))
+            .with_status(204)
+            .create();
+
+        test_client(&server)
+            .patch_rrsets("example.com.", rrsets)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_patch_rrsets_error() {
+        let mut server = Server::new();
+        let _m = server
+            .mock("PATCH", "/api/v1/servers/localhost/zones/example.com.")
+            .with_status(422)
+            .with_body(
+                r#"{"error": "RRset www.example.com. IN A: Conflicts with pre-existing RRset"}"#,
+            )
+            .create();
+
+        let result = test_client(&server).patch_rrsets("example.com.", vec![]);
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("Conflicts with pre-existing RRset"));
+    }
+}
@@ -0,0 +1,473 @@
+use anyhow::anyhow;
+use client::{PowerDnsComment, PowerDnsRRSet, PowerDnsRecord, PowerDnsZone};
+use figment::{providers::Serialized, Figment};
+use mockall_double::double;
+use serde::{Deserialize, Serialize};
+
+mod client;
+
+use super::{DnsRecord, DnsService, RecordOwnership, RegisterDnsService, ZoneCache};
+use crate::config::ConfigurationError;
+
+#[double]
+use client::PowerDnsClient;
+
+pub struct PowerDnsService {
+    client: PowerDnsClient,
+    ttl: u32,
+    zones: ZoneCache<PowerDnsZone>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PowerDnsServiceConfig {
+    #[serde(skip_serializing)]
+    api_key: String,
+    base_url: String,
+    server_id: String,
+    dns_ttl: u32,
+}
+
+impl Default for PowerDnsServiceConfig {
+    fn default() -> Self {
+        Self {
+            api_key: Default::default(),
+            base_url: "http://localhost:8081".to_owned(),
+            server_id: "localhost".to_owned(),
+            dns_ttl: 300,
+        }
+    }
+}
+
+impl RegisterDnsService for PowerDnsService {
+    fn provider_name() -> &'static str {
+        "powerdns"
+    }
+
+    fn configure(figment: &Figment) -> Result<Self, ConfigurationError> {
+        let configuration = figment.extract_inner::<PowerDnsServiceConfig>("powerdns")?;
+        Ok(Self {
+            client: PowerDnsClient::new(
+                configuration.base_url,
+                configuration.server_id,
+                configuration.api_key,
+            ),
+            ttl: configuration.dns_ttl,
+            zones: ZoneCache::new(),
+        })
+    }
+
+    fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(
+            "powerdns",
+            PowerDnsServiceConfig::default(),
+        ))
+    }
+}
+
+/// PowerDNS expects fully qualified names terminated by a dot.
+fn canonical_name(domain_name: &str) -> String {
+    format!("{}.", domain_name.trim_end_matches('.'))
+}
+
+impl PowerDnsService {
+    fn get_zone(&self, domain_name: &str) -> anyhow::Result<PowerDnsZone> {
+        self.zones
+            .find_zone(domain_name, |name| self.find_zone_by_name(name))
+    }
+
+    fn find_zone_by_name(&self, name: &str) -> anyhow::Result<Option<PowerDnsZone>> {
+        let zone_name = canonical_name(name);
+        let mut zones: Vec<PowerDnsZone> = self
+            .client
+            .list_zones(&zone_name)?
+            .into_iter()
+            .filter(|z| z.name == zone_name)
+            .collect();
+
+        match zones.len() {
+            0 | 1 => Ok(zones.pop()),
+            _ => Err(anyhow!("Too many zones for domain {:?}", zones)),
+        }
+    }
+}
+
+impl DnsService for PowerDnsService {
+    /// The ownership marker is kept in the comments of the RRSet.
+    fn set_dns_record(
+        &self,
+        domain_name: &str,
+        record: &DnsRecord,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        let zone = self.get_zone(domain_name)?;
+        let name = canonical_name(domain_name);
+        if matches!(record, DnsRecord::Cname(_)) && name == zone.name {
+            return Err(anyhow!(
+                "PowerDNS doesn't support CNAME records at the zone apex {}",
+                zone.name
+            ));
+        }
+
+        // The zone listing doesn't include records, so the current RRSets are fetched separately
+        let existing_rrsets: Vec<PowerDnsRRSet> = self
+            .client
+            .get_zone(&zone.id)?
+            .rrsets
+            .into_iter()
+            .filter(|r| r.name == name && record.occupies(&r.type_) && !r.records.is_empty())
+            .collect();
+        for rrset in &existing_rrsets {
+            let marker = rrset.comments.first().map(|c| c.content.as_str());
+            ownership.verify(domain_name, marker)?;
+        }
+
+        let mut rrsets: Vec<PowerDnsRRSet> = existing_rrsets
+            .into_iter()
+            .filter(|r| r.type_ != record.record_type())
+            .map(|r| {
+                info!(
+                    "Deleting conflicting {} records for {}",
+                    r.type_, domain_name
+                );
+                PowerDnsRRSet {
+                    name: r.name,
+                    type_: r.type_,
+                    changetype: Some("DELETE".to_owned()),
+                    ..Default::default()
+                }
+            })
+            .collect();
+        let content = match record {
+            DnsRecord::Address(_) => record.content(),
+            DnsRecord::Cname(target) => canonical_name(target),
+        };
+        // REPLACE swaps the whole RRSet, so duplicate records are collapsed into one
+        rrsets.push(PowerDnsRRSet {
+            name,
+            type_: record.record_type().to_owned(),
+            ttl: Some(self.ttl),
+            changetype: Some("REPLACE".to_owned()),
+            records: vec![PowerDnsRecord {
+                content,
+                disabled: false,
+            }],
+            comments: vec![PowerDnsComment {
+                content: ownership.marker(),
+                account: ownership.instance_id.clone(),
+            }],
+        });
+        self.client.patch_rrsets(&zone.id, rrsets)
+    }
+
+    fn get_dns_records(&self, domain_name: &str) -> anyhow::Result<Vec<DnsRecord>> {
+        let zone = self.get_zone(domain_name)?;
+        let name = canonical_name(domain_name);
+        Ok(self
+            .client
+            .get_zone(&zone.id)?
+            .rrsets
+            .iter()
+            .filter(|r| r.name == name)
+            .flat_map(|r| {
+                r.records
+                    .iter()
+                    .filter(|record| !record.disabled)
+                    .filter_map(|record| DnsRecord::parse(&r.type_, &record.content))
+            })
+            .collect())
+    }
+
+    fn delete_dns_records(
+        &self,
+        domain_name: &str,
+        ownership: &RecordOwnership,
+    ) -> anyhow::Result<()> {
+        let zone = self.get_zone(domain_name)?;
+        let name = canonical_name(domain_name);
+        let rrsets: Vec<PowerDnsRRSet> = self
+            .client
+            .get_zone(&zone.id)?
+            .rrsets
+            .into_iter()
+            .filter(|r| {
+                r.name == name
+                    && ["A", "AAAA", "CNAME"].contains(&r.type_.as_str())
+                    && !r.records.is_empty()
+            })
+            .collect();
+        if rrsets.is_empty() {
+            return Ok(());
+        }
+        for rrset in &rrsets {
+            let marker = rrset.comments.first().map(|c| c.content.as_str());
+            ownership.verify(domain_name, marker)?;
+        }
+
+        info!("Deleting records for {}", domain_name);
+        let rrsets = rrsets
+            .into_iter()
+            .map(|r| PowerDnsRRSet {
+                name: r.name,
+                type_: r.type_,
+                changetype: Some("DELETE".to_owned()),
+                ..Default::default()
+            })
+            .collect();
+        self.client.patch_rrsets(&zone.id, rrsets)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::IpAddr, str::FromStr};
+
+    use super::*;
+
+    fn zone() -> PowerDnsZone {
+        PowerDnsZone {
+            id: "example.com.".to_string(),
+            name: "example.com.".to_string(),
+            kind: "Native".to_string(),
+            rrsets: vec![],
+        }
+    }
+
+    fn service(client: PowerDnsClient) -> PowerDnsService {
+        PowerDnsService {
+            client,
+            ttl: 60,
+            zones: ZoneCache::new(),
+        }
+    }
+
+    #[test]
+    fn test_canonical_name() {
+        assert_eq!(canonical_name("example.com"), "example.com.");
+        assert_eq!(canonical_name("example.com."), "example.com.");
+    }
+
+    fn rrset(comment: Option<&str>) -> PowerDnsRRSet {
+        PowerDnsRRSet {
+            name: "www.example.com.".to_string(),
+            type_: "A".to_string(),
+            ttl: Some(300),
+            changetype: None,
+            records: vec![PowerDnsRecord {
+                content: "198.51.100.4".to_string(),
+                disabled: false,
+            }],
+            comments: comment
+                .map(|content| PowerDnsComment {
+                    content: content.to_string(),
+                    account: "pond".to_string(),
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn ownership() -> RecordOwnership {
+        RecordOwnership::new("pond", "example")
+    }
+
+    fn address(ip_address: &str) -> DnsRecord {
+        DnsRecord::Address(IpAddr::from_str(ip_address).unwrap())
+    }
+
+    fn mock_zone(mock: &mut PowerDnsClient, rrsets: Vec<PowerDnsRRSet>) {
+        mock.expect_list_zones()
+            .withf(|name| name == "www.example.com." || name == "example.com.")
+            .returning(|name| match name {
+                "example.com." => Ok(vec![zone()]),
+                _ => Ok(vec![]),
+            });
+        mock.expect_get_zone().returning(move |_| {
+            Ok(PowerDnsZone {
+                rrsets: rrsets.clone(),
+                ..zone()
+            })
+        });
+    }
+
+    #[test]
+    fn test_replaces_rrset() {
+        let mut mock = PowerDnsClient::default();
+        mock_zone(
+            &mut mock,
+            vec![rrset(Some("pond-owner instance=pond deployment=example"))],
+        );
+        mock.expect_patch_rrsets()
+            .times(1)
+            .returning(|zone_id, rrsets| {
+                assert_eq!(zone_id, "example.com.");
+                assert_eq!(rrsets.len(), 1);
+                assert_eq!(rrsets[0].name, "www.example.com.");
+                assert_eq!(rrsets[0].type_, "A");
+                assert_eq!(rrsets[0].ttl, Some(60));
+                assert_eq!(rrsets[0].records[0].content, "127.0.0.1");
+                assert_eq!(
+                    rrsets[0].comments[0].content,
+                    "pond-owner instance=pond deployment=example"
+                );
+                Ok(())
+            });
+
+        service(mock)
+            .set_dns_record("www.example.com", &address("127.0.0.1"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_duplicate_records_are_replaced_by_one() {
+        let mut mock = PowerDnsClient::default();
+        let mut duplicates = rrset(Some("pond-owner instance=pond deployment=example"));
+        duplicates.records.push(PowerDnsRecord {
+            content: "198.51.100.5".to_string(),
+            disabled: false,
+        });
+        mock_zone(&mut mock, vec![duplicates]);
+        mock.expect_patch_rrsets().times(1).returning(|_, rrsets| {
+            assert_eq!(rrsets.len(), 1);
+            let contents: Vec<&str> = rrsets[0]
+                .records
+                .iter()
+                .map(|r| r.content.as_str())
+                .collect();
+            assert_eq!(contents, vec!["127.0.0.1"]);
+            Ok(())
+        });
+
+        service(mock)
+            .set_dns_record("www.example.com", &address("127.0.0.1"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_creates_missing_rrset() {
+        let mut mock = PowerDnsClient::default();
+        mock_zone(&mut mock, vec![]);
+        mock.expect_patch_rrsets().times(1).returning(|_, _| Ok(()));
+
+        service(mock)
+            .set_dns_record("www.example.com", &address("127.0.0.1"), &ownership())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_foreign_rrset_is_refused() {
+        let mut mock = PowerDnsClient::default();
+        mock_zone(&mut mock, vec![rrset(None)]);
+        mock.expect_patch_rrsets().never();
+
+        let result =
+            service(mock).set_dns_record("www.example.com", &address("127.0.0.1"), &ownership());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_foreign_rrset_is_taken_over() {
+        let mut mock = PowerDnsClient::default();
+        mock_zone(
+            &mut mock,
+            vec![rrset(Some("pond-owner instance=pond deployment=shop"))],
+        );
+        mock.expect_patch_rrsets().times(1).returning(|_, _| Ok(()));
+
+        let ownership = RecordOwnership {
+            takeover: true,
+            ..ownership()
+        };
+        service(mock)
+            .set_dns_record("www.example.com", &address("127.0.0.1"), &ownership)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_unknown_zone_fails() {
+        let mut mock = PowerDnsClient::default();
+        mock.expect_list_zones().returning(|_| Ok(vec![]));
+
+        let result =
+            service(mock).set_dns_record("www.example.com", &address("127.0.0.1"), &ownership());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_get_dns_records_skips_disabled_records() {
+        let mut mock = PowerDnsClient::default();
+        let mut disabled = rrset(None);
+        disabled.type_ = "AAAA".to_string();
+        disabled.records = vec![PowerDnsRecord {
+            content: "::1".to_string(),
+            disabled: true,
+        }];
+        mock_zone(&mut mock, vec![rrset(None), disabled]);
+
+        let records = service(mock).get_dns_records("www.example.com").unwrap();
+        assert_eq!(records, vec![address("198.51.100.4")]);
+    }
+
+    #[test]
+    fn test_cname_deletes_owned_address_rrset() {
+        let mut mock = PowerDnsClient::default();
+        mock_zone(
+            &mut mock,
+            vec![rrset(Some("pond-owner instance=pond deployment=example"))],
+        );
+        mock.expect_patch_rrsets().times(1).returning(|_, rrsets| {
+            assert_eq!(rrsets.len(), 2);
+            assert_eq!(rrsets[0].type_, "A");
+            assert_eq!(rrsets[0].changetype.as_deref(), Some("DELETE"));
+            assert_eq!(rrsets[1].type_, "CNAME");
+            assert_eq!(rrsets[1].records[0].content, "blog.example.org.");
+            Ok(())
+        });
+
+        service(mock)
+            .set_dns_record(
+                "www.example.com",
+                &DnsRecord::cname("blog.example.org"),
+                &ownership(),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_apex_cname_fails() {
+        let mut mock = PowerDnsClient::default();
+        mock_zone(&mut mock, vec![]);
+
+        let result = service(mock).set_dns_record(
+            "example.com",
+            &DnsRecord::cname("blog.example.org"),
+            &ownership(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_delete_dns_records_deletes_owned_rrsets() {
+        let mut mock = PowerDnsClient::default();
+        mock_zone(
+            &mut mock,
+            vec![rrset(Some("pond-owner instance=pond deployment=example"))],
+        );
+        mock.expect_patch_rrsets().times(1).returning(|_, rrsets| {
+            assert_eq!(rrsets.len(), 1);
+            assert_eq!(rrsets[0].type_, "A");
+            assert_eq!(rrsets[0].changetype.as_deref(), Some("DELETE"));
+            Ok(())
+        });
+
+        service(mock)
+            .delete_dns_records("www.example.com", &ownership())
+            .unwrap();
+
+        let mut mock = PowerDnsClient::default();
+        mock_zone(&mut mock, vec![rrset(None)]);
+        mock.expect_patch_rrsets().never();
+        assert!(service(mock)
+            .delete_dns_records("www.example.com", &ownership())
+            .is_err());
+    }
+}
@@ -0,0 +1,365 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use figment::{providers::Serialized, Figment};
+use serde::{Deserialize, Serialize};
+
+use super::{desired_records, DnsRecord, RecordOwnership};
+use crate::{
+    config::ConfigurationError,
+    deployments::DeploymentStore,
+    root_domains::{RootDomain, RootDomains},
+};
+
+/// A record of a deployed domain that doesn't match what pond configures. `actual` holds the
+/// records of the same type and the records conflicting with it.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DnsDrift {
+    pub deployment_name: String,
+    pub domain_name: String,
+    pub expected: DnsRecord,
+    pub actual: Vec<DnsRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct DnsReconcilerConfig {
+    auto_reconcile: bool,
+    reconcile_interval_seconds: u64,
+}
+
+impl Default for DnsReconcilerConfig {
+    fn default() -> Self {
+        Self {
+            auto_reconcile: false,
+            reconcile_interval_seconds: 300,
+        }
+    }
+}
+
+/// A drifted record along with what is needed to repair it.
+type Repair = (Arc<RootDomain>, RecordOwnership, DnsDrift);
+
+/// Compares the records of every deployed domain with the addresses of its root domain and, if
+/// `auto_reconcile` is enabled, points them back at pond. Deployments below root domains whose
+/// records pond doesn't manage are skipped.
+pub struct DnsReconciler {
+    deployments: Arc<DeploymentStore>,
+    instance_id: String,
+    root_domains: Arc<RootDomains>,
+    auto_reconcile: bool,
+    interval: Duration,
+}
+
+impl DnsReconciler {
+    pub fn configure(
+        figment: &Figment,
+        deployments: Arc<DeploymentStore>,
+        instance_id: String,
+        root_domains: Arc<RootDomains>,
+    ) -> Result<Self, ConfigurationError> {
+        let config: DnsReconcilerConfig = figment.extract_inner("dns")?;
+        Ok(DnsReconciler {
+            deployments,
+            instance_id,
+            root_domains,
+            auto_reconcile: config.auto_reconcile,
+            interval: Duration::from_secs(config.reconcile_interval_seconds),
+        })
+    }
+
+    pub fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default("dns", DnsReconcilerConfig::default()))
+    }
+
+    /// The drift of every deployed domain, or of those below the root domain an access token
+    /// is restricted to. Domains whose records can't be read are logged and left out.
+    pub fn detect_drift(
+        &self,
+        token_root_domain_name: Option<&str>,
+    ) -> anyhow::Result<Vec<DnsDrift>> {
+        Ok(self
+            .find_drift(token_root_domain_name)?
+            .into_iter()
+            .map(|(_, _, drift)| drift)
+            .collect())
+    }
+
+    /// Logs the current drift and repairs it when `auto_reconcile` is enabled. Domains whose
+    /// records can't be read or repaired are logged and skipped.
+    pub fn reconcile(&self) -> anyhow::Result<()> {
+        for (root_domain, ownership, drift) in self.find_drift(None)? {
+            warn!(
+                "DNS drift for {} of deployment {}: expected {} but found {:?}",
+                drift.domain_name, drift.deployment_name, drift.expected, drift.actual
+            );
+            if !self.auto_reconcile {
+                continue;
+            }
+
+            info!("Setting {} record {}", drift.domain_name, drift.expected);
+            if let Err(e) = root_domain.dns_service.set_dns_record(
+                &drift.domain_name,
+                &drift.expected,
+                &ownership,
+            ) {
+                error!(
+                    "Failed to repair {} record of {}: {:?}",
+                    drift.expected.record_type(),
+                    drift.domain_name,
+                    e
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs [`DnsReconciler::reconcile`] every `reconcile_interval_seconds`.
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(self.interval);
+            if let Err(e) = self.reconcile() {
+                error!("Failed to reconcile DNS records: {:?}", e);
+            }
+        })
+    }
+
+    fn find_drift(&self, root_domain_name: Option<&str>) -> anyhow::Result<Vec<Repair>> {
+        let mut result = vec![];
+        let deployments = self.deployments.list()?;
+        for manifest in &deployments {
+            let root_domain = self.root_domains.for_manifest(manifest);
+            if !root_domain.manages_records
+                || root_domain_name.is_some_and(|name| name != root_domain.name)
+            {
+                continue;
+            }
+            let ownership = RecordOwnership {
+                takeover: manifest.takeover,
+                ..RecordOwnership::new(&self.instance_id, &manifest.name)
+            }
+            .adopting(manifest, &deployments);
+            // Domains whose records couldn't be read are `None` and skipped
+            let mut records: HashMap<String, Option<Vec<DnsRecord>>> = HashMap::new();
+            for (domain_name, expected) in
+                desired_records(manifest, &root_domain.name, &root_domain.ip_addresses())
+            {
+                let domain_records = records.entry(domain_name.clone()).or_insert_with(|| {
+                    root_domain
+                        .dns_service
+                        .get_dns_records(&domain_name)
+                        .inspect_err(|e| {
+                            error!("Failed to read DNS records of {}: {:?}", domain_name, e)
+                        })
+                        .ok()
+                });
+                let Some(domain_records) = domain_records else {
+                    continue;
+                };
+                let actual: Vec<DnsRecord> = domain_records
+                    .iter()
+                    .filter(|r| expected.occupies(r.record_type()))
+                    .cloned()
+                    .collect();
+                if actual != [expected.clone()] {
+                    let drift = DnsDrift {
+                        deployment_name: manifest.name.clone(),
+                        domain_name,
+                        expected,
+                        actual,
+                    };
+                    result.push((root_domain.clone(), ownership.clone(), drift));
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::IpAddr, str::FromStr};
+
+    use super::*;
+    use crate::{ingress::dns::MockDnsService, manifest::test::manifest, Manifest};
+
+    fn deployments() -> Arc<DeploymentStore> {
+        let deployments = DeploymentStore::in_memory();
+        deployments
+            .record(
+                &Manifest {
+                    cname_domains: vec!["example.org".to_owned()],
+                    ..manifest("blog", &["blog.example.com", "example.com"])
+                },
+                "digest",
+                None,
+            )
+            .unwrap();
+        deployments
+            .record(
+                &Manifest {
+                    root_domain_name: Some("example.net".to_owned()),
+                    ..manifest("shop", &["shop.example.net"])
+                },
+                "digest",
+                None,
+            )
+            .unwrap();
+        Arc::new(deployments)
+    }
+
+    fn reconciler(dns_service: MockDnsService, auto_reconcile: bool) -> DnsReconciler {
+        let mut root_domains = RootDomains::new(RootDomain {
+            name: "example.com".to_owned(),
+            dns_service: Arc::new(dns_service),
+            manages_records: true,
+            ip_v4_address: Some("203.0.113.10".parse().unwrap()),
+            ip_v6_address: Some("2001:db8::10".parse().unwrap()),
+        });
+        // Records below this root are managed by someone else and must not be looked up
+        root_domains.add(RootDomain {
+            name: "example.net".to_owned(),
+            dns_service: Arc::new(MockDnsService::new()),
+            manages_records: false,
+            ip_v4_address: Some("203.0.113.20".parse().unwrap()),
+            ip_v6_address: None,
+        });
+        DnsReconciler {
+            deployments: deployments(),
+            instance_id: "pond".to_owned(),
+            root_domains: Arc::new(root_domains),
+            auto_reconcile,
+            interval: Duration::from_secs(1),
+        }
+    }
+
+    fn address(ip_address: &str) -> DnsRecord {
+        DnsRecord::Address(IpAddr::from_str(ip_address).unwrap())
+    }
+
+    fn dns_service_with_drift() -> MockDnsService {
+        let mut dns_service = MockDnsService::new();
+        dns_service
+            .expect_get_dns_records()
+            .returning(|domain_name| match domain_name {
+                "blog.example.com" => Ok(vec![
+                    address("203.0.113.10"),
+                    address("2001:db8::10"),
+                    DnsRecord::cname("ignored.example.com"),
+                ]),
+                _ => Ok(vec![address("198.51.100.4")]),
+            });
+        dns_service
+    }
+
+    #[test]
+    fn test_detects_changed_and_missing_records() {
+        let drift = reconciler(dns_service_with_drift(), false)
+            .detect_drift(None)
+            .unwrap();
+        assert_eq!(
+            drift,
+            vec![
+                DnsDrift {
+                    deployment_name: "blog".to_owned(),
+                    domain_name: "blog.example.com".to_owned(),
+                    expected: address("203.0.113.10"),
+                    actual: vec![
+                        address("203.0.113.10"),
+                        DnsRecord::cname("ignored.example.com")
+                    ],
+                },
+                DnsDrift {
+                    deployment_name: "blog".to_owned(),
+                    domain_name: "blog.example.com".to_owned(),
+                    expected: address("2001:db8::10"),
+                    actual: vec![
+                        address("2001:db8::10"),
+                        DnsRecord::cname("ignored.example.com")
+                    ],
+                },
+                DnsDrift {
+                    deployment_name: "blog".to_owned(),
+                    domain_name: "example.com".to_owned(),
+                    expected: address("203.0.113.10"),
+                    actual: vec![address("198.51.100.4")],
+                },
+                DnsDrift {
+                    deployment_name: "blog".to_owned(),
+                    domain_name: "example.com".to_owned(),
+                    expected: address("2001:db8::10"),
+                    actual: vec![],
+                },
+                DnsDrift {
+                    deployment_name: "blog".to_owned(),
+                    domain_name: "example.org".to_owned(),
+                    expected: DnsRecord::cname("blog.example.com"),
+                    actual: vec![address("198.51.100.4")],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_drift_is_only_repaired_with_auto_reconcile() {
+        let mut dns_service = dns_service_with_drift();
+        dns_service.expect_set_dns_record().never();
+        reconciler(dns_service, false).reconcile().unwrap();
+
+        let mut dns_service = dns_service_with_drift();
+        dns_service
+            .expect_set_dns_record()
+            .times(5)
+            .returning(|_, _, ownership| {
+                assert_eq!(ownership.deployment_name, "blog");
+                Ok(())
+            });
+        reconciler(dns_service, true).reconcile().unwrap();
+    }
+
+    #[test]
+    fn test_drift_is_limited_to_the_root_domain_of_the_token() {
+        let drift = reconciler(dns_service_with_drift(), false)
+            .detect_drift(Some("example.com"))
+            .unwrap();
+        assert_eq!(drift.len(), 5);
+
+        let mut dns_service = MockDnsService::new();
+        dns_service.expect_get_dns_records().never();
+        let drift = reconciler(dns_service, false)
+            .detect_drift(Some("example.org"))
+            .unwrap();
+        assert!(drift.is_empty());
+    }
+
+    #[test]
+    fn test_unreadable_domains_are_skipped() {
+        let mut dns_service = MockDnsService::new();
+        dns_service
+            .expect_get_dns_records()
+            .times(3)
+            .returning(|domain_name| match domain_name {
+                "example.com" => Err(anyhow::anyhow!("Zone is unavailable")),
+                _ => Ok(vec![]),
+            });
+        let drift = reconciler(dns_service, false).detect_drift(None).unwrap();
+        let domain_names: Vec<&str> = drift.iter().map(|d| d.domain_name.as_str()).collect();
+        assert_eq!(
+            domain_names,
+            vec!["blog.example.com", "blog.example.com", "example.org"]
+        );
+    }
+
+    #[test]
+    fn test_failed_repairs_do_not_stop_reconciliation() {
+        let mut dns_service = dns_service_with_drift();
+        dns_service
+            .expect_set_dns_record()
+            .times(5)
+            .returning(|_, _, _| Err(anyhow::anyhow!("Record is owned by somebody else")));
+        reconciler(dns_service, true).reconcile().unwrap();
+    }
+}
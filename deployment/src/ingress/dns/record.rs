@@ -0,0 +1,120 @@
+use std::{fmt, net::IpAddr};
+
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+/// A record pond points a domain name at.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DnsRecord {
+    /// An `A` or `AAAA` record, depending on the address.
+    Address(IpAddr),
+    /// A `CNAME` record pointing at the host name. Providers that support it flatten the
+    /// record at the zone apex.
+    Cname(String),
+}
+
+impl DnsRecord {
+    pub fn cname(target: &str) -> Self {
+        DnsRecord::Cname(target.trim_end_matches('.').to_lowercase())
+    }
+
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            DnsRecord::Address(IpAddr::V4(_)) => "A",
+            DnsRecord::Address(IpAddr::V6(_)) => "AAAA",
+            DnsRecord::Cname(_) => "CNAME",
+        }
+    }
+
+    /// The record value without a trailing dot.
+    pub fn content(&self) -> String {
+        match self {
+            DnsRecord::Address(ip_address) => ip_address.to_string(),
+            DnsRecord::Cname(target) => target.clone(),
+        }
+    }
+
+    /// Whether a record of `type_` occupies the same name and type slot as this record.
+    ///
+    /// A `CNAME` can't coexist with other records of the same name, so it conflicts with
+    /// the address records pond manages and vice versa.
+    pub fn conflicts_with(&self, type_: &str) -> bool {
+        match self {
+            DnsRecord::Address(_) => type_ == "CNAME",
+            DnsRecord::Cname(_) => type_ == "A" || type_ == "AAAA",
+        }
+    }
+
+    /// Whether an existing record of `type_` is this kind of record or conflicts with it.
+    pub(crate) fn occupies(&self, type_: &str) -> bool {
+        self.record_type() == type_ || self.conflicts_with(type_)
+    }
+
+    /// Parses an `A`, `AAAA` or `CNAME` record, ignoring records of other types.
+    pub(crate) fn parse(type_: &str, value: &str) -> Option<DnsRecord> {
+        match type_ {
+            "A" | "AAAA" => value.parse().ok().map(DnsRecord::Address),
+            "CNAME" => Some(DnsRecord::cname(value)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.record_type(), self.content())
+    }
+}
+
+impl Serialize for DnsRecord {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut record = serializer.serialize_struct("DnsRecord", 2)?;
+        record.serialize_field("type", self.record_type())?;
+        record.serialize_field("value", &self.content())?;
+        record.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            DnsRecord::parse("A", "127.0.0.1"),
+            Some(DnsRecord::Address(IpAddr::V4(Ipv4Addr::LOCALHOST)))
+        );
+        assert_eq!(
+            DnsRecord::parse("AAAA", "::1"),
+            Some(DnsRecord::Address(IpAddr::V6(Ipv6Addr::LOCALHOST)))
+        );
+        assert_eq!(
+            DnsRecord::parse("CNAME", "Blog.Example.com."),
+            Some(DnsRecord::Cname("blog.example.com".to_owned()))
+        );
+        assert_eq!(DnsRecord::parse("TXT", "127.0.0.1"), None);
+        assert_eq!(DnsRecord::parse("A", "not an address"), None);
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let address = DnsRecord::Address(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let cname = DnsRecord::cname("blog.example.com");
+        assert!(address.occupies("A"));
+        assert!(address.occupies("CNAME"));
+        assert!(!address.occupies("AAAA"));
+        assert!(cname.occupies("A"));
+        assert!(cname.occupies("AAAA"));
+        assert!(!cname.occupies("TXT"));
+    }
+
+    #[test]
+    fn test_serialize() {
+        assert_eq!(
+            serde_json::to_value(DnsRecord::cname("blog.example.com")).unwrap(),
+            serde_json::json!({ "type": "CNAME", "value": "blog.example.com" })
+        );
+    }
+}
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use figment::Figment;
+
+use super::{
+    cloudflare::CloudflareDnsService, digitalocean::DigitalOceanDnsService,
+    hetzner::HetznerDnsService, powerdns::PowerDnsService, DnsService, NoOpDnsService,
+};
+use crate::config::ConfigurationError;
+
+const DNS_PROVIDER: &str = "dns.provider";
+const LEGACY_CLOUDFLARE_ENABLED: &str = "cloudflare.enabled";
+
+type BoxedDnsService = Box<dyn DnsService + Send + Sync + 'static>;
+
+pub trait RegisterDnsService: DnsService + Sized {
+    fn provider_name() -> &'static str;
+
+    fn configure(figment: &Figment) -> Result<Self, ConfigurationError>;
+
+    fn figment_default_values() -> Figment {
+        Figment::new()
+    }
+}
+
+struct DnsProvider {
+    configure: fn(&Figment) -> Result<BoxedDnsService, ConfigurationError>,
+    figment_default_values: fn() -> Figment,
+}
+
+pub struct DnsServiceRegistry {
+    providers: HashMap<&'static str, DnsProvider>,
+}
+
+impl DnsServiceRegistry {
+    pub fn new() -> DnsServiceRegistry {
+        DnsServiceRegistry {
+            providers: HashMap::new(),
+        }
+    }
+
+    pub fn register<D: RegisterDnsService + Send + Sync + 'static>(&mut self) {
+        self.providers.insert(
+            D::provider_name(),
+            DnsProvider {
+                configure: configure_boxed::<D>,
+                figment_default_values: D::figment_default_values,
+            },
+        );
+    }
+
+    pub fn figment_default_values(&self) -> Figment {
+        self.providers
+            .values()
+            .fold(Figment::new(), |figment, provider| {
+                figment.join((provider.figment_default_values)())
+            })
+    }
+
+    pub fn configure(&self, figment: &Figment) -> Result<BoxedDnsService, ConfigurationError> {
+        let provider_name = Self::provider_name(figment)?;
+        let provider = self
+            .providers
+            .get(provider_name.as_str())
+            .ok_or(ConfigurationError::UnknownDnsProvider(provider_name))?;
+        (provider.configure)(figment)
+    }
+
+    /// Whether the configured provider manages records at all.
+    pub fn manages_records(figment: &Figment) -> Result<bool, ConfigurationError> {
+        Ok(Self::provider_name(figment)? != NoOpDnsService::provider_name())
+    }
+
+    fn provider_name(figment: &Figment) -> Result<String, ConfigurationError> {
+        if figment.contains(DNS_PROVIDER) {
+            return Ok(figment.extract_inner(DNS_PROVIDER)?);
+        }
+
+        // Configurations written before `dns.provider` existed only know about cloudflare
+        let cloudflare_enabled = figment
+            .extract_inner::<bool>(LEGACY_CLOUDFLARE_ENABLED)
+            .unwrap_or(false);
+        if cloudflare_enabled {
+            Ok(CloudflareDnsService::provider_name().to_owned())
+        } else {
+            Ok(NoOpDnsService::provider_name().to_owned())
+        }
+    }
+}
+
+fn configure_boxed<D: RegisterDnsService + Send + Sync + 'static>(
+    figment: &Figment,
+) -> Result<BoxedDnsService, ConfigurationError> {
+    Ok(Box::new(D::configure(figment)?))
+}
+
+impl Default for DnsServiceRegistry {
+    fn default() -> Self {
+        let mut registry = DnsServiceRegistry::new();
+        registry.register::<NoOpDnsService>();
+        registry.register::<CloudflareDnsService>();
+        registry.register::<HetznerDnsService>();
+        registry.register::<DigitalOceanDnsService>();
+        registry.register::<PowerDnsService>();
+        registry
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use figment::providers::Serialized;
+
+    use super::*;
+
+    fn figment(values: serde_json::Value) -> Figment {
+        DnsServiceRegistry::default()
+            .figment_default_values()
+            .merge(Serialized::globals(values))
+    }
+
+    #[test]
+    fn test_missing_provider_defaults_to_no_op() {
+        let figment = figment(serde_json::json!({}));
+        assert_eq!(DnsServiceRegistry::provider_name(&figment).unwrap(), "none");
+        assert!(!DnsServiceRegistry::manages_records(&figment).unwrap());
+    }
+
+    #[test]
+    fn test_legacy_cloudflare_enabled_selects_cloudflare() {
+        let figment = figment(serde_json::json!({
+            "cloudflare": { "enabled": true, "api_key": "key" }
+        }));
+        assert_eq!(
+            DnsServiceRegistry::provider_name(&figment).unwrap(),
+            "cloudflare"
+        );
+        assert!(DnsServiceRegistry::manages_records(&figment).unwrap());
+    }
+
+    #[test]
+    fn test_explicit_provider_wins_over_legacy_flag() {
+        let figment = figment(serde_json::json!({
+            "dns": { "provider": "none" },
+            "cloudflare": { "enabled": true }
+        }));
+        assert_eq!(DnsServiceRegistry::provider_name(&figment).unwrap(), "none");
+        assert!(DnsServiceRegistry::default().configure(&figment).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_provider_is_rejected() {
+        let figment = figment(serde_json::json!({ "dns": { "provider": "route53" } }));
+        let result = DnsServiceRegistry::default().configure(&figment);
+        assert!(matches!(
+            result,
+            Err(ConfigurationError::UnknownDnsProvider(name)) if name == "route53"
+        ));
+    }
+
+    #[test]
+    fn test_provider_without_credentials_is_rejected() {
+        let figment = figment(serde_json::json!({ "dns": { "provider": "hetzner" } }));
+        let result = DnsServiceRegistry::default().configure(&figment);
+        assert!(matches!(
+            result,
+            Err(ConfigurationError::MissingConfigurationValue(_))
+        ));
+    }
+
+    #[test]
+    fn test_default_providers_are_registered() {
+        let registry = DnsServiceRegistry::default();
+        for provider in ["none", "cloudflare", "hetzner", "digitalocean", "powerdns"] {
+            assert!(registry.providers.contains_key(provider));
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use anyhow::anyhow;
+
+/// Returns the names that could be the zone of `domain_name`, most specific first.
+///
+/// The walk stops at the registrable domain according to the Public Suffix List, so
+/// `www.example.co.uk` yields `www.example.co.uk` and `example.co.uk` but never `co.uk`.
+pub(crate) fn zone_candidates(domain_name: &str) -> Vec<&str> {
+    let domain_name = domain_name.trim_end_matches('.');
+    let registrable_domain = match psl::domain_str(domain_name) {
+        Some(registrable_domain) => registrable_domain,
+        None => return vec![domain_name],
+    };
+
+    let mut candidates = vec![];
+    let mut candidate = domain_name;
+    loop {
+        candidates.push(candidate);
+        if candidate.len() <= registrable_domain.len() {
+            break;
+        }
+        match candidate.split_once('.') {
+            Some((_, parent)) => candidate = parent,
+            None => break,
+        }
+    }
+    candidates
+}
+
+/// Remembers which zone hosts a domain so every record update doesn't walk the zones again.
+pub(crate) struct ZoneCache<Z> {
+    zones: Mutex<HashMap<String, Z>>,
+}
+
+impl<Z: Clone> ZoneCache<Z> {
+    pub fn new() -> Self {
+        ZoneCache {
+            zones: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Finds the zone of `domain_name` by asking `lookup` for every candidate name until it
+    /// returns a zone.
+    pub fn find_zone(
+        &self,
+        domain_name: &str,
+        mut lookup: impl FnMut(&str) -> anyhow::Result<Option<Z>>,
+    ) -> anyhow::Result<Z> {
+        let domain_name = domain_name.trim_end_matches('.').to_lowercase();
+        if let Some(zone) = self.lock()?.get(&domain_name) {
+            return Ok(zone.clone());
+        }
+
+        for candidate in zone_candidates(&domain_name) {
+            if let Some(zone) = lookup(candidate)? {
+                self.lock()?.insert(domain_name, zone.clone());
+                return Ok(zone);
+            }
+        }
+        Err(anyhow!("No zone found for domain {}", domain_name))
+    }
+
+    fn lock(&self) -> anyhow::Result<std::sync::MutexGuard<'_, HashMap<String, Z>>> {
+        self.zones
+            .lock()
+            .map_err(|_e| anyhow!("Zone cache mutex is poisoned"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_zone_candidates() {
+        assert_eq!(zone_candidates("example.com"), vec!["example.com"]);
+        assert_eq!(
+            zone_candidates("a.dev.example.com"),
+            vec!["a.dev.example.com", "dev.example.com", "example.com"]
+        );
+        assert_eq!(
+            zone_candidates("www.example.co.uk."),
+            vec!["www.example.co.uk", "example.co.uk"]
+        );
+        assert_eq!(zone_candidates("co.uk"), vec!["co.uk"]);
+    }
+
+    #[test]
+    fn test_find_zone_prefers_delegated_subzone() {
+        let cache = ZoneCache::new();
+        let zone = cache
+            .find_zone("app.dev.example.com", |name| {
+                Ok(["dev.example.com", "example.com"]
+                    .contains(&name)
+                    .then(|| name.to_owned()))
+            })
+            .unwrap();
+        assert_eq!(zone, "dev.example.com");
+    }
+
+    #[test]
+    fn test_find_zone_is_cached() {
+        let cache = ZoneCache::new();
+        let mut lookups = 0;
+        for _ in 0..3 {
+            let zone = cache
+                .find_zone("www.example.co.uk", |name| {
+                    lookups += 1;
+                    Ok((name == "example.co.uk").then(|| name.to_owned()))
+                })
+                .unwrap();
+            assert_eq!(zone, "example.co.uk");
+        }
+        assert_eq!(lookups, 2);
+    }
+
+    #[test]
+    fn test_find_zone_without_match_fails() {
+        let cache: ZoneCache<String> = ZoneCache::new();
+        assert!(cache.find_zone("www.example.com", |_| Ok(None)).is_err());
+    }
+
+    #[test]
+    fn test_find_zone_propagates_lookup_errors() {
+        let cache: ZoneCache<String> = ZoneCache::new();
+        let result = cache.find_zone("www.example.com", |_| Err(anyhow!("API down")));
+        assert_eq!(result.unwrap_err().to_string(), "API down");
+    }
+}
@@ -4,14 +4,33 @@ pub use nginx::NginxStaticSiteIngressService;
 use std::io;
 use std::path::Path;
 
-use crate::deployer::DeploymentHandle;
+use crate::{deployer::DeploymentHandle, maintenance::Maintenance, Manifest};
 
+#[cfg(test)]
+use mockall::automock;
+
+#[cfg_attr(test, automock)]
 pub trait StaticSiteIngressService {
     fn add_static_site_ingress(
         &self,
-        deployment_name: &str,
+        manifest: &Manifest,
         disk_location: &Path,
-        domain_names: &[String],
+        message_stream: DeploymentHandle,
+    ) -> io::Result<()>;
+
+    /// Serves the maintenance page instead of the deployment, or the deployment again for
+    /// `None`, until the next call. Redeploying keeps the maintenance page.
+    fn set_static_site_maintenance(
+        &self,
+        manifest: &Manifest,
+        maintenance: Option<Maintenance>,
+        message_stream: DeploymentHandle,
+    ) -> io::Result<()>;
+
+    /// Stops serving the deployment and removes its DNS records.
+    fn remove_static_site_ingress(
+        &self,
+        manifest: &Manifest,
         message_stream: DeploymentHandle,
     ) -> io::Result<()>;
 }
@@ -2,25 +2,47 @@ use figment::{providers::Serialized, Figment};
 use handlebars::Handlebars;
 use serde::{Deserialize, Serialize};
 use std::{
-    io,
-    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    fs, io,
+    net::IpAddr,
     path::{Path, PathBuf},
     process::Command,
+    sync::Arc,
     thread,
     time::Duration,
 };
 
 use super::StaticSiteIngressService;
-use crate::{config::ConfigurationError, deployer::DeploymentHandle, ingress::dns::DnsService};
+use crate::{
+    config::ConfigurationError,
+    deployer::DeploymentHandle,
+    deployments::DeploymentStore,
+    ingress::dns::{desired_records, RecordOwnership},
+    maintenance::{Maintenance, BYPASS_COOKIE_NAME},
+    root_domains::{RootDomain, RootDomains},
+    Manifest,
+};
+
+/// Where `static_site.sh` puts the files of each site.
+const SITES_DIRECTORY: &str = "/var/www";
+const DEFAULT_MAINTENANCE_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><title>Maintenance</title></head>
+<body><h1>Down for maintenance</h1><p>We will be back soon.</p></body>
+</html>
+";
 
 pub struct NginxStaticSiteIngressService {
     handlebars: Handlebars<'static>,
+    pub instance_id: String,
+    pub root_domains: Arc<RootDomains>,
+    /// The recorded deployments, whose domains aren't adopted by other deployments.
+    pub deployments: Arc<DeploymentStore>,
     pub certbot_command_name: String,
+    pub nginx_command_name: String,
     pub nginx_sites_available: PathBuf,
     pub nginx_sites_enabled: PathBuf,
-    pub dns_service: Box<dyn DnsService + 'static + Send + Sync>,
-    pub ip_v4_address: Option<Ipv4Addr>,
-    pub ip_v6_address: Option<Ipv6Addr>,
+    /// Where the maintenance pages of sites are kept.
+    pub maintenance_path: PathBuf,
     pub dns_wait_timeout: std::time::Duration,
     pub dns_fixed_wait_timeout: std::time::Duration,
     pub dns_use_fixed_wait_timeout: bool,
@@ -29,19 +51,23 @@ pub struct NginxStaticSiteIngressService {
 impl NginxStaticSiteIngressService {
     pub fn configure(
         figment: &Figment,
-        dns_service: Box<dyn DnsService + 'static + Send + Sync>,
+        instance_id: String,
+        root_domains: Arc<RootDomains>,
+        deployments: Arc<DeploymentStore>,
     ) -> Result<Self, ConfigurationError> {
         let handlebars = Handlebars::new();
         let config: NginxIngressConfig = figment.extract_inner("nginx_ingress")?;
 
         Ok(NginxStaticSiteIngressService {
             handlebars,
-            dns_service,
+            instance_id,
+            root_domains,
+            deployments,
             certbot_command_name: config.certbot_command_name,
+            nginx_command_name: config.nginx_command_name,
             nginx_sites_available: config.sites_available_path,
             nginx_sites_enabled: config.sites_enabled_path,
-            ip_v4_address: config.ip_v4_address,
-            ip_v6_address: config.ip_v6_address,
+            maintenance_path: config.maintenance_path,
             dns_wait_timeout: Duration::from_secs(config.dns_wait_timeout_seconds),
             dns_fixed_wait_timeout: Duration::from_secs(config.dns_fixed_wait_timeout_seconds),
             dns_use_fixed_wait_timeout: config.dns_use_fixed_wait_timeout,
@@ -52,8 +78,10 @@ impl NginxStaticSiteIngressService {
         Figment::from(Serialized::defaults(serde_json::json!({
             "nginx_ingress":{
                 "certbot_command_name": "certbot",
+                "nginx_command_name": "nginx",
                 "sites_available_path": "/etc/nginx/sites-available",
                 "sites_enabled_path": "/etc/nginx/sites-enabled",
+                "maintenance_path": "/var/lib/pond/maintenance",
                 "dns_use_fixed_wait_timeout": true,
                 "dns_fixed_wait_timeout_seconds": 10,
                 "dns_wait_timeout_seconds": 30
@@ -73,60 +101,105 @@ impl NginxStaticSiteIngressService {
             command.args(["--domain", d]);
         }
 
-        crate::helpers::run_command(command, deployment_handle.clone())?;
+        crate::helpers::run_command(
+            command,
+            &crate::execution::ExecutionSettings::default(),
+            deployment_handle.clone(),
+        )?;
+        Ok(())
+    }
+
+    /// Certbot names certificates after their first domain.
+    fn delete_certificate(
+        &self,
+        domain_names: &[String],
+        deployment_handle: &mut DeploymentHandle,
+    ) -> io::Result<()> {
+        let Some(certificate_name) = domain_names.first() else {
+            return Ok(());
+        };
+        let mut command = Command::new(&self.certbot_command_name);
+        command.args(["delete", "-n", "--cert-name", certificate_name]);
+        let exit_status = crate::helpers::run_command(
+            command,
+            &crate::execution::ExecutionSettings::default(),
+            deployment_handle.clone(),
+        )?;
+        if !exit_status.success() {
+            return Err(io::Error::other(format!(
+                "certbot exited with status {}",
+                exit_status
+            )));
+        }
         Ok(())
     }
 
     fn set_dns_records(
         &self,
         deployment_handle: &mut DeploymentHandle,
-        domain_name: &str,
+        manifest: &Manifest,
+        root_domain: &RootDomain,
+        ownership: &RecordOwnership,
     ) -> anyhow::Result<()> {
-        if let Some(ip_address) = self.ip_v4_address {
+        let ip_addresses = root_domain.ip_addresses();
+        if ip_addresses.is_empty() {
             writeln!(
                 deployment_handle.info(),
-                "Setting DNS record for domain {} to IPv4 address {}",
-                domain_name,
-                ip_address
+                "No IP addresses configured. Not setting any records"
             )
             .ok();
-            self.dns_service
-                .set_dns_record(domain_name, IpAddr::V4(ip_address))?;
+            return Ok(());
         }
 
-        if let Some(ip_address) = self.ip_v6_address {
+        for (domain_name, record) in desired_records(manifest, &root_domain.name, &ip_addresses) {
             writeln!(
                 deployment_handle.info(),
-                "Setting DNS record for domain {} to IPv6 address {}",
+                "Setting DNS record for domain {} to {}",
                 domain_name,
-                ip_address
+                record
             )
             .ok();
-            self.dns_service
-                .set_dns_record(domain_name, IpAddr::V6(ip_address))?;
+            root_domain
+                .dns_service
+                .set_dns_record(&domain_name, &record, ownership)?;
         }
+        Ok(())
+    }
 
-        if self.ip_v4_address.is_none() && self.ip_v6_address.is_none() {
+    fn delete_dns_records(
+        &self,
+        deployment_handle: &mut DeploymentHandle,
+        manifest: &Manifest,
+        root_domain: &RootDomain,
+    ) -> anyhow::Result<()> {
+        if root_domain.ip_addresses().is_empty() {
+            return Ok(());
+        }
+        let ownership = RecordOwnership::new(&self.instance_id, &manifest.name)
+            .adopting(manifest, &self.deployments.list()?);
+        let mut domain_names = manifest.served_domain_names();
+        domain_names.sort();
+        domain_names.dedup();
+        for domain_name in &domain_names {
             writeln!(
                 deployment_handle.info(),
-                "No IP addresses configured. Not setting any records"
+                "Deleting DNS records of domain {}",
+                domain_name
             )
             .ok();
+            root_domain
+                .dns_service
+                .delete_dns_records(domain_name, &ownership)?;
         }
-
         Ok(())
     }
 
-    fn wait_for_dns_records(&self, domain_name: &str) -> anyhow::Result<()> {
-        let mut records: Vec<_> = vec![];
-        if let Some(add) = self.ip_v4_address {
-            records.push(IpAddr::V4(add));
-        }
-        if let Some(add) = self.ip_v6_address {
-            records.push(IpAddr::V6(add));
-        }
-
-        if records.is_empty() {
+    fn wait_for_dns_records(
+        &self,
+        domain_name: &str,
+        ip_addresses: Vec<IpAddr>,
+    ) -> anyhow::Result<()> {
+        if ip_addresses.is_empty() {
             return Ok(());
         }
         if self.dns_use_fixed_wait_timeout {
@@ -135,12 +208,84 @@ impl NginxStaticSiteIngressService {
         } else {
             crate::ingress::dns::wait_for_dns_records(
                 domain_name,
-                records.into_iter(),
+                ip_addresses.into_iter(),
                 self.dns_wait_timeout,
             )
         }
     }
 
+    fn deployment_data<'a>(
+        &self,
+        manifest: &'a Manifest,
+        disk_location: &'a Path,
+    ) -> io::Result<NginxStaticSiteDeploymentData<'a>> {
+        // Variable names can't contain hyphens
+        let variable_suffix = manifest.name.replace('-', "_");
+        let maintenance =
+            self.read_maintenance(&manifest.name)?
+                .map(|maintenance| NginxMaintenanceData {
+                    variable: format!("pond_maintenance_{}", variable_suffix),
+                    page_path: self.maintenance_files(&manifest.name).1,
+                    bypass_ips: maintenance.bypass_ips,
+                    bypass_cookie: maintenance.bypass_cookie,
+                    bypass_cookie_name: BYPASS_COOKIE_NAME,
+                });
+        Ok(NginxStaticSiteDeploymentData {
+            deployment_name: &manifest.name,
+            disk_location,
+            domain_names: manifest.served_domain_names().join(" "),
+            canary_weight: manifest.canary.as_ref().map(|c| c.weight),
+            root_variable: format!("pond_root_{}", variable_suffix),
+            split_variable: format!("pond_split_{}", variable_suffix),
+            maintenance,
+        })
+    }
+
+    /// The settings and the page of the maintenance of `deployment_name`.
+    fn maintenance_files(&self, deployment_name: &str) -> (PathBuf, PathBuf) {
+        (
+            self.maintenance_path
+                .join(format!("{}.json", deployment_name)),
+            self.maintenance_path
+                .join(format!("{}.html", deployment_name)),
+        )
+    }
+
+    fn read_maintenance(&self, deployment_name: &str) -> io::Result<Option<Maintenance>> {
+        let (settings_path, _) = self.maintenance_files(deployment_name);
+        match fs::read(&settings_path) {
+            Ok(settings) => Ok(Some(serde_json::from_slice(&settings)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write_maintenance(
+        &self,
+        deployment_name: &str,
+        maintenance: Option<&Maintenance>,
+    ) -> io::Result<()> {
+        let (settings_path, page_path) = self.maintenance_files(deployment_name);
+        let Some(maintenance) = maintenance else {
+            for path in [settings_path, page_path] {
+                match fs::remove_file(path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            return Ok(());
+        };
+        fs::create_dir_all(&self.maintenance_path)?;
+        fs::write(
+            page_path,
+            maintenance
+                .page
+                .as_deref()
+                .unwrap_or(DEFAULT_MAINTENANCE_PAGE),
+        )?;
+        fs::write(settings_path, serde_json::to_vec(maintenance)?)
+    }
+
     fn configure_nginx(
         &self,
         data: NginxStaticSiteDeploymentData<'_>,
@@ -169,39 +314,82 @@ impl NginxStaticSiteIngressService {
             .ok();
         })?;
         writeln!(deployment_handle.info(), "Enabling site through symlink").ok();
-        Ok(if !sites_enabled_path.exists() {
+        if !sites_enabled_path.exists() {
             #[cfg(unix)]
             std::os::unix::fs::symlink(sites_available_path, sites_enabled_path)?;
             #[cfg(not(unix))]
             panic!("Windows not supported");
-        })
+        }
+        Ok(())
+    }
+
+    fn remove_nginx_config(
+        &self,
+        deployment_name: &str,
+        deployment_handle: &mut DeploymentHandle,
+    ) -> io::Result<()> {
+        let file_name = deployment_name.to_owned() + ".conf";
+        for path in [
+            self.nginx_sites_enabled.join(&file_name),
+            self.nginx_sites_available.join(&file_name),
+        ] {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    writeln!(
+                        deployment_handle.error(),
+                        "Failed to remove file {:?} due to error: {:?}",
+                        path,
+                        e
+                    )
+                    .ok();
+                    return Err(e);
+                }
+                _ => {}
+            }
+        }
+
+        let mut command = Command::new(&self.nginx_command_name);
+        command.args(["-s", "reload"]);
+        let exit_status = crate::helpers::run_command(
+            command,
+            &crate::execution::ExecutionSettings::default(),
+            deployment_handle.clone(),
+        )?;
+        if !exit_status.success() {
+            return Err(io::Error::other(format!(
+                "Reloading nginx exited with status {}",
+                exit_status
+            )));
+        }
+        Ok(())
     }
 }
 
 impl StaticSiteIngressService for NginxStaticSiteIngressService {
     fn add_static_site_ingress(
         &self,
-        deployment_name: &str,
+        manifest: &Manifest,
         disk_location: &Path,
-        domain_names: &[String],
         mut deployment_handle: DeploymentHandle,
     ) -> io::Result<()> {
-        for domain_name in domain_names {
-            self.set_dns_records(&mut deployment_handle, domain_name)
-                .map_err(io::Error::other)?;
+        let domain_names = &manifest.served_domain_names();
+        let root_domain = self.root_domains.for_manifest(manifest);
+        let recorded = self.deployments.list().map_err(io::Error::other)?;
+        let ownership = RecordOwnership {
+            takeover: manifest.takeover,
+            ..RecordOwnership::new(&self.instance_id, &manifest.name)
         }
+        .adopting(manifest, &recorded);
+        self.set_dns_records(&mut deployment_handle, manifest, &root_domain, &ownership)
+            .map_err(io::Error::other)?;
 
         writeln!(deployment_handle.info(), "Waiting for DNS records").ok();
         for domain_name in domain_names {
-            self.wait_for_dns_records(domain_name)
+            self.wait_for_dns_records(domain_name, root_domain.ip_addresses())
                 .map_err(io::Error::other)?;
         }
 
-        let data = NginxStaticSiteDeploymentData {
-            deployment_name,
-            disk_location,
-            domain_names: &domain_names.join(" "),
-        };
+        let data = self.deployment_data(manifest, disk_location)?;
         write!(deployment_handle.info(), "Configuring nginx").ok();
         self.configure_nginx(data, &mut deployment_handle)?;
 
@@ -211,22 +399,79 @@ impl StaticSiteIngressService for NginxStaticSiteIngressService {
 
         Ok(())
     }
+
+    fn set_static_site_maintenance(
+        &self,
+        manifest: &Manifest,
+        maintenance: Option<Maintenance>,
+        mut deployment_handle: DeploymentHandle,
+    ) -> io::Result<()> {
+        self.write_maintenance(&manifest.name, maintenance.as_ref())?;
+        let site_directory = Path::new(SITES_DIRECTORY).join(&manifest.name);
+        let data = self.deployment_data(manifest, &site_directory)?;
+        writeln!(deployment_handle.info(), "Configuring nginx").ok();
+        self.configure_nginx(data, &mut deployment_handle)?;
+        // Writing the configuration drops the certificate, which certbot installs again
+        self.run_certbot(&manifest.served_domain_names(), &mut deployment_handle)
+    }
+
+    fn remove_static_site_ingress(
+        &self,
+        manifest: &Manifest,
+        mut deployment_handle: DeploymentHandle,
+    ) -> io::Result<()> {
+        writeln!(deployment_handle.info(), "Removing nginx configuration").ok();
+        self.remove_nginx_config(&manifest.name, &mut deployment_handle)?;
+        self.write_maintenance(&manifest.name, None)?;
+
+        // A certificate left behind only fails to renew, so removal goes on without it
+        let domain_names = manifest.served_domain_names();
+        if let Err(e) = self.delete_certificate(&domain_names, &mut deployment_handle) {
+            writeln!(
+                deployment_handle.error(),
+                "Failed to delete the certificate: {}",
+                e
+            )
+            .ok();
+        }
+
+        let root_domain = self.root_domains.for_manifest(manifest);
+        self.delete_dns_records(&mut deployment_handle, manifest, &root_domain)
+            .map_err(io::Error::other)?;
+        Ok(())
+    }
 }
 
 #[derive(Serialize)]
 struct NginxStaticSiteDeploymentData<'a> {
     deployment_name: &'a str,
     disk_location: &'a Path,
-    domain_names: &'a str,
+    domain_names: String,
+    /// Splits visitors between the roots of the deployment and its canary.
+    canary_weight: Option<u8>,
+    root_variable: String,
+    /// The root picked for a visitor, unless a health check asks for the canary.
+    split_variable: String,
+    maintenance: Option<NginxMaintenanceData>,
+}
+
+#[derive(Serialize)]
+struct NginxMaintenanceData {
+    /// Set to 0 for the addresses that bypass the maintenance page.
+    variable: String,
+    page_path: PathBuf,
+    bypass_ips: Vec<String>,
+    bypass_cookie: Option<String>,
+    bypass_cookie_name: &'static str,
 }
 
 #[derive(Deserialize, Serialize)]
 struct NginxIngressConfig {
     certbot_command_name: String,
+    nginx_command_name: String,
     sites_available_path: PathBuf,
     sites_enabled_path: PathBuf,
-    ip_v4_address: Option<Ipv4Addr>,
-    ip_v6_address: Option<Ipv6Addr>,
+    maintenance_path: PathBuf,
     dns_wait_timeout_seconds: u64,
     dns_fixed_wait_timeout_seconds: u64,
     dns_use_fixed_wait_timeout: bool,
@@ -236,10 +481,10 @@ impl Default for NginxIngressConfig {
     fn default() -> Self {
         NginxIngressConfig {
             certbot_command_name: "certbot".to_owned(),
+            nginx_command_name: "nginx".to_owned(),
             sites_available_path: "/etc/nginx/sites-available".into(),
             sites_enabled_path: "/etc/nginx/sites-enabled".into(),
-            ip_v4_address: None,
-            ip_v6_address: None,
+            maintenance_path: "/var/lib/pond/maintenance".into(),
             dns_wait_timeout_seconds: 30,
             dns_fixed_wait_timeout_seconds: 10,
             dns_use_fixed_wait_timeout: true,
@@ -249,20 +494,33 @@ impl Default for NginxIngressConfig {
 
 #[cfg(test)]
 mod test {
-    use crate::ingress::dns::MockDnsService;
+    use crate::{
+        deployments::DeploymentStore,
+        ingress::dns::{DnsRecord, MockDnsService},
+        root_domains::{RootDomain, RootDomains},
+        Manifest,
+    };
 
     use super::{NginxStaticSiteIngressService, StaticSiteIngressService};
-    use std::{io, net::Ipv4Addr};
+    use std::{io, net::Ipv4Addr, sync::Arc};
 
     fn test_nginx_ingress_service(dns_service: MockDnsService) -> NginxStaticSiteIngressService {
         NginxStaticSiteIngressService {
             handlebars: handlebars::Handlebars::new(),
+            instance_id: "pond".to_owned(),
+            root_domains: Arc::new(RootDomains::new(RootDomain {
+                name: "example.com".to_owned(),
+                dns_service: Arc::new(dns_service),
+                manages_records: true,
+                ip_v4_address: Some(Ipv4Addr::new(127, 0, 0, 1)),
+                ip_v6_address: None,
+            })),
+            deployments: Arc::new(DeploymentStore::in_memory()),
             certbot_command_name: "echo".to_owned(),
+            nginx_command_name: "echo".to_owned(),
             nginx_sites_available: std::env::temp_dir().join("sites-available"),
             nginx_sites_enabled: std::env::temp_dir().join("sites-enabled"),
-            dns_service: Box::new(dns_service),
-            ip_v4_address: Some(Ipv4Addr::new(127, 0, 0, 1)),
-            ip_v6_address: None,
+            maintenance_path: std::env::temp_dir().join("pond-maintenance"),
             dns_wait_timeout: std::time::Duration::from_secs(1),
             dns_fixed_wait_timeout: std::time::Duration::from_secs(0),
             dns_use_fixed_wait_timeout: true,
@@ -276,11 +534,16 @@ mod test {
         dns_service
             .expect_set_dns_record()
             .times(1)
-            .returning(|_, addr| {
+            .returning(|_, record, ownership| {
                 assert_eq!(
-                    addr,
-                    std::net::IpAddr::V4(std::net::Ipv4Addr::new(127, 0, 0, 1))
+                    record,
+                    &DnsRecord::Address(std::net::IpAddr::V4(std::net::Ipv4Addr::new(
+                        127, 0, 0, 1
+                    )))
                 );
+                assert_eq!(ownership.instance_id, "pond");
+                assert_eq!(ownership.deployment_name, "test_site");
+                assert!(!ownership.takeover);
                 Ok(())
             });
 
@@ -296,9 +559,8 @@ mod test {
 
         service
             .add_static_site_ingress(
-                "test_site",
+                &crate::manifest::test::manifest("test_site", &["localhost"]),
                 "/var/www/test_site".as_ref(),
-                &["localhost".to_owned()],
                 message_stream,
             )
             .unwrap();
@@ -313,4 +575,225 @@ mod test {
 
         assert!(command_output.contains("--nginx -n --expand --domain localhost\n"))
     }
+
+    #[test]
+    fn test_legacy_records_are_adopted_unless_another_deployment_serves_them() {
+        let mut dns_service = MockDnsService::new();
+        dns_service
+            .expect_set_dns_record()
+            .times(2)
+            .returning(|domain_name, _, ownership| {
+                ownership.verify(domain_name, Some("Record created by pond"))
+            });
+        let mut service = test_nginx_ingress_service(dns_service);
+        std::fs::create_dir(&service.nginx_sites_available).ok();
+        std::fs::create_dir(&service.nginx_sites_enabled).ok();
+        let manifest = Manifest::parse(
+            "name = \"legacy-site\"\ndeployment_type = \"static-site\"\ndomain_names = [\"localhost\"]",
+            None,
+        )
+        .unwrap();
+
+        // Sites deployed before the upgrade aren't recorded yet
+        let (message_stream, _) = crate::deployer::deployment_handle();
+        service
+            .add_static_site_ingress(&manifest, "/var/www/legacy-site".as_ref(), message_stream)
+            .unwrap();
+
+        let deployments = DeploymentStore::in_memory();
+        let other = Manifest {
+            name: "other-site".to_owned(),
+            ..manifest.clone()
+        };
+        deployments.record(&other, "digest", None).unwrap();
+        service.deployments = Arc::new(deployments);
+        let (message_stream, _) = crate::deployer::deployment_handle();
+        let error = service
+            .add_static_site_ingress(&manifest, "/var/www/legacy-site".as_ref(), message_stream)
+            .unwrap_err();
+        assert!(error.to_string().contains("takeover"), "{}", error);
+    }
+
+    #[test]
+    fn test_canary_splits_traffic() {
+        let (message_stream, _message_consumer) = crate::deployer::deployment_handle();
+        let mut dns_service = MockDnsService::new();
+        dns_service
+            .expect_set_dns_record()
+            .returning(|_, _, _| Ok(()));
+        let service = test_nginx_ingress_service(dns_service);
+        std::fs::create_dir(&service.nginx_sites_available).ok();
+        std::fs::create_dir(&service.nginx_sites_enabled).ok();
+
+        service
+            .add_static_site_ingress(
+                &Manifest {
+                    canary: Some(crate::manifest::Canary { weight: 10 }),
+                    ..crate::manifest::test::manifest("canary-site", &["localhost"])
+                },
+                "/var/www/canary-site.canary".as_ref(),
+                message_stream,
+            )
+            .unwrap();
+        let config =
+            std::fs::read_to_string(service.nginx_sites_available.join("canary-site.conf"))
+                .unwrap();
+        assert!(
+            config.contains(
+                "$pond_split_canary_site {\n    10% /var/www/canary-site.canary;\n    * /var/www/canary-site;\n}"
+            ),
+            "{}",
+            config
+        );
+        assert!(
+            config.contains(
+                "map $http_x_pond_canary $pond_root_canary_site {\n    \"1\" /var/www/canary-site.canary;\n    default $pond_split_canary_site;\n}"
+            ),
+            "{}",
+            config
+        );
+        assert!(
+            config.contains("root $pond_root_canary_site;"),
+            "{}",
+            config
+        );
+    }
+
+    #[test]
+    fn test_remove_static_site_ingress() {
+        let (message_stream, _message_consumer) = crate::deployer::deployment_handle();
+        let mut dns_service = MockDnsService::new();
+        dns_service
+            .expect_delete_dns_records()
+            .times(2)
+            .returning(|domain_name, ownership| {
+                assert!(["removed.example.com", "www.example.org"].contains(&domain_name));
+                assert_eq!(ownership.deployment_name, "removed");
+                Ok(())
+            });
+        let service = test_nginx_ingress_service(dns_service);
+        std::fs::create_dir(&service.nginx_sites_available).ok();
+        std::fs::create_dir(&service.nginx_sites_enabled).ok();
+        let available = service.nginx_sites_available.join("removed.conf");
+        let enabled = service.nginx_sites_enabled.join("removed.conf");
+        std::fs::write(&available, "server {}").unwrap();
+        std::fs::remove_file(&enabled).ok();
+        std::os::unix::fs::symlink(&available, &enabled).unwrap();
+
+        let manifest = Manifest::parse(
+            "name = \"removed\"\ndeployment_type = \"static-site\"\ndomain_names = [\"removed.example.com\"]\ncname_domains = [\"www.example.org\"]",
+            None,
+        )
+        .unwrap();
+        service
+            .remove_static_site_ingress(&manifest, message_stream)
+            .unwrap();
+        assert!(!available.exists());
+        assert!(!enabled.exists());
+    }
+
+    #[test]
+    fn test_maintenance_survives_redeploys() {
+        let mut dns_service = MockDnsService::new();
+        dns_service
+            .expect_set_dns_record()
+            .returning(|_, _, _| Ok(()));
+        let service = test_nginx_ingress_service(dns_service);
+        std::fs::create_dir(&service.nginx_sites_available).ok();
+        std::fs::create_dir(&service.nginx_sites_enabled).ok();
+        let manifest = Manifest::parse(
+            "name = \"maintained-site\"\ndeployment_type = \"static-site\"\ndomain_names = [\"localhost\"]",
+            None,
+        )
+        .unwrap();
+        let config_path = service.nginx_sites_available.join("maintained-site.conf");
+        let maintenance = crate::maintenance::Maintenance {
+            page: Some("<h1>Back soon</h1>".to_owned()),
+            bypass_ips: vec!["203.0.113.0/24".to_owned()],
+            bypass_cookie: Some("let-me-in-0123456789".to_owned()),
+        };
+
+        let (message_stream, _) = crate::deployer::deployment_handle();
+        service
+            .set_static_site_maintenance(&manifest, Some(maintenance), message_stream)
+            .unwrap();
+        let page_path = service.maintenance_path.join("maintained-site.html");
+        assert_eq!(
+            std::fs::read_to_string(&page_path).unwrap(),
+            "<h1>Back soon</h1>"
+        );
+        let (message_stream, _) = crate::deployer::deployment_handle();
+        service
+            .add_static_site_ingress(
+                &manifest,
+                "/var/www/maintained-site".as_ref(),
+                message_stream,
+            )
+            .unwrap();
+        let config = std::fs::read_to_string(&config_path).unwrap();
+        for expected in [
+            "geo $pond_maintenance_maintained_site {\n    default 1;\n    203.0.113.0/24 0;\n}",
+            &format!("alias {};", page_path.display()),
+            "if ($cookie_pond_maintenance_bypass = \"let-me-in-0123456789\") {",
+            "return 503;",
+            "root /var/www/maintained-site;",
+        ] {
+            assert!(config.contains(expected), "{}", config);
+        }
+
+        let (message_stream, _) = crate::deployer::deployment_handle();
+        service
+            .set_static_site_maintenance(&manifest, None, message_stream)
+            .unwrap();
+        let config = std::fs::read_to_string(&config_path).unwrap();
+        assert!(!config.contains("503"), "{}", config);
+        assert!(!page_path.exists());
+    }
+
+    #[test]
+    fn test_maintenance_of_canaries() {
+        let mut dns_service = MockDnsService::new();
+        dns_service
+            .expect_set_dns_record()
+            .returning(|_, _, _| Ok(()));
+        let service = test_nginx_ingress_service(dns_service);
+        std::fs::create_dir(&service.nginx_sites_available).ok();
+        std::fs::create_dir(&service.nginx_sites_enabled).ok();
+        let manifest = Manifest {
+            canary: Some(crate::manifest::Canary { weight: 10 }),
+            ..Manifest::parse(
+                "name = \"maintained-canary\"\ndeployment_type = \"static-site\"\ndomain_names = [\"localhost\"]",
+                None,
+            )
+            .unwrap()
+        };
+        let maintenance = crate::maintenance::Maintenance {
+            bypass_cookie: Some("let-me-in-0123456789".to_owned()),
+            ..Default::default()
+        };
+
+        let (message_stream, _) = crate::deployer::deployment_handle();
+        service
+            .set_static_site_maintenance(&manifest, Some(maintenance), message_stream)
+            .unwrap();
+        let config =
+            std::fs::read_to_string(service.nginx_sites_available.join("maintained-canary.conf"))
+                .unwrap();
+        for expected in [
+            "split_clients \"${remote_addr}${http_user_agent}\" $pond_split_maintained_canary {",
+            "geo $pond_maintenance_maintained_canary {\n    default 1;\n}",
+            "root $pond_root_maintained_canary;",
+            "set $pond_maintenance $pond_maintenance_maintained_canary;",
+            "if ($pond_maintenance) {\n        return 503;\n    }",
+        ] {
+            assert!(config.contains(expected), "{}", config);
+        }
+        // Locations with their own root would serve the main deployment to everyone
+        assert!(!config.contains("location /"), "{}", config);
+        let roots = config
+            .lines()
+            .filter(|line| line.trim_start().starts_with("root "))
+            .count();
+        assert_eq!(roots, 1, "{}", config);
+    }
 }
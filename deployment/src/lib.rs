@@ -1,17 +1,52 @@
 #[macro_use]
 extern crate log;
 
+mod artifact;
+mod artifact_store;
+mod build;
 mod deployer;
+mod deployments;
+mod domains;
+mod execution;
+mod git;
+mod health;
 mod helpers;
 mod ingress;
+mod maintenance;
 mod manager;
 mod manifest;
+mod reaper;
+mod releases;
+mod root_domains;
+mod secrets;
+mod state;
+mod uploads;
+mod verification;
 
 pub mod config;
 
+pub use artifact_store::{ArtifactReference, ArtifactStore, StoredArtifact};
+pub use build::Builder;
 pub use deployer::deployment_handle;
 pub use deployer::Deployer;
 pub use deployer::DeploymentLogs;
 pub use deployer::LogStream;
-pub use manager::DeploymentManager;
-pub use manifest::Manifest;
+pub use deployer::Redactions;
+pub use deployments::{Deployment, DeploymentStore};
+pub use domains::{DomainClaim, DomainVerificationError, DomainVerifier, TokenScope};
+pub use execution::{ExecutionSettings, ResourceLimits};
+pub use git::GitSettings;
+pub use health::HealthChecker;
+pub use ingress::dns::{DnsDrift, DnsReconciler};
+pub use maintenance::Maintenance;
+pub use manager::{ArtifactSource, DeploymentError, DeploymentManager, DeploymentRequest};
+pub use manifest::{
+    BuildStep, Canary, GitSource, HealthCheck, Manifest, ManifestError, ManifestFormat,
+    ManifestSource,
+};
+pub use reaper::DeploymentReaper;
+pub use releases::{Release, ReleaseStore, SyncError, SyncSession, SyncedFile};
+pub use root_domains::{RootDomain, RootDomains};
+pub use secrets::{ResolvedEnvironment, SecretError, SecretStore};
+pub use uploads::{UploadError, UploadSession, UploadStore};
+pub use verification::{ArtifactVerification, PublicKey, SigningKeys, VerificationError};
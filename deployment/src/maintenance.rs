@@ -0,0 +1,101 @@
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+/// Name of the cookie that lets visitors through to a site in maintenance.
+pub const BYPASS_COOKIE_NAME: &str = "pond_maintenance_bypass";
+
+/// Serves a page with status 503 instead of a site, except to the allowed addresses and to
+/// visitors with the bypass cookie.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Maintenance {
+    /// HTML served instead of the site. Defaults to a short notice.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub page: Option<String>,
+    /// Addresses or CIDR ranges, e.g. `203.0.113.0/24`, that still see the site.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bypass_ips: Vec<String>,
+    /// Visitors whose `pond_maintenance_bypass` cookie has this value still see the site.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bypass_cookie: Option<String>,
+}
+
+impl Maintenance {
+    /// Makes sure the addresses and the cookie can be put into the configuration of the
+    /// web server as they are.
+    pub fn validate(&self) -> Result<(), String> {
+        for address in &self.bypass_ips {
+            if !is_valid_address_range(address) {
+                return Err(format!(
+                    "{:?} is neither an IP address nor a CIDR range",
+                    address
+                ));
+            }
+        }
+        if let Some(cookie) = &self.bypass_cookie {
+            let valid = (16..=128).contains(&cookie.len())
+                && cookie
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                return Err(
+                    "The bypass cookie has to be 16 to 128 letters, digits, hyphens or underscores"
+                        .to_owned(),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_address_range(range: &str) -> bool {
+    let (address, prefix_length) = match range.split_once('/') {
+        Some((address, prefix_length)) => (address, Some(prefix_length)),
+        None => (range, None),
+    };
+    let Ok(address) = address.parse::<IpAddr>() else {
+        return false;
+    };
+    let max_prefix_length = if address.is_ipv4() { 32 } else { 128 };
+    prefix_length.is_none_or(|prefix_length| {
+        prefix_length
+            .parse::<u8>()
+            .is_ok_and(|prefix_length| prefix_length <= max_prefix_length)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let maintenance = Maintenance {
+            page: Some("<h1>Back soon</h1>".to_owned()),
+            bypass_ips: vec![
+                "203.0.113.7".to_owned(),
+                "198.51.100.0/24".to_owned(),
+                "2001:db8::/32".to_owned(),
+            ],
+            bypass_cookie: Some("let-me-in-0123456789".to_owned()),
+        };
+        assert_eq!(maintenance.validate(), Ok(()));
+        assert_eq!(Maintenance::default().validate(), Ok(()));
+
+        for address in ["localhost", "203.0.113.7/33", "203.0.113.7; return 200"] {
+            let maintenance = Maintenance {
+                bypass_ips: vec![address.to_owned()],
+                ..Default::default()
+            };
+            assert!(maintenance.validate().is_err(), "{}", address);
+        }
+        for cookie in ["short", "\"; return 200; \"0123456789"] {
+            let maintenance = Maintenance {
+                bypass_cookie: Some(cookie.to_owned()),
+                ..Default::default()
+            };
+            assert!(maintenance.validate().is_err(), "{}", cookie);
+        }
+    }
+}
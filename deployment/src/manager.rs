@@ -1,50 +1,715 @@
-use std::{collections::HashMap, io, path::Path, sync::Arc, thread};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+};
 
-use crate::{deployer::handle::deployment_handle, Deployer, DeploymentLogs, Manifest};
+use crate::{
+    artifact::{embedded_manifest, EMBEDDED_MANIFESTS},
+    artifact_store::ArtifactStore,
+    build::Builder,
+    deployer::{handle::redacted_deployment_handle, DeploymentHandle, Redactions},
+    deployments::{Deployment, DeploymentStore},
+    domains::{DomainVerifier, TokenScope},
+    git::{self, GitSettings},
+    health::HealthChecker,
+    ingress::dns::DnsReconciler,
+    maintenance::Maintenance,
+    manifest::{GitSource, ManifestError, ManifestSource},
+    reaper::DeploymentReaper,
+    releases::{ReleaseStore, SyncError},
+    root_domains::RootDomains,
+    secrets::{ResolvedEnvironment, SecretError, SecretStore},
+    uploads::UploadStore,
+    verification::{verify_checksum, ArtifactVerification, SigningKeys, VerificationError},
+    Deployer, DeploymentLogs, Manifest,
+};
+use rand::{distributions::DistString, thread_rng};
+use schemars::schema::Schema;
+
+/// Where the artifact of a deployment comes from.
+#[derive(Clone, Debug)]
+pub enum ArtifactSource {
+    /// A file that was just uploaded and is moved into the artifact store.
+    File(PathBuf),
+    /// The artifact with this SHA-256 digest in the artifact store.
+    Stored(String),
+    /// The release assembled from the completed sync with this id.
+    Sync(String),
+    /// Archived from the git `source` of the manifest once the deployment starts.
+    Git,
+}
+
+/// An artifact ready to be deployed.
+struct PreparedArtifact {
+    sha256: String,
+    location: PathBuf,
+    /// What the signature covers, which is the listing of the files for releases.
+    signed_location: PathBuf,
+    /// The deployment a release was synced for.
+    deployment_name: Option<String>,
+}
+
+/// What the deployment thread deploys.
+enum PendingArtifact {
+    Prepared(PreparedArtifact),
+    Git(GitSource),
+}
+
+/// A recorded deployment whose artifact is still kept, so it can be deployed again.
+struct Redeployment {
+    deployment: Deployment,
+    artifact_location: PathBuf,
+    deployer: Arc<dyn Deployer + Send + Sync>,
+}
+
+/// The deployment a deployment that fails its health check is rolled back to.
+struct Rollback {
+    previous: Deployment,
+    deployer: Arc<dyn Deployer + Send + Sync>,
+    /// Deploys the artifact of `previous` again in case its files are gone.
+    redeployment: Option<Redeployment>,
+}
+
+/// What to deploy, on behalf of whom.
+#[derive(Clone, Debug)]
+pub struct DeploymentRequest {
+    /// Overrides the values of a manifest embedded in the artifact.
+    pub manifest: Option<ManifestSource>,
+    pub artifact: ArtifactSource,
+    /// The root domain the access token is restricted to, if any.
+    pub root_domain_name: Option<String>,
+    /// Identifies the access token in the metadata of the artifact.
+    pub uploaded_by: Option<String>,
+    pub verification: ArtifactVerification,
+}
+
+impl DeploymentRequest {
+    pub fn new(artifact: ArtifactSource) -> Self {
+        DeploymentRequest {
+            manifest: None,
+            artifact,
+            root_domain_name: None,
+            uploaded_by: None,
+            verification: ArtifactVerification::default(),
+        }
+    }
+}
 
 pub struct DeploymentManager {
     deployers: HashMap<&'static str, Arc<dyn Deployer + Send + Sync>>,
-    root_domain_name: String,
+    root_domains: Arc<RootDomains>,
+    deployments: Arc<DeploymentStore>,
+    domains: Arc<DomainVerifier>,
+    secrets: Arc<SecretStore>,
+    uploads: Arc<UploadStore>,
+    artifacts: Arc<ArtifactStore>,
+    releases: Arc<ReleaseStore>,
+    redactions: Redactions,
+    signing_keys: SigningKeys,
+    builder: Builder,
+    git_settings: GitSettings,
+    health_checker: HealthChecker,
+    dns_reconciler: Option<Arc<DnsReconciler>>,
+    reaper: Option<Arc<DeploymentReaper>>,
 }
 
 impl DeploymentManager {
-    pub fn new(root_domain_name: impl AsRef<str>) -> DeploymentManager {
+    pub fn new(
+        root_domains: Arc<RootDomains>,
+        deployments: Arc<DeploymentStore>,
+        domains: Arc<DomainVerifier>,
+        secrets: Arc<SecretStore>,
+        uploads: Arc<UploadStore>,
+        artifacts: Arc<ArtifactStore>,
+        releases: Arc<ReleaseStore>,
+    ) -> DeploymentManager {
         DeploymentManager {
             deployers: HashMap::new(),
-            root_domain_name: root_domain_name.as_ref().to_owned(),
+            root_domains,
+            deployments,
+            domains,
+            secrets,
+            uploads,
+            artifacts,
+            releases,
+            redactions: Redactions::default(),
+            signing_keys: SigningKeys::default(),
+            builder: Builder::default(),
+            git_settings: GitSettings::default(),
+            health_checker: HealthChecker::default(),
+            dns_reconciler: None,
+            reaper: None,
         }
     }
 
-    pub fn deploy(
+    /// Starts deploying the artifact of `request`. The artifact is rejected unless it matches
+    /// the checksum and signature of its verification.
+    pub fn deploy(&self, request: DeploymentRequest) -> Result<DeploymentLogs, DeploymentError> {
+        let verification = &request.verification;
+        let prepared = self.prepare_artifact(&request)?;
+        let manifest = load_manifest(
+            request.manifest,
+            prepared.as_ref().map(|p| p.location.as_path()),
+        )?;
+        if let Some(deployment_name) = prepared
+            .as_ref()
+            .and_then(|p| p.deployment_name.as_ref())
+            .filter(|n| **n != manifest.name)
+        {
+            return Err(DeploymentError::InvalidManifest(vec![
+                ManifestError::field(
+                    "name",
+                    format!("The files were synced for deployment {}", deployment_name),
+                ),
+            ]));
+        }
+        let token = TokenScope {
+            name: request.uploaded_by.clone(),
+            root_domain_name: request.root_domain_name.clone(),
+        };
+        let manifest = self.resolve_manifest(manifest, &token)?;
+        if manifest.canary.is_some() {
+            self.check_canary(&manifest)?;
+        }
+        let artifact = match (prepared, &manifest.source) {
+            (Some(prepared), _) => {
+                self.signing_keys
+                    .verify_signature(&manifest.name, &prepared.signed_location, verification)
+                    .map_err(DeploymentError::Verification)?;
+                PendingArtifact::Prepared(prepared)
+            }
+            (None, Some(source)) => {
+                self.git_settings.check(source).map_err(|e| {
+                    DeploymentError::InvalidManifest(vec![ManifestError::field("source.git", e)])
+                })?;
+                // Commits aren't signed, so sites with signing keys can't be deployed from git
+                let unsigned = ArtifactVerification {
+                    signature: None,
+                    ..verification.clone()
+                };
+                self.signing_keys
+                    .verify_signature(&manifest.name, Path::new(""), &unsigned)
+                    .map_err(DeploymentError::Verification)?;
+                PendingArtifact::Git(source.clone())
+            }
+            (None, None) => {
+                return Err(DeploymentError::InvalidManifest(vec![
+                    ManifestError::field(
+                        "source",
+                        "Deployments without an artifact need a git source",
+                    ),
+                ]))
+            }
+        };
+        let deployer = self
+            .deployers
+            .get(manifest.deployment_type.as_str())
+            .ok_or(DeploymentError::UnknownDeploymentType)?
+            .clone();
+        let environment = self
+            .secrets
+            .resolve(&manifest.name, &manifest.env)
+            .map_err(DeploymentError::Secret)?;
+        let build_environment = match &manifest.build {
+            Some(build) => self
+                .secrets
+                .resolve(&manifest.name, &build.env)
+                .map_err(DeploymentError::Secret)?,
+            None => Default::default(),
+        };
+        let rollback = match &manifest.health_check {
+            Some(_) => self.rollback(&manifest.name),
+            None => None,
+        };
+        let deployments = self.deployments.clone();
+        let artifacts = self.artifacts.clone();
+        let secrets = self.secrets.clone();
+        let builder = self.builder.clone();
+        let health_checker = self.health_checker.clone();
+        let uploaded_by = request.uploaded_by;
+        let git_settings = self.git_settings.clone();
+        let (mut handle, log) = redacted_deployment_handle(self.redactions.clone());
+        for secret in environment
+            .secret_values
+            .iter()
+            .chain(&build_environment.secret_values)
+        {
+            handle.redact(secret);
+        }
+
+        thread::spawn(move || {
+            let result = match artifact {
+                PendingArtifact::Prepared(prepared) => {
+                    Ok((prepared.sha256, prepared.location, None))
+                }
+                PendingArtifact::Git(source) => fetch_git_source(
+                    &source,
+                    &artifacts,
+                    uploaded_by.as_deref(),
+                    &git_settings,
+                    handle.clone(),
+                ),
+            }
+            .and_then(|(artifact_sha256, artifact_location, commit_sha)| {
+                deploy_artifact(
+                    &*deployer,
+                    &builder,
+                    &manifest,
+                    &environment,
+                    &build_environment,
+                    &artifact_location,
+                    handle.clone(),
+                )?;
+                if let Some(health_check) = &manifest.health_check {
+                    health_checker
+                        .check(
+                            health_check,
+                            &manifest.served_domain_names(),
+                            manifest.canary.is_some(),
+                            handle.clone(),
+                        )
+                        .inspect_err(|_e| {
+                            roll_back(rollback, &manifest, &secrets, &builder, handle.clone());
+                        })?;
+                }
+                Ok((artifact_sha256, commit_sha))
+            });
+            match result {
+                Ok((artifact_sha256, commit_sha)) => {
+                    let recorded = match manifest.canary {
+                        Some(_) => deployments.record_canary(
+                            &manifest,
+                            &artifact_sha256,
+                            commit_sha.as_deref(),
+                        ),
+                        None => {
+                            deployments.record(&manifest, &artifact_sha256, commit_sha.as_deref())
+                        }
+                    };
+                    if let Err(e) = recorded {
+                        error!("Failed to record deployment {}: {:?}", manifest.name, e);
+                    }
+                    if let Err(e) = artifacts
+                        .add_reference(&artifact_sha256, &manifest.name)
+                        .and_then(|_| artifacts.apply_retention())
+                    {
+                        error!("Failed to update artifact store: {:?}", e);
+                    }
+                    writeln!(handle.info(), "Deployment succeeded").ok()
+                }
+                Err(e) => writeln!(handle.error(), "Deployment failed: {:?}", e).ok(),
+            };
+        });
+        Ok(log)
+    }
+
+    /// Verifies the checksum of the artifact and moves an uploaded one into the artifact store.
+    /// Synced files are assembled into a release, whose checksum is that of its file listing.
+    /// Git sources are only fetched once the deployment runs.
+    fn prepare_artifact(
         &self,
-        manifest: &str,
-        artifact_location: &Path,
+        request: &DeploymentRequest,
+    ) -> Result<Option<PreparedArtifact>, DeploymentError> {
+        let expected = request.verification.sha256.as_deref();
+        let sha256 = match &request.artifact {
+            ArtifactSource::File(location) => {
+                let sha256 = verify_checksum(location, expected)
+                    .inspect_err(|_e| {
+                        fs::remove_file(location).ok();
+                    })
+                    .map_err(DeploymentError::Verification)?;
+                self.artifacts
+                    .store(location, &sha256, request.uploaded_by.as_deref())
+                    .map_err(|e| DeploymentError::IOError(io::Error::other(e)))?;
+                sha256
+            }
+            ArtifactSource::Stored(sha256) => {
+                let sha256 = sha256.trim().to_ascii_lowercase();
+                if expected.is_some_and(|e| !e.trim().eq_ignore_ascii_case(&sha256)) {
+                    return Err(DeploymentError::Verification(
+                        VerificationError::ChecksumMismatch {
+                            expected: expected.unwrap_or_default().to_owned(),
+                            actual: sha256,
+                        },
+                    ));
+                }
+                match self.artifacts.get(&sha256) {
+                    Ok(Some(artifact)) => artifact.sha256,
+                    Ok(None) => return Err(DeploymentError::UnknownArtifact(sha256)),
+                    Err(e) => return Err(DeploymentError::IOError(io::Error::other(e))),
+                }
+            }
+            ArtifactSource::Sync(id) => {
+                let release = self.releases.assemble(id).map_err(DeploymentError::Sync)?;
+                let signed_location = self.releases.checksums_path(&release);
+                let sha256 = verify_checksum(&signed_location, expected)
+                    .map_err(DeploymentError::Verification)?;
+                return Ok(Some(PreparedArtifact {
+                    sha256,
+                    location: self.releases.path(&release),
+                    signed_location,
+                    deployment_name: Some(release.deployment_name),
+                }));
+            }
+            ArtifactSource::Git => return Ok(None),
+        };
+        let location = self.artifacts.path(&sha256);
+        Ok(Some(PreparedArtifact {
+            sha256,
+            signed_location: location.clone(),
+            location,
+            deployment_name: None,
+        }))
+    }
+
+    /// The recorded deployment of `name`, which a failed deployment is rolled back to.
+    fn rollback(&self, name: &str) -> Option<Rollback> {
+        let previous = self
+            .deployments
+            .get(name)
+            .inspect_err(|e| error!("Failed to read deployment {}: {:?}", name, e))
+            .ok()??;
+        let deployer = self
+            .deployers
+            .get(previous.manifest.deployment_type.as_str())?
+            .clone();
+        Some(Rollback {
+            redeployment: self.redeployment(previous.clone()),
+            previous,
+            deployer,
+        })
+    }
+
+    /// Finds the artifact of `deployment` in the artifact store or among the releases.
+    fn redeployment(&self, deployment: Deployment) -> Option<Redeployment> {
+        let name = &deployment.manifest.name;
+        let sha256 = deployment.artifact_sha256.as_deref()?;
+        let artifact_location = if self.artifacts.get(sha256).ok()?.is_some() {
+            self.artifacts.path(sha256)
+        } else {
+            self.releases.path(&self.releases.find(name, sha256).ok()??)
+        };
+        let deployer = self
+            .deployers
+            .get(deployment.manifest.deployment_type.as_str())?
+            .clone();
+        Some(Redeployment {
+            deployment,
+            artifact_location,
+            deployer,
+        })
+    }
+
+    /// Makes the canary of deployment `name` its only deployment, serving it to everyone.
+    pub fn promote(
+        &self,
+        name: &str,
+        token_root_domain_name: Option<&str>,
+    ) -> Result<DeploymentLogs, DeploymentError> {
+        let deployment = self.with_canary(name, token_root_domain_name)?;
+        let mut canary = *deployment.canary.expect("Checked by with_canary");
+        canary.manifest.canary = None;
+        self.finish_canary(canary)
+    }
+
+    /// Ends the canary of deployment `name`, serving the current deployment to everyone again.
+    pub fn abort(
+        &self,
+        name: &str,
+        token_root_domain_name: Option<&str>,
+    ) -> Result<DeploymentLogs, DeploymentError> {
+        let mut deployment = self.with_canary(name, token_root_domain_name)?;
+        deployment.canary = None;
+        self.finish_canary(deployment)
+    }
+
+    /// Canaries share the domains of the deployment they are served next to.
+    fn check_canary(&self, manifest: &Manifest) -> Result<(), DeploymentError> {
+        let current = self
+            .deployments
+            .get(&manifest.name)
+            .map_err(|e| DeploymentError::IOError(io::Error::other(e)))?;
+        let message = match current {
+            None => format!(
+                "There is no deployment of {} to serve the canary next to",
+                manifest.name
+            ),
+            Some(current)
+                if current.manifest.served_domain_names() != manifest.served_domain_names() =>
+            {
+                "A canary has to be served on the domains of the current deployment".to_owned()
+            }
+            Some(_) => return Ok(()),
+        };
+        Err(DeploymentError::InvalidManifest(vec![
+            ManifestError::field("canary", message),
+        ]))
+    }
+
+    /// The deployment `name` if it has a canary and the access token may change it.
+    fn with_canary(
+        &self,
+        name: &str,
+        token_root_domain_name: Option<&str>,
+    ) -> Result<Deployment, DeploymentError> {
+        let deployment = self
+            .deployments
+            .get(name)
+            .map_err(|e| DeploymentError::IOError(io::Error::other(e)))?
+            .filter(|d| d.canary.is_some())
+            .ok_or_else(|| DeploymentError::NoCanary(name.to_owned()))?;
+        check_token_scope(&deployment.manifest, token_root_domain_name)?;
+        Ok(deployment)
+    }
+
+    /// Deploys the files currently served for deployment `source` as deployment `target`,
+    /// with the recorded manifest of `target`. Nothing is uploaded or built again.
+    pub fn promote_to(
+        &self,
+        source: &str,
+        target: &str,
+        token_root_domain_name: Option<&str>,
     ) -> Result<DeploymentLogs, DeploymentError> {
-        let manifest = self.parse_manifest(manifest)?;
+        if source == target {
+            return Err(DeploymentError::InvalidManifest(vec![
+                ManifestError::field("name", "A deployment can't be promoted to itself"),
+            ]));
+        }
+        let source = self.recorded(source)?;
+        let target = self.recorded(target)?;
+        check_token_scope(&source.manifest, token_root_domain_name)?;
+        check_token_scope(&target.manifest, token_root_domain_name)?;
+        if source.manifest.deployment_type != target.manifest.deployment_type {
+            return Err(DeploymentError::InvalidManifest(vec![
+                ManifestError::field(
+                    "deployment_type",
+                    format!(
+                        "{} is a {} deployment",
+                        target.manifest.name, target.manifest.deployment_type
+                    ),
+                ),
+            ]));
+        }
+        if !self
+            .signing_keys
+            .allows_promotion(&source.manifest.name, &target.manifest.name)
+        {
+            return Err(DeploymentError::Verification(
+                VerificationError::MissingSignature,
+            ));
+        }
         let deployer = self
             .deployers
-            .get(manifest.deployment_type.as_str())
+            .get(target.manifest.deployment_type.as_str())
             .ok_or(DeploymentError::UnknownDeploymentType)?
             .clone();
-        let artifact_location = artifact_location.to_owned();
-        let (mut handle, log) = deployment_handle();
+        let manifest = target.manifest.clone();
+        let environment = self
+            .secrets
+            .resolve(&manifest.name, &manifest.env)
+            .map_err(DeploymentError::Secret)?;
+        let rollback = match &manifest.health_check {
+            Some(_) => self.rollback(&manifest.name),
+            None => None,
+        };
+        let deployments = self.deployments.clone();
+        let artifacts = self.artifacts.clone();
+        let secrets = self.secrets.clone();
+        let builder = self.builder.clone();
+        let health_checker = self.health_checker.clone();
+        let (mut handle, log) = redacted_deployment_handle(self.redactions.clone());
+        for secret in &environment.secret_values {
+            handle.redact(secret);
+        }
 
         thread::spawn(move || {
-            match deployer.deploy(manifest, &artifact_location, handle.clone()) {
-                Ok(_) => writeln!(handle.info(), "Deployment succeeded").ok(),
+            writeln!(
+                handle.info(),
+                "Promoting {} to {}",
+                source.manifest.name,
+                manifest.name
+            )
+            .ok();
+            let result = deployer
+                .promote(
+                    &source.manifest,
+                    manifest.clone(),
+                    &environment.variables,
+                    handle.clone(),
+                )
+                .and_then(|()| match &manifest.health_check {
+                    Some(health_check) => health_checker
+                        .check(
+                            health_check,
+                            &manifest.served_domain_names(),
+                            false,
+                            handle.clone(),
+                        )
+                        .inspect_err(|_e| {
+                            roll_back(rollback, &manifest, &secrets, &builder, handle.clone());
+                        }),
+                    None => Ok(()),
+                });
+            match result {
+                Ok(()) => {
+                    if let Err(e) = deployments.record_promotion(&manifest, &source) {
+                        error!("Failed to record deployment {}: {:?}", manifest.name, e);
+                    }
+                    if let Some(sha256) = &source.artifact_sha256 {
+                        if let Err(e) = artifacts.add_reference(sha256, &manifest.name) {
+                            error!("Failed to update artifact store: {:?}", e);
+                        }
+                    }
+                    writeln!(handle.info(), "Deployment succeeded").ok()
+                }
                 Err(e) => writeln!(handle.error(), "Deployment failed: {:?}", e).ok(),
             };
         });
         Ok(log)
     }
 
-    fn parse_manifest(&self, manifest: &str) -> Result<Manifest, DeploymentError> {
-        let mut manifest: Manifest =
-            toml::from_str(manifest).map_err(|_e| DeploymentError::CouldNotParseManifest)?;
-        if manifest.domain_names.is_empty() {
-            manifest
-                .domain_names
-                .push(format!("{}.{}", manifest.name, self.root_domain_name));
+    /// Starts serving the page of `maintenance` instead of deployment `name`, or the
+    /// deployment again for `None`. The deployed files aren't touched.
+    pub fn set_maintenance(
+        &self,
+        name: &str,
+        maintenance: Option<Maintenance>,
+        token_root_domain_name: Option<&str>,
+    ) -> Result<DeploymentLogs, DeploymentError> {
+        if let Some(maintenance) = &maintenance {
+            maintenance
+                .validate()
+                .map_err(DeploymentError::InvalidMaintenance)?;
+        }
+        let deployment = self.recorded(name)?;
+        check_token_scope(&deployment.manifest, token_root_domain_name)?;
+        let deployer = self
+            .deployers
+            .get(deployment.manifest.deployment_type.as_str())
+            .ok_or(DeploymentError::UnknownDeploymentType)?
+            .clone();
+        // A running canary keeps being served to those who bypass the maintenance page
+        let mut manifest = deployment.manifest;
+        manifest.canary = deployment.canary.and_then(|canary| canary.manifest.canary);
+        let (mut handle, log) = redacted_deployment_handle(self.redactions.clone());
+
+        thread::spawn(move || {
+            match deployer.set_maintenance(&manifest, maintenance.as_ref(), handle.clone()) {
+                Ok(()) if maintenance.is_some() => writeln!(handle.info(), "Maintenance started"),
+                Ok(()) => writeln!(handle.info(), "Maintenance ended"),
+                Err(e) => writeln!(handle.error(), "Failed to change maintenance: {:?}", e),
+            }
+            .ok();
+        });
+        Ok(log)
+    }
+
+    /// Makes sure an access token restricted to `token_root_domain_name` may manage the
+    /// deployment `name`, e.g. its secrets. Such tokens can only manage deployments that were
+    /// recorded below their root domain.
+    pub fn check_scope(
+        &self,
+        name: &str,
+        token_root_domain_name: Option<&str>,
+    ) -> Result<(), DeploymentError> {
+        if token_root_domain_name.is_none() {
+            return Ok(());
+        }
+        let deployment = self.recorded(name)?;
+        check_token_scope(&deployment.manifest, token_root_domain_name)
+    }
+
+    fn recorded(&self, name: &str) -> Result<Deployment, DeploymentError> {
+        self.deployments
+            .get(name)
+            .map_err(|e| DeploymentError::IOError(io::Error::other(e)))?
+            .ok_or_else(|| DeploymentError::UnknownDeployment(name.to_owned()))
+    }
+
+    /// Deploys `deployment` again as the only deployment of its name.
+    fn finish_canary(&self, deployment: Deployment) -> Result<DeploymentLogs, DeploymentError> {
+        let sha256 = deployment.artifact_sha256.clone().unwrap_or_default();
+        let redeployment = self
+            .redeployment(deployment)
+            .ok_or(DeploymentError::UnknownArtifact(sha256))?;
+        let deployments = self.deployments.clone();
+        let secrets = self.secrets.clone();
+        let builder = self.builder.clone();
+        let (mut handle, log) = redacted_deployment_handle(self.redactions.clone());
+        thread::spawn(move || {
+            match redeploy(&redeployment, &secrets, &builder, handle.clone()) {
+                Ok(()) => {
+                    let deployment = &redeployment.deployment;
+                    if let Err(e) = deployments.record(
+                        &deployment.manifest,
+                        deployment.artifact_sha256.as_deref().unwrap_or_default(),
+                        deployment.commit_sha.as_deref(),
+                    ) {
+                        error!(
+                            "Failed to record deployment {}: {:?}",
+                            deployment.manifest.name, e
+                        );
+                    }
+                    writeln!(handle.info(), "Deployment succeeded")
+                }
+                Err(e) => writeln!(handle.error(), "Deployment failed: {:?}", e),
+            }
+            .ok();
+        });
+        Ok(log)
+    }
+
+    /// Picks the root domain of `manifest` and adds the default domain if needed. Tokens
+    /// restricted to a root domain can't replace deployments outside of it.
+    fn resolve_manifest(
+        &self,
+        mut manifest: Manifest,
+        token: &TokenScope,
+    ) -> Result<Manifest, DeploymentError> {
+        let token_root_domain_name = token.root_domain_name.as_deref();
+        if token_root_domain_name.is_some() {
+            match self.recorded(&manifest.name) {
+                Ok(recorded) => check_token_scope(&recorded.manifest, token_root_domain_name)?,
+                Err(DeploymentError::UnknownDeployment(_)) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let root_domain_name = match (manifest.root_domain_name.as_deref(), token_root_domain_name)
+        {
+            (Some(requested), Some(allowed)) if requested != allowed => {
+                return Err(DeploymentError::RootDomainNotAllowed(requested.to_owned()))
+            }
+            (requested, allowed) => requested
+                .or(allowed)
+                .map(str::to_owned)
+                .unwrap_or_else(|| self.root_domains.default_root().name.clone()),
+        };
+        let root_domain = self
+            .root_domains
+            .get(&root_domain_name)
+            .ok_or(DeploymentError::UnknownRootDomain(root_domain_name))?;
+        manifest.root_domain_name = Some(root_domain.name.clone());
+
+        let default_domain_name = manifest.default_domain_name(&root_domain.name);
+        // Custom domains aliasing the default host need it to resolve as well
+        if manifest.domain_names.is_empty()
+            || (!manifest.cname_domains.is_empty()
+                && !manifest.domain_names.contains(&default_domain_name))
+        {
+            manifest.domain_names.push(default_domain_name);
+        }
+
+        for domain_name in manifest.served_domain_names() {
+            let allowed = self
+                .domains
+                .is_allowed(&domain_name, token)
+                .map_err(|e| DeploymentError::IOError(io::Error::other(e)))?;
+            if !allowed {
+                return Err(DeploymentError::UnverifiedDomain(domain_name));
+            }
         }
         Ok(manifest)
     }
@@ -56,15 +721,1096 @@ impl DeploymentManager {
         self.deployers
             .insert(D::deployment_type(), Arc::new(deployer));
     }
+
+    /// The JSON Schema of manifests, limiting `deployment_type` to the registered deployers.
+    pub fn manifest_schema(&self) -> serde_json::Value {
+        let mut schema = schemars::schema_for!(Manifest);
+        let deployment_type = schema.schema.object().properties.get_mut("deployment_type");
+        if let Some(Schema::Object(deployment_type)) = deployment_type {
+            let mut deployment_types: Vec<_> = self.deployers.keys().collect();
+            deployment_types.sort();
+            deployment_type.enum_values =
+                Some(deployment_types.into_iter().map(|t| (*t).into()).collect());
+        }
+        serde_json::to_value(schema).expect("Schemas can always be serialized")
+    }
+
+    pub fn artifacts(&self) -> Arc<ArtifactStore> {
+        self.artifacts.clone()
+    }
+
+    /// The releases of static sites deployed through file-level syncs.
+    pub fn releases(&self) -> Arc<ReleaseStore> {
+        self.releases.clone()
+    }
+
+    pub fn uploads(&self) -> Arc<UploadStore> {
+        self.uploads.clone()
+    }
+
+    pub fn secrets(&self) -> Arc<SecretStore> {
+        self.secrets.clone()
+    }
+
+    /// The custom domains deployments may use.
+    pub fn domains(&self) -> Arc<DomainVerifier> {
+        self.domains.clone()
+    }
+
+    /// Sets the keys deployments have to be signed with, by deployment name.
+    pub fn set_signing_keys(&mut self, signing_keys: SigningKeys) {
+        self.signing_keys = signing_keys;
+    }
+
+    /// Sets which repositories manifests may be deployed from.
+    pub fn set_git_settings(&mut self, git_settings: GitSettings) {
+        self.git_settings = git_settings;
+    }
+
+    /// Sets how the build steps of manifests are run.
+    pub fn set_builder(&mut self, builder: Builder) {
+        self.builder = builder;
+    }
+
+    /// Sets where the health checks of manifests are sent.
+    pub fn set_health_checker(&mut self, health_checker: HealthChecker) {
+        self.health_checker = health_checker;
+    }
+
+    /// Sets what is masked in the logs of every deployment in addition to its secrets.
+    pub fn set_redactions(&mut self, redactions: Redactions) {
+        self.redactions = redactions;
+    }
+
+    pub fn set_dns_reconciler(&mut self, dns_reconciler: DnsReconciler) {
+        self.dns_reconciler = Some(Arc::new(dns_reconciler));
+    }
+
+    /// The reconciler for the records of deployed domains, if pond manages DNS records.
+    pub fn dns_reconciler(&self) -> Option<Arc<DnsReconciler>> {
+        self.dns_reconciler.clone()
+    }
+
+    pub fn set_reaper(&mut self, reaper: DeploymentReaper) {
+        self.reaper = Some(Arc::new(reaper));
+    }
+
+    /// The job removing expired deployments, if one is configured.
+    pub fn reaper(&self) -> Option<Arc<DeploymentReaper>> {
+        self.reaper.clone()
+    }
+
+    /// Starts the jobs that keep running next to deployments.
+    pub fn start_background_jobs(&self) {
+        self.uploads.clone().spawn();
+        self.releases.clone().spawn();
+        if let Some(dns_reconciler) = &self.dns_reconciler {
+            dns_reconciler.clone().spawn();
+        }
+        if let Some(reaper) = &self.reaper {
+            reaper.clone().spawn(self.deployers.clone());
+        }
+    }
+}
+
+/// Refuses changes to deployments below another root domain than that of the access token.
+fn check_token_scope(
+    manifest: &Manifest,
+    token_root_domain_name: Option<&str>,
+) -> Result<(), DeploymentError> {
+    if let (Some(root_domain_name), Some(allowed)) =
+        (manifest.root_domain_name.as_deref(), token_root_domain_name)
+    {
+        if root_domain_name != allowed {
+            return Err(DeploymentError::RootDomainNotAllowed(
+                root_domain_name.to_owned(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Runs the build step of `manifest`, if any, and deploys the result.
+fn deploy_artifact(
+    deployer: &(dyn Deployer + Send + Sync),
+    builder: &Builder,
+    manifest: &Manifest,
+    environment: &ResolvedEnvironment,
+    build_environment: &ResolvedEnvironment,
+    artifact_location: &Path,
+    deployment_handle: DeploymentHandle,
+) -> io::Result<()> {
+    let build = match &manifest.build {
+        Some(step) => Some(builder.build(
+            step,
+            &build_environment.variables,
+            artifact_location,
+            deployment_handle.clone(),
+        )?),
+        None => None,
+    };
+    let artifact_location = build.as_ref().map_or(artifact_location, |b| &b.output);
+    deployer.deploy(
+        manifest.clone(),
+        &environment.variables,
+        artifact_location,
+        deployment_handle,
+    )
+}
+
+/// Resolves the environment of a recorded deployment and deploys it again.
+fn redeploy(
+    redeployment: &Redeployment,
+    secrets: &SecretStore,
+    builder: &Builder,
+    deployment_handle: DeploymentHandle,
+) -> io::Result<()> {
+    let manifest = &redeployment.deployment.manifest;
+    let environment = secrets
+        .resolve(&manifest.name, &manifest.env)
+        .map_err(io::Error::other)?;
+    let build_environment = match &manifest.build {
+        Some(build) => secrets
+            .resolve(&manifest.name, &build.env)
+            .map_err(io::Error::other)?,
+        None => Default::default(),
+    };
+    for secret in environment
+        .secret_values
+        .iter()
+        .chain(&build_environment.secret_values)
+    {
+        deployment_handle.redact(secret);
+    }
+    deploy_artifact(
+        &*redeployment.deployer,
+        builder,
+        manifest,
+        &environment,
+        &build_environment,
+        &redeployment.artifact_location,
+        deployment_handle,
+    )
+}
+
+/// Serves the previous deployment again after `failed` failed its health check, deploying its
+/// artifact again only if the deployer no longer has its files.
+fn roll_back(
+    rollback: Option<Rollback>,
+    failed: &Manifest,
+    secrets: &SecretStore,
+    builder: &Builder,
+    mut deployment_handle: DeploymentHandle,
+) {
+    let Some(rollback) = rollback else {
+        writeln!(
+            deployment_handle.error(),
+            "There is no previous deployment to roll back to"
+        )
+        .ok();
+        return;
+    };
+    writeln!(
+        deployment_handle.info(),
+        "Rolling back to the previous deployment"
+    )
+    .ok();
+    let restored = rollback
+        .deployer
+        .roll_back(
+            failed,
+            &rollback.previous.manifest,
+            deployment_handle.clone(),
+        )
+        .unwrap_or_else(|e| {
+            writeln!(
+                deployment_handle.error(),
+                "Failed to restore the previous files: {:?}",
+                e
+            )
+            .ok();
+            false
+        });
+    let result = match (restored, &rollback.redeployment) {
+        (true, _) => Ok(()),
+        (false, Some(redeployment)) => {
+            writeln!(
+                deployment_handle.info(),
+                "Deploying the previous artifact again"
+            )
+            .ok();
+            redeploy(redeployment, secrets, builder, deployment_handle.clone())
+        }
+        (false, None) => Err(io::Error::other(
+            "The files and the artifact of the previous deployment are gone",
+        )),
+    };
+    match result {
+        Ok(()) => writeln!(
+            deployment_handle.info(),
+            "Rolled back to the previous deployment"
+        ),
+        Err(e) => writeln!(deployment_handle.error(), "Rollback failed: {:?}", e),
+    }
+    .ok();
+}
+
+/// Archives the files of a git source into the artifact store, returning the digest and
+/// location of the archive along with the commit it was made from.
+fn fetch_git_source(
+    source: &GitSource,
+    artifacts: &ArtifactStore,
+    uploaded_by: Option<&str>,
+    settings: &GitSettings,
+    mut deployment_handle: DeploymentHandle,
+) -> io::Result<(String, PathBuf, Option<String>)> {
+    writeln!(
+        deployment_handle.info(),
+        "Fetching {} at {}",
+        source.git,
+        source.reference
+    )
+    .ok();
+    let location = std::env::temp_dir().join(format!(
+        "pond-git-{}.zip",
+        rand::distributions::Alphanumeric.sample_string(&mut thread_rng(), 16)
+    ));
+    let commit_sha = git::archive(source, settings, &location, deployment_handle.clone())?;
+    let sha256 = verify_checksum(&location, None).map_err(io::Error::other)?;
+    artifacts
+        .store(&location, &sha256, uploaded_by)
+        .map_err(io::Error::other)?;
+    writeln!(deployment_handle.info(), "Archived commit {}", commit_sha).ok();
+    Ok((sha256.clone(), artifacts.path(&sha256), Some(commit_sha)))
+}
+
+fn load_manifest(
+    uploaded: Option<ManifestSource>,
+    artifact_location: Option<&Path>,
+) -> Result<Manifest, DeploymentError> {
+    // Artifacts of other deployment types need not be zip archives
+    let embedded = artifact_location
+        .and_then(|location| {
+            embedded_manifest(location)
+                .inspect_err(|e| debug!("No manifest read from {:?}: {:?}", location, e))
+                .ok()
+        })
+        .flatten();
+    let result = match (embedded, uploaded) {
+        (Some((_, embedded)), Some(uploaded)) => {
+            Manifest::parse_with_overrides(&embedded, &uploaded)
+        }
+        (Some((file_name, embedded)), None) => Manifest::parse(&embedded.content, embedded.format)
+            .map_err(|errors| {
+                errors
+                    .into_iter()
+                    .map(|e| ManifestError {
+                        message: format!("{}: {}", file_name, e.message),
+                        ..e
+                    })
+                    .collect()
+            }),
+        (None, Some(uploaded)) => Manifest::parse(&uploaded.content, uploaded.format),
+        (None, None) => Err(vec![ManifestError::field(
+            "manifest",
+            format!(
+                "No manifest was uploaded and the artifact contains none of {}",
+                EMBEDDED_MANIFESTS
+                    .map(|(file_name, _)| file_name)
+                    .join(", ")
+            ),
+        )]),
+    };
+    result.map_err(DeploymentError::InvalidManifest)
 }
 
 #[derive(Debug)]
 pub enum DeploymentError {
-    CouldNotParseManifest,
+    InvalidManifest(Vec<ManifestError>),
     UnknownDeploymentType,
+    UnverifiedDomain(String),
+    UnknownRootDomain(String),
+    RootDomainNotAllowed(String),
+    /// No artifact with this digest is stored.
+    UnknownArtifact(String),
+    /// The deployment with this name has no canary to promote or abort.
+    NoCanary(String),
+    /// No deployment with this name is recorded.
+    UnknownDeployment(String),
+    InvalidMaintenance(String),
+    Sync(SyncError),
+    Secret(SecretError),
+    Verification(VerificationError),
     IOError(io::Error),
 }
 
 pub trait RegisterDeployment: Deployer {
     fn deployment_type() -> &'static str;
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        artifact::test::zip_artifact,
+        domains::MockTxtResolver,
+        ingress::dns::NoOpDnsService,
+        releases::test::complete_sync,
+        root_domains::{RootDomain, RootDomains},
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn root_domain(name: &str) -> RootDomain {
+        RootDomain {
+            name: name.to_owned(),
+            dns_service: Arc::new(NoOpDnsService),
+            manages_records: false,
+            ip_v4_address: None,
+            ip_v6_address: None,
+        }
+    }
+
+    fn parse_manifest(
+        manifest: &str,
+        token_root_domain_name: Option<&str>,
+    ) -> Result<Manifest, DeploymentError> {
+        let manifest = Manifest::parse(manifest, None).map_err(DeploymentError::InvalidManifest)?;
+        let token = TokenScope {
+            name: None,
+            root_domain_name: token_root_domain_name.map(str::to_owned),
+        };
+        manager().resolve_manifest(manifest, &token)
+    }
+
+    /// Each manager keeps its uploads, artifacts and releases apart, as tests run in parallel.
+    fn manager() -> DeploymentManager {
+        static MANAGERS: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "manager-{}-{}",
+            std::process::id(),
+            MANAGERS.fetch_add(1, Ordering::Relaxed)
+        );
+        let state_directory = std::env::temp_dir().join(format!("pond-{}", name));
+        std::fs::remove_dir_all(&state_directory).ok();
+
+        let mut root_domains = RootDomains::new(root_domain("example.com"));
+        root_domains.add(root_domain("example.net"));
+        DeploymentManager::new(
+            Arc::new(root_domains),
+            Arc::new(DeploymentStore::in_memory()),
+            Arc::new(DomainVerifier::in_memory(
+                &["example.com".to_owned(), "example.net".to_owned()],
+                Box::new(MockTxtResolver::new()),
+            )),
+            Arc::new(SecretStore::in_memory(None).unwrap()),
+            Arc::new(
+                UploadStore::open(
+                    &state_directory.join("uploads"),
+                    std::time::Duration::from_secs(60),
+                )
+                .unwrap(),
+            ),
+            Arc::new(ArtifactStore::open(&state_directory.join("artifacts")).unwrap()),
+            Arc::new(crate::releases::test::store(&name)),
+        )
+    }
+
+    #[test]
+    fn test_default_domain_name_is_added_without_domains() {
+        let manifest =
+            parse_manifest("name = \"blog\"\ndeployment_type = \"static-site\"", None).unwrap();
+        assert_eq!(manifest.domain_names, vec!["blog.example.com"]);
+        assert_eq!(manifest.root_domain_name.as_deref(), Some("example.com"));
+    }
+
+    #[test]
+    fn test_cname_domains_keep_default_domain_name() {
+        let manifest = parse_manifest(
+            r#"
+                name = "blog"
+                deployment_type = "static-site"
+                domain_names = ["shop.example.com"]
+                cname_domains = ["www.example.com"]
+                "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            manifest.domain_names,
+            vec!["shop.example.com", "blog.example.com"]
+        );
+        assert_eq!(
+            manifest.served_domain_names(),
+            vec!["shop.example.com", "blog.example.com", "www.example.com"]
+        );
+    }
+
+    #[test]
+    fn test_unverified_domains_are_rejected() {
+        let result = parse_manifest(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+            cname_domains = ["blog.example.org"]
+            "#,
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(DeploymentError::UnverifiedDomain(domain_name)) if domain_name == "blog.example.org"
+        ));
+    }
+
+    #[test]
+    fn test_root_domain_is_picked_by_manifest_or_token() {
+        let manifest = parse_manifest(
+            r#"
+                name = "blog"
+                deployment_type = "static-site"
+                root_domain_name = "example.net"
+                "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(manifest.domain_names, vec!["blog.example.net"]);
+
+        let manifest = parse_manifest(
+            "name = \"blog\"\ndeployment_type = \"static-site\"",
+            Some("example.net"),
+        )
+        .unwrap();
+        assert_eq!(manifest.domain_names, vec!["blog.example.net"]);
+        assert_eq!(manifest.root_domain_name.as_deref(), Some("example.net"));
+    }
+
+    #[test]
+    fn test_root_domain_must_match_token() {
+        let result = parse_manifest(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+            root_domain_name = "example.com"
+            "#,
+            Some("example.net"),
+        );
+        assert!(matches!(
+            result,
+            Err(DeploymentError::RootDomainNotAllowed(name)) if name == "example.com"
+        ));
+    }
+
+    #[test]
+    fn test_unknown_root_domain_is_rejected() {
+        let result = parse_manifest(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+            root_domain_name = "example.org"
+            "#,
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(DeploymentError::UnknownRootDomain(name)) if name == "example.org"
+        ));
+    }
+
+    #[test]
+    fn test_manifest_schema_lists_deployment_types() {
+        let mut manager = manager();
+        manager.register_deployer(crate::deployer::StaticSiteDeployer::new(
+            "./scripts",
+            Box::new(crate::ingress::static_site::MockStaticSiteIngressService::new()),
+        ));
+        let schema = manager.manifest_schema();
+        assert_eq!(
+            schema["properties"]["deployment_type"]["enum"],
+            serde_json::json!(["static-site"])
+        );
+        assert_eq!(schema["additionalProperties"], false);
+        assert!(schema["required"]
+            .as_array()
+            .unwrap()
+            .contains(&"name".into()));
+    }
+
+    #[test]
+    fn test_manifest_is_read_from_artifact() {
+        let artifact = zip_artifact(
+            "manager",
+            &[(
+                "pond.toml",
+                "name = \"blog\"\ndeployment_type = \"static-site\"\ndomain_names = [\"blog.example.com\"]",
+            )],
+        );
+        let manifest = load_manifest(None, Some(&artifact)).unwrap();
+        assert_eq!(manifest.domain_names, vec!["blog.example.com"]);
+
+        let uploaded = ManifestSource {
+            content: "domain_names = [\"www.example.com\"]".to_owned(),
+            format: None,
+        };
+        let manifest = load_manifest(Some(uploaded), Some(&artifact)).unwrap();
+        assert_eq!(manifest.name, "blog");
+        assert_eq!(manifest.domain_names, vec!["www.example.com"]);
+    }
+
+    #[test]
+    fn test_missing_manifest_is_rejected() {
+        let artifact = zip_artifact("manager-empty", &[("index.html", "")]);
+        let result = load_manifest(None, Some(&artifact));
+        assert!(matches!(
+            result,
+            Err(DeploymentError::InvalidManifest(errors)) if errors[0].field.as_deref() == Some("manifest")
+        ));
+    }
+
+    #[test]
+    fn test_unverified_artifacts_are_rejected() {
+        let files = [(
+            "pond.toml",
+            "name = \"blog\"\ndeployment_type = \"static-site\"",
+        )];
+        let mut manager = manager();
+        let mut signing_keys = SigningKeys::default();
+        signing_keys.add(
+            "blog".to_owned(),
+            crate::PublicKey::parse(crate::verification::test::MINISIGN_PUBLIC_KEY).unwrap(),
+        );
+        manager.set_signing_keys(signing_keys);
+
+        let tampered = zip_artifact("manager-tampered", &files);
+        let mut request = DeploymentRequest::new(ArtifactSource::File(tampered.clone()));
+        request.verification.sha256 = Some("0".repeat(64));
+        assert!(matches!(
+            manager.deploy(request),
+            Err(DeploymentError::Verification(
+                VerificationError::ChecksumMismatch { .. }
+            ))
+        ));
+        assert!(!tampered.exists());
+
+        let artifact = zip_artifact("manager-signed", &files);
+        let sha256 = crate::verification::verify_checksum(&artifact, None).unwrap();
+        assert!(matches!(
+            manager.deploy(DeploymentRequest::new(ArtifactSource::File(artifact))),
+            Err(DeploymentError::Verification(
+                VerificationError::MissingSignature
+            ))
+        ));
+
+        // The artifact was stored and can be deployed again without uploading it
+        assert!(matches!(
+            manager.deploy(DeploymentRequest::new(ArtifactSource::Stored(sha256))),
+            Err(DeploymentError::Verification(
+                VerificationError::MissingSignature
+            ))
+        ));
+        assert!(matches!(
+            manager.deploy(DeploymentRequest::new(ArtifactSource::Stored(
+                "0".repeat(64)
+            ))),
+            Err(DeploymentError::UnknownArtifact(_))
+        ));
+    }
+
+    #[test]
+    fn test_synced_releases_are_verified() {
+        let mut manager = manager();
+        let mut signing_keys = SigningKeys::default();
+        signing_keys.add(
+            "blog".to_owned(),
+            crate::PublicKey::parse(crate::verification::test::MINISIGN_PUBLIC_KEY).unwrap(),
+        );
+        manager.set_signing_keys(signing_keys);
+        let manifest = (
+            "pond.toml",
+            "name = \"blog\"\ndeployment_type = \"static-site\"",
+        );
+
+        let session = complete_sync(&manager.releases, "shop", &[manifest]);
+        assert!(matches!(
+            manager.deploy(DeploymentRequest::new(ArtifactSource::Sync(session.id))),
+            Err(DeploymentError::InvalidManifest(errors)) if errors[0].field.as_deref() == Some("name")
+        ));
+
+        let session = complete_sync(&manager.releases, "blog", &[manifest, ("index.html", "")]);
+        assert!(matches!(
+            manager.deploy(DeploymentRequest::new(ArtifactSource::Sync(
+                session.id.clone()
+            ))),
+            Err(DeploymentError::Verification(
+                VerificationError::MissingSignature
+            ))
+        ));
+        // The release was assembled, which ends the sync
+        assert!(matches!(
+            manager.deploy(DeploymentRequest::new(ArtifactSource::Sync(session.id))),
+            Err(DeploymentError::Sync(SyncError::NotFound(_)))
+        ));
+    }
+
+    /// Writes the `index.html` of the archive or directory it deploys to the logs.
+    struct IndexDeployer;
+
+    impl Deployer for IndexDeployer {
+        fn deploy(
+            &self,
+            _manifest: Manifest,
+            _environment: &std::collections::BTreeMap<String, String>,
+            artifact_location: &Path,
+            mut deployment_handle: crate::deployer::DeploymentHandle,
+        ) -> io::Result<()> {
+            if artifact_location.is_dir() {
+                let index = fs::read(artifact_location.join("index.html"))?;
+                deployment_handle.info().write_all(&index)?;
+                return Ok(());
+            }
+            let mut archive = zip::ZipArchive::new(fs::File::open(artifact_location)?)?;
+            io::copy(
+                &mut archive.by_name("index.html")?,
+                deployment_handle.info(),
+            )?;
+            Ok(())
+        }
+
+        fn promote(
+            &self,
+            source: &Manifest,
+            manifest: Manifest,
+            _environment: &std::collections::BTreeMap<String, String>,
+            mut deployment_handle: crate::deployer::DeploymentHandle,
+        ) -> io::Result<()> {
+            write!(
+                deployment_handle.info(),
+                "Copied {} to {}",
+                source.name,
+                manifest.name
+            )
+        }
+
+        /// Restores the files of the previous deployment, unless the name of the failed one
+        /// ends with `-pruned`.
+        fn roll_back(
+            &self,
+            failed: &Manifest,
+            previous: &Manifest,
+            mut deployment_handle: crate::deployer::DeploymentHandle,
+        ) -> io::Result<bool> {
+            if failed.name.ends_with("-pruned") {
+                return Ok(false);
+            }
+            write!(deployment_handle.info(), "Restored {}", previous.name)?;
+            Ok(true)
+        }
+
+        fn set_maintenance(
+            &self,
+            _manifest: &Manifest,
+            _maintenance: Option<&Maintenance>,
+            _deployment_handle: crate::deployer::DeploymentHandle,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn remove(
+            &self,
+            _manifest: &Manifest,
+            _deployment_handle: crate::deployer::DeploymentHandle,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl RegisterDeployment for IndexDeployer {
+        fn deployment_type() -> &'static str {
+            "static-site"
+        }
+    }
+
+    #[test]
+    fn test_git_source_is_deployed() {
+        let (repository, commit) = crate::git::test::bare_repository("manager");
+        let mut manager = manager();
+        manager.register_deployer(IndexDeployer);
+        let git_request = || {
+            let mut request = DeploymentRequest::new(ArtifactSource::Git);
+            request.manifest = Some(ManifestSource {
+                content: format!(
+                    "name = \"blog\"\ndeployment_type = \"static-site\"\nsource = {{ git = \"file://{}\", ref = \"main\", path = \"dist\" }}",
+                    repository.display()
+                ),
+                format: None,
+            });
+            request
+        };
+        assert!(matches!(
+            manager.deploy(git_request()),
+            Err(DeploymentError::InvalidManifest(errors)) if errors[0].field.as_deref() == Some("source.git")
+        ));
+
+        manager.set_git_settings(crate::git::test::file_settings());
+        let mut logs = manager.deploy(git_request()).unwrap();
+        let output = io::read_to_string(logs.info()).unwrap();
+        assert!(output.contains("Hello"), "{}", output);
+        assert!(output.contains("Deployment succeeded"), "{}", output);
+
+        let deployment = manager.deployments.get("blog").unwrap().unwrap();
+        assert_eq!(deployment.commit_sha, Some(commit));
+        let artifact_sha256 = deployment.artifact_sha256.unwrap();
+        assert!(manager.artifacts.get(&artifact_sha256).unwrap().is_some());
+
+        let mut request = DeploymentRequest::new(ArtifactSource::Git);
+        request.manifest = Some(ManifestSource {
+            content: "name = \"blog\"\ndeployment_type = \"static-site\"".to_owned(),
+            format: None,
+        });
+        assert!(matches!(
+            manager.deploy(request),
+            Err(DeploymentError::InvalidManifest(errors)) if errors[0].field.as_deref() == Some("source")
+        ));
+    }
+
+    #[test]
+    fn test_build_output_is_deployed() {
+        let mut manager = manager();
+        manager.set_builder(crate::build::test::builder());
+        manager.register_deployer(IndexDeployer);
+        let artifact = zip_artifact(
+            "manager-build",
+            &[
+                (
+                    "pond.toml",
+                    "name = \"blog\"\ndeployment_type = \"static-site\"\n\n[build]\ncommand = \"mkdir dist && echo Built $GREETING > dist/index.html\"\noutput_dir = \"dist\"\nenv = { GREETING = \"today\" }",
+                ),
+                ("index.html", "Source"),
+            ],
+        );
+        let mut logs = manager
+            .deploy(DeploymentRequest::new(ArtifactSource::File(artifact)))
+            .unwrap();
+        let output = io::read_to_string(logs.info()).unwrap();
+        assert!(output.contains("Built today"), "{}", output);
+        assert!(!output.contains("Source"), "{}", output);
+        assert!(output.contains("Deployment succeeded"), "{}", output);
+    }
+
+    #[test]
+    fn test_unhealthy_deployments_are_rolled_back() {
+        let mut server = mockito::Server::new();
+        let mut manager = manager();
+        manager.register_deployer(IndexDeployer);
+        manager.set_health_checker(crate::health::test::checker(&server));
+        let deploy = |manager: &DeploymentManager, name: &str, index: &str| {
+            let artifact = zip_artifact(
+                &format!("manager-{}-{}", name, index),
+                &[
+                    (
+                        "pond.toml",
+                        &format!("name = \"{}\"\ndeployment_type = \"static-site\"\n\n[health_check]\npath = \"/health\"\nretries = 0", name),
+                    ),
+                    ("index.html", index),
+                ],
+            );
+            let mut logs = manager
+                .deploy(DeploymentRequest::new(ArtifactSource::File(artifact)))
+                .unwrap();
+            let output = io::read_to_string(logs.info()).unwrap();
+            let errors = io::read_to_string(logs.error()).unwrap();
+            (output, errors)
+        };
+
+        let healthy = server.mock("GET", "/health").create();
+        for name in ["blog", "blog-pruned"] {
+            let (output, _) = deploy(&manager, name, "First");
+            assert!(output.contains("Deployment succeeded"), "{}", output);
+        }
+        let deployed = manager.deployments.get("blog").unwrap().unwrap();
+        healthy.remove();
+
+        let _unhealthy = server.mock("GET", "/health").with_status(503).create();
+        let (output, errors) = deploy(&manager, "blog", "Second");
+        // The files of the previous deployment are served again
+        let rolled_back = output.split_once("Second").map(|(_, rest)| rest);
+        assert!(
+            rolled_back
+                .is_some_and(|r| r.contains("Restored blogRolled back") && !r.contains("First")),
+            "{}",
+            output
+        );
+        assert!(errors.contains("503"), "{}", errors);
+        assert!(errors.contains("Deployment failed"), "{}", errors);
+        assert_eq!(manager.deployments.get("blog").unwrap().unwrap(), deployed);
+
+        // Without them, the previous artifact is deployed again after the new one
+        let (output, _) = deploy(&manager, "blog-pruned", "Second");
+        let rolled_back = output.split_once("Second").map(|(_, rest)| rest);
+        assert!(
+            rolled_back.is_some_and(|r| r.contains("again\nFirstRolled back")),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_canaries_are_promoted_or_aborted() {
+        let mut manager = manager();
+        manager.register_deployer(IndexDeployer);
+        let deploy = |name: &str, canary: &str, index: &str| {
+            let artifact = zip_artifact(
+                name,
+                &[
+                    (
+                        "pond.toml",
+                        &format!(
+                            "name = \"blog\"\ndeployment_type = \"static-site\"\n{}",
+                            canary
+                        ),
+                    ),
+                    ("index.html", index),
+                ],
+            );
+            manager
+                .deploy(DeploymentRequest::new(ArtifactSource::File(artifact)))
+                .map(|mut logs| io::read_to_string(logs.info()).unwrap())
+        };
+        let canary = "canary = { weight = 10 }";
+        assert!(matches!(
+            deploy("manager-canary-alone", canary, "Alone"),
+            Err(DeploymentError::InvalidManifest(errors)) if errors[0].field.as_deref() == Some("canary")
+        ));
+        assert!(matches!(
+            manager.promote("blog", None),
+            Err(DeploymentError::NoCanary(_))
+        ));
+
+        deploy("manager-canary-first", "", "First").unwrap();
+        let first = manager.deployments.get("blog").unwrap().unwrap();
+        let output = deploy("manager-canary-second", canary, "Second").unwrap();
+        assert!(output.contains("Deployment succeeded"), "{}", output);
+        let deployment = manager.deployments.get("blog").unwrap().unwrap();
+        assert_eq!(deployment.artifact_sha256, first.artifact_sha256);
+        let second = deployment.canary.unwrap();
+        assert_eq!(second.manifest.canary.as_ref().unwrap().weight, 10);
+
+        let output = io::read_to_string(manager.promote("blog", None).unwrap().info()).unwrap();
+        assert!(output.starts_with("Second"), "{}", output);
+        let deployment = manager.deployments.get("blog").unwrap().unwrap();
+        assert_eq!(deployment.artifact_sha256, second.artifact_sha256);
+        assert_eq!(deployment.manifest.canary, None);
+        assert_eq!(deployment.canary, None);
+
+        deploy("manager-canary-third", canary, "Third").unwrap();
+        assert!(matches!(
+            manager.abort("blog", Some("example.net")),
+            Err(DeploymentError::RootDomainNotAllowed(_))
+        ));
+        let output = io::read_to_string(manager.abort("blog", None).unwrap().info()).unwrap();
+        assert!(output.starts_with("Second"), "{}", output);
+        assert_eq!(
+            manager.deployments.get("blog").unwrap().unwrap(),
+            deployment
+        );
+    }
+
+    #[test]
+    fn test_deployments_are_promoted_to_other_deployments() {
+        let mut manager = manager();
+        manager.register_deployer(IndexDeployer);
+        let deploy = |manager: &DeploymentManager, name: &str, index: &str| {
+            let artifact = zip_artifact(
+                &format!("manager-promote-{}", name),
+                &[
+                    (
+                        "pond.toml",
+                        &format!("name = \"{}\"\ndeployment_type = \"static-site\"", name),
+                    ),
+                    ("index.html", index),
+                ],
+            );
+            let mut logs = manager
+                .deploy(DeploymentRequest::new(ArtifactSource::File(artifact)))
+                .unwrap();
+            io::read_to_string(logs.info()).unwrap();
+        };
+        deploy(&manager, "app-staging", "Staging");
+        assert!(matches!(
+            manager.promote_to("app-staging", "app", None),
+            Err(DeploymentError::UnknownDeployment(name)) if name == "app"
+        ));
+        deploy(&manager, "app", "Production");
+        assert!(matches!(
+            manager.promote_to("app", "app", None),
+            Err(DeploymentError::InvalidManifest(_))
+        ));
+        assert!(matches!(
+            manager.promote_to("app-staging", "app", Some("example.net")),
+            Err(DeploymentError::RootDomainNotAllowed(_))
+        ));
+
+        let target = manager.deployments.get("app").unwrap().unwrap();
+        let source = manager.deployments.get("app-staging").unwrap().unwrap();
+        let output = io::read_to_string(
+            manager
+                .promote_to("app-staging", "app", None)
+                .unwrap()
+                .info(),
+        )
+        .unwrap();
+        assert!(output.contains("Copied app-staging to app"), "{}", output);
+        assert!(output.contains("Deployment succeeded"), "{}", output);
+        let promoted = manager.deployments.get("app").unwrap().unwrap();
+        assert_eq!(promoted.manifest, target.manifest);
+        assert_eq!(promoted.artifact_sha256, source.artifact_sha256);
+        assert_eq!(promoted.promoted_from.as_deref(), Some("app-staging"));
+        // Redeployments build the artifact of the source with the manifest of the target
+        let redeployment = manager.redeployment(promoted).unwrap();
+        assert_eq!(redeployment.deployment.manifest, target.manifest);
+        assert_eq!(
+            redeployment.artifact_location,
+            manager
+                .artifacts
+                .path(source.artifact_sha256.as_deref().unwrap())
+        );
+
+        deploy(&manager, "other", "Other");
+        let mut other = manager.deployments.get("other").unwrap().unwrap();
+        other.manifest.root_domain_name = Some("example.net".to_owned());
+        manager
+            .deployments
+            .record(&other.manifest, "digest", None)
+            .unwrap();
+        assert!(matches!(
+            manager.promote_to("other", "app", Some("example.com")),
+            Err(DeploymentError::RootDomainNotAllowed(_))
+        ));
+
+        let mut signing_keys = SigningKeys::default();
+        signing_keys.add(
+            "app".to_owned(),
+            crate::verification::PublicKey::parse(crate::verification::test::MINISIGN_PUBLIC_KEY)
+                .unwrap(),
+        );
+        manager.set_signing_keys(signing_keys);
+        assert!(matches!(
+            manager.promote_to("app-staging", "app", None),
+            Err(DeploymentError::Verification(
+                VerificationError::MissingSignature
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_maintenance_is_checked() {
+        let mut manager = manager();
+        manager.register_deployer(IndexDeployer);
+        let maintenance = Maintenance {
+            bypass_ips: vec!["203.0.113.7".to_owned()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            manager.set_maintenance("blog", Some(maintenance.clone()), None),
+            Err(DeploymentError::UnknownDeployment(_))
+        ));
+
+        let artifact = zip_artifact(
+            "manager-maintenance",
+            &[
+                (
+                    "pond.toml",
+                    "name = \"blog\"\ndeployment_type = \"static-site\"",
+                ),
+                ("index.html", "Blog"),
+            ],
+        );
+        let mut logs = manager
+            .deploy(DeploymentRequest::new(ArtifactSource::File(artifact)))
+            .unwrap();
+        io::read_to_string(logs.info()).unwrap();
+
+        let invalid = Maintenance {
+            bypass_ips: vec!["everyone".to_owned()],
+            ..Default::default()
+        };
+        assert!(matches!(
+            manager.set_maintenance("blog", Some(invalid), None),
+            Err(DeploymentError::InvalidMaintenance(_))
+        ));
+        assert!(matches!(
+            manager.set_maintenance("blog", Some(maintenance.clone()), Some("example.net")),
+            Err(DeploymentError::RootDomainNotAllowed(_))
+        ));
+        let mut logs = manager
+            .set_maintenance("blog", Some(maintenance), None)
+            .unwrap();
+        let output = io::read_to_string(logs.info()).unwrap();
+        assert!(output.contains("Maintenance started"), "{}", output);
+        let mut logs = manager.set_maintenance("blog", None, None).unwrap();
+        let output = io::read_to_string(logs.info()).unwrap();
+        assert!(output.contains("Maintenance ended"), "{}", output);
+    }
+
+    #[test]
+    fn test_scoped_tokens_only_manage_their_deployments() {
+        let mut manager = manager();
+        manager.register_deployer(IndexDeployer);
+        let artifact = zip_artifact(
+            "manager-scope",
+            &[
+                (
+                    "pond.toml",
+                    "name = \"blog\"\ndeployment_type = \"static-site\"",
+                ),
+                ("index.html", "Blog"),
+            ],
+        );
+        let mut logs = manager
+            .deploy(DeploymentRequest::new(ArtifactSource::File(artifact)))
+            .unwrap();
+        io::read_to_string(logs.info()).unwrap();
+
+        assert!(manager.check_scope("blog", None).is_ok());
+        assert!(manager.check_scope("unknown", None).is_ok());
+        assert!(manager.check_scope("blog", Some("example.com")).is_ok());
+        assert!(matches!(
+            manager.check_scope("blog", Some("example.net")),
+            Err(DeploymentError::RootDomainNotAllowed(_))
+        ));
+        assert!(matches!(
+            manager.check_scope("unknown", Some("example.com")),
+            Err(DeploymentError::UnknownDeployment(_))
+        ));
+    }
+
+    #[test]
+    fn test_scoped_tokens_cant_replace_other_deployments() {
+        let mut manager = manager();
+        manager.register_deployer(IndexDeployer);
+        let files = [
+            (
+                "pond.toml",
+                "name = \"blog\"\ndeployment_type = \"static-site\"",
+            ),
+            ("index.html", "Blog"),
+        ];
+        let mut logs = manager
+            .deploy(DeploymentRequest::new(ArtifactSource::File(zip_artifact(
+                "manager-replace",
+                &files,
+            ))))
+            .unwrap();
+        io::read_to_string(logs.info()).unwrap();
+
+        let request = DeploymentRequest {
+            root_domain_name: Some("example.net".to_owned()),
+            ..DeploymentRequest::new(ArtifactSource::File(zip_artifact(
+                "manager-replace-scoped",
+                &files,
+            )))
+        };
+        assert!(matches!(
+            manager.deploy(request),
+            Err(DeploymentError::RootDomainNotAllowed(root_domain_name)) if root_domain_name == "example.com"
+        ));
+    }
+}
@@ -1,9 +1,842 @@
-use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+};
 
-#[derive(Deserialize)]
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{
+    helpers::{is_relative_path, is_valid_dns_label, is_valid_domain_name},
+    secrets::{is_valid_secret_name, SECRET_REFERENCE_PREFIX},
+};
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct Manifest {
     pub name: String,
     pub deployment_type: String,
+    /// The root domain the default host is created below. Defaults to the root domain of the
+    /// access token or the configured `root_domain_name`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub root_domain_name: Option<String>,
     #[serde(default)]
     pub domain_names: Vec<String>,
+    /// Custom domains that get a `CNAME` record pointing at the default host of the deployment.
+    #[serde(default)]
+    pub cname_domains: Vec<String>,
+    /// Allows the deployment to claim DNS records owned by someone else.
+    #[serde(default)]
+    pub takeover: bool,
+    /// Environment variables for the deployer. Values of the form `secret://<name>` are replaced
+    /// by the secret `<name>` of the deployment.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+    /// Where the files are fetched from when no artifact is uploaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<GitSource>,
+    /// Builds the files to deploy from the uploaded source.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<BuildStep>,
+    /// Probes the domains once the deployment is done, rolling back if they are unhealthy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheck>,
+    /// Serves the deployment to a share of the visitors next to the current one, until it is
+    /// promoted or aborted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canary: Option<Canary>,
+    /// Removes the deployment this many seconds after it was deployed, e.g. for previews.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_seconds: Option<u64>,
+    /// Removes the deployment at this time, in seconds since the Unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Canary {
+    /// The percentage of visitors served by the canary, from 1 to 99.
+    pub weight: u8,
+}
+
+/// A command run in a throwaway copy of the artifact, whose output directory is deployed
+/// instead of the artifact.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct BuildStep {
+    /// Run through `sh -c`, e.g. `npm ci && npm run build`.
+    pub command: String,
+    /// Where the command writes the files to deploy, relative to the root of the artifact.
+    pub output_dir: String,
+    /// Limited by the `build.max_timeout_seconds` of the server.
+    #[serde(default = "default_build_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Environment variables for the command, which may refer to secrets like `env`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+fn default_build_timeout_seconds() -> u64 {
+    10 * 60
+}
+
+/// An HTTP request sent to every domain of a deployment after it was deployed.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct HealthCheck {
+    #[serde(default = "default_health_check_path")]
+    pub path: String,
+    /// Redirects aren't followed, so this may be a redirect status as well.
+    #[serde(default = "default_expected_status")]
+    pub expected_status: u16,
+    /// Text the body of the response has to contain.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body_contains: Option<String>,
+    /// How often a failed request is repeated before the deployment is rolled back.
+    #[serde(default = "default_health_check_retries")]
+    pub retries: u32,
+    /// For each request.
+    #[serde(default = "default_health_check_timeout_seconds")]
+    pub timeout_seconds: u64,
+}
+
+fn default_health_check_path() -> String {
+    "/".to_owned()
+}
+
+fn default_expected_status() -> u16 {
+    200
+}
+
+fn default_health_check_retries() -> u32 {
+    3
+}
+
+fn default_health_check_timeout_seconds() -> u64 {
+    10
+}
+
+/// A git repository whose files at `ref` are deployed.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct GitSource {
+    /// An `https://` URL of the repository, or a `file://` URL if the server allows them.
+    pub git: String,
+    /// The branch, tag or commit to deploy.
+    #[serde(rename = "ref")]
+    pub reference: String,
+    /// The directory within the repository to deploy. Defaults to its root.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+/// A problem found in a manifest. Syntax errors carry the 1-based `line` and `column` they
+/// were found at, all others the `field` they concern.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ManifestError {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<usize>,
+}
+
+impl ManifestError {
+    pub(crate) fn field(field: impl Into<String>, message: impl Into<String>) -> Self {
+        ManifestError {
+            field: Some(field.into()),
+            message: message.into(),
+            line: None,
+            column: None,
+        }
+    }
+
+    fn syntax(message: impl Into<String>, location: Option<(usize, usize)>) -> Self {
+        ManifestError {
+            field: None,
+            message: message.into(),
+            line: location.map(|(line, _)| line),
+            column: location.map(|(_, column)| column),
+        }
+    }
+
+    fn from_toml(manifest: &str, error: toml::de::Error) -> Self {
+        let location = error.span().map(|span| {
+            let before = &manifest[..span.start];
+            let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+            (
+                before.matches('\n').count() + 1,
+                before[line_start..].chars().count() + 1,
+            )
+        });
+        Self::syntax(error.message(), location)
+    }
+
+    fn from_json(error: serde_json::Error) -> Self {
+        let location = (error.line() > 0).then(|| (error.line(), error.column()));
+        Self::syntax(without_location(error.to_string()), location)
+    }
+
+    fn from_yaml(error: serde_yaml::Error) -> Self {
+        let location = error.location().map(|l| (l.line(), l.column()));
+        Self::syntax(without_location(error.to_string()), location)
+    }
+}
+
+/// A manifest document as uploaded, before it is parsed.
+#[derive(Clone, Debug)]
+pub struct ManifestSource {
+    pub content: String,
+    /// The format of `content`, sniffed if `None`.
+    pub format: Option<ManifestFormat>,
+}
+
+fn deserialize<T: DeserializeOwned>(
+    manifest: &str,
+    format: Option<ManifestFormat>,
+) -> Result<T, ManifestError> {
+    match format.unwrap_or_else(|| ManifestFormat::sniff(manifest)) {
+        ManifestFormat::Toml => {
+            toml::from_str(manifest).map_err(|e| ManifestError::from_toml(manifest, e))
+        }
+        ManifestFormat::Json => serde_json::from_str(manifest).map_err(ManifestError::from_json),
+        ManifestFormat::Yaml => serde_yaml::from_str(manifest).map_err(ManifestError::from_yaml),
+    }
+}
+
+fn merge(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    match (base, overrides) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Strips the ` at line 1 column 2` suffix serde_json and serde_yaml add to their messages.
+fn without_location(message: String) -> String {
+    match message.rsplit_once(" at line ") {
+        Some((message, _)) => message.to_owned(),
+        None => message,
+    }
+}
+
+/// The formats manifests can be written in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ManifestFormat {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl ManifestFormat {
+    /// The format of a manifest uploaded as `media_type`, like `application/json`. Returns
+    /// `None` for media types that don't determine the format, like `text/plain`.
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        let subtype = media_type
+            .split(';')
+            .next()?
+            .rsplit('/')
+            .next()?
+            .trim()
+            .to_lowercase();
+        match subtype.as_str() {
+            "toml" | "x-toml" => Some(ManifestFormat::Toml),
+            "json" => Some(ManifestFormat::Json),
+            "yaml" | "x-yaml" => Some(ManifestFormat::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format from the first line that isn't empty or a comment.
+    pub fn sniff(manifest: &str) -> Self {
+        let first_line = manifest
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty() && !line.starts_with('#'))
+            .unwrap_or_default();
+        if first_line.starts_with('{') {
+            return ManifestFormat::Json;
+        }
+        let is_yaml_key = first_line.split_once(':').is_some_and(|(key, _)| {
+            !key.is_empty()
+                && key
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        });
+        if first_line.starts_with("---") || is_yaml_key {
+            ManifestFormat::Yaml
+        } else {
+            ManifestFormat::Toml
+        }
+    }
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.field, self.line, self.column) {
+            (Some(field), _, _) => write!(f, "{}: {}", field, self.message),
+            (None, Some(line), Some(column)) => {
+                write!(f, "line {}, column {}: {}", line, column, self.message)
+            }
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl Manifest {
+    /// Parses and validates a manifest, sniffing the format if it isn't known.
+    pub fn parse(
+        manifest: &str,
+        format: Option<ManifestFormat>,
+    ) -> Result<Manifest, Vec<ManifestError>> {
+        let parsed: Manifest = deserialize(manifest, format).map_err(|e| vec![e])?;
+        parsed.validate()?;
+        Ok(parsed)
+    }
+
+    /// Parses `base` with the values of `overrides` taking precedence. Tables are merged key
+    /// by key, all other values are replaced.
+    pub fn parse_with_overrides(
+        base: &ManifestSource,
+        overrides: &ManifestSource,
+    ) -> Result<Manifest, Vec<ManifestError>> {
+        let mut merged: serde_json::Value =
+            deserialize(&base.content, base.format).map_err(|e| vec![e])?;
+        let overrides: serde_json::Value =
+            deserialize(&overrides.content, overrides.format).map_err(|e| vec![e])?;
+        merge(&mut merged, overrides);
+
+        let parsed: Manifest = serde_json::from_value(merged)
+            .map_err(|e| vec![ManifestError::syntax(e.to_string(), None)])?;
+        parsed.validate()?;
+        Ok(parsed)
+    }
+
+    /// Checks the manifest, returning every problem found.
+    pub fn validate(&self) -> Result<(), Vec<ManifestError>> {
+        let mut errors = vec![];
+        // The name becomes the first label of the default host
+        if !is_valid_dns_label(&self.name) || self.name.chars().any(|c| c.is_ascii_uppercase()) {
+            errors.push(ManifestError::field(
+                "name",
+                format!(
+                    "{:?} is not a DNS label of lowercase letters, digits and hyphens",
+                    self.name
+                ),
+            ));
+        }
+        if self.deployment_type.is_empty() {
+            errors.push(ManifestError::field("deployment_type", "must not be empty"));
+        }
+        if let Some(root_domain_name) = &self.root_domain_name {
+            if !is_valid_domain_name(root_domain_name) {
+                errors.push(ManifestError::field(
+                    "root_domain_name",
+                    format!("{:?} is not a valid domain name", root_domain_name),
+                ));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        let domains = self
+            .domain_names
+            .iter()
+            .enumerate()
+            .map(|(i, d)| (format!("domain_names[{}]", i), d))
+            .chain(
+                self.cname_domains
+                    .iter()
+                    .enumerate()
+                    .map(|(i, d)| (format!("cname_domains[{}]", i), d)),
+            );
+        for (field, domain_name) in domains {
+            if !is_valid_domain_name(domain_name) {
+                errors.push(ManifestError::field(
+                    field,
+                    format!("{:?} is not a valid domain name", domain_name),
+                ));
+            } else if !seen.insert(domain_name.to_lowercase()) {
+                errors.push(ManifestError::field(
+                    field,
+                    format!("{} is listed more than once", domain_name),
+                ));
+            }
+        }
+
+        validate_env("env", &self.env, &mut errors);
+
+        if let Some(source) = &self.source {
+            if !source.git.starts_with("https://") && !source.git.starts_with("file://") {
+                errors.push(ManifestError::field(
+                    "source.git",
+                    format!("{:?} is not an https:// or file:// URL", source.git),
+                ));
+            }
+            // The ref is passed to git, which must not take it for an option
+            if source.reference.is_empty()
+                || source.reference.starts_with('-')
+                || source
+                    .reference
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control())
+            {
+                errors.push(ManifestError::field(
+                    "source.ref",
+                    format!("{:?} is not a valid git ref", source.reference),
+                ));
+            }
+            if let Some(path) = source.path.as_deref().filter(|p| !is_relative_path(p)) {
+                errors.push(ManifestError::field(
+                    "source.path",
+                    format!("{:?} is not a relative path within the repository", path),
+                ));
+            }
+        }
+
+        if let Some(build) = &self.build {
+            if build.command.trim().is_empty() {
+                errors.push(ManifestError::field("build.command", "must not be empty"));
+            }
+            if !is_relative_path(&build.output_dir) {
+                errors.push(ManifestError::field(
+                    "build.output_dir",
+                    format!(
+                        "{:?} is not a relative path within the artifact",
+                        build.output_dir
+                    ),
+                ));
+            }
+            if build.timeout_seconds == 0 {
+                errors.push(ManifestError::field(
+                    "build.timeout_seconds",
+                    "must be positive",
+                ));
+            }
+            validate_env("build.env", &build.env, &mut errors);
+        }
+
+        if let Some(health_check) = &self.health_check {
+            if !health_check.path.starts_with('/')
+                || health_check
+                    .path
+                    .chars()
+                    .any(|c| c.is_whitespace() || c.is_control())
+            {
+                errors.push(ManifestError::field(
+                    "health_check.path",
+                    format!("{:?} is not an absolute URL path", health_check.path),
+                ));
+            }
+            if !(100..=599).contains(&health_check.expected_status) {
+                errors.push(ManifestError::field(
+                    "health_check.expected_status",
+                    format!("{} is not an HTTP status", health_check.expected_status),
+                ));
+            }
+            if health_check.timeout_seconds == 0 {
+                errors.push(ManifestError::field(
+                    "health_check.timeout_seconds",
+                    "must be positive",
+                ));
+            }
+        }
+
+        if let Some(canary) = &self.canary {
+            if !(1..=99).contains(&canary.weight) {
+                errors.push(ManifestError::field(
+                    "canary.weight",
+                    "must be between 1 and 99",
+                ));
+            }
+        }
+
+        if self.ttl_seconds.is_some() && self.expires_at.is_some() {
+            errors.push(ManifestError::field(
+                "expires_at",
+                "can't be combined with ttl_seconds",
+            ));
+        }
+        if self.ttl_seconds == Some(0) {
+            errors.push(ManifestError::field("ttl_seconds", "must be positive"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// The host every deployment gets below the root domain.
+    pub fn default_domain_name(&self, root_domain_name: &str) -> String {
+        format!("{}.{}", self.name, root_domain_name)
+    }
+
+    /// All domains the deployment is served on.
+    pub fn served_domain_names(&self) -> Vec<String> {
+        self.domain_names
+            .iter()
+            .chain(&self.cname_domains)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Checks the names of environment variables and the secrets their values refer to.
+fn validate_env(field: &str, env: &BTreeMap<String, String>, errors: &mut Vec<ManifestError>) {
+    for (variable, value) in env {
+        let valid_variable = variable
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && variable
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid_variable {
+            errors.push(ManifestError::field(
+                format!("{}.{}", field, variable),
+                "is not a valid environment variable name",
+            ));
+        }
+        if let Some(name) = value.strip_prefix(SECRET_REFERENCE_PREFIX) {
+            if !is_valid_secret_name(name) {
+                errors.push(ManifestError::field(
+                    format!("{}.{}", field, variable),
+                    format!("{:?} is not a valid secret name", name),
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// A static site serving `domain_names`, leaving every other field at its default so tests
+    /// don't change with each new field. It isn't validated.
+    pub(crate) fn manifest(name: &str, domain_names: &[&str]) -> Manifest {
+        serde_json::from_value(serde_json::json!({
+            "name": name,
+            "deployment_type": "static-site",
+            "domain_names": domain_names,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_parse_valid_manifest() {
+        let manifest = Manifest::parse(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+            domain_names = ["blog.example.com"]
+            cname_domains = ["www.example.org"]
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(manifest.name, "blog");
+        assert_eq!(manifest.cname_domains, vec!["www.example.org"]);
+    }
+
+    #[test]
+    fn test_parse_json_and_yaml_manifests() {
+        let toml = Manifest::parse(
+            "name = \"blog\"\ndeployment_type = \"static-site\"\ndomain_names = [\"blog.example.com\"]",
+            None,
+        )
+        .unwrap();
+        let json = r#"{"name": "blog", "deployment_type": "static-site", "domain_names": ["blog.example.com"]}"#;
+        let yaml = "# Deployed by CI\nname: blog\ndeployment_type: static-site\ndomain_names:\n  - blog.example.com\n";
+        assert_eq!(Manifest::parse(json, None).unwrap(), toml);
+        assert_eq!(Manifest::parse(yaml, None).unwrap(), toml);
+        assert_eq!(
+            Manifest::parse(yaml, Some(ManifestFormat::Yaml)).unwrap(),
+            toml
+        );
+    }
+
+    #[test]
+    fn test_format_from_media_type() {
+        assert_eq!(
+            ManifestFormat::from_media_type("application/json; charset=utf-8"),
+            Some(ManifestFormat::Json)
+        );
+        assert_eq!(
+            ManifestFormat::from_media_type("application/x-yaml"),
+            Some(ManifestFormat::Yaml)
+        );
+        assert_eq!(
+            ManifestFormat::from_media_type("application/toml"),
+            Some(ManifestFormat::Toml)
+        );
+        assert_eq!(ManifestFormat::from_media_type("text/plain"), None);
+    }
+
+    #[test]
+    fn test_syntax_errors_have_a_location() {
+        let errors =
+            Manifest::parse("name = \"blog\"\ndeployment_type = static-site\n", None).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, Some(2));
+        assert_eq!(errors[0].column, Some(19));
+
+        let errors = Manifest::parse("{\n  \"name\": \"blog\",\n  \"deployment_type\"\n}", None)
+            .unwrap_err();
+        assert_eq!(errors[0].line, Some(4));
+        assert!(!errors[0].message.contains(" at line "));
+
+        let errors =
+            Manifest::parse("name: blog\ndeployment_type: [static-site\n", None).unwrap_err();
+        assert!(errors[0].line.is_some());
+    }
+
+    #[test]
+    fn test_unknown_fields_are_rejected() {
+        let errors = Manifest::parse(
+            "name = \"blog\"\ndeployment_type = \"static-site\"\ndomains = [\"example.org\"]\n",
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(errors[0].line, Some(3));
+        assert!(errors[0].message.contains("unknown field `domains`"));
+    }
+
+    #[test]
+    fn test_all_validation_errors_are_returned() {
+        let errors = Manifest::parse(
+            r#"
+            name = "My_Blog"
+            deployment_type = "static-site"
+            domain_names = ["blog.example.com", "-invalid.example.com"]
+            cname_domains = ["Blog.example.com"]
+            "#,
+            None,
+        )
+        .unwrap_err();
+        let fields: Vec<_> = errors.iter().map(|e| e.field.as_deref().unwrap()).collect();
+        assert_eq!(fields, vec!["name", "domain_names[1]", "cname_domains[0]"]);
+    }
+
+    #[test]
+    fn test_environment() {
+        let manifest = Manifest::parse(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+
+            [env]
+            MODE = "production"
+            API_TOKEN = "secret://api-token"
+            "#,
+            None,
+        )
+        .unwrap();
+        assert_eq!(manifest.env["API_TOKEN"], "secret://api-token");
+
+        let errors = Manifest::parse(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+
+            [env]
+            1MODE = "production"
+            API_TOKEN = "secret://../token"
+            "#,
+            None,
+        )
+        .unwrap_err();
+        let fields: Vec<_> = errors.iter().map(|e| e.field.as_deref().unwrap()).collect();
+        assert_eq!(fields, vec!["env.1MODE", "env.API_TOKEN"]);
+    }
+
+    #[test]
+    fn test_overrides_take_precedence() {
+        let base = ManifestSource {
+            content: "name = \"blog\"\ndeployment_type = \"static-site\"\ndomain_names = [\"blog.example.com\"]\ntakeover = true".to_owned(),
+            format: None,
+        };
+        let overrides = ManifestSource {
+            content: r#"{"domain_names": ["www.example.com"], "takeover": false}"#.to_owned(),
+            format: Some(ManifestFormat::Json),
+        };
+        let manifest = Manifest::parse_with_overrides(&base, &overrides).unwrap();
+        assert_eq!(manifest.name, "blog");
+        assert_eq!(manifest.domain_names, vec!["www.example.com"]);
+        assert!(!manifest.takeover);
+    }
+
+    #[test]
+    fn test_git_source() {
+        let manifest = Manifest::parse(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+            source = { git = "https://example.com/blog.git", ref = "main", path = "dist" }
+            "#,
+            None,
+        )
+        .unwrap();
+        let source = manifest.source.unwrap();
+        assert_eq!(source.reference, "main");
+        assert_eq!(source.path.as_deref(), Some("dist"));
+
+        let errors = Manifest::parse(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+            source = { git = "ssh://example.com/blog.git", ref = "--upload-pack=x", path = "../dist" }
+            "#,
+            None,
+        )
+        .unwrap_err();
+        let fields: Vec<_> = errors.iter().map(|e| e.field.as_deref().unwrap()).collect();
+        assert_eq!(fields, vec!["source.git", "source.ref", "source.path"]);
+    }
+
+    #[test]
+    fn test_build_step() {
+        let manifest = Manifest::parse(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+
+            [build]
+            command = "npm ci && npm run build"
+            output_dir = "dist"
+            env = { NODE_ENV = "production" }
+            "#,
+            None,
+        )
+        .unwrap();
+        let build = manifest.build.unwrap();
+        assert_eq!(build.output_dir, "dist");
+        assert_eq!(build.timeout_seconds, 600);
+        assert_eq!(build.env["NODE_ENV"], "production");
+
+        let errors = Manifest::parse(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+
+            [build]
+            command = " "
+            output_dir = "/var/www"
+            timeout_seconds = 0
+            env = { "NODE-ENV" = "production" }
+            "#,
+            None,
+        )
+        .unwrap_err();
+        let fields: Vec<_> = errors.iter().map(|e| e.field.as_deref().unwrap()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "build.command",
+                "build.output_dir",
+                "build.timeout_seconds",
+                "build.env.NODE-ENV"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_health_check() {
+        let manifest = Manifest::parse(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+
+            [health_check]
+            body_contains = "Welcome"
+            "#,
+            None,
+        )
+        .unwrap();
+        let health_check = manifest.health_check.unwrap();
+        assert_eq!(health_check.path, "/");
+        assert_eq!(health_check.expected_status, 200);
+        assert_eq!(health_check.retries, 3);
+        assert_eq!(health_check.body_contains.as_deref(), Some("Welcome"));
+
+        let errors = Manifest::parse(
+            r#"
+            name = "blog"
+            deployment_type = "static-site"
+
+            [health_check]
+            path = "health check"
+            expected_status = 42
+            timeout_seconds = 0
+            "#,
+            None,
+        )
+        .unwrap_err();
+        let fields: Vec<_> = errors.iter().map(|e| e.field.as_deref().unwrap()).collect();
+        assert_eq!(
+            fields,
+            vec![
+                "health_check.path",
+                "health_check.expected_status",
+                "health_check.timeout_seconds"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_canary() {
+        let manifest = Manifest::parse(
+            "name = \"blog\"\ndeployment_type = \"static-site\"\ncanary = { weight = 10 }",
+            None,
+        )
+        .unwrap();
+        assert_eq!(manifest.canary, Some(Canary { weight: 10 }));
+
+        for weight in [0, 100] {
+            let errors = Manifest::parse(
+                &format!(
+                    "name = \"blog\"\ndeployment_type = \"static-site\"\ncanary = {{ weight = {} }}",
+                    weight
+                ),
+                None,
+            )
+            .unwrap_err();
+            assert_eq!(errors[0].field.as_deref(), Some("canary.weight"));
+        }
+    }
+
+    #[test]
+    fn test_expiry() {
+        let manifest = Manifest::parse(
+            "name = \"preview\"\ndeployment_type = \"static-site\"\nttl_seconds = 3600",
+            None,
+        )
+        .unwrap();
+        assert_eq!(manifest.ttl_seconds, Some(3600));
+
+        let errors = Manifest::parse(
+            "name = \"preview\"\ndeployment_type = \"static-site\"\nttl_seconds = 0",
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(errors[0].field.as_deref(), Some("ttl_seconds"));
+        let errors = Manifest::parse(
+            "name = \"preview\"\ndeployment_type = \"static-site\"\nttl_seconds = 60\nexpires_at = 1700000000",
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(errors[0].field.as_deref(), Some("expires_at"));
+    }
 }
@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use figment::{providers::Serialized, Figment};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::ConfigurationError,
+    deployer::{deployment_handle, Deployer},
+    deployments::{Deployment, DeploymentStore},
+};
+
+const REAPER: &str = "reaper";
+
+/// The registered deployers by deployment type.
+pub(crate) type Deployers = HashMap<&'static str, Arc<dyn Deployer + Send + Sync>>;
+
+#[derive(Serialize, Deserialize)]
+struct ReaperConfig {
+    interval_seconds: u64,
+}
+
+impl Default for ReaperConfig {
+    fn default() -> Self {
+        Self {
+            interval_seconds: 60,
+        }
+    }
+}
+
+/// Removes deployments once their `ttl_seconds` or `expires_at` has passed, along with
+/// everything their deployer created for them.
+pub struct DeploymentReaper {
+    deployments: Arc<DeploymentStore>,
+    interval: Duration,
+}
+
+impl DeploymentReaper {
+    pub fn configure(
+        figment: &Figment,
+        deployments: Arc<DeploymentStore>,
+    ) -> Result<Self, ConfigurationError> {
+        let config: ReaperConfig = figment.extract_inner(REAPER)?;
+        Ok(DeploymentReaper {
+            deployments,
+            interval: Duration::from_secs(config.interval_seconds),
+        })
+    }
+
+    pub fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(REAPER, ReaperConfig::default()))
+    }
+
+    /// The deployments that expire, soonest first. Those whose time has passed are removed
+    /// within `interval_seconds`.
+    pub fn expiring(&self) -> anyhow::Result<Vec<Deployment>> {
+        self.deployments.expiring()
+    }
+
+    /// Removes the expired deployments, returning their names. Deployments that can't be
+    /// removed are logged and tried again the next time.
+    pub(crate) fn reap(&self, deployers: &Deployers) -> anyhow::Result<Vec<String>> {
+        let now = now();
+        let mut reaped = vec![];
+        for deployment in self.expiring()? {
+            if deployment
+                .expires_at
+                .is_some_and(|expires_at| expires_at > now)
+            {
+                break;
+            }
+            let manifest = &deployment.manifest;
+            let Some(deployer) = deployers.get(manifest.deployment_type.as_str()) else {
+                warn!(
+                    "Can't remove expired deployment {} of unknown type {}",
+                    manifest.name, manifest.deployment_type
+                );
+                continue;
+            };
+
+            info!("Removing expired deployment {}", manifest.name);
+            let (handle, _logs) = deployment_handle();
+            if let Err(e) = deployer.remove(manifest, handle) {
+                error!(
+                    "Failed to remove expired deployment {}: {:?}",
+                    manifest.name, e
+                );
+                continue;
+            }
+            self.deployments.remove(&manifest.name)?;
+            reaped.push(manifest.name.clone());
+        }
+        Ok(reaped)
+    }
+
+    /// Runs [`DeploymentReaper::reap`] every `interval_seconds`.
+    pub(crate) fn spawn(self: Arc<Self>, deployers: Deployers) -> JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(self.interval);
+            if let Err(e) = self.reap(&deployers) {
+                error!("Failed to remove expired deployments: {:?}", e);
+            }
+        })
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, io, path::Path, sync::Mutex};
+
+    use super::*;
+    use crate::{deployer::DeploymentHandle, Manifest};
+
+    /// Remembers the deployments it removed, failing for those named `stuck`.
+    #[derive(Default)]
+    struct RecordingDeployer {
+        removed: Mutex<Vec<String>>,
+    }
+
+    impl Deployer for RecordingDeployer {
+        fn deploy(
+            &self,
+            _manifest: Manifest,
+            _environment: &BTreeMap<String, String>,
+            _artifact_location: &Path,
+            _deployment_handle: DeploymentHandle,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn promote(
+            &self,
+            _source: &Manifest,
+            _manifest: Manifest,
+            _environment: &BTreeMap<String, String>,
+            _deployment_handle: DeploymentHandle,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn roll_back(
+            &self,
+            _failed: &Manifest,
+            _previous: &Manifest,
+            _deployment_handle: DeploymentHandle,
+        ) -> io::Result<bool> {
+            Ok(false)
+        }
+
+        fn set_maintenance(
+            &self,
+            _manifest: &Manifest,
+            _maintenance: Option<&crate::maintenance::Maintenance>,
+            _deployment_handle: DeploymentHandle,
+        ) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn remove(
+            &self,
+            manifest: &Manifest,
+            _deployment_handle: DeploymentHandle,
+        ) -> io::Result<()> {
+            if manifest.name == "stuck" {
+                return Err(io::Error::other("Busy"));
+            }
+            self.removed.lock().unwrap().push(manifest.name.clone());
+            Ok(())
+        }
+    }
+
+    fn manifest(name: &str, expires_at: Option<u64>) -> Manifest {
+        Manifest::parse(
+            &format!("name = \"{}\"\ndeployment_type = \"static-site\"", name),
+            None,
+        )
+        .map(|manifest| Manifest {
+            expires_at,
+            ..manifest
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_expired_deployments_are_removed() {
+        let deployments = Arc::new(DeploymentStore::in_memory());
+        for (name, expires_at) in [
+            ("expired", Some(1)),
+            ("stuck", Some(2)),
+            ("preview", Some(now() + 3600)),
+            ("blog", None),
+        ] {
+            deployments
+                .record(&manifest(name, expires_at), "digest", None)
+                .unwrap();
+        }
+        let reaper = DeploymentReaper {
+            deployments: deployments.clone(),
+            interval: Duration::from_secs(60),
+        };
+        let deployer = Arc::new(RecordingDeployer::default());
+        let deployers: Deployers = HashMap::from([(
+            "static-site",
+            deployer.clone() as Arc<dyn Deployer + Send + Sync>,
+        )]);
+
+        assert_eq!(reaper.reap(&deployers).unwrap(), vec!["expired"]);
+        assert_eq!(*deployer.removed.lock().unwrap(), vec!["expired"]);
+        let remaining: Vec<String> = deployments
+            .list()
+            .unwrap()
+            .into_iter()
+            .map(|manifest| manifest.name)
+            .collect();
+        assert_eq!(remaining, vec!["blog", "preview", "stuck"]);
+
+        let expiring: Vec<String> = reaper
+            .expiring()
+            .unwrap()
+            .into_iter()
+            .map(|deployment| deployment.manifest.name)
+            .collect();
+        assert_eq!(expiring, vec!["stuck", "preview"]);
+    }
+}
@@ -0,0 +1,609 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    error, fmt, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use figment::{providers::Serialized, Figment};
+use rand::{distributions::DistString, thread_rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::ConfigurationError,
+    helpers::{is_relative_path, is_valid_dns_label},
+    state::JsonFile,
+};
+
+const RELEASES: &str = "releases";
+const RELEASES_FILE_NAME: &str = "releases.json";
+const SYNCS_FILE_NAME: &str = "syncs.json";
+const SYNCS_DIRECTORY: &str = "syncs";
+const ID_LENGTH: usize = 32;
+
+#[derive(Serialize, Deserialize)]
+struct ReleasesConfig {
+    keep_last: usize,
+    expiry_seconds: u64,
+    cleanup_interval_seconds: u64,
+}
+
+impl Default for ReleasesConfig {
+    fn default() -> Self {
+        Self {
+            keep_last: 3,
+            expiry_seconds: 24 * 60 * 60,
+            cleanup_interval_seconds: 10 * 60,
+        }
+    }
+}
+
+/// A file of a site and the hex encoded SHA-256 digest of its content.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SyncedFile {
+    /// Relative to the root of the site.
+    pub path: String,
+    pub sha256: String,
+}
+
+/// The files of a new release of a site, of which only the missing ones are uploaded.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SyncSession {
+    pub id: String,
+    pub deployment_name: String,
+    pub files: Vec<SyncedFile>,
+    /// The digests of the files the previous release doesn't contain and that weren't uploaded
+    /// yet.
+    pub missing: BTreeSet<String>,
+    /// When the sync was started or the last file received, in seconds since the Unix epoch.
+    pub updated_at: u64,
+}
+
+/// A directory holding every file of one version of a site.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct Release {
+    pub id: String,
+    pub deployment_name: String,
+    pub files: Vec<SyncedFile>,
+    /// In seconds since the Unix epoch.
+    pub created_at: u64,
+}
+
+#[derive(Debug)]
+pub enum SyncError {
+    NotFound(String),
+    InvalidDeploymentName(String),
+    /// The path is absolute, empty, listed twice or leaves the site.
+    InvalidPath(String),
+    InvalidDigest(String),
+    /// The file was not missing from the sync.
+    UnexpectedFile(String),
+    DigestMismatch {
+        expected: String,
+        actual: String,
+    },
+    Incomplete(SyncSession),
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for SyncError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncError::NotFound(id) => write!(f, "Sync {} does not exist", id),
+            SyncError::InvalidDeploymentName(name) => {
+                write!(f, "{:?} is not a valid deployment name", name)
+            }
+            SyncError::InvalidPath(path) => write!(f, "{:?} is not a valid file path", path),
+            SyncError::InvalidDigest(sha256) => {
+                write!(f, "{:?} is not a hex encoded SHA-256 digest", sha256)
+            }
+            SyncError::UnexpectedFile(sha256) => {
+                write!(f, "No missing file has the SHA-256 digest {}", sha256)
+            }
+            SyncError::DigestMismatch { expected, actual } => write!(
+                f,
+                "The file has the SHA-256 digest {} instead of {}",
+                actual, expected
+            ),
+            SyncError::Incomplete(session) => write!(
+                f,
+                "Sync {} is incomplete, {} files are missing",
+                session.id,
+                session.missing.len()
+            ),
+            SyncError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for SyncError {}
+
+impl From<anyhow::Error> for SyncError {
+    fn from(error: anyhow::Error) -> Self {
+        SyncError::Other(error)
+    }
+}
+
+impl From<std::io::Error> for SyncError {
+    fn from(error: std::io::Error) -> Self {
+        SyncError::Other(error.into())
+    }
+}
+
+/// Releases of static sites assembled from file-level syncs, kept in
+/// `<state_directory>/releases/<deployment_name>/<release_id>`. Files that didn't change are
+/// hard-linked from the previous release, so only new content has to be uploaded. The last
+/// `keep_last` releases of a site are kept.
+pub struct ReleaseStore {
+    directory: PathBuf,
+    syncs_directory: PathBuf,
+    releases: JsonFile<BTreeMap<String, Vec<Release>>>,
+    syncs: JsonFile<BTreeMap<String, SyncSession>>,
+    keep_last: usize,
+    expiry: Duration,
+    cleanup_interval: Duration,
+}
+
+impl ReleaseStore {
+    pub fn configure(
+        figment: &Figment,
+        state_directory: &Path,
+    ) -> Result<Self, ConfigurationError> {
+        let config: ReleasesConfig = figment.extract_inner(RELEASES)?;
+        let mut store =
+            ReleaseStore::open(state_directory).map_err(|e| ConfigurationError::Other(e.into()))?;
+        store.keep_last = config.keep_last.max(1);
+        store.expiry = Duration::from_secs(config.expiry_seconds);
+        store.cleanup_interval = Duration::from_secs(config.cleanup_interval_seconds);
+        Ok(store)
+    }
+
+    pub fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(RELEASES, ReleasesConfig::default()))
+    }
+
+    pub fn open(state_directory: &Path) -> anyhow::Result<Self> {
+        let defaults = ReleasesConfig::default();
+        Ok(ReleaseStore {
+            directory: state_directory.join(RELEASES),
+            syncs_directory: state_directory.join(SYNCS_DIRECTORY),
+            releases: JsonFile::open(state_directory, RELEASES_FILE_NAME)?,
+            syncs: JsonFile::open(state_directory, SYNCS_FILE_NAME)?,
+            keep_last: defaults.keep_last,
+            expiry: Duration::from_secs(defaults.expiry_seconds),
+            cleanup_interval: Duration::from_secs(defaults.cleanup_interval_seconds),
+        })
+    }
+
+    /// Starts a sync of `files` for the next release of `deployment_name`, listing the digests
+    /// the latest release doesn't contain as missing.
+    pub fn create(
+        &self,
+        deployment_name: &str,
+        files: Vec<SyncedFile>,
+    ) -> Result<SyncSession, SyncError> {
+        if !is_valid_dns_label(deployment_name)
+            || deployment_name.chars().any(|c| c.is_ascii_uppercase())
+        {
+            return Err(SyncError::InvalidDeploymentName(deployment_name.to_owned()));
+        }
+        let mut paths = BTreeSet::new();
+        let files = files
+            .into_iter()
+            .map(|file| {
+                if !is_relative_path(&file.path) || !paths.insert(file.path.clone()) {
+                    return Err(SyncError::InvalidPath(file.path));
+                }
+                Ok(SyncedFile {
+                    sha256: normalize_digest(&file.sha256)?,
+                    ..file
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let known: BTreeSet<String> = self
+            .latest(deployment_name)?
+            .map(|release| release.files.into_iter().map(|f| f.sha256).collect())
+            .unwrap_or_default();
+        let session = SyncSession {
+            id: rand::distributions::Alphanumeric.sample_string(&mut thread_rng(), ID_LENGTH),
+            deployment_name: deployment_name.to_owned(),
+            missing: files
+                .iter()
+                .map(|f| f.sha256.clone())
+                .filter(|sha256| !known.contains(sha256))
+                .collect(),
+            files,
+            updated_at: now(),
+        };
+        fs::create_dir_all(self.sync_path(&session.id))?;
+        self.syncs.update(|syncs| {
+            syncs.insert(session.id.clone(), session.clone());
+        })?;
+        Ok(session)
+    }
+
+    pub fn get(&self, id: &str) -> Result<SyncSession, SyncError> {
+        self.syncs
+            .read(|syncs| syncs.get(id).cloned())?
+            .ok_or_else(|| SyncError::NotFound(id.to_owned()))
+    }
+
+    /// Stores the content of a missing file, which has to match its digest.
+    pub fn upload(&self, id: &str, sha256: &str, content: &[u8]) -> Result<SyncSession, SyncError> {
+        let sha256 = normalize_digest(sha256)?;
+        let actual = format!("{:x}", Sha256::digest(content));
+        if actual != sha256 {
+            return Err(SyncError::DigestMismatch {
+                expected: sha256,
+                actual,
+            });
+        }
+        let path = self.sync_path(id).join(&sha256);
+        self.syncs.update(|syncs| {
+            let session = syncs
+                .get_mut(id)
+                .ok_or_else(|| SyncError::NotFound(id.to_owned()))?;
+            if !session.missing.contains(&sha256) {
+                return Err(SyncError::UnexpectedFile(sha256));
+            }
+            fs::write(&path, content)?;
+            session.missing.remove(&sha256);
+            session.updated_at = now();
+            Ok(session.clone())
+        })?
+    }
+
+    /// Builds the release of a completed sync by hard-linking unchanged files from the latest
+    /// release and moving the uploaded ones in, then ends the sync. Releases beyond
+    /// `keep_last` are removed.
+    pub fn assemble(&self, id: &str) -> Result<Release, SyncError> {
+        let session = self.get(id)?;
+        if !session.missing.is_empty() {
+            return Err(SyncError::Incomplete(session));
+        }
+        let previous = self.latest(&session.deployment_name)?;
+        let mut sources: HashMap<&str, PathBuf> = HashMap::new();
+        if let Some(previous) = &previous {
+            let directory = self.path(previous);
+            for file in &previous.files {
+                sources.insert(&file.sha256, directory.join(&file.path));
+            }
+        }
+
+        let release = Release {
+            id: rand::distributions::Alphanumeric.sample_string(&mut thread_rng(), ID_LENGTH),
+            deployment_name: session.deployment_name.clone(),
+            files: session.files.clone(),
+            created_at: now(),
+        };
+        let directory = self.path(&release);
+        let sync_path = self.sync_path(id);
+        let result = (|| -> std::io::Result<()> {
+            fs::create_dir_all(&directory)?;
+            for file in &release.files {
+                let uploaded = sync_path.join(&file.sha256);
+                let source = sources.get(file.sha256.as_str()).unwrap_or(&uploaded);
+                let target = directory.join(&file.path);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                // The state directory may not support hard links
+                if fs::hard_link(source, &target).is_err() {
+                    fs::copy(source, &target)?;
+                }
+            }
+            fs::write(self.checksums_path(&release), checksums(&release))
+        })();
+        if let Err(e) = result {
+            fs::remove_dir_all(&directory).ok();
+            return Err(e.into());
+        }
+
+        let removed = self.releases.update(|releases| {
+            let site = releases.entry(release.deployment_name.clone()).or_default();
+            site.push(release.clone());
+            let removed_count = site.len().saturating_sub(self.keep_last);
+            site.drain(..removed_count).collect::<Vec<_>>()
+        })?;
+        for old in &removed {
+            info!("Removing release {} of {}", old.id, old.deployment_name);
+            fs::remove_dir_all(self.path(old)).ok();
+            fs::remove_file(self.checksums_path(old)).ok();
+        }
+        self.syncs.update(|syncs| syncs.remove(id))?;
+        fs::remove_dir_all(&sync_path).ok();
+        Ok(release)
+    }
+
+    /// The directory holding the files of `release`.
+    pub fn path(&self, release: &Release) -> PathBuf {
+        self.directory
+            .join(&release.deployment_name)
+            .join(&release.id)
+    }
+
+    /// The `sha256sum` listing of the files of `release`, which is what signatures of a release
+    /// cover.
+    pub fn checksums_path(&self, release: &Release) -> PathBuf {
+        self.directory
+            .join(&release.deployment_name)
+            .join(format!("{}.sha256sums", release.id))
+    }
+
+    /// Removes the syncs that didn't receive a file within `expiry_seconds`, returning how many
+    /// were removed.
+    pub fn remove_expired(&self) -> anyhow::Result<usize> {
+        let expired_before = now().saturating_sub(self.expiry.as_secs());
+        let expired = self.syncs.update(|syncs| {
+            let expired: Vec<String> = syncs
+                .values()
+                .filter(|s| s.updated_at < expired_before)
+                .map(|s| s.id.clone())
+                .collect();
+            for id in &expired {
+                syncs.remove(id);
+            }
+            expired
+        })?;
+        for id in &expired {
+            info!("Removing abandoned sync {}", id);
+            if let Err(e) = fs::remove_dir_all(self.sync_path(id)) {
+                warn!("Failed to remove abandoned sync {}: {:?}", id, e);
+            }
+        }
+        Ok(expired.len())
+    }
+
+    /// Runs [`ReleaseStore::remove_expired`] every `cleanup_interval_seconds`.
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(self.cleanup_interval);
+            if let Err(e) = self.remove_expired() {
+                error!("Failed to remove abandoned syncs: {:?}", e);
+            }
+        })
+    }
+
+    /// The kept release of `deployment_name` whose file listing has the SHA-256 digest
+    /// `sha256`, which is the digest deployments of releases are recorded with.
+    pub fn find(&self, deployment_name: &str, sha256: &str) -> anyhow::Result<Option<Release>> {
+        self.releases.read(|releases| {
+            releases
+                .get(deployment_name)
+                .into_iter()
+                .flatten()
+                .find(|release| format!("{:x}", Sha256::digest(checksums(release))) == sha256)
+                .cloned()
+        })
+    }
+
+    fn latest(&self, deployment_name: &str) -> anyhow::Result<Option<Release>> {
+        self.releases.read(|releases| {
+            releases
+                .get(deployment_name)
+                .and_then(|site| site.last())
+                .cloned()
+        })
+    }
+
+    fn sync_path(&self, id: &str) -> PathBuf {
+        // Ids are only ever generated here, but they are also taken from URLs
+        let id: String = id.chars().filter(char::is_ascii_alphanumeric).collect();
+        self.syncs_directory.join(id)
+    }
+}
+
+fn normalize_digest(sha256: &str) -> Result<String, SyncError> {
+    let sha256 = sha256.trim().to_ascii_lowercase();
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(SyncError::InvalidDigest(sha256));
+    }
+    Ok(sha256)
+}
+
+/// The files of `release` in the format of `sha256sum`, sorted by path.
+fn checksums(release: &Release) -> String {
+    let mut files: Vec<&SyncedFile> = release.files.iter().collect();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    files
+        .into_iter()
+        .map(|f| format!("{}  {}\n", f.sha256, f.path))
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub(crate) fn store(name: &str) -> ReleaseStore {
+        let state_directory = std::env::temp_dir().join(format!("pond-releases-{}", name));
+        fs::remove_dir_all(&state_directory).ok();
+        ReleaseStore::open(&state_directory).unwrap()
+    }
+
+    fn file(path: &str, content: &str) -> SyncedFile {
+        SyncedFile {
+            path: path.to_owned(),
+            sha256: format!("{:x}", Sha256::digest(content)),
+        }
+    }
+
+    /// Syncs `files`, uploading whatever is missing.
+    pub(crate) fn complete_sync(
+        store: &ReleaseStore,
+        deployment_name: &str,
+        files: &[(&str, &str)],
+    ) -> SyncSession {
+        let session = store
+            .create(
+                deployment_name,
+                files.iter().map(|(path, c)| file(path, c)).collect(),
+            )
+            .unwrap();
+        for (path, content) in files {
+            let sha256 = file(path, content).sha256;
+            if session.missing.contains(&sha256) {
+                store.upload(&session.id, &sha256, content.as_bytes()).ok();
+            }
+        }
+        session
+    }
+
+    /// Syncs `files` and assembles the release.
+    fn sync(
+        store: &ReleaseStore,
+        deployment_name: &str,
+        files: &[(&str, &str)],
+    ) -> (SyncSession, Release) {
+        let session = complete_sync(store, deployment_name, files);
+        let release = store.assemble(&session.id).unwrap();
+        (session, release)
+    }
+
+    #[test]
+    fn test_only_changed_files_are_uploaded() {
+        let store = store("changed");
+        let (session, first) = sync(
+            &store,
+            "blog",
+            &[("index.html", "Hello"), ("css/site.css", "body {}")],
+        );
+        assert_eq!(session.missing.len(), 2);
+
+        let (session, second) = sync(
+            &store,
+            "blog",
+            &[
+                ("index.html", "Hello World"),
+                ("css/site.css", "body {}"),
+                ("css/copy.css", "body {}"),
+            ],
+        );
+        assert_eq!(
+            session.missing,
+            BTreeSet::from([file("", "Hello World").sha256])
+        );
+        let directory = store.path(&second);
+        assert_eq!(
+            fs::read_to_string(directory.join("index.html")).unwrap(),
+            "Hello World"
+        );
+        assert_eq!(
+            fs::read_to_string(directory.join("css/copy.css")).unwrap(),
+            "body {}"
+        );
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = |path: PathBuf| fs::metadata(path).unwrap().ino();
+            assert_eq!(
+                inode(directory.join("css/site.css")),
+                inode(store.path(&first).join("css/site.css"))
+            );
+        }
+        assert_eq!(
+            fs::read_to_string(store.checksums_path(&second)).unwrap(),
+            format!(
+                "{}  css/copy.css\n{}  css/site.css\n{}  index.html\n",
+                file("", "body {}").sha256,
+                file("", "body {}").sha256,
+                file("", "Hello World").sha256
+            )
+        );
+        assert!(matches!(
+            store.get(&session.id),
+            Err(SyncError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_uploads_are_checked() {
+        let store = store("checked");
+        let session = store
+            .create("blog", vec![file("index.html", "Hello")])
+            .unwrap();
+        let sha256 = &session.files[0].sha256;
+        assert!(matches!(
+            store.upload(&session.id, sha256, b"Tampered"),
+            Err(SyncError::DigestMismatch { .. })
+        ));
+        assert!(matches!(
+            store.upload(&session.id, &file("", "Other").sha256, b"Other"),
+            Err(SyncError::UnexpectedFile(_))
+        ));
+        assert!(matches!(
+            store.assemble(&session.id),
+            Err(SyncError::Incomplete(_))
+        ));
+        let updated = store.upload(&session.id, sha256, b"Hello").unwrap();
+        assert!(updated.missing.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_syncs_are_rejected() {
+        let store = store("invalid");
+        for path in [
+            "",
+            "/etc/passwd",
+            "../index.html",
+            "css/../../x",
+            "./index.html",
+        ] {
+            assert!(matches!(
+                store.create("blog", vec![file(path, "")]),
+                Err(SyncError::InvalidPath(_))
+            ));
+        }
+        assert!(matches!(
+            store.create("blog", vec![file("index.html", ""), file("index.html", "")]),
+            Err(SyncError::InvalidPath(_))
+        ));
+        assert!(matches!(
+            store.create("../blog", vec![]),
+            Err(SyncError::InvalidDeploymentName(_))
+        ));
+        let mut invalid = file("index.html", "");
+        invalid.sha256 = "abc".to_owned();
+        assert!(matches!(
+            store.create("blog", vec![invalid]),
+            Err(SyncError::InvalidDigest(_))
+        ));
+    }
+
+    #[test]
+    fn test_old_releases_are_removed() {
+        let mut store = store("removed");
+        store.keep_last = 2;
+        let (_, first) = sync(&store, "blog", &[("index.html", "1")]);
+        sync(&store, "blog", &[("index.html", "2")]);
+        let (_, third) = sync(&store, "blog", &[("index.html", "3")]);
+        assert!(!store.path(&first).exists());
+        assert!(!store.checksums_path(&first).exists());
+        assert!(store.path(&third).exists());
+        assert_eq!(store.latest("blog").unwrap(), Some(third.clone()));
+
+        let digest = |release: &Release| {
+            format!(
+                "{:x}",
+                Sha256::digest(fs::read(store.checksums_path(release)).unwrap())
+            )
+        };
+        assert_eq!(
+            store.find("blog", &digest(&third)).unwrap(),
+            Some(third.clone())
+        );
+        assert_eq!(store.find("shop", &digest(&third)).unwrap(), None);
+        assert_eq!(store.find("blog", &file("", "1").sha256).unwrap(), None);
+    }
+}
@@ -0,0 +1,226 @@
+use std::{
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
+
+use figment::{
+    providers::Serialized,
+    value::{Dict, Value},
+    Figment,
+};
+use serde::Deserialize;
+
+use crate::{
+    config::ConfigurationError,
+    ingress::dns::{DnsService, DnsServiceRegistry},
+    Manifest,
+};
+
+const ROOT_DOMAIN_NAME: &str = "root_domain_name";
+const ROOT_DOMAINS: &str = "root_domains";
+
+#[derive(Deserialize)]
+struct AddressConfig {
+    ip_v4_address: Option<Ipv4Addr>,
+    ip_v6_address: Option<Ipv6Addr>,
+}
+
+/// A domain deployments get their default host `<name>.<root>` below, along with the DNS
+/// provider and addresses its records are managed with.
+pub struct RootDomain {
+    pub name: String,
+    pub dns_service: Arc<dyn DnsService + Send + Sync>,
+    pub manages_records: bool,
+    pub ip_v4_address: Option<Ipv4Addr>,
+    pub ip_v6_address: Option<Ipv6Addr>,
+}
+
+impl RootDomain {
+    /// Configures the root domain `name` from the DNS and `nginx_ingress` settings of `figment`.
+    pub fn configure(figment: &Figment, name: impl AsRef<str>) -> Result<Self, ConfigurationError> {
+        let addresses: AddressConfig = figment.extract_inner("nginx_ingress")?;
+        Ok(RootDomain {
+            name: name.as_ref().to_owned(),
+            dns_service: DnsServiceRegistry::default().configure(figment)?.into(),
+            manages_records: DnsServiceRegistry::manages_records(figment)?,
+            ip_v4_address: addresses.ip_v4_address,
+            ip_v6_address: addresses.ip_v6_address,
+        })
+    }
+
+    /// The addresses domains below this root point at.
+    pub fn ip_addresses(&self) -> Vec<IpAddr> {
+        self.ip_v4_address
+            .map(IpAddr::V4)
+            .into_iter()
+            .chain(self.ip_v6_address.map(IpAddr::V6))
+            .collect()
+    }
+}
+
+/// The root domains pond deploys to. `root_domain_name` is the default, further roots are
+/// listed in the `root_domains` table, each overriding any of the top-level settings:
+///
+/// ```toml
+/// root_domain_name = "example.com"
+///
+/// [root_domains."example.org"]
+/// dns = { provider = "hetzner" }
+/// hetzner = { api_token = "..." }
+/// nginx_ingress = { ip_v4_address = "203.0.113.20" }
+/// ```
+pub struct RootDomains {
+    default_name: String,
+    root_domains: BTreeMap<String, Arc<RootDomain>>,
+}
+
+impl RootDomains {
+    pub fn new(default: RootDomain) -> RootDomains {
+        let mut root_domains = RootDomains {
+            default_name: default.name.clone(),
+            root_domains: BTreeMap::new(),
+        };
+        root_domains.add(default);
+        root_domains
+    }
+
+    pub fn configure(figment: &Figment) -> Result<Self, ConfigurationError> {
+        let default_name: String = figment
+            .extract_inner(ROOT_DOMAIN_NAME)
+            .map_err(|_e| ConfigurationError::MissingConfigurationValue(ROOT_DOMAIN_NAME.into()))?;
+        let overrides: BTreeMap<String, Dict> = if figment.contains(ROOT_DOMAINS) {
+            figment.extract_inner(ROOT_DOMAINS)?
+        } else {
+            BTreeMap::new()
+        };
+        let configure = |name: &str| match overrides.get(name) {
+            Some(values) => RootDomain::configure(
+                &figment
+                    .clone()
+                    .merge(Serialized::globals(Value::from(values.clone()))),
+                name,
+            ),
+            None => RootDomain::configure(figment, name),
+        };
+
+        let mut root_domains = RootDomains::new(configure(&default_name)?);
+        for name in overrides.keys().filter(|name| **name != default_name) {
+            root_domains.add(configure(name)?);
+        }
+        Ok(root_domains)
+    }
+
+    pub fn add(&mut self, root_domain: RootDomain) {
+        self.root_domains
+            .insert(root_domain.name.clone(), Arc::new(root_domain));
+    }
+
+    pub fn default_root(&self) -> Arc<RootDomain> {
+        self.root_domains[&self.default_name].clone()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<RootDomain>> {
+        self.root_domains.get(name).cloned()
+    }
+
+    /// The root domain `manifest` was deployed below. Deployments that predate multiple root
+    /// domains belong to the default.
+    pub fn for_manifest(&self, manifest: &Manifest) -> Arc<RootDomain> {
+        manifest
+            .root_domain_name
+            .as_deref()
+            .and_then(|name| self.get(name))
+            .unwrap_or_else(|| self.default_root())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<RootDomain>> {
+        self.root_domains.values()
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.root_domains.keys().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ingress::{dns::DnsServiceRegistry, static_site::NginxStaticSiteIngressService};
+
+    fn figment(values: serde_json::Value) -> Figment {
+        DnsServiceRegistry::default()
+            .figment_default_values()
+            .join(NginxStaticSiteIngressService::figment_default_values())
+            .merge(Serialized::globals(values))
+    }
+
+    #[test]
+    fn test_single_root_domain() {
+        let root_domains = RootDomains::configure(&figment(serde_json::json!({
+            "root_domain_name": "example.com",
+            "nginx_ingress": { "ip_v4_address": "203.0.113.10" }
+        })))
+        .unwrap();
+        assert_eq!(root_domains.names(), vec!["example.com"]);
+        let default_root = root_domains.default_root();
+        assert_eq!(default_root.name, "example.com");
+        assert_eq!(
+            default_root.ip_v4_address,
+            Some(Ipv4Addr::new(203, 0, 113, 10))
+        );
+        assert!(!default_root.manages_records);
+    }
+
+    #[test]
+    fn test_root_domains_override_top_level_settings() {
+        let root_domains = RootDomains::configure(&figment(serde_json::json!({
+            "root_domain_name": "example.com",
+            "nginx_ingress": {
+                "ip_v4_address": "203.0.113.10",
+                "ip_v6_address": "2001:db8::10"
+            },
+            "root_domains": {
+                "example.org": {
+                    "nginx_ingress": { "ip_v4_address": "203.0.113.20" }
+                }
+            }
+        })))
+        .unwrap();
+        assert_eq!(root_domains.names(), vec!["example.com", "example.org"]);
+        assert_eq!(
+            root_domains.get("example.org").unwrap().ip_addresses(),
+            vec![
+                "203.0.113.20".parse::<IpAddr>().unwrap(),
+                "2001:db8::10".parse::<IpAddr>().unwrap()
+            ]
+        );
+        assert_eq!(
+            root_domains.default_root().ip_v4_address,
+            Some(Ipv4Addr::new(203, 0, 113, 10))
+        );
+    }
+
+    #[test]
+    fn test_root_domains_have_own_dns_provider() {
+        let result = RootDomains::configure(&figment(serde_json::json!({
+            "root_domain_name": "example.com",
+            "root_domains": {
+                "example.org": { "dns": { "provider": "route53" } }
+            }
+        })));
+        assert!(matches!(
+            result,
+            Err(ConfigurationError::UnknownDnsProvider(name)) if name == "route53"
+        ));
+    }
+
+    #[test]
+    fn test_missing_root_domain_name_is_rejected() {
+        let result = RootDomains::configure(&figment(serde_json::json!({})));
+        assert!(matches!(
+            result,
+            Err(ConfigurationError::MissingConfigurationValue(name)) if name == ROOT_DOMAIN_NAME
+        ));
+    }
+}
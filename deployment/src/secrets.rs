@@ -0,0 +1,324 @@
+use std::{collections::BTreeMap, error, fmt, path::Path};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use figment::Figment;
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ConfigurationError, state::JsonFile};
+
+const SECRETS_FILE_NAME: &str = "secrets.json";
+const NONCE_LENGTH: usize = 12;
+
+/// The prefix of environment values that refer to a secret of the deployment.
+pub const SECRET_REFERENCE_PREFIX: &str = "secret://";
+
+#[derive(Debug)]
+pub enum SecretError {
+    /// No `secrets.key` is configured, so secrets can't be stored or read.
+    NotConfigured,
+    InvalidName(String),
+    NotFound(String),
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::NotConfigured => write!(f, "No secrets.key is configured"),
+            SecretError::InvalidName(name) => write!(
+                f,
+                "{:?} is not a valid secret name. Use letters, digits, '_', '-' and '.'",
+                name
+            ),
+            SecretError::NotFound(name) => write!(f, "Secret {} does not exist", name),
+            SecretError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for SecretError {}
+
+impl From<anyhow::Error> for SecretError {
+    fn from(error: anyhow::Error) -> Self {
+        SecretError::Other(error)
+    }
+}
+
+#[derive(Deserialize, Serialize, Default)]
+struct SecretsConfig {
+    key: Option<String>,
+}
+
+/// The environment of a deployment with secret references replaced by their values.
+#[derive(Default)]
+pub struct ResolvedEnvironment {
+    pub variables: BTreeMap<String, String>,
+    /// The values taken from the secret store, which must not show up in logs.
+    pub secret_values: Vec<String>,
+}
+
+/// Secrets of deployments, encrypted with ChaCha20-Poly1305 using the configured
+/// `secrets.key` and kept in `<state_directory>/secrets.json`.
+pub struct SecretStore {
+    cipher: Option<ChaCha20Poly1305>,
+    secrets: JsonFile<BTreeMap<String, BTreeMap<String, String>>>,
+}
+
+impl SecretStore {
+    pub fn configure(
+        figment: &Figment,
+        state_directory: &Path,
+    ) -> Result<Self, ConfigurationError> {
+        let config: SecretsConfig = if figment.contains("secrets") {
+            figment.extract_inner("secrets")?
+        } else {
+            SecretsConfig::default()
+        };
+        let cipher = config.key.as_deref().map(cipher).transpose()?;
+        Ok(SecretStore {
+            cipher,
+            secrets: JsonFile::open(state_directory, SECRETS_FILE_NAME)
+                .map_err(|e| ConfigurationError::Other(e.into()))?,
+        })
+    }
+
+    /// A store that doesn't persist secrets, encrypting them with `key` if given.
+    pub fn in_memory(key: Option<&str>) -> Result<Self, ConfigurationError> {
+        Ok(SecretStore {
+            cipher: key.map(cipher).transpose()?,
+            secrets: JsonFile::in_memory(),
+        })
+    }
+
+    /// The names of the secrets of `deployment_name`.
+    pub fn names(&self, deployment_name: &str) -> Result<Vec<String>, SecretError> {
+        Ok(self.secrets.read(|secrets| {
+            secrets
+                .get(deployment_name)
+                .map(|s| s.keys().cloned().collect())
+                .unwrap_or_default()
+        })?)
+    }
+
+    pub fn set(&self, deployment_name: &str, name: &str, value: &str) -> Result<(), SecretError> {
+        validate_name(name)?;
+        let cipher = self.cipher.as_ref().ok_or(SecretError::NotConfigured)?;
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: associated_data(deployment_name, name).as_bytes(),
+                },
+            )
+            .map_err(|e| anyhow::anyhow!("Failed to encrypt secret: {}", e))?;
+        let encrypted = BASE64.encode([nonce.as_slice(), &ciphertext].concat());
+
+        self.secrets.update(|secrets| {
+            secrets
+                .entry(deployment_name.to_owned())
+                .or_default()
+                .insert(name.to_owned(), encrypted);
+        })?;
+        Ok(())
+    }
+
+    pub fn delete(&self, deployment_name: &str, name: &str) -> Result<(), SecretError> {
+        let removed = self.secrets.update(|secrets| {
+            let deployment_secrets = secrets.get_mut(deployment_name)?;
+            let removed = deployment_secrets.remove(name);
+            if deployment_secrets.is_empty() {
+                secrets.remove(deployment_name);
+            }
+            removed
+        })?;
+        removed
+            .map(|_| ())
+            .ok_or_else(|| SecretError::NotFound(name.to_owned()))
+    }
+
+    pub fn get(&self, deployment_name: &str, name: &str) -> Result<String, SecretError> {
+        let encrypted = self
+            .secrets
+            .read(|secrets| secrets.get(deployment_name)?.get(name).cloned())?
+            .ok_or_else(|| SecretError::NotFound(name.to_owned()))?;
+        let cipher = self.cipher.as_ref().ok_or(SecretError::NotConfigured)?;
+
+        let decoded = BASE64
+            .decode(encrypted)
+            .map_err(|e| anyhow::anyhow!("Secret {} is corrupted: {}", name, e))?;
+        if decoded.len() < NONCE_LENGTH {
+            return Err(anyhow::anyhow!("Secret {} is corrupted", name).into());
+        }
+        let (nonce, ciphertext) = decoded.split_at(NONCE_LENGTH);
+        let plaintext = cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: associated_data(deployment_name, name).as_bytes(),
+                },
+            )
+            .map_err(|_e| {
+                anyhow::anyhow!(
+                    "Secret {} could not be decrypted. Was secrets.key changed?",
+                    name
+                )
+            })?;
+        Ok(String::from_utf8(plaintext).map_err(anyhow::Error::from)?)
+    }
+
+    /// Replaces the `secret://<name>` references in `environment` with the secrets of
+    /// `deployment_name`.
+    pub fn resolve(
+        &self,
+        deployment_name: &str,
+        environment: &BTreeMap<String, String>,
+    ) -> Result<ResolvedEnvironment, SecretError> {
+        let mut resolved = ResolvedEnvironment {
+            variables: BTreeMap::new(),
+            secret_values: vec![],
+        };
+        for (variable, value) in environment {
+            let value = match value.strip_prefix(SECRET_REFERENCE_PREFIX) {
+                Some(name) => {
+                    let secret = self.get(deployment_name, name)?;
+                    resolved.secret_values.push(secret.clone());
+                    secret
+                }
+                None => value.clone(),
+            };
+            resolved.variables.insert(variable.clone(), value);
+        }
+        Ok(resolved)
+    }
+}
+
+/// Whether `name` can be used as the name of a secret.
+pub fn is_valid_secret_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 128
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+fn validate_name(name: &str) -> Result<(), SecretError> {
+    if is_valid_secret_name(name) {
+        Ok(())
+    } else {
+        Err(SecretError::InvalidName(name.to_owned()))
+    }
+}
+
+// Binds every ciphertext to its slot so secrets can't be swapped between deployments
+fn associated_data(deployment_name: &str, name: &str) -> String {
+    format!("{}/{}", deployment_name, name)
+}
+
+fn cipher(key: &str) -> Result<ChaCha20Poly1305, ConfigurationError> {
+    let key = BASE64.decode(key.trim()).map_err(|e| {
+        ConfigurationError::Other(format!("secrets.key is not valid base64: {}", e).into())
+    })?;
+    if key.len() != 32 {
+        return Err(ConfigurationError::Other(
+            "secrets.key must be 32 bytes long".into(),
+        ));
+    }
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const KEY: &str = "MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=";
+
+    #[test]
+    fn test_set_and_get_secret() {
+        let store = SecretStore::in_memory(Some(KEY)).unwrap();
+        store.set("blog", "api-token", "hunter2").unwrap();
+        assert_eq!(store.get("blog", "api-token").unwrap(), "hunter2");
+        assert_eq!(store.names("blog").unwrap(), vec!["api-token"]);
+        assert!(store.names("shop").unwrap().is_empty());
+
+        // The stored value is encrypted
+        let stored = store
+            .secrets
+            .read(|s| s["blog"]["api-token"].clone())
+            .unwrap();
+        assert!(!String::from_utf8_lossy(&BASE64.decode(stored).unwrap()).contains("hunter2"));
+    }
+
+    #[test]
+    fn test_secrets_are_bound_to_their_deployment() {
+        let store = SecretStore::in_memory(Some(KEY)).unwrap();
+        store.set("blog", "token", "hunter2").unwrap();
+        store
+            .secrets
+            .update(|s| {
+                let value = s["blog"]["token"].clone();
+                s.entry("shop".to_owned())
+                    .or_default()
+                    .insert("token".to_owned(), value);
+            })
+            .unwrap();
+        assert!(matches!(
+            store.get("shop", "token"),
+            Err(SecretError::Other(_))
+        ));
+    }
+
+    #[test]
+    fn test_delete_secret() {
+        let store = SecretStore::in_memory(Some(KEY)).unwrap();
+        store.set("blog", "token", "hunter2").unwrap();
+        store.delete("blog", "token").unwrap();
+        assert!(matches!(
+            store.delete("blog", "token"),
+            Err(SecretError::NotFound(_))
+        ));
+        assert!(matches!(
+            store.get("blog", "token"),
+            Err(SecretError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_resolve_environment() {
+        let store = SecretStore::in_memory(Some(KEY)).unwrap();
+        store.set("blog", "token", "hunter2").unwrap();
+        let environment = BTreeMap::from([
+            ("API_TOKEN".to_owned(), "secret://token".to_owned()),
+            ("MODE".to_owned(), "production".to_owned()),
+        ]);
+        let resolved = store.resolve("blog", &environment).unwrap();
+        assert_eq!(resolved.variables["API_TOKEN"], "hunter2");
+        assert_eq!(resolved.variables["MODE"], "production");
+        assert_eq!(resolved.secret_values, vec!["hunter2"]);
+
+        assert!(matches!(
+            store.resolve("shop", &environment),
+            Err(SecretError::NotFound(name)) if name == "token"
+        ));
+    }
+
+    #[test]
+    fn test_store_without_key() {
+        let store = SecretStore::in_memory(None).unwrap();
+        assert!(matches!(
+            store.set("blog", "token", "hunter2"),
+            Err(SecretError::NotConfigured)
+        ));
+        assert!(matches!(
+            store.set("blog", "../token", "hunter2"),
+            Err(SecretError::InvalidName(_))
+        ));
+        assert!(SecretStore::in_memory(Some("c2hvcnQ=")).is_err());
+    }
+}
@@ -0,0 +1,106 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use anyhow::anyhow;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// A value kept in a JSON file below the state directory.
+///
+/// The file is read once when opening and rewritten after every update. The directory is only
+/// created by the first update.
+pub(crate) struct JsonFile<T> {
+    path: Option<PathBuf>,
+    value: Mutex<T>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> JsonFile<T> {
+    pub fn open(state_directory: &Path, file_name: &str) -> anyhow::Result<Self> {
+        let path = state_directory.join(file_name);
+        let value = if path.exists() {
+            serde_json::from_str(&fs::read_to_string(&path)?)
+                .map_err(|e| anyhow!("Could not read {}: {}", path.display(), e))?
+        } else {
+            T::default()
+        };
+
+        Ok(JsonFile {
+            path: Some(path),
+            value: Mutex::new(value),
+        })
+    }
+
+    /// A value that isn't persisted.
+    pub fn in_memory() -> Self {
+        JsonFile {
+            path: None,
+            value: Mutex::new(T::default()),
+        }
+    }
+
+    pub fn read<R>(&self, read: impl FnOnce(&T) -> R) -> anyhow::Result<R> {
+        Ok(read(&*self.lock()?))
+    }
+
+    /// Changes the value and writes it to disk before any other update can happen.
+    pub fn update<R>(&self, update: impl FnOnce(&mut T) -> R) -> anyhow::Result<R> {
+        let mut value = self.lock()?;
+        let result = update(&mut value);
+
+        if let Some(path) = &self.path {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            // Write to a temporary file first so a crash never leaves a truncated file behind
+            let temporary_path = path.with_extension("json.tmp");
+            fs::write(&temporary_path, serde_json::to_vec_pretty(&*value)?)?;
+            fs::rename(&temporary_path, path)?;
+        }
+        Ok(result)
+    }
+
+    fn lock(&self) -> anyhow::Result<MutexGuard<'_, T>> {
+        self.value
+            .lock()
+            .map_err(|_e| anyhow!("State mutex is poisoned"))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    #[test]
+    fn test_values_survive_reopening() {
+        let state_directory = std::env::temp_dir().join("pond-json-file-test");
+        fs::remove_dir_all(&state_directory).ok();
+
+        let file: JsonFile<BTreeMap<String, u32>> =
+            JsonFile::open(&state_directory, "values.json").unwrap();
+        assert!(file.read(|values| values.is_empty()).unwrap());
+        assert!(!state_directory.exists());
+        file.update(|values| values.insert("a".to_owned(), 1))
+            .unwrap();
+
+        let reopened: JsonFile<BTreeMap<String, u32>> =
+            JsonFile::open(&state_directory, "values.json").unwrap();
+        assert_eq!(reopened.read(|values| values["a"]).unwrap(), 1);
+        fs::remove_dir_all(&state_directory).ok();
+    }
+
+    #[test]
+    fn test_invalid_file_is_rejected() {
+        let state_directory = std::env::temp_dir().join("pond-json-file-invalid-test");
+        fs::create_dir_all(&state_directory).unwrap();
+        fs::write(state_directory.join("values.json"), "not json").unwrap();
+
+        let result: anyhow::Result<JsonFile<BTreeMap<String, u32>>> =
+            JsonFile::open(&state_directory, "values.json");
+        assert!(result.is_err());
+        fs::remove_dir_all(&state_directory).ok();
+    }
+}
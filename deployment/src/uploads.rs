@@ -0,0 +1,358 @@
+use std::{
+    collections::BTreeMap,
+    error, fmt,
+    fs::{self, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use figment::{providers::Serialized, Figment};
+use rand::{distributions::DistString, thread_rng};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::ConfigurationError, state::JsonFile};
+
+const UPLOADS: &str = "uploads";
+const UPLOADS_FILE_NAME: &str = "uploads.json";
+const UPLOADS_DIRECTORY: &str = "uploads";
+const UPLOAD_ID_LENGTH: usize = 32;
+
+#[derive(Serialize, Deserialize)]
+struct UploadsConfig {
+    max_length: u64,
+    expiry_seconds: u64,
+    cleanup_interval_seconds: u64,
+}
+
+impl Default for UploadsConfig {
+    fn default() -> Self {
+        Self {
+            max_length: 4 * 1024 * 1024 * 1024,
+            expiry_seconds: 24 * 60 * 60,
+            cleanup_interval_seconds: 10 * 60,
+        }
+    }
+}
+
+/// An artifact that is uploaded in chunks.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct UploadSession {
+    pub id: String,
+    /// The size of the whole artifact in bytes.
+    pub length: u64,
+    /// The number of bytes received so far, which is where the next chunk has to start.
+    pub offset: u64,
+    /// When the last chunk was received, in seconds since the Unix epoch.
+    pub updated_at: u64,
+}
+
+impl UploadSession {
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.length
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    NotFound(String),
+    /// The chunk doesn't start where the previous one ended.
+    OffsetMismatch {
+        expected: u64,
+    },
+    /// The upload would grow beyond its announced length or the configured maximum.
+    TooLong {
+        max_length: u64,
+    },
+    Incomplete(UploadSession),
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::NotFound(id) => write!(f, "Upload {} does not exist", id),
+            UploadError::OffsetMismatch { expected } => {
+                write!(f, "The next chunk has to start at offset {}", expected)
+            }
+            UploadError::TooLong { max_length } => {
+                write!(f, "Uploads may not be longer than {} bytes", max_length)
+            }
+            UploadError::Incomplete(session) => write!(
+                f,
+                "Upload {} is incomplete, {} of {} bytes were received",
+                session.id, session.offset, session.length
+            ),
+            UploadError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for UploadError {}
+
+impl From<anyhow::Error> for UploadError {
+    fn from(error: anyhow::Error) -> Self {
+        UploadError::Other(error)
+    }
+}
+
+impl From<std::io::Error> for UploadError {
+    fn from(error: std::io::Error) -> Self {
+        UploadError::Other(error.into())
+    }
+}
+
+/// Artifacts being uploaded in chunks, kept in `<state_directory>/uploads` until they are
+/// deployed. Sessions that don't receive a chunk for `expiry_seconds` are removed.
+pub struct UploadStore {
+    directory: PathBuf,
+    sessions: JsonFile<BTreeMap<String, UploadSession>>,
+    max_length: u64,
+    expiry: Duration,
+    cleanup_interval: Duration,
+}
+
+impl UploadStore {
+    pub fn configure(
+        figment: &Figment,
+        state_directory: &Path,
+    ) -> Result<Self, ConfigurationError> {
+        let config: UploadsConfig = figment.extract_inner(UPLOADS)?;
+        let mut store =
+            UploadStore::open(state_directory, Duration::from_secs(config.expiry_seconds))
+                .map_err(|e| ConfigurationError::Other(e.into()))?;
+        store.max_length = config.max_length;
+        store.cleanup_interval = Duration::from_secs(config.cleanup_interval_seconds);
+        Ok(store)
+    }
+
+    pub fn figment_default_values() -> Figment {
+        Figment::from(Serialized::default(UPLOADS, UploadsConfig::default()))
+    }
+
+    pub fn open(state_directory: &Path, expiry: Duration) -> anyhow::Result<Self> {
+        let defaults = UploadsConfig::default();
+        Ok(UploadStore {
+            directory: state_directory.join(UPLOADS_DIRECTORY),
+            sessions: JsonFile::open(state_directory, UPLOADS_FILE_NAME)?,
+            max_length: defaults.max_length,
+            expiry,
+            cleanup_interval: Duration::from_secs(defaults.cleanup_interval_seconds),
+        })
+    }
+
+    /// Starts an upload of `length` bytes.
+    pub fn create(&self, length: u64) -> Result<UploadSession, UploadError> {
+        if length > self.max_length {
+            return Err(UploadError::TooLong {
+                max_length: self.max_length,
+            });
+        }
+        let session = UploadSession {
+            id: rand::distributions::Alphanumeric
+                .sample_string(&mut thread_rng(), UPLOAD_ID_LENGTH),
+            length,
+            offset: 0,
+            updated_at: now(),
+        };
+        fs::create_dir_all(&self.directory)?;
+        fs::write(self.path(&session.id), [])?;
+        self.sessions.update(|sessions| {
+            sessions.insert(session.id.clone(), session.clone());
+        })?;
+        Ok(session)
+    }
+
+    pub fn get(&self, id: &str) -> Result<UploadSession, UploadError> {
+        self.sessions
+            .read(|sessions| sessions.get(id).cloned())?
+            .ok_or_else(|| UploadError::NotFound(id.to_owned()))
+    }
+
+    /// Writes `chunk` at `offset`, which has to be the end of the data received so far.
+    pub fn append(
+        &self,
+        id: &str,
+        offset: u64,
+        chunk: &[u8],
+    ) -> Result<UploadSession, UploadError> {
+        let path = self.path(id);
+        self.sessions.update(|sessions| {
+            let session = sessions
+                .get_mut(id)
+                .ok_or_else(|| UploadError::NotFound(id.to_owned()))?;
+            if offset != session.offset {
+                return Err(UploadError::OffsetMismatch {
+                    expected: session.offset,
+                });
+            }
+            if offset + chunk.len() as u64 > session.length {
+                return Err(UploadError::TooLong {
+                    max_length: session.length,
+                });
+            }
+
+            let mut file = OpenOptions::new().write(true).open(&path)?;
+            // Drops whatever a chunk interrupted before its session was updated left behind
+            file.set_len(offset)?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(chunk)?;
+            session.offset += chunk.len() as u64;
+            session.updated_at = now();
+            Ok(session.clone())
+        })?
+    }
+
+    /// Moves the completed upload to `destination` and ends its session.
+    pub fn take(&self, id: &str, destination: &Path) -> Result<UploadSession, UploadError> {
+        let path = self.path(id);
+        self.sessions.update(|sessions| {
+            let session = sessions
+                .get(id)
+                .ok_or_else(|| UploadError::NotFound(id.to_owned()))?;
+            if !session.is_complete() {
+                return Err(UploadError::Incomplete(session.clone()));
+            }
+            // The state directory may be on another file system than the destination
+            if fs::rename(&path, destination).is_err() {
+                fs::copy(&path, destination)?;
+                fs::remove_file(&path)?;
+            }
+            Ok(sessions.remove(id).expect("Session was found before"))
+        })?
+    }
+
+    /// Removes the sessions that didn't receive a chunk within `expiry_seconds`, returning how
+    /// many were removed.
+    pub fn remove_expired(&self) -> anyhow::Result<usize> {
+        let expired_before = now().saturating_sub(self.expiry.as_secs());
+        let expired = self.sessions.update(|sessions| {
+            let expired: Vec<String> = sessions
+                .values()
+                .filter(|s| s.updated_at < expired_before)
+                .map(|s| s.id.clone())
+                .collect();
+            for id in &expired {
+                sessions.remove(id);
+            }
+            expired
+        })?;
+        for id in &expired {
+            info!("Removing abandoned upload {}", id);
+            if let Err(e) = fs::remove_file(self.path(id)) {
+                warn!("Failed to remove abandoned upload {}: {:?}", id, e);
+            }
+        }
+        Ok(expired.len())
+    }
+
+    /// Runs [`UploadStore::remove_expired`] every `cleanup_interval_seconds`.
+    pub fn spawn(self: Arc<Self>) -> JoinHandle<()> {
+        thread::spawn(move || loop {
+            thread::sleep(self.cleanup_interval);
+            if let Err(e) = self.remove_expired() {
+                error!("Failed to remove abandoned uploads: {:?}", e);
+            }
+        })
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        // Ids are only ever generated here, but they are also taken from URLs
+        let id: String = id.chars().filter(char::is_ascii_alphanumeric).collect();
+        self.directory.join(format!("{}.part", id))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn store(name: &str, expiry: Duration) -> (UploadStore, PathBuf) {
+        let state_directory = std::env::temp_dir().join(format!("pond-uploads-{}", name));
+        fs::remove_dir_all(&state_directory).ok();
+        (
+            UploadStore::open(&state_directory, expiry).unwrap(),
+            state_directory,
+        )
+    }
+
+    #[test]
+    fn test_chunked_upload() {
+        let (store, state_directory) = store("chunked", Duration::from_secs(60));
+        let session = store.create(11).unwrap();
+        store.append(&session.id, 0, b"Hello ").unwrap();
+        assert!(matches!(
+            store.append(&session.id, 0, b"Hello "),
+            Err(UploadError::OffsetMismatch { expected: 6 })
+        ));
+        assert!(matches!(
+            store.append(&session.id, 6, b"World!"),
+            Err(UploadError::TooLong { max_length: 11 })
+        ));
+
+        let destination = state_directory.join("artifact.zip");
+        assert!(matches!(
+            store.take(&session.id, &destination),
+            Err(UploadError::Incomplete(_))
+        ));
+        let updated = store.append(&session.id, 6, b"World").unwrap();
+        assert!(updated.is_complete());
+        assert_eq!(store.get(&session.id).unwrap(), updated);
+
+        store.take(&session.id, &destination).unwrap();
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "Hello World");
+        assert!(matches!(
+            store.get(&session.id),
+            Err(UploadError::NotFound(_))
+        ));
+        fs::remove_dir_all(&state_directory).ok();
+    }
+
+    #[test]
+    fn test_sessions_survive_reopening() {
+        let (store, state_directory) = store("reopening", Duration::from_secs(60));
+        let session = store.create(4).unwrap();
+        store.append(&session.id, 0, b"ab").unwrap();
+
+        let reopened = UploadStore::open(&state_directory, Duration::from_secs(60)).unwrap();
+        assert_eq!(reopened.get(&session.id).unwrap().offset, 2);
+        reopened.append(&session.id, 2, b"cd").unwrap();
+        fs::remove_dir_all(&state_directory).ok();
+    }
+
+    #[test]
+    fn test_remove_expired() {
+        let (store, state_directory) = store("expired", Duration::from_secs(60));
+        let abandoned = store.create(4).unwrap();
+        let active = store.create(4).unwrap();
+        store
+            .sessions
+            .update(|sessions| sessions.get_mut(&abandoned.id).unwrap().updated_at -= 120)
+            .unwrap();
+
+        assert_eq!(store.remove_expired().unwrap(), 1);
+        assert!(store.get(&abandoned.id).is_err());
+        assert!(!store.path(&abandoned.id).exists());
+        assert!(store.get(&active.id).is_ok());
+        fs::remove_dir_all(&state_directory).ok();
+    }
+
+    #[test]
+    fn test_uploads_are_limited() {
+        let (store, state_directory) = store("limited", Duration::from_secs(60));
+        assert!(matches!(
+            store.create(u64::MAX),
+            Err(UploadError::TooLong { .. })
+        ));
+        fs::remove_dir_all(&state_directory).ok();
+    }
+}
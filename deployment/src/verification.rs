@@ -0,0 +1,418 @@
+use std::{
+    error, fmt,
+    fs::{self, File},
+    io::{self, Read},
+    path::Path,
+};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use figment::Figment;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::ConfigurationError;
+
+const SIGNING_KEYS: &str = "signing_keys";
+const ED25519_PUBLIC_KEY_LENGTH: usize = 32;
+
+/// What the uploader claims about an artifact.
+#[derive(Clone, Debug, Default)]
+pub struct ArtifactVerification {
+    /// The expected SHA-256 digest of the artifact, hex encoded.
+    pub sha256: Option<String>,
+    /// A detached minisign signature or a base64 encoded ed25519 signature of the artifact.
+    pub signature: Option<String>,
+    /// The public keys of the access token the deployment was requested with.
+    pub public_keys: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum VerificationError {
+    InvalidChecksum(String),
+    ChecksumMismatch {
+        expected: String,
+        actual: String,
+    },
+    /// A public key applies to the deployment, but no signature was uploaded.
+    MissingSignature,
+    /// A signature was uploaded, but no public key applies to the deployment.
+    NoPublicKey,
+    InvalidSignature,
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VerificationError::InvalidChecksum(checksum) => {
+                write!(f, "{:?} is not a hex encoded SHA-256 digest", checksum)
+            }
+            VerificationError::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "The SHA-256 digest of the artifact is {} but {} was expected",
+                actual, expected
+            ),
+            VerificationError::MissingSignature => {
+                write!(f, "Deployments of this artifact have to be signed")
+            }
+            VerificationError::NoPublicKey => write!(
+                f,
+                "A signature was uploaded but no public key is configured to verify it"
+            ),
+            VerificationError::InvalidSignature => {
+                write!(f, "The signature of the artifact is not valid")
+            }
+            VerificationError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for VerificationError {}
+
+impl From<anyhow::Error> for VerificationError {
+    fn from(error: anyhow::Error) -> Self {
+        VerificationError::Other(error)
+    }
+}
+
+impl From<io::Error> for VerificationError {
+    fn from(error: io::Error) -> Self {
+        VerificationError::Other(error.into())
+    }
+}
+
+/// A key artifacts can be signed with, either a minisign public key or a raw ed25519 key,
+/// both base64 encoded.
+pub enum PublicKey {
+    Minisign(minisign_verify::PublicKey),
+    Ed25519(Vec<u8>),
+}
+
+impl PublicKey {
+    pub fn parse(public_key: &str) -> anyhow::Result<Self> {
+        let public_key = public_key.trim();
+        if let Ok(key) = minisign_verify::PublicKey::decode(public_key)
+            .or_else(|_e| minisign_verify::PublicKey::from_base64(public_key))
+        {
+            return Ok(PublicKey::Minisign(key));
+        }
+        match BASE64.decode(public_key) {
+            Ok(key) if key.len() == ED25519_PUBLIC_KEY_LENGTH => Ok(PublicKey::Ed25519(key)),
+            _ => Err(anyhow::anyhow!(
+                "{:?} is neither a minisign nor an ed25519 public key",
+                public_key
+            )),
+        }
+    }
+
+    fn verify(&self, artifact_location: &Path, signature: &str) -> anyhow::Result<bool> {
+        match self {
+            PublicKey::Minisign(key) => {
+                let Ok(signature) = minisign_verify::Signature::decode(signature) else {
+                    return Ok(false);
+                };
+                let Ok(mut verifier) = key.verify_stream(&signature) else {
+                    return Ok(false);
+                };
+                read_chunks(artifact_location, |chunk| verifier.update(chunk))?;
+                Ok(verifier.finalize().is_ok())
+            }
+            PublicKey::Ed25519(key) => {
+                let Ok(signature) = BASE64.decode(signature.trim()) else {
+                    return Ok(false);
+                };
+                let artifact = fs::read(artifact_location)?;
+                Ok(
+                    ring::signature::UnparsedPublicKey::new(&ring::signature::ED25519, key)
+                        .verify(&artifact, &signature)
+                        .is_ok(),
+                )
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SigningKeyConfig {
+    /// The deployment names the key applies to. `*` matches any number of characters.
+    deployment_names: String,
+    public_key: String,
+}
+
+struct SigningKey {
+    deployment_names: String,
+    public_key: PublicKey,
+}
+
+/// Public keys that deployments have to be signed with, by deployment name.
+#[derive(Default)]
+pub struct SigningKeys {
+    keys: Vec<SigningKey>,
+}
+
+impl SigningKeys {
+    pub fn configure(figment: &Figment) -> Result<Self, ConfigurationError> {
+        let configs: Vec<SigningKeyConfig> = if figment.contains(SIGNING_KEYS) {
+            figment.extract_inner(SIGNING_KEYS)?
+        } else {
+            vec![]
+        };
+        let mut keys = SigningKeys::default();
+        for config in configs {
+            let public_key = PublicKey::parse(&config.public_key)
+                .map_err(|e| ConfigurationError::Other(e.into()))?;
+            keys.add(config.deployment_names, public_key);
+        }
+        Ok(keys)
+    }
+
+    pub fn add(&mut self, deployment_names: String, public_key: PublicKey) {
+        self.keys.push(SigningKey {
+            deployment_names,
+            public_key,
+        });
+    }
+
+    fn keys_for<'a>(&'a self, deployment_name: &'a str) -> impl Iterator<Item = &'a PublicKey> {
+        self.keys
+            .iter()
+            .filter(move |k| matches_pattern(&k.deployment_names, deployment_name))
+            .map(|k| &k.public_key)
+    }
+
+    /// Whether files deployed as `source` may be served as `target` without being signed
+    /// again, which is the case if `target` accepts every key `source` accepts.
+    pub(crate) fn allows_promotion(&self, source: &str, target: &str) -> bool {
+        let mut source_keys = false;
+        let mut target_keys = false;
+        for key in &self.keys {
+            let for_source = matches_pattern(&key.deployment_names, source);
+            let for_target = matches_pattern(&key.deployment_names, target);
+            if for_source && !for_target {
+                return false;
+            }
+            source_keys |= for_source;
+            target_keys |= for_target;
+        }
+        source_keys || !target_keys
+    }
+
+    /// Checks the signature of the artifact against the keys of `deployment_name` and the
+    /// access token. Deployments without keys don't have to be signed.
+    pub(crate) fn verify_signature(
+        &self,
+        deployment_name: &str,
+        artifact_location: &Path,
+        verification: &ArtifactVerification,
+    ) -> Result<(), VerificationError> {
+        let token_keys = verification
+            .public_keys
+            .iter()
+            .map(|k| PublicKey::parse(k))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let keys: Vec<&PublicKey> = token_keys
+            .iter()
+            .chain(self.keys_for(deployment_name))
+            .collect();
+
+        let signature = match (&verification.signature, keys.is_empty()) {
+            (None, true) => return Ok(()),
+            (None, false) => return Err(VerificationError::MissingSignature),
+            (Some(_), true) => return Err(VerificationError::NoPublicKey),
+            (Some(signature), false) => signature,
+        };
+        for key in keys {
+            if key.verify(artifact_location, signature)? {
+                return Ok(());
+            }
+        }
+        Err(VerificationError::InvalidSignature)
+    }
+}
+
+/// Computes the SHA-256 digest of the artifact and compares it to the expected one, if any.
+pub(crate) fn verify_checksum(
+    artifact_location: &Path,
+    expected: Option<&str>,
+) -> Result<String, VerificationError> {
+    let expected = expected.map(|e| e.trim().to_ascii_lowercase());
+    if let Some(expected) = &expected {
+        if expected.len() != 64 || !expected.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(VerificationError::InvalidChecksum(expected.clone()));
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    read_chunks(artifact_location, |chunk| hasher.update(chunk))?;
+    let actual = format!("{:x}", hasher.finalize());
+    match expected {
+        Some(expected) if expected != actual => {
+            Err(VerificationError::ChecksumMismatch { expected, actual })
+        }
+        _ => Ok(actual),
+    }
+}
+
+fn read_chunks(path: &Path, mut consume: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(()),
+            read => consume(&buffer[..read]),
+        }
+    }
+}
+
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            name.starts_with(prefix)
+                && (0..=name.len() - prefix.len())
+                    .any(|skip| matches_pattern(rest, &name[prefix.len() + skip..]))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+
+    use super::*;
+
+    // Taken from the tests of minisign-verify, signing the content "test"
+    pub(crate) const MINISIGN_PUBLIC_KEY: &str =
+        "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    pub(crate) const MINISIGN_SIGNATURE: &str =
+        "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+    pub(crate) const TEST_SHA256: &str =
+        "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    pub(crate) fn artifact(name: &str, content: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("pond-verification-{}", name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn verification(signature: Option<&str>, public_keys: &[&str]) -> ArtifactVerification {
+        ArtifactVerification {
+            sha256: None,
+            signature: signature.map(str::to_owned),
+            public_keys: public_keys.iter().map(|k| k.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let path = artifact("checksum", "test");
+        assert_eq!(verify_checksum(&path, None).unwrap(), TEST_SHA256);
+        assert_eq!(
+            verify_checksum(&path, Some(&TEST_SHA256.to_uppercase())).unwrap(),
+            TEST_SHA256
+        );
+        assert!(matches!(
+            verify_checksum(&path, Some(&"0".repeat(64))),
+            Err(VerificationError::ChecksumMismatch { actual, .. }) if actual == TEST_SHA256
+        ));
+        assert!(matches!(
+            verify_checksum(&path, Some("abc")),
+            Err(VerificationError::InvalidChecksum(_))
+        ));
+    }
+
+    #[test]
+    fn test_minisign_signature() {
+        let mut keys = SigningKeys::default();
+        keys.add(
+            "blog*".to_owned(),
+            PublicKey::parse(MINISIGN_PUBLIC_KEY).unwrap(),
+        );
+        let signed = artifact("minisign", "test");
+        let tampered = artifact("minisign-tampered", "Test");
+
+        keys.verify_signature(
+            "blog",
+            &signed,
+            &verification(Some(MINISIGN_SIGNATURE), &[]),
+        )
+        .unwrap();
+        assert!(matches!(
+            keys.verify_signature(
+                "blog-staging",
+                &tampered,
+                &verification(Some(MINISIGN_SIGNATURE), &[])
+            ),
+            Err(VerificationError::InvalidSignature)
+        ));
+        assert!(matches!(
+            keys.verify_signature("blog", &signed, &verification(None, &[])),
+            Err(VerificationError::MissingSignature)
+        ));
+        // Deployments without keys don't have to be signed, but can't be verified either
+        keys.verify_signature("shop", &signed, &verification(None, &[]))
+            .unwrap();
+        assert!(matches!(
+            keys.verify_signature(
+                "shop",
+                &signed,
+                &verification(Some(MINISIGN_SIGNATURE), &[])
+            ),
+            Err(VerificationError::NoPublicKey)
+        ));
+    }
+
+    #[test]
+    fn test_ed25519_signature_with_token_key() {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&ring::rand::SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let public_key = BASE64.encode(key_pair.public_key().as_ref());
+        let signature = BASE64.encode(key_pair.sign(b"site").as_ref());
+        let keys = SigningKeys::default();
+
+        let signed = artifact("ed25519", "site");
+        keys.verify_signature(
+            "shop",
+            &signed,
+            &verification(Some(&signature), &[&public_key]),
+        )
+        .unwrap();
+        let tampered = artifact("ed25519-tampered", "tampered site");
+        assert!(matches!(
+            keys.verify_signature(
+                "shop",
+                &tampered,
+                &verification(Some(&signature), &[&public_key])
+            ),
+            Err(VerificationError::InvalidSignature)
+        ));
+        assert!(PublicKey::parse("c2hvcnQ=").is_err());
+    }
+
+    #[test]
+    fn test_allows_promotion() {
+        let mut keys = SigningKeys::default();
+        assert!(keys.allows_promotion("app-staging", "app"));
+        keys.add(
+            "app*".to_owned(),
+            PublicKey::parse(MINISIGN_PUBLIC_KEY).unwrap(),
+        );
+        assert!(keys.allows_promotion("app-staging", "app"));
+        assert!(!keys.allows_promotion("preview", "app"));
+        assert!(!keys.allows_promotion("app", "blog"));
+        assert!(keys.allows_promotion("preview", "blog"));
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("blog", "blog"));
+        assert!(!matches_pattern("blog", "blog-staging"));
+        assert!(matches_pattern("blog*", "blog-staging"));
+        assert!(matches_pattern("*-staging", "blog-staging"));
+        assert!(matches_pattern("*", "shop"));
+        assert!(!matches_pattern("blog-*", "shop"));
+    }
+}
@@ -0,0 +1,5 @@
+#!/usr/bin/env bash
+set -e
+
+SITE_DIRECTORY=/var/www/$DEPLOYMENT_NAME
+rm -rf "$SITE_DIRECTORY" "$SITE_DIRECTORY.canary" "$SITE_DIRECTORY.previous"
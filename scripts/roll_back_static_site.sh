@@ -0,0 +1,12 @@
+#!/usr/bin/env bash
+set -e
+
+SITE_DIRECTORY=/var/www/$DEPLOYMENT_NAME
+# A failed canary is removed, which serves the current deployment to everyone again
+if [ -n "$CANARY_WEIGHT" ]; then
+    rm -rf "$SITE_DIRECTORY.canary"
+    exit 0
+fi
+
+rm -rf "$SITE_DIRECTORY"
+mv "$SITE_DIRECTORY.previous" "$SITE_DIRECTORY"
@@ -3,5 +3,30 @@ set -e
 
 echo $ARTIFACT_LOCATION
 
-rm -rf /var/www/$DEPLOYMENT_NAME || true
-unzip $ARTIFACT_LOCATION -d /var/www/$DEPLOYMENT_NAME
\ No newline at end of file
+SITE_DIRECTORY=/var/www/$DEPLOYMENT_NAME
+CANARY_DIRECTORY=$SITE_DIRECTORY.canary
+PREVIOUS_DIRECTORY=$SITE_DIRECTORY.previous
+# Canaries are served from their own directory next to the current deployment
+if [ -n "$CANARY_WEIGHT" ]; then
+    TARGET_DIRECTORY=$CANARY_DIRECTORY
+    rm -rf "$TARGET_DIRECTORY" || true
+else
+    TARGET_DIRECTORY=$SITE_DIRECTORY
+    # The current deployment is kept to roll back to
+    rm -rf "$PREVIOUS_DIRECTORY" || true
+    if [ -d "$SITE_DIRECTORY" ]; then
+        mv "$SITE_DIRECTORY" "$PREVIOUS_DIRECTORY"
+    fi
+fi
+
+# Releases assembled from synced files are directories
+if [ -d "$ARTIFACT_LOCATION" ]; then
+    cp -r "$ARTIFACT_LOCATION" "$TARGET_DIRECTORY"
+else
+    unzip $ARTIFACT_LOCATION -d "$TARGET_DIRECTORY"
+fi
+
+# Deploying to everyone ends any canary
+if [ -z "$CANARY_WEIGHT" ]; then
+    rm -rf "$CANARY_DIRECTORY"
+fi
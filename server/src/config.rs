@@ -10,7 +10,40 @@ use rocket::{
 #[derive(Deserialize)]
 #[serde(crate = "rocket::serde")]
 pub struct AuthorizationConfig {
-    pub access_token: String,
+    pub access_token: Option<String>,
+    /// Further tokens, each optionally restricted to deploying below one root domain.
+    #[serde(default)]
+    pub access_tokens: Vec<AccessToken>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct AccessToken {
+    pub token: String,
+    /// Identifies the token in the metadata of the artifacts uploaded with it.
+    pub name: Option<String>,
+    pub root_domain_name: Option<String>,
+    /// Artifacts deployed with this token have to be signed with one of these keys.
+    #[serde(default)]
+    pub public_keys: Vec<String>,
+}
+
+impl AuthorizationConfig {
+    /// Looks up the token matching `token`. The `access_token` isn't restricted to a root domain.
+    pub fn find(&self, token: &str) -> Option<AccessToken> {
+        if self.access_token.as_deref() == Some(token) {
+            return Some(AccessToken {
+                token: token.to_owned(),
+                name: None,
+                root_domain_name: None,
+                public_keys: vec![],
+            });
+        }
+        self.access_tokens
+            .iter()
+            .find(|t| t.token == token)
+            .cloned()
+    }
 }
 
 pub fn figment() -> Figment {
@@ -34,4 +67,28 @@ mod test {
         assert_eq!(extracted1, "normal");
         assert_eq!(extracted.log_level, rocket::config::LogLevel::Normal);
     }
+
+    #[test]
+    fn test_find_access_token() {
+        let config: AuthorizationConfig = Figment::new()
+            .merge(Toml::string(
+                r#"
+                access_token = "admin"
+
+                [[access_tokens]]
+                token = "brand"
+                root_domain_name = "example.org"
+                public_keys = ["RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3"]
+                "#,
+            ))
+            .extract()
+            .unwrap();
+        assert_eq!(config.find("admin").unwrap().root_domain_name, None);
+        assert_eq!(
+            config.find("brand").unwrap().root_domain_name.as_deref(),
+            Some("example.org")
+        );
+        assert_eq!(config.find("brand").unwrap().public_keys.len(), 1);
+        assert!(config.find("other").is_none());
+    }
 }
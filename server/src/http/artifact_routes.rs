@@ -0,0 +1,75 @@
+use pond_deployment::{DeploymentManager, StoredArtifact};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::sync::Arc;
+
+use super::auth::AuthenticatedUser;
+
+#[get("/artifacts")]
+pub fn list_artifacts(
+    _user: AuthenticatedUser,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<Vec<StoredArtifact>>, Custom<String>> {
+    let artifacts = deployment_service
+        .artifacts()
+        .list()
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?;
+    Ok(Json(artifacts))
+}
+
+/// Reports whether an artifact is stored, in which case it can be deployed by its digest alone.
+#[get("/artifacts/<sha256>")]
+pub fn get_artifact(
+    _user: AuthenticatedUser,
+    sha256: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<StoredArtifact>, Custom<String>> {
+    deployment_service
+        .artifacts()
+        .get(&sha256.to_ascii_lowercase())
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .map(Json)
+        .ok_or_else(|| {
+            Custom(
+                Status::NotFound,
+                format!("No artifact with SHA-256 digest {} is stored", sha256),
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rocket_test;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+
+    fn authorization() -> Header<'static> {
+        Header::new("Authorization", "Bearer test_access_token")
+    }
+
+    #[test]
+    fn test_unknown_artifact() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let sha256 = "0".repeat(64);
+        let response = client
+            .get(uri!(super::get_artifact(&sha256)))
+            .header(authorization())
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let boundary = "X-POND-BOUNDARY";
+        let response = client
+            .post(uri!(crate::http::deployment_routes::deploy))
+            .header(authorization())
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", boundary)))
+            .body(format!(
+                "--{b}\r\nContent-Disposition: form-data; name=\"sha256\"\r\n\r\n{sha256}\r\n--{b}--\r\n",
+                b = boundary,
+                sha256 = sha256
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
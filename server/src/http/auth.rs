@@ -1,3 +1,4 @@
+use pond_deployment::TokenScope;
 use rocket::{
     http::Status,
     request::{self, FromRequest, Outcome, Request},
@@ -5,7 +6,24 @@ use rocket::{
 
 use crate::config::AuthorizationConfig;
 
-pub struct AuthenticatedUser;
+pub struct AuthenticatedUser {
+    /// The name of the access token, if it has one.
+    pub name: Option<String>,
+    /// The root domain deployments of this user are restricted to.
+    pub root_domain_name: Option<String>,
+    /// The keys artifacts deployed by this user have to be signed with.
+    pub public_keys: Vec<String>,
+}
+
+impl AuthenticatedUser {
+    /// What the access token may use, e.g. which claimed domains.
+    pub fn token_scope(&self) -> TokenScope {
+        TokenScope {
+            name: self.name.clone(),
+            root_domain_name: self.root_domain_name.clone(),
+        }
+    }
+}
 
 const AUTHORIZATION: &str = "Authorization";
 const AUTHORIZATION_SCHEME_PREFIX: &str = "Bearer ";
@@ -28,13 +46,16 @@ impl<'r> FromRequest<'r> for AuthenticatedUser {
 
         req.rocket()
             .state::<AuthorizationConfig>()
-            .map(move |my_config: &AuthorizationConfig| {
-                if my_config.access_token == auth_token {
-                    Outcome::Success(AuthenticatedUser {})
-                } else {
-                    Outcome::Error((Status::Unauthorized, "Incorrect access token"))
-                }
-            })
+            .map(
+                move |my_config: &AuthorizationConfig| match my_config.find(auth_token) {
+                    Some(access_token) => Outcome::Success(AuthenticatedUser {
+                        name: access_token.name,
+                        root_domain_name: access_token.root_domain_name,
+                        public_keys: access_token.public_keys,
+                    }),
+                    None => Outcome::Error((Status::Unauthorized, "Incorrect access token")),
+                },
+            )
             .unwrap_or(Outcome::Forward(Status::InternalServerError))
     }
 }
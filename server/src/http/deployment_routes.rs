@@ -1,58 +1,559 @@
-use lazy_static::lazy_static;
-use pond_deployment::DeploymentManager;
+use pond_deployment::{
+    ArtifactSource, ArtifactVerification, DeploymentError, DeploymentManager, DeploymentRequest,
+    Maintenance, ManifestError, ManifestFormat, ManifestSource, SecretError, VerificationError,
+};
 use rand::distributions::DistString;
 use rand::thread_rng;
-use regex::Regex;
-use rocket::form::Form;
+use rocket::data::ToByteUnit;
+use rocket::form::{self, DataField, Form, FromFormField, ValueField};
 use rocket::fs::TempFile;
 use rocket::http::Status;
 use rocket::response::status::Custom;
+use rocket::serde::json::{Json, Value};
+use rocket::serde::Serialize;
+use rocket::tokio::task::spawn_blocking;
 use rocket::State;
+use std::sync::Arc;
 
 use crate::message::AsyncLogStream;
 
 use super::auth::AuthenticatedUser;
+use super::{sync_routes, upload_routes};
 
-lazy_static! {
-    static ref NAME_VALIDATION_REGEX: Regex = Regex::new("[a-zA-Z-]{3,50}").unwrap();
+/// A manifest in any of the supported formats. The format is taken from the content type of
+/// the field and sniffed if it has none.
+#[derive(Debug)]
+pub struct ManifestField(ManifestSource);
+
+#[rocket::async_trait]
+impl<'r> FromFormField<'r> for ManifestField {
+    fn from_value(field: ValueField<'r>) -> form::Result<'r, Self> {
+        Ok(ManifestField(ManifestSource {
+            content: field.value.to_owned(),
+            format: None,
+        }))
+    }
+
+    async fn from_data(field: DataField<'r, '_>) -> form::Result<'r, Self> {
+        let format = ManifestFormat::from_media_type(&field.content_type.media_type().to_string());
+        let limit = field
+            .request
+            .limits()
+            .get("string")
+            .unwrap_or(256.kibibytes());
+        let content = field.data.open(limit).into_string().await?;
+        if !content.is_complete() {
+            return Err(form::Error::validation("Manifest is too large").into());
+        }
+        Ok(ManifestField(ManifestSource {
+            content: content.into_inner(),
+            format,
+        }))
+    }
 }
 
 #[derive(Debug, FromForm)]
-pub struct DeploymentRequest<'r> {
-    manifest: &'r str,
-    artifact: TempFile<'r>,
+pub struct DeploymentForm<'r> {
+    /// Overrides the `pond.toml`, `pond.json` or `pond.yaml` at the root of the artifact.
+    /// Without an artifact, the manifest's git `source` is deployed.
+    manifest: Option<ManifestField>,
+    artifact: Option<TempFile<'r>>,
+    /// The id of a completed upload, instead of the `artifact`.
+    upload: Option<String>,
+    /// The id of a completed sync of a static site's files, instead of the `artifact`.
+    sync: Option<String>,
+    /// The hex encoded SHA-256 digest of the artifact. Without `artifact` and `upload`, the
+    /// stored artifact with this digest is deployed again.
+    sha256: Option<String>,
+    /// A detached minisign or base64 encoded ed25519 signature of the artifact.
+    signature: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ManifestErrors {
+    errors: Vec<ManifestError>,
+}
+
+#[derive(Responder)]
+pub enum DeploymentRouteError {
+    InvalidManifest(Custom<Json<ManifestErrors>>),
+    Other(Custom<String>),
+}
+
+impl From<Custom<String>> for DeploymentRouteError {
+    fn from(error: Custom<String>) -> Self {
+        DeploymentRouteError::Other(error)
+    }
+}
+
+impl From<DeploymentError> for DeploymentRouteError {
+    fn from(error: DeploymentError) -> Self {
+        let error = match error {
+            DeploymentError::InvalidManifest(errors) => {
+                return DeploymentRouteError::InvalidManifest(Custom(
+                    Status::UnprocessableEntity,
+                    Json(ManifestErrors { errors }),
+                ))
+            }
+            DeploymentError::UnverifiedDomain(domain_name) => Custom(
+                Status::Forbidden,
+                format!(
+                    "Domain {} has not been verified. Claim it through POST /domains first",
+                    domain_name
+                ),
+            ),
+            DeploymentError::UnknownRootDomain(root_domain_name) => Custom(
+                Status::BadRequest,
+                format!("Unknown root domain {}", root_domain_name),
+            ),
+            DeploymentError::UnknownArtifact(sha256) => Custom(
+                Status::NotFound,
+                format!("No artifact with SHA-256 digest {} is stored", sha256),
+            ),
+            DeploymentError::NoCanary(name) => Custom(
+                Status::NotFound,
+                format!("Deployment {} has no canary", name),
+            ),
+            DeploymentError::UnknownDeployment(name) => Custom(
+                Status::NotFound,
+                format!("There is no deployment {}", name),
+            ),
+            DeploymentError::InvalidMaintenance(message) => {
+                Custom(Status::UnprocessableEntity, message)
+            }
+            DeploymentError::Sync(e) => sync_routes::error_response(e),
+            DeploymentError::RootDomainNotAllowed(root_domain_name) => Custom(
+                Status::Forbidden,
+                format!(
+                    "The access token may not deploy below root domain {}",
+                    root_domain_name
+                ),
+            ),
+            DeploymentError::Secret(SecretError::NotFound(name)) => Custom(
+                Status::UnprocessableEntity,
+                format!(
+                    "The manifest refers to secret {} which does not exist. Set it through PUT /deployments/<name>/secrets/{} first",
+                    name, name
+                ),
+            ),
+            DeploymentError::Secret(SecretError::NotConfigured) => Custom(
+                Status::UnprocessableEntity,
+                "The manifest refers to secrets but the server has no secrets.key configured"
+                    .to_owned(),
+            ),
+            DeploymentError::Verification(
+                e @ (VerificationError::InvalidChecksum(_)
+                | VerificationError::ChecksumMismatch { .. }
+                | VerificationError::NoPublicKey),
+            ) => Custom(Status::UnprocessableEntity, e.to_string()),
+            DeploymentError::Verification(
+                e @ (VerificationError::MissingSignature | VerificationError::InvalidSignature),
+            ) => Custom(Status::Forbidden, e.to_string()),
+            e => Custom(
+                Status::InternalServerError,
+                format!("Failed to start deployment {:?}", e),
+            ),
+        };
+        DeploymentRouteError::Other(error)
+    }
 }
 
 #[post("/deploy", data = "<request>")]
 pub async fn deploy<'r>(
-    _user: AuthenticatedUser,
-    mut request: Form<DeploymentRequest<'r>>,
-    deployment_service: &State<DeploymentManager>,
-) -> Result<AsyncLogStream, Custom<String>> {
+    user: AuthenticatedUser,
+    mut request: Form<DeploymentForm<'r>>,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<AsyncLogStream, DeploymentRouteError> {
     let artifact_location = std::env::temp_dir().join(format!(
         "artifact-{}.tar.gz",
         rand::distributions::Alphanumeric.sample_string(&mut thread_rng(), 5)
     ));
 
-    request
-        .artifact
-        .persist_to(&artifact_location)
-        .await
-        .map_err(|e| {
-            Custom(
-                Status::InternalServerError,
-                format!("Failed to save artifact: {:?}", e),
+    let upload = request.upload.take();
+    let sync = request.sync.take();
+    let sha256 = request.sha256.take();
+    let artifact = match (request.artifact.as_mut(), upload, sync, sha256.as_deref()) {
+        (Some(artifact), None, None, _) => {
+            artifact.persist_to(&artifact_location).await.map_err(|e| {
+                Custom(
+                    Status::InternalServerError,
+                    format!("Failed to save artifact: {:?}", e),
+                )
+            })?;
+            ArtifactSource::File(artifact_location)
+        }
+        (None, Some(upload), None, _) => {
+            let uploads = deployment_service.uploads();
+            let destination = artifact_location.clone();
+            spawn_blocking(move || uploads.take(&upload, &destination))
+                .await
+                .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+                .map_err(upload_routes::error_response)?;
+            ArtifactSource::File(artifact_location)
+        }
+        (None, None, Some(sync), _) => ArtifactSource::Sync(sync),
+        (None, None, None, Some(sha256)) => ArtifactSource::Stored(sha256.to_owned()),
+        (None, None, None, None) => ArtifactSource::Git,
+        _ => {
+            return Err(Custom(
+                Status::UnprocessableEntity,
+                "Only one of an artifact and the id of an upload or sync may be given".to_owned(),
             )
-        })?;
+            .into())
+        }
+    };
 
-    let result = deployment_service
-        .deploy(request.manifest, &artifact_location)
-        .map_err(|e| {
-            Custom(
-                Status::InternalServerError,
-                format!("Failed to start deployment {:?}", e),
-            )
-        })?;
+    let deployment_request = DeploymentRequest {
+        manifest: request.manifest.take().map(|manifest| manifest.0),
+        artifact,
+        root_domain_name: user.root_domain_name,
+        uploaded_by: user.name,
+        verification: ArtifactVerification {
+            sha256,
+            signature: request.signature.take(),
+            public_keys: user.public_keys,
+        },
+    };
+    // Verifying and preparing the artifact blocks and must not run on the async executor
+    let deployment_service = deployment_service.inner().clone();
+    let result = spawn_blocking(move || deployment_service.deploy(deployment_request))
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))??;
 
     Ok(AsyncLogStream::from_deployment_logs(result))
 }
+
+/// Deploys the files served for `deployment_name` as `target`, which keeps its own manifest.
+/// The access token has to be allowed to deploy `target`.
+#[post("/deployments/<deployment_name>/promote-to/<target>")]
+pub fn promote_to(
+    user: AuthenticatedUser,
+    deployment_name: &str,
+    target: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<AsyncLogStream, DeploymentRouteError> {
+    let result =
+        deployment_service.promote_to(deployment_name, target, user.root_domain_name.as_deref())?;
+    Ok(AsyncLogStream::from_deployment_logs(result))
+}
+
+/// Serves a maintenance page with status 503 instead of the deployment, except to the
+/// addresses and the cookie of `maintenance`.
+#[post("/deployments/<deployment_name>/maintenance", data = "<maintenance>")]
+pub async fn start_maintenance(
+    user: AuthenticatedUser,
+    deployment_name: &str,
+    maintenance: Json<Maintenance>,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<AsyncLogStream, DeploymentRouteError> {
+    set_maintenance(
+        user,
+        deployment_name,
+        Some(maintenance.into_inner()),
+        deployment_service,
+    )
+    .await
+}
+
+/// Serves the deployment to everyone again.
+#[delete("/deployments/<deployment_name>/maintenance")]
+pub async fn end_maintenance(
+    user: AuthenticatedUser,
+    deployment_name: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<AsyncLogStream, DeploymentRouteError> {
+    set_maintenance(user, deployment_name, None, deployment_service).await
+}
+
+async fn set_maintenance(
+    user: AuthenticatedUser,
+    deployment_name: &str,
+    maintenance: Option<Maintenance>,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<AsyncLogStream, DeploymentRouteError> {
+    let deployment_service = deployment_service.inner().clone();
+    let deployment_name = deployment_name.to_owned();
+    // Reading the recorded deployment blocks and must not run on the async executor
+    let result = spawn_blocking(move || {
+        deployment_service.set_maintenance(
+            &deployment_name,
+            maintenance,
+            user.root_domain_name.as_deref(),
+        )
+    })
+    .await
+    .map_err(|e| Custom(Status::InternalServerError, e.to_string()))??;
+    Ok(AsyncLogStream::from_deployment_logs(result))
+}
+
+/// A deployment the reaper removes once `expires_at` has passed.
+#[derive(Serialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ExpiringDeployment {
+    name: String,
+    deployment_type: String,
+    domain_names: Vec<String>,
+    /// In seconds since the Unix epoch.
+    expires_at: Option<u64>,
+}
+
+/// Lists the deployments that expire, soonest first.
+#[get("/deployments/expiring")]
+pub fn expiring_deployments(
+    _user: AuthenticatedUser,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<Vec<ExpiringDeployment>>, Custom<String>> {
+    let reaper = deployment_service.reaper().ok_or_else(|| {
+        Custom(
+            Status::NotFound,
+            "Expired deployments are not removed".to_owned(),
+        )
+    })?;
+    let expiring = reaper
+        .expiring()
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?;
+    Ok(Json(
+        expiring
+            .into_iter()
+            .map(|deployment| ExpiringDeployment {
+                domain_names: deployment.manifest.served_domain_names(),
+                name: deployment.manifest.name,
+                deployment_type: deployment.manifest.deployment_type,
+                expires_at: deployment.expires_at,
+            })
+            .collect(),
+    ))
+}
+
+/// Serves the canary of a deployment to everyone and makes it the current deployment.
+#[post("/deployments/<deployment_name>/promote")]
+pub fn promote_canary(
+    user: AuthenticatedUser,
+    deployment_name: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<AsyncLogStream, DeploymentRouteError> {
+    let result = deployment_service.promote(deployment_name, user.root_domain_name.as_deref())?;
+    Ok(AsyncLogStream::from_deployment_logs(result))
+}
+
+/// Removes the canary of a deployment, serving the current deployment to everyone again.
+#[post("/deployments/<deployment_name>/abort")]
+pub fn abort_canary(
+    user: AuthenticatedUser,
+    deployment_name: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<AsyncLogStream, DeploymentRouteError> {
+    let result = deployment_service.abort(deployment_name, user.root_domain_name.as_deref())?;
+    Ok(AsyncLogStream::from_deployment_logs(result))
+}
+
+/// The JSON Schema manifests are validated against, for editors and CI.
+#[get("/schema/manifest.json")]
+pub fn manifest_schema(deployment_service: &State<Arc<DeploymentManager>>) -> Json<Value> {
+    Json(deployment_service.manifest_schema())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rocket_test;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+    use rocket::serde::json::Value;
+
+    const BOUNDARY: &str = "X-POND-BOUNDARY";
+
+    fn deployment_form(manifest: &str) -> String {
+        format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"manifest\"\r\n\r\n{m}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"artifact\"; filename=\"site.tar.gz\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\nartifact\r\n--{b}--\r\n",
+            b = BOUNDARY,
+            m = manifest
+        )
+    }
+
+    #[test]
+    fn test_invalid_manifest_is_unprocessable() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::deploy))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY)))
+            .body(deployment_form(
+                "name = \"My Blog\"\ndeployment_type = \"static-site\"",
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["errors"][0]["field"], "name");
+    }
+
+    #[test]
+    fn test_manifest_content_type_selects_format() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"manifest\"; filename=\"pond.json\"\r\n\
+             Content-Type: application/json\r\n\r\n{{\"name\": \"blog\", \"deployment_type\": \"static-site\", \"unknown\": 1}}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"artifact\"; filename=\"site.tar.gz\"\r\n\
+             Content-Type: application/octet-stream\r\n\r\nartifact\r\n--{b}--\r\n",
+            b = BOUNDARY
+        );
+        let response = client
+            .post(uri!(super::deploy))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY)))
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["errors"][0]["line"], 1);
+        assert!(body["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("unknown field `unknown`"));
+    }
+
+    #[test]
+    fn test_manifest_schema() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client.get(uri!(super::manifest_schema)).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let schema: Value = response.into_json().unwrap();
+        assert_eq!(schema["title"], "Manifest");
+        assert_eq!(
+            schema["properties"]["deployment_type"]["enum"][0],
+            "static-site"
+        );
+    }
+
+    #[test]
+    fn test_missing_manifest_is_unprocessable() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"artifact\"; filename=\"site.zip\"\r\n\
+             Content-Type: application/zip\r\n\r\nartifact\r\n--{b}--\r\n",
+            b = BOUNDARY
+        );
+        let response = client
+            .post(uri!(super::deploy))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY)))
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["errors"][0]["field"], "manifest");
+    }
+
+    #[test]
+    fn test_checksum_mismatch_is_unprocessable() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"sha256\"\r\n\r\n{sha256}\r\n\
+             --{b}\r\nContent-Disposition: form-data; name=\"artifact\"; filename=\"site.zip\"\r\n\
+             Content-Type: application/zip\r\n\r\nartifact\r\n--{b}--\r\n",
+            b = BOUNDARY,
+            sha256 = "0".repeat(64)
+        );
+        let response = client
+            .post(uri!(super::deploy))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY)))
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        assert!(response
+            .into_string()
+            .unwrap()
+            .contains("The SHA-256 digest of the artifact is"));
+    }
+
+    #[test]
+    fn test_manifest_without_artifact_needs_git_source() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let body = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"manifest\"\r\n\r\n\
+             name = \"blog\"\ndeployment_type = \"static-site\"\r\n--{b}--\r\n",
+            b = BOUNDARY
+        );
+        let response = client
+            .post(uri!(super::deploy))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", BOUNDARY)))
+            .body(body)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+        let body: Value = response.into_json().unwrap();
+        assert_eq!(body["errors"][0]["field"], "source");
+    }
+
+    #[test]
+    fn test_deployments_without_canary_cannot_be_promoted() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        for uri in [
+            uri!(super::promote_canary("unknown")),
+            uri!(super::abort_canary("unknown")),
+        ] {
+            let response = client
+                .post(uri)
+                .header(Header::new("Authorization", "Bearer test_access_token"))
+                .dispatch();
+            assert_eq!(response.status(), Status::NotFound);
+        }
+    }
+
+    #[test]
+    fn test_promoting_to_unknown_deployments() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::promote_to(
+                "promote-staging",
+                "promote-production"
+            )))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_maintenance_of_unknown_deployments() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::start_maintenance("maintenance-unknown")))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .header(ContentType::JSON)
+            .body(r#"{"bypass_ips": ["203.0.113.7"]}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client
+            .post(uri!(super::start_maintenance("maintenance-unknown")))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .header(ContentType::JSON)
+            .body(r#"{"bypass_ips": ["everyone"]}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let response = client
+            .delete(uri!(super::end_maintenance("maintenance-unknown")))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_expiring_deployments() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client.get(uri!(super::expiring_deployments)).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = client
+            .get(uri!(super::expiring_deployments))
+            .header(Header::new("Authorization", "Bearer test_access_token"))
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body: Value = response.into_json().unwrap();
+        assert!(body.is_array());
+    }
+}
@@ -0,0 +1,56 @@
+use pond_deployment::{DeploymentManager, DnsDrift};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::State;
+use std::sync::Arc;
+
+use super::auth::AuthenticatedUser;
+
+#[get("/dns/drift")]
+pub async fn dns_drift(
+    user: AuthenticatedUser,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<Vec<DnsDrift>>, Custom<String>> {
+    let dns_reconciler = deployment_service.dns_reconciler().ok_or_else(|| {
+        Custom(
+            Status::NotFound,
+            "DNS records are not managed by pond".to_owned(),
+        )
+    })?;
+
+    // The DNS providers use blocking HTTP clients which must not run on the async executor
+    let drift = rocket::tokio::task::spawn_blocking(move || {
+        dns_reconciler.detect_drift(user.root_domain_name.as_deref())
+    })
+    .await
+    .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+    .map_err(|e| {
+        Custom(
+            Status::BadGateway,
+            format!("Failed to read DNS records: {:?}", e),
+        )
+    })?;
+
+    Ok(Json(drift))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rocket_test;
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::Client;
+
+    #[test]
+    fn test_drift_without_dns_provider() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .get(uri!(super::dns_drift))
+            .header(Header::new(
+                "Authorization",
+                "Bearer test_access_token".to_owned(),
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
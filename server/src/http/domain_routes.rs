@@ -0,0 +1,163 @@
+use pond_deployment::{DeploymentManager, DomainClaim, DomainVerificationError};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::serde::Deserialize;
+use rocket::State;
+use std::sync::Arc;
+
+use super::auth::AuthenticatedUser;
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct ClaimDomainRequest {
+    domain_name: String,
+}
+
+fn error_response(error: DomainVerificationError) -> Custom<String> {
+    let status = match error {
+        DomainVerificationError::InvalidDomainName(_) => Status::BadRequest,
+        DomainVerificationError::NotClaimed(_) => Status::NotFound,
+        DomainVerificationError::AlreadyClaimed(_) => Status::Conflict,
+        DomainVerificationError::ChallengeNotFound(_) => Status::UnprocessableEntity,
+        DomainVerificationError::Other(_) => Status::InternalServerError,
+    };
+    Custom(status, error.to_string())
+}
+
+/// Lists the domains claimed with the access token, or all of them for unrestricted tokens.
+#[get("/domains")]
+pub fn list_domains(
+    user: AuthenticatedUser,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<Vec<DomainClaim>>, Custom<String>> {
+    let domains = deployment_service
+        .domains()
+        .list(&user.token_scope())
+        .map_err(|e| error_response(e.into()))?;
+    Ok(Json(domains))
+}
+
+/// Claims a custom domain and returns the TXT record that has to be published to verify it.
+#[post("/domains", data = "<request>")]
+pub fn claim_domain(
+    user: AuthenticatedUser,
+    request: Json<ClaimDomainRequest>,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<DomainClaim>, Custom<String>> {
+    let claim = deployment_service
+        .domains()
+        .claim(&request.domain_name, &user.token_scope())
+        .map_err(error_response)?;
+    Ok(Json(claim))
+}
+
+#[post("/domains/<domain_name>/verify")]
+pub async fn verify_domain(
+    user: AuthenticatedUser,
+    domain_name: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<DomainClaim>, Custom<String>> {
+    let domains = deployment_service.domains();
+    let domain_name = domain_name.to_owned();
+    let token = user.token_scope();
+    // The resolver blocks and must not run on the async executor
+    let claim = rocket::tokio::task::spawn_blocking(move || domains.verify(&domain_name, &token))
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, format!("{:?}", e)))?
+        .map_err(error_response)?;
+    Ok(Json(claim))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rocket_test;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+    use rocket::serde::json::Value;
+
+    fn authorization() -> Header<'static> {
+        Header::new("Authorization", "Bearer test_access_token")
+    }
+
+    #[test]
+    fn test_claim_domain_returns_challenge() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::claim_domain))
+            .header(authorization())
+            .header(ContentType::JSON)
+            .body(r#"{"domain_name": "claimed.example.org"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let claim: Value = response.into_json().unwrap();
+        assert_eq!(
+            claim["challenge_record_name"],
+            "_pond-challenge.claimed.example.org"
+        );
+        assert_eq!(claim["verified"], false);
+
+        let response = client
+            .get(uri!(super::list_domains))
+            .header(authorization())
+            .dispatch();
+        let domains: Vec<Value> = response.into_json().unwrap();
+        assert!(domains
+            .iter()
+            .any(|d| d["domain_name"] == "claimed.example.org"));
+    }
+
+    #[test]
+    fn test_claim_root_domain_is_rejected() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::claim_domain))
+            .header(authorization())
+            .header(ContentType::JSON)
+            .body(r#"{"domain_name": "blog.example.com"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[test]
+    fn test_verify_unclaimed_domain() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::verify_domain("unclaimed.example.org")))
+            .header(authorization())
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_unverified_claims_are_taken_over() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::claim_domain))
+            .header(authorization())
+            .header(ContentType::JSON)
+            .body(r#"{"domain_name": "admin.example.org"}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+
+        let scoped = Header::new("Authorization", "Bearer scoped_access_token");
+        let response = client
+            .post(uri!(super::claim_domain))
+            .header(scoped.clone())
+            .header(ContentType::JSON)
+            .body(r#"{"domain_name": "admin.example.org"}"#)
+            .dispatch();
+        // The claim isn't verified yet, so the other token takes it over
+        assert_eq!(response.status(), Status::Ok);
+        let claim: Value = response.into_json().unwrap();
+        assert_eq!(claim["claimed_by"]["name"], "scoped");
+        let response = client
+            .get(uri!(super::list_domains))
+            .header(scoped)
+            .dispatch();
+        let domains: Vec<Value> = response.into_json().unwrap();
+        assert!(domains
+            .iter()
+            .any(|d| d["domain_name"] == "admin.example.org"));
+    }
+}
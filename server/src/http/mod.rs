@@ -1,2 +1,8 @@
+pub mod artifact_routes;
 pub mod auth;
 pub mod deployment_routes;
+pub mod dns_routes;
+pub mod domain_routes;
+pub mod secret_routes;
+pub mod sync_routes;
+pub mod upload_routes;
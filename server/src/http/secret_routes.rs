@@ -0,0 +1,122 @@
+use pond_deployment::{DeploymentManager, SecretError};
+use rocket::http::Status;
+use rocket::response::status::{Custom, NoContent};
+use rocket::serde::json::Json;
+use rocket::State;
+use std::sync::Arc;
+
+use super::auth::AuthenticatedUser;
+use super::deployment_routes::DeploymentRouteError;
+
+fn error_response(error: SecretError) -> Custom<String> {
+    let status = match error {
+        SecretError::NotConfigured => Status::ServiceUnavailable,
+        SecretError::InvalidName(_) => Status::BadRequest,
+        SecretError::NotFound(_) => Status::NotFound,
+        SecretError::Other(_) => Status::InternalServerError,
+    };
+    Custom(status, error.to_string())
+}
+
+/// Lists the names of the secrets of a deployment. Values are never returned. Access tokens
+/// restricted to a root domain only see the secrets of deployments below it.
+#[get("/deployments/<deployment_name>/secrets")]
+pub fn list_secrets(
+    user: AuthenticatedUser,
+    deployment_name: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<Vec<String>>, DeploymentRouteError> {
+    deployment_service.check_scope(deployment_name, user.root_domain_name.as_deref())?;
+    let names = deployment_service
+        .secrets()
+        .names(deployment_name)
+        .map_err(error_response)?;
+    Ok(Json(names))
+}
+
+#[put("/deployments/<deployment_name>/secrets/<name>", data = "<value>")]
+pub fn set_secret(
+    user: AuthenticatedUser,
+    deployment_name: &str,
+    name: &str,
+    value: String,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<NoContent, DeploymentRouteError> {
+    deployment_service.check_scope(deployment_name, user.root_domain_name.as_deref())?;
+    deployment_service
+        .secrets()
+        .set(deployment_name, name, &value)
+        .map_err(error_response)?;
+    Ok(NoContent)
+}
+
+#[delete("/deployments/<deployment_name>/secrets/<name>")]
+pub fn delete_secret(
+    user: AuthenticatedUser,
+    deployment_name: &str,
+    name: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<NoContent, DeploymentRouteError> {
+    deployment_service.check_scope(deployment_name, user.root_domain_name.as_deref())?;
+    deployment_service
+        .secrets()
+        .delete(deployment_name, name)
+        .map_err(error_response)?;
+    Ok(NoContent)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rocket_test;
+    use rocket::http::{Header, Status};
+    use rocket::local::blocking::Client;
+
+    fn authorization() -> Header<'static> {
+        Header::new("Authorization", "Bearer test_access_token")
+    }
+
+    #[test]
+    fn test_manage_secrets() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .put(uri!(super::set_secret("secret-test", "api-token")))
+            .header(authorization())
+            .body("hunter2")
+            .dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+
+        let response = client
+            .get(uri!(super::list_secrets("secret-test")))
+            .header(authorization())
+            .dispatch();
+        assert_eq!(response.into_string().unwrap(), r#"["api-token"]"#);
+
+        let response = client
+            .delete(uri!(super::delete_secret("secret-test", "api-token")))
+            .header(authorization())
+            .dispatch();
+        assert_eq!(response.status(), Status::NoContent);
+        let response = client
+            .delete(uri!(super::delete_secret("secret-test", "api-token")))
+            .header(authorization())
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_scoped_tokens_only_manage_recorded_deployments() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .put(uri!(super::set_secret("secret-scope-test", "api-token")))
+            .header(Header::new("Authorization", "Bearer scoped_access_token"))
+            .body("hunter2")
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client
+            .get(uri!(super::list_secrets("secret-scope-test")))
+            .header(Header::new("Authorization", "Bearer scoped_access_token"))
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
@@ -0,0 +1,173 @@
+use pond_deployment::{DeploymentManager, SyncError, SyncSession, SyncedFile};
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::serde::Deserialize;
+use rocket::tokio::task::spawn_blocking;
+use rocket::State;
+use std::sync::Arc;
+
+use super::auth::AuthenticatedUser;
+
+/// The name of the limit of the size of one synced file.
+const SYNC_FILE_LIMIT: &str = "sync-file";
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CreateSyncRequest {
+    deployment_name: String,
+    /// Every file of the new release.
+    files: Vec<SyncedFile>,
+}
+
+pub(crate) fn error_response(error: SyncError) -> Custom<String> {
+    let status = match error {
+        SyncError::NotFound(_) => Status::NotFound,
+        SyncError::InvalidDeploymentName(_)
+        | SyncError::InvalidPath(_)
+        | SyncError::InvalidDigest(_) => Status::BadRequest,
+        SyncError::DigestMismatch { .. } => Status::UnprocessableEntity,
+        SyncError::UnexpectedFile(_) | SyncError::Incomplete(_) => Status::Conflict,
+        SyncError::Other(_) => Status::InternalServerError,
+    };
+    Custom(status, error.to_string())
+}
+
+/// Starts a sync of the files of a new release. The response lists the digests of the files
+/// that have to be uploaded through `PUT /syncs/<id>/files/<sha256>`.
+#[post("/syncs", data = "<request>")]
+pub fn create_sync(
+    _user: AuthenticatedUser,
+    request: Json<CreateSyncRequest>,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Custom<Json<SyncSession>>, Custom<String>> {
+    let request = request.into_inner();
+    let session = deployment_service
+        .releases()
+        .create(&request.deployment_name, request.files)
+        .map_err(error_response)?;
+    Ok(Custom(Status::Created, Json(session)))
+}
+
+#[get("/syncs/<id>")]
+pub fn get_sync(
+    _user: AuthenticatedUser,
+    id: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<SyncSession>, Custom<String>> {
+    let session = deployment_service
+        .releases()
+        .get(id)
+        .map_err(error_response)?;
+    Ok(Json(session))
+}
+
+#[put("/syncs/<id>/files/<sha256>", data = "<content>")]
+pub async fn upload_sync_file(
+    _user: AuthenticatedUser,
+    id: &str,
+    sha256: &str,
+    content: Data<'_>,
+    limits: &Limits,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<SyncSession>, Custom<String>> {
+    let limit = limits.get(SYNC_FILE_LIMIT).unwrap_or(64.mebibytes());
+    let content = content.open(limit).into_bytes().await.map_err(|e| {
+        Custom(
+            Status::InternalServerError,
+            format!("Failed to read file: {:?}", e),
+        )
+    })?;
+    if !content.is_complete() {
+        return Err(Custom(
+            Status::PayloadTooLarge,
+            format!("Files may not be larger than {}", limit),
+        ));
+    }
+
+    let releases = deployment_service.releases();
+    let id = id.to_owned();
+    let sha256 = sha256.to_owned();
+    let session = spawn_blocking(move || releases.upload(&id, &sha256, &content))
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .map_err(error_response)?;
+    Ok(Json(session))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rocket_test;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+    use rocket::serde::json::Value;
+
+    /// The SHA-256 digest of `Hello`.
+    const HELLO_SHA256: &str = "185f8db32271fe25f561a6fc938b2e264306ec304eda518007d1764826381969";
+
+    fn authorization() -> Header<'static> {
+        Header::new("Authorization", "Bearer test_access_token")
+    }
+
+    #[test]
+    fn test_sync() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::create_sync))
+            .header(authorization())
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"{{"deployment_name": "sync-test", "files": [{{"path": "index.html", "sha256": "{}"}}]}}"#,
+                HELLO_SHA256
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let session: Value = response.into_json().unwrap();
+        let id = session["id"].as_str().unwrap();
+
+        let response = client
+            .put(uri!(super::upload_sync_file(id, HELLO_SHA256)))
+            .header(authorization())
+            .body("Hello World")
+            .dispatch();
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        let boundary = "X-POND-BOUNDARY";
+        let response = client
+            .post(uri!(crate::http::deployment_routes::deploy))
+            .header(authorization())
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", boundary)))
+            .body(format!(
+                "--{b}\r\nContent-Disposition: form-data; name=\"sync\"\r\n\r\n{id}\r\n--{b}--\r\n",
+                b = boundary,
+                id = id
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let response = client
+            .put(uri!(super::upload_sync_file(id, HELLO_SHA256)))
+            .header(authorization())
+            .body("Hello")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let session: Value = response.into_json().unwrap();
+        assert_eq!(session["missing"], Value::Array(vec![]));
+    }
+
+    #[test]
+    fn test_invalid_path() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::create_sync))
+            .header(authorization())
+            .header(ContentType::JSON)
+            .body(format!(
+                r#"{{"deployment_name": "blog", "files": [{{"path": "../index.html", "sha256": "{}"}}]}}"#,
+                HELLO_SHA256
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+}
@@ -0,0 +1,184 @@
+use pond_deployment::{DeploymentManager, UploadError, UploadSession};
+use rocket::data::{Data, Limits, ToByteUnit};
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Outcome, Request};
+use rocket::response::status::Custom;
+use rocket::serde::json::Json;
+use rocket::serde::Deserialize;
+use rocket::tokio::task::spawn_blocking;
+use rocket::State;
+use std::sync::Arc;
+
+use super::auth::AuthenticatedUser;
+
+const UPLOAD_OFFSET: &str = "Upload-Offset";
+/// The name of the limit of the size of one chunk.
+const UPLOAD_CHUNK_LIMIT: &str = "upload-chunk";
+
+#[derive(Deserialize)]
+#[serde(crate = "rocket::serde")]
+pub struct CreateUploadRequest {
+    /// The size of the whole artifact in bytes.
+    length: u64,
+}
+
+/// The `Upload-Offset` header of a chunk, which is where it starts within the artifact.
+pub struct UploadOffset(u64);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UploadOffset {
+    type Error = &'static str;
+
+    async fn from_request(req: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        match req.headers().get_one(UPLOAD_OFFSET).map(str::parse) {
+            Some(Ok(offset)) => Outcome::Success(UploadOffset(offset)),
+            _ => Outcome::Error((
+                Status::BadRequest,
+                "Missing or invalid Upload-Offset header",
+            )),
+        }
+    }
+}
+
+pub(crate) fn error_response(error: UploadError) -> Custom<String> {
+    let status = match error {
+        UploadError::NotFound(_) => Status::NotFound,
+        UploadError::OffsetMismatch { .. } | UploadError::Incomplete(_) => Status::Conflict,
+        UploadError::TooLong { .. } => Status::PayloadTooLarge,
+        UploadError::Other(_) => Status::InternalServerError,
+    };
+    Custom(status, error.to_string())
+}
+
+/// Starts an upload whose chunks are sent through `PATCH /uploads/<id>`.
+#[post("/uploads", data = "<request>")]
+pub fn create_upload(
+    _user: AuthenticatedUser,
+    request: Json<CreateUploadRequest>,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Custom<Json<UploadSession>>, Custom<String>> {
+    let session = deployment_service
+        .uploads()
+        .create(request.length)
+        .map_err(error_response)?;
+    Ok(Custom(Status::Created, Json(session)))
+}
+
+#[get("/uploads/<id>")]
+pub fn get_upload(
+    _user: AuthenticatedUser,
+    id: &str,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<UploadSession>, Custom<String>> {
+    let session = deployment_service
+        .uploads()
+        .get(id)
+        .map_err(error_response)?;
+    Ok(Json(session))
+}
+
+/// Appends the body to the upload. It has to start at the offset the upload has reached, which
+/// `GET /uploads/<id>` reports after an interrupted chunk.
+#[patch("/uploads/<id>", data = "<chunk>")]
+pub async fn append_upload(
+    _user: AuthenticatedUser,
+    id: &str,
+    offset: UploadOffset,
+    chunk: Data<'_>,
+    limits: &Limits,
+    deployment_service: &State<Arc<DeploymentManager>>,
+) -> Result<Json<UploadSession>, Custom<String>> {
+    let limit = limits.get(UPLOAD_CHUNK_LIMIT).unwrap_or(16.mebibytes());
+    let chunk = chunk.open(limit).into_bytes().await.map_err(|e| {
+        Custom(
+            Status::InternalServerError,
+            format!("Failed to read chunk: {:?}", e),
+        )
+    })?;
+    if !chunk.is_complete() {
+        return Err(Custom(
+            Status::PayloadTooLarge,
+            format!("Chunks may not be larger than {}", limit),
+        ));
+    }
+
+    let uploads = deployment_service.uploads();
+    let id = id.to_owned();
+    let session = spawn_blocking(move || uploads.append(&id, offset.0, &chunk))
+        .await
+        .map_err(|e| Custom(Status::InternalServerError, e.to_string()))?
+        .map_err(error_response)?;
+    Ok(Json(session))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::rocket_test;
+    use rocket::http::{ContentType, Header, Status};
+    use rocket::local::blocking::Client;
+    use rocket::serde::json::Value;
+
+    fn authorization() -> Header<'static> {
+        Header::new("Authorization", "Bearer test_access_token")
+    }
+
+    #[test]
+    fn test_chunked_upload() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .post(uri!(super::create_upload))
+            .header(authorization())
+            .header(ContentType::JSON)
+            .body(r#"{"length": 11}"#)
+            .dispatch();
+        assert_eq!(response.status(), Status::Created);
+        let session: Value = response.into_json().unwrap();
+        let id = session["id"].as_str().unwrap();
+
+        let response = client
+            .patch(uri!(super::append_upload(id)))
+            .header(authorization())
+            .header(Header::new("Upload-Offset", "0"))
+            .body("Hello ")
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .patch(uri!(super::append_upload(id)))
+            .header(authorization())
+            .header(Header::new("Upload-Offset", "0"))
+            .body("World")
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+
+        let response = client
+            .get(uri!(super::get_upload(id)))
+            .header(authorization())
+            .dispatch();
+        let session: Value = response.into_json().unwrap();
+        assert_eq!(session["offset"], 6);
+        assert_eq!(session["length"], 11);
+
+        let boundary = "X-POND-BOUNDARY";
+        let response = client
+            .post(uri!(crate::http::deployment_routes::deploy))
+            .header(authorization())
+            .header(ContentType::new("multipart", "form-data").with_params(("boundary", boundary)))
+            .body(format!(
+                "--{b}\r\nContent-Disposition: form-data; name=\"upload\"\r\n\r\n{id}\r\n--{b}--\r\n",
+                b = boundary,
+                id = id
+            ))
+            .dispatch();
+        assert_eq!(response.status(), Status::Conflict);
+    }
+
+    #[test]
+    fn test_unknown_upload() {
+        let client = Client::tracked(rocket_test()).expect("valid rocket instance");
+        let response = client
+            .get(uri!(super::get_upload("unknown")))
+            .header(authorization())
+            .dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+    }
+}
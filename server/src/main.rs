@@ -6,11 +6,33 @@ mod http;
 mod message;
 
 use config::AuthorizationConfig;
-use http::deployment_routes::deploy;
+use http::artifact_routes::{get_artifact, list_artifacts};
+use http::deployment_routes::{
+    abort_canary, deploy, end_maintenance, expiring_deployments, manifest_schema, promote_canary,
+    promote_to, start_maintenance,
+};
+use http::dns_routes::dns_drift;
+use http::domain_routes::{claim_domain, list_domains, verify_domain};
+use http::secret_routes::{delete_secret, list_secrets, set_secret};
+use http::sync_routes::{create_sync, get_sync, upload_sync_file};
+use http::upload_routes::{append_upload, create_upload, get_upload};
+use pond_deployment::DeploymentManager;
 use rocket::fairing::AdHoc;
+use std::sync::Arc;
 
-#[launch]
-fn rocket() -> _ {
+#[rocket::main]
+async fn main() {
+    let rocket = rocket();
+    // Started here rather than in `rocket()` so tests don't run the background jobs
+    if let Some(deployment_manager) = rocket.state::<Arc<DeploymentManager>>() {
+        deployment_manager.start_background_jobs();
+    }
+    if let Err(e) = rocket.launch().await {
+        panic!("Failed to launch server: {:?}", e);
+    }
+}
+
+fn rocket() -> rocket::Rocket<rocket::Build> {
     let figment = config::figment();
     let deployment_manager = match pond_deployment::config::manager(&figment) {
         Ok(manager) => manager,
@@ -20,8 +42,35 @@ fn rocket() -> _ {
     };
 
     rocket::custom(figment)
-        .mount("/", routes![deploy])
-        .manage(deployment_manager)
+        .mount(
+            "/",
+            routes![
+                deploy,
+                promote_canary,
+                promote_to,
+                start_maintenance,
+                end_maintenance,
+                abort_canary,
+                expiring_deployments,
+                manifest_schema,
+                dns_drift,
+                list_domains,
+                claim_domain,
+                verify_domain,
+                list_secrets,
+                set_secret,
+                delete_secret,
+                create_upload,
+                get_upload,
+                append_upload,
+                list_artifacts,
+                get_artifact,
+                create_sync,
+                get_sync,
+                upload_sync_file
+            ],
+        )
+        .manage(Arc::new(deployment_manager))
         .attach(AdHoc::config::<AuthorizationConfig>())
 }
 
@@ -30,5 +79,17 @@ fn rocket_test() -> rocket::Rocket<rocket::Build> {
     std::env::set_var("POND_PROFILE", "test");
     std::env::set_var("POND_ROOT_DOMAIN_NAME", "example.com");
     std::env::set_var("POND_ACCESS_TOKEN", "test_access_token");
+    std::env::set_var(
+        "POND_ACCESS_TOKENS",
+        "[{token=\"scoped_access_token\",name=\"scoped\",root_domain_name=\"example.com\"}]",
+    );
+    std::env::set_var(
+        "POND_STATE_DIRECTORY",
+        std::env::temp_dir().join("pond-server-test-state"),
+    );
+    std::env::set_var(
+        "POND_SECRETS",
+        "{key=\"MDEyMzQ1Njc4OWFiY2RlZjAxMjM0NTY3ODlhYmNkZWY=\"}",
+    );
     rocket()
 }
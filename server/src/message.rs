@@ -114,11 +114,11 @@ mod tests {
     async fn test_async_log_stream() {
         let (mut handle, logs) = pond_deployment::deployment_handle();
         let jh = std::thread::spawn(move || {
-            handle.info().write(&[0]).unwrap();
+            handle.info().write_all(&[0]).unwrap();
             thread::sleep(std::time::Duration::from_millis(50));
-            handle.error().write(&[1]).unwrap();
+            handle.error().write_all(&[1]).unwrap();
             thread::sleep(std::time::Duration::from_millis(50));
-            handle.info().write(&[2]).unwrap();
+            handle.info().write_all(&[2]).unwrap();
             thread::sleep(std::time::Duration::from_millis(50));
         });
         let mut stream = super::AsyncLogStream::from_deployment_logs(logs);